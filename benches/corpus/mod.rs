@@ -0,0 +1,162 @@
+//! Deterministic synthetic versions index for benchmarks
+//!
+//! The real `/versions` file can't be checked in and changes every few
+//! minutes, so benchmarks run against a generated one with the same shape:
+//! a compacted section with one line per gem whose version list lengths are
+//! heavy-tailed (most gems have a handful of releases, a few have hundreds),
+//! followed by appended update lines that repeat earlier gem names with one
+//! or two versions each. The same config always yields the same bytes.
+
+#![allow(dead_code)]
+
+/// Shape of the generated index
+#[derive(Debug, Clone)]
+pub struct CorpusConfig {
+    /// Distinct gems in the compacted section
+    pub gems: usize,
+    /// Appended update lines as a fraction of `gems` (0.5 adds half as many lines again)
+    pub duplicate_rate: f64,
+    /// Pareto shape of the version-list length; lower means a longer tail
+    pub version_tail: f64,
+    /// Longest version list in the compacted section
+    pub max_versions: usize,
+    /// PRNG seed
+    pub seed: u64,
+}
+
+impl Default for CorpusConfig {
+    /// Roughly a tenth of rubygems.org, about 2 MB
+    fn default() -> Self {
+        CorpusConfig {
+            gems: 18_000,
+            duplicate_rate: 0.6,
+            version_tail: 1.1,
+            max_versions: 400,
+            seed: 0x6765_6d73,
+        }
+    }
+}
+
+const WORDS: &[&str] = &[
+    "active", "rails", "rack", "json", "http", "aws", "sdk", "core", "rspec", "mock", "puma",
+    "sinatra", "log", "cache", "redis", "pg", "mysql", "auth", "omni", "api", "client", "parser",
+    "xml", "yaml", "thor", "cli", "test", "unit", "form", "view", "asset", "job", "queue", "mail",
+    "storage", "record", "model", "support", "sass", "coffee", "image", "pdf",
+];
+
+const PLATFORMS: &[&str] = &["java", "x86_64-linux", "arm64-darwin", "x64-mingw-ucrt"];
+
+/// Generate a complete versions file (metadata, separator and gem lines)
+pub fn generate(config: &CorpusConfig) -> Vec<u8> {
+    let mut rng = SplitMix64(config.seed);
+    let names: Vec<String> = (0..config.gems).map(|i| gem_name(&mut rng, i)).collect();
+
+    let mut out = Vec::with_capacity(config.gems * 120);
+    out.extend_from_slice(b"created_at: 2024-04-01T00:00:05Z\n---\n");
+
+    for name in &names {
+        let count = pareto(&mut rng, config.version_tail, config.max_versions);
+        push_line(&mut out, &mut rng, name, count);
+    }
+
+    // Updates favour a small set of active gems, like the real append log
+    let updates = (config.gems as f64 * config.duplicate_rate) as usize;
+    for _ in 0..updates {
+        let active = (config.gems / 20).max(1);
+        let index = if rng.next_f64() < 0.7 {
+            rng.below(active)
+        } else {
+            rng.below(config.gems)
+        };
+        let count = if rng.next_f64() < 0.9 { 1 } else { 2 };
+        push_line(&mut out, &mut rng, &names[index], count);
+    }
+
+    out
+}
+
+/// Every `step`-th gem name, for building allow/block lists
+pub fn sample_names(config: &CorpusConfig, step: usize) -> Vec<String> {
+    let mut rng = SplitMix64(config.seed);
+    (0..config.gems)
+        .map(|i| gem_name(&mut rng, i))
+        .step_by(step.max(1))
+        .collect()
+}
+
+fn gem_name(rng: &mut SplitMix64, index: usize) -> String {
+    let first = WORDS[rng.below(WORDS.len())];
+    let second = WORDS[rng.below(WORDS.len())];
+    let separator = if rng.next_f64() < 0.8 { '-' } else { '_' };
+    // The index suffix keeps names unique without a lookup table
+    format!("{}{}{}{}", first, separator, second, index)
+}
+
+fn push_line(out: &mut Vec<u8>, rng: &mut SplitMix64, name: &str, versions: usize) {
+    out.extend_from_slice(name.as_bytes());
+    out.push(b' ');
+
+    let (mut major, mut minor, mut patch) = (0u32, rng.below(5) as u32, 0u32);
+    for i in 0..versions {
+        if i > 0 {
+            out.push(b',');
+        }
+        if rng.next_f64() < 0.02 {
+            out.push(b'-'); // yanked
+        }
+        match rng.below(20) {
+            0 => {
+                major += 1;
+                minor = 0;
+                patch = 0;
+            }
+            1..=4 => {
+                minor += 1;
+                patch = 0;
+            }
+            _ => patch += 1,
+        }
+        out.extend_from_slice(format!("{}.{}.{}", major, minor, patch).as_bytes());
+        if rng.next_f64() < 0.03 {
+            out.extend_from_slice(b".rc1");
+        }
+        if rng.next_f64() < 0.05 {
+            out.push(b'-');
+            out.extend_from_slice(PLATFORMS[rng.below(PLATFORMS.len())].as_bytes());
+        }
+    }
+
+    out.push(b' ');
+    let hash = [rng.next_u64(), rng.next_u64()];
+    for word in hash {
+        out.extend_from_slice(format!("{:016x}", word).as_bytes());
+    }
+    out.push(b'\n');
+}
+
+/// Version list length with a heavy tail, clamped to `1..=max`
+fn pareto(rng: &mut SplitMix64, shape: f64, max: usize) -> usize {
+    let u = 1.0 - rng.next_f64(); // (0, 1]
+    (u.powf(-1.0 / shape) as usize).clamp(1, max)
+}
+
+/// SplitMix64: tiny, seedable and stable across platforms and releases
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
@@ -0,0 +1,142 @@
+//! Throughput of the filter hot loops over a synthetic versions index
+//!
+//! Run with `cargo bench`. Set `GEM_INDEX_BENCH_GEMS` to change the corpus
+//! size, e.g. `GEM_INDEX_BENCH_GEMS=180000` for a full rubygems.org-sized file.
+
+mod corpus;
+
+use corpus::CorpusConfig;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use gem_index_filter::{
+    filter_slice, filter_versions_streaming, DigestAlgorithm, FilterMode, SliceMode, VersionOutput,
+};
+use std::collections::{BTreeSet, HashSet};
+use std::hint::black_box;
+
+fn config() -> CorpusConfig {
+    let mut config = CorpusConfig::default();
+    if let Some(gems) = std::env::var("GEM_INDEX_BENCH_GEMS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+    {
+        config.gems = gems;
+    }
+    config
+}
+
+fn bench_streaming(c: &mut Criterion) {
+    let config = config();
+    let input = corpus::generate(&config);
+    // A typical private allowlist covers a few percent of the index
+    let allowed = corpus::sample_names(&config, 30);
+    let blocked = corpus::sample_names(&config, 200);
+    let allowlist: HashSet<&str> = allowed.iter().map(String::as_str).collect();
+    let blocklist: HashSet<&str> = blocked.iter().map(String::as_str).collect();
+
+    let mut group = c.benchmark_group("filter_versions_streaming");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+
+    let modes = [
+        ("passthrough", FilterMode::Passthrough),
+        ("allow", FilterMode::Allow(&allowlist)),
+        ("block", FilterMode::Block(&blocklist)),
+    ];
+    for (name, mode) in modes {
+        for (suffix, version_output) in [
+            ("", VersionOutput::Preserve),
+            ("+strip", VersionOutput::Strip),
+        ] {
+            group.bench_function(
+                BenchmarkId::from_parameter(format!("{name}{suffix}")),
+                |b| {
+                    let mut output = Vec::with_capacity(input.len());
+                    b.iter(|| {
+                        output.clear();
+                        filter_versions_streaming(
+                            black_box(input.as_slice()),
+                            &mut output,
+                            mode,
+                            version_output,
+                            None,
+                        )
+                        .unwrap()
+                    })
+                },
+            );
+        }
+    }
+
+    group.bench_function(BenchmarkId::from_parameter("passthrough+sha256"), |b| {
+        let mut output = Vec::with_capacity(input.len());
+        b.iter(|| {
+            output.clear();
+            filter_versions_streaming(
+                black_box(input.as_slice()),
+                &mut output,
+                FilterMode::Passthrough,
+                VersionOutput::Preserve,
+                Some(DigestAlgorithm::Sha256),
+            )
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn bench_slice(c: &mut Criterion) {
+    let config = config();
+    let input = corpus::generate(&config);
+    let allowed = corpus::sample_names(&config, 30);
+    let hash_set: HashSet<&str> = allowed.iter().map(String::as_str).collect();
+    let btree_set: BTreeSet<&str> = allowed.iter().map(String::as_str).collect();
+    let mut sorted: Vec<&str> = allowed.iter().map(String::as_str).collect();
+    sorted.sort_unstable();
+
+    let mut group = c.benchmark_group("filter_slice");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+
+    let mut output = Vec::with_capacity(input.len());
+    group.bench_function("allow/HashSet", |b| {
+        b.iter(|| {
+            output.clear();
+            let mode = SliceMode::Allow(&hash_set);
+            filter_slice(
+                black_box(&input),
+                &mut output,
+                mode,
+                VersionOutput::Preserve,
+            )
+            .unwrap()
+        })
+    });
+    group.bench_function("allow/BTreeSet", |b| {
+        b.iter(|| {
+            output.clear();
+            let mode = SliceMode::Allow(&btree_set);
+            filter_slice(
+                black_box(&input),
+                &mut output,
+                mode,
+                VersionOutput::Preserve,
+            )
+            .unwrap()
+        })
+    });
+    group.bench_function("allow/sorted slice", |b| {
+        b.iter(|| {
+            output.clear();
+            let mode = SliceMode::Allow(&sorted[..]);
+            filter_slice(
+                black_box(&input),
+                &mut output,
+                mode,
+                VersionOutput::Preserve,
+            )
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_streaming, bench_slice);
+criterion_main!(benches);
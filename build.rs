@@ -0,0 +1,67 @@
+//! Generates the compiled-in allowlist for the `baked-allowlist` feature
+
+fn main() {
+    #[cfg(feature = "baked-allowlist")]
+    baked::generate();
+}
+
+#[cfg(feature = "baked-allowlist")]
+mod baked {
+    use std::collections::BTreeSet;
+    use std::env;
+    use std::fmt::Write as _;
+    use std::path::Path;
+
+    const LIST_VAR: &str = "GEM_INDEX_FILTER_ALLOWLIST";
+
+    pub fn generate() {
+        println!("cargo:rerun-if-env-changed={}", LIST_VAR);
+        let path = env::var(LIST_VAR).unwrap_or_else(|_| {
+            panic!(
+                "the baked-allowlist feature needs {}=<gem list file> at build time",
+                LIST_VAR
+            )
+        });
+        println!("cargo:rerun-if-changed={}", path);
+        let text = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+
+        // Same format as runtime lists, minus patterns: a phf set can only hold exact names
+        let gems: BTreeSet<&str> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        if let Some(pattern) = gems
+            .iter()
+            .find(|gem| gem.contains(['*', '?', '[']) || gem.starts_with('/'))
+        {
+            panic!(
+                "{} contains the pattern {}; expand patterns before baking a list",
+                path, pattern
+            );
+        }
+
+        let mut set = phf_codegen::Set::new();
+        for gem in &gems {
+            set.entry(*gem);
+        }
+        let mut code = String::new();
+        writeln!(
+            code,
+            "/// The {} gems listed in `{}` when the crate was built",
+            gems.len(),
+            path.escape_default()
+        )
+        .unwrap();
+        writeln!(
+            code,
+            "pub static BAKED_ALLOWLIST: phf::Set<&'static str> = {};",
+            set.build()
+        )
+        .unwrap();
+
+        let out = Path::new(&env::var("OUT_DIR").unwrap()).join("baked_allowlist.rs");
+        std::fs::write(out, code).unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+//! Input decoding and allocation tracking shared by the fuzz targets
+
+#![allow(dead_code)]
+
+use gem_index_filter::{FilterMode, VersionOutput};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Filter settings and data decoded from one fuzz input
+///
+/// Layout: a flags byte, a chunk-size byte, then the gem list (names
+/// separated by newlines) and the versions file, split at the first NUL.
+pub struct Case<'a> {
+    pub mode: u8,
+    pub version_output: VersionOutput,
+    pub digest: bool,
+    pub chunk_size: usize,
+    pub gems: HashSet<&'a str>,
+    pub versions: &'a [u8],
+}
+
+impl<'a> Case<'a> {
+    pub fn decode(data: &'a [u8]) -> Option<Self> {
+        let (&flags, rest) = data.split_first()?;
+        let (&chunk, rest) = rest.split_first()?;
+        let (list, versions) = match rest.iter().position(|&b| b == 0) {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => (&[][..], rest),
+        };
+        let gems = std::str::from_utf8(list).ok()?.lines().collect();
+
+        Some(Case {
+            mode: flags % 3,
+            version_output: if flags & 4 == 0 {
+                VersionOutput::Preserve
+            } else {
+                VersionOutput::Strip
+            },
+            digest: flags & 8 != 0,
+            chunk_size: usize::from(chunk) + 1,
+            gems,
+            versions,
+        })
+    }
+
+    pub fn filter_mode(&self) -> FilterMode<'_> {
+        match self.mode {
+            0 => FilterMode::Passthrough,
+            1 => FilterMode::Allow(&self.gems),
+            _ => FilterMode::Block(&self.gems),
+        }
+    }
+}
+
+/// System allocator that records the peak number of live bytes
+pub struct PeakAlloc;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(live, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+/// Bytes allocated at the highest point while running `f`, above what was live before
+pub fn peak_during<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = LIVE.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let result = f();
+    (result, PEAK.load(Ordering::Relaxed) - before)
+}
+
+/// `Write` that only counts bytes, so output doesn't show up as retained memory
+#[derive(Default)]
+pub struct CountingWriter(pub usize);
+
+impl std::io::Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
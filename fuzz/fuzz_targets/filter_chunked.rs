@@ -0,0 +1,38 @@
+//! `SliceFilter` fed in arbitrary chunk sizes must not panic and must agree
+//! byte for byte with `filter_versions_streaming` on every input both accept.
+
+#![no_main]
+
+mod common;
+
+use common::Case;
+use gem_index_filter::{filter_versions_streaming, SliceFilter};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some(case) = Case::decode(data) else {
+        return;
+    };
+
+    let mut expected = Vec::new();
+    let streamed = filter_versions_streaming(
+        case.versions,
+        &mut expected,
+        case.filter_mode(),
+        case.version_output,
+        None,
+    );
+
+    let mut filter = SliceFilter::new(case.filter_mode().into(), case.version_output);
+    let mut output = Vec::new();
+    let chunked = case
+        .versions
+        .chunks(case.chunk_size)
+        .try_for_each(|chunk| filter.push(chunk, &mut output))
+        .and_then(|()| filter.finish(&mut output));
+
+    assert_eq!(streamed.is_ok(), chunked.is_ok());
+    if chunked.is_ok() {
+        assert_eq!(output, expected);
+    }
+});
@@ -0,0 +1,53 @@
+//! `filter_versions_streaming` must not panic on arbitrary input, must never
+//! grow the data, and must only hold about one line in memory at a time.
+
+#![no_main]
+
+mod common;
+
+use common::{peak_during, Case, CountingWriter, PeakAlloc};
+use gem_index_filter::{filter_versions_streaming, DigestAlgorithm};
+use libfuzzer_sys::fuzz_target;
+
+#[global_allocator]
+static ALLOC: PeakAlloc = PeakAlloc;
+
+/// BufReader buffer, digest state and slack for the line buffer doubling
+const FIXED_OVERHEAD: usize = 64 * 1024;
+
+fuzz_target!(|data: &[u8]| {
+    let Some(case) = Case::decode(data) else {
+        return;
+    };
+    let digest = case.digest.then_some(DigestAlgorithm::Sha256);
+    let longest_line = case
+        .versions
+        .split(|&b| b == b'\n')
+        .map(<[u8]>::len)
+        .max()
+        .unwrap_or(0);
+
+    let mut output = CountingWriter::default();
+    let (result, peak) = peak_during(|| {
+        filter_versions_streaming(
+            case.versions,
+            &mut output,
+            case.filter_mode(),
+            case.version_output,
+            digest,
+        )
+    });
+
+    // Memory scales with the longest line, never with the whole file
+    assert!(
+        peak <= FIXED_OVERHEAD + 2 * longest_line,
+        "peak {} bytes for longest line {}",
+        peak,
+        longest_line
+    );
+    // Only a missing trailing newline can add a byte
+    assert!(output.0 <= case.versions.len() + 1);
+    if let Ok(checksum) = result {
+        assert_eq!(checksum.is_some(), case.digest);
+    }
+});
@@ -0,0 +1,134 @@
+//! Native extension behind the `gem_index_filter` Ruby gem
+//!
+//! Exposes `GemIndexFilter._filter(io_in, io_out, allow, block, strip, digest)`;
+//! `lib/gem_index_filter.rb` wraps it in the keyword-argument `filter` method.
+//! Any object responding to `read(length)` / `write(string)` works as an IO,
+//! and data is streamed through in chunks instead of being read whole.
+
+use gem_index_filter::{filter_versions_streaming, DigestAlgorithm, FilterMode, VersionOutput};
+use magnus::{function, prelude::*, Error, RString, Ruby, Value};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+/// Bytes requested from the Ruby IO per `read` call
+const READ_CHUNK: usize = 64 * 1024;
+
+#[magnus::init]
+fn init(ruby: &Ruby) -> Result<(), Error> {
+    let module = ruby.define_module("GemIndexFilter")?;
+    module.define_singleton_method("_filter", function!(filter, 6))?;
+    Ok(())
+}
+
+fn filter(
+    ruby: &Ruby,
+    io_in: Value,
+    io_out: Value,
+    allow: Option<Vec<String>>,
+    block: Option<Vec<String>>,
+    strip: bool,
+    digest: Option<String>,
+) -> Result<Option<String>, Error> {
+    let digest_algorithm = match digest.as_deref() {
+        None => None,
+        Some("sha256") => Some(DigestAlgorithm::Sha256),
+        Some("sha512") => Some(DigestAlgorithm::Sha512),
+        Some(other) => {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                format!("unknown digest algorithm '{}' (expected sha256 or sha512)", other),
+            ))
+        }
+    };
+    let version_output = if strip {
+        VersionOutput::Strip
+    } else {
+        VersionOutput::Preserve
+    };
+
+    // Same preprocessing as the CLI: allowlist minus blocklist when both are given
+    let allowlist: Option<HashSet<&str>> = allow.as_ref().map(|gems| {
+        let blocked: HashSet<&str> = block.iter().flatten().map(String::as_str).collect();
+        gems.iter()
+            .map(String::as_str)
+            .filter(|gem| !blocked.contains(gem))
+            .collect()
+    });
+    let blocklist: Option<HashSet<&str>> = block
+        .as_ref()
+        .map(|gems| gems.iter().map(String::as_str).collect());
+    let mode = match (&allowlist, &blocklist) {
+        (Some(allowlist), _) => FilterMode::Allow(allowlist),
+        (None, Some(blocklist)) => FilterMode::Block(blocklist),
+        (None, None) => FilterMode::Passthrough,
+    };
+
+    let mut reader = RubyReader {
+        io: io_in,
+        error: None,
+    };
+    let mut writer = RubyWriter {
+        ruby,
+        io: io_out,
+        error: None,
+    };
+    let result =
+        filter_versions_streaming(&mut reader, &mut writer, mode, version_output, digest_algorithm);
+
+    // Re-raise the original Ruby exception rather than a wrapped IOError
+    if let Some(error) = reader.error.or(writer.error) {
+        return Err(error);
+    }
+    result.map_err(|e| Error::new(ruby.exception_io_error(), e.to_string()))
+}
+
+/// `Read` over a Ruby IO, keeping any exception it raises
+struct RubyReader {
+    io: Value,
+    error: Option<Error>,
+}
+
+impl Read for RubyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let chunk: Option<RString> = self
+            .io
+            .funcall("read", (buf.len().min(READ_CHUNK),))
+            .map_err(|e| stash(&mut self.error, e))?;
+        let Some(chunk) = chunk else {
+            return Ok(0); // EOF
+        };
+
+        // SAFETY: the slice is copied out before any other Ruby code can run
+        let bytes = unsafe { chunk.as_slice() };
+        let n = bytes.len().min(buf.len());
+        buf[..n].copy_from_slice(&bytes[..n]);
+        Ok(n)
+    }
+}
+
+/// `Write` over a Ruby IO, keeping any exception it raises
+struct RubyWriter<'a> {
+    ruby: &'a Ruby,
+    io: Value,
+    error: Option<Error>,
+}
+
+impl Write for RubyWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let _: Value = self
+            .io
+            .funcall("write", (self.ruby.str_from_slice(buf),))
+            .map_err(|e| stash(&mut self.error, e))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn stash(slot: &mut Option<Error>, error: Error) -> std::io::Error {
+    let io_error = std::io::Error::other(error.to_string());
+    *slot = Some(error);
+    io_error
+}
@@ -0,0 +1,24 @@
+# frozen_string_literal: true
+
+require_relative "gem_index_filter/version"
+require "gem_index_filter/gem_index_filter"
+
+# Fast streaming filter for RubyGems versions index files, backed by the
+# gem-index-filter Rust crate.
+module GemIndexFilter
+  # Filter a versions file from +io_in+ into +io_out+.
+  #
+  # +allow+ and +block+ are arrays of gem names; when both are given the
+  # blocked gems are removed from the allowlist. +strip+ replaces version
+  # lists with "0". +digest+ may be "sha256" or "sha512", in which case the
+  # hex checksum of the output is returned; otherwise returns nil.
+  #
+  #   File.open("versions") do |input|
+  #     File.open("versions.filtered", "w") do |output|
+  #       GemIndexFilter.filter(input, output, allow: %w[rails sinatra], digest: "sha256")
+  #     end
+  #   end
+  def self.filter(io_in, io_out, allow: nil, block: nil, strip: false, digest: nil)
+    _filter(io_in, io_out, allow&.map(&:to_s), block&.map(&:to_s), strip ? true : false, digest&.to_s)
+  end
+end
@@ -0,0 +1,41 @@
+# frozen_string_literal: true
+
+require "minitest/autorun"
+require "stringio"
+require "gem_index_filter"
+
+class TestGemIndexFilter < Minitest::Test
+  VERSIONS = <<~INDEX
+    created_at: 2024-04-01T00:00:05Z
+    ---
+    rails 7.0.0,7.0.1 abc123
+    activerecord 7.0.0 def456
+    sinatra 3.0.0 ghi789
+  INDEX
+
+  def test_allowlist
+    output = StringIO.new
+    assert_nil GemIndexFilter.filter(StringIO.new(VERSIONS), output, allow: %w[rails sinatra])
+    assert_equal "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0,7.0.1 abc123\nsinatra 3.0.0 ghi789\n",
+                 output.string
+  end
+
+  def test_block_strip_and_digest
+    output = StringIO.new
+    digest = GemIndexFilter.filter(StringIO.new(VERSIONS), output, block: %w[rails], strip: true, digest: "sha256")
+    assert_equal "created_at: 2024-04-01T00:00:05Z\n---\nactiverecord 0 def456\nsinatra 0 ghi789\n", output.string
+    assert_equal 64, digest.length
+  end
+
+  def test_unknown_digest
+    assert_raises(ArgumentError) do
+      GemIndexFilter.filter(StringIO.new(VERSIONS), StringIO.new, digest: "md5")
+    end
+  end
+
+  def test_missing_separator
+    assert_raises(IOError) do
+      GemIndexFilter.filter(StringIO.new("rails 7.0.0 abc123\n"), StringIO.new)
+    end
+  end
+end
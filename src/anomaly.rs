@@ -0,0 +1,310 @@
+//! Warnings for versions files that parse but look corrupted upstream
+//!
+//! A versions file can be well-formed and still be wrong. [`AnomalyDetector`]
+//! flags patterns the compact index never produces on its own:
+//!
+//! - the same gem line (name and info checksum) appended twice, since every
+//!   append changes the gem's `info` file and so its checksum
+//! - a gem's full version list listed again with fewer versions, where a
+//!   genuine update only appends new versions or yank records
+//! - a `created_at` older than the one in a previous snapshot
+//!
+//! Run it as a separate pass with [`analyze_versions`], or register
+//! `&detector` on a [`FilterPipeline`](crate::FilterPipeline) to check lines
+//! as they are filtered; it never drops or rewrites a line. Unlike the filter,
+//! it keeps a little state per gem.
+
+use crate::diff::{read_gem_line, read_metadata};
+use crate::parser::GemLine;
+use crate::pipeline::GemFilter;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufReader, Read};
+
+/// A suspicious pattern in a versions file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anomaly {
+    /// A gem line repeated with the checksum of the gem's previous line
+    DuplicateLine {
+        /// 1-based gem line number, when known
+        line: Option<usize>,
+        /// Gem name
+        gem: String,
+        /// The repeated info checksum
+        md5: String,
+    },
+    /// A gem's version list listed again from its first version, but shorter
+    ShrunkVersions {
+        /// 1-based gem line number, when known
+        line: Option<usize>,
+        /// Gem name
+        gem: String,
+        /// Versions known for the gem before this line
+        previous: usize,
+        /// Versions on this line
+        current: usize,
+    },
+    /// `created_at` went backwards relative to a previous snapshot
+    TimestampRegression {
+        /// `created_at` of the previous snapshot
+        previous: String,
+        /// `created_at` of this file
+        current: String,
+    },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = |line: &Option<usize>| line.map_or(String::new(), |n| format!("line {}: ", n));
+        match self {
+            Anomaly::DuplicateLine { line: n, gem, md5 } => {
+                write!(f, "{}{} repeated with checksum {}", line(n), gem, md5)
+            }
+            Anomaly::ShrunkVersions {
+                line: n,
+                gem,
+                previous,
+                current,
+            } => write!(
+                f,
+                "{}{} relisted with {} versions, down from {}",
+                line(n),
+                gem,
+                current,
+                previous
+            ),
+            Anomaly::TimestampRegression { previous, current } => write!(
+                f,
+                "created_at {} is older than the previous snapshot's {}",
+                current, previous
+            ),
+        }
+    }
+}
+
+/// What was last seen of a gem
+struct GemHistory {
+    first_version: String,
+    versions: usize,
+    md5: String,
+}
+
+#[derive(Default)]
+struct State {
+    gems: HashMap<String, GemHistory>,
+    anomalies: Vec<Anomaly>,
+}
+
+/// Collects [`Anomaly`] warnings from the lines it is shown
+#[derive(Default)]
+pub struct AnomalyDetector {
+    previous_created_at: Option<String>,
+    state: RefCell<State>,
+}
+
+impl AnomalyDetector {
+    /// A detector with no previous snapshot to compare `created_at` against
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also flag a `created_at` older than `previous`, from the last snapshot
+    pub fn after(previous: impl Into<String>) -> Self {
+        AnomalyDetector {
+            previous_created_at: Some(previous.into()),
+            ..Self::default()
+        }
+    }
+
+    /// Check a metadata line, such as `created_at: 2024-04-01T00:00:05Z`
+    pub fn observe_metadata(&self, line: &str) {
+        let (Some(previous), Some(current)) = (
+            &self.previous_created_at,
+            line.trim().strip_prefix("created_at:").map(str::trim),
+        ) else {
+            return;
+        };
+        // RFC 3339 UTC timestamps order the same as strings
+        if current < previous.as_str() {
+            self.state
+                .borrow_mut()
+                .anomalies
+                .push(Anomaly::TimestampRegression {
+                    previous: previous.clone(),
+                    current: current.to_string(),
+                });
+        }
+    }
+
+    /// Check a gem line; `line_number` is its 1-based gem line number, when known
+    pub fn observe_line(&self, line: &str, line_number: Option<usize>) {
+        let Some(gem_line) = GemLine::parse(line) else {
+            return;
+        };
+        let (gem, md5) = (gem_line.name, gem_line.md5);
+        let first_version = gem_line.versions.split(',').next().unwrap_or_default();
+        let yanked = gem_line.version_entries().filter(|e| e.yanked).count();
+        let added = gem_line.version_entries().count() - yanked;
+
+        let state = &mut *self.state.borrow_mut();
+        let Some(history) = state.gems.get_mut(gem) else {
+            state.gems.insert(
+                gem.to_string(),
+                GemHistory {
+                    first_version: first_version.to_string(),
+                    versions: added,
+                    md5: md5.to_string(),
+                },
+            );
+            return;
+        };
+
+        if history.md5 == md5 {
+            state.anomalies.push(Anomaly::DuplicateLine {
+                line: line_number,
+                gem: gem.to_string(),
+                md5: md5.to_string(),
+            });
+        }
+
+        // Appended lines start at a new version; one starting at the gem's
+        // first version is the whole list again
+        if first_version == history.first_version && yanked == 0 {
+            if added < history.versions {
+                state.anomalies.push(Anomaly::ShrunkVersions {
+                    line: line_number,
+                    gem: gem.to_string(),
+                    previous: history.versions,
+                    current: added,
+                });
+            }
+            history.versions = added;
+        } else {
+            history.versions = (history.versions + added).saturating_sub(yanked);
+        }
+        history.md5.clear();
+        history.md5.push_str(md5);
+    }
+
+    /// Anomalies found so far, in the order they were found
+    pub fn anomalies(&self) -> Vec<Anomaly> {
+        self.state.borrow().anomalies.clone()
+    }
+
+    /// Consume the detector, returning what it found
+    pub fn into_anomalies(self) -> Vec<Anomaly> {
+        self.state.into_inner().anomalies
+    }
+}
+
+/// Inline checking in a pipeline: every line passes through unchanged
+impl GemFilter for &AnomalyDetector {
+    fn filter<'a>(&self, _name: &str, line: Cow<'a, str>) -> Option<Cow<'a, str>> {
+        self.observe_line(&line, None);
+        Some(line)
+    }
+}
+
+/// Read a whole versions file and report its anomalies
+///
+/// `previous_created_at` is the `created_at` of the last snapshot, if any.
+pub fn analyze_versions<R: Read>(
+    input: R,
+    previous_created_at: Option<&str>,
+) -> std::io::Result<Vec<Anomaly>> {
+    let detector = match previous_created_at {
+        Some(previous) => AnomalyDetector::after(previous),
+        None => AnomalyDetector::new(),
+    };
+    let mut reader = BufReader::new(input);
+
+    let metadata = read_metadata(&mut reader)?;
+    for line in metadata.lines() {
+        detector.observe_metadata(line);
+    }
+
+    let mut line = String::new();
+    let mut line_number = 0;
+    while read_gem_line(&mut reader, &mut line)? {
+        line_number += 1;
+        detector.observe_line(&line, Some(line_number));
+    }
+
+    Ok(detector.into_anomalies())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FilterPipeline, VersionOutput};
+
+    const VERSIONS: &str = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0,7.0.1 abc123
+sinatra 3.0.0 ghi789
+rails 7.0.2 def456
+rails -7.0.1 jkl000
+sinatra 3.0.0 ghi789
+rails 7.0.0,7.0.2 mno111
+rails 7.0.0 pqr222
+"#;
+
+    #[test]
+    fn test_analyze_versions() {
+        let anomalies =
+            analyze_versions(VERSIONS.as_bytes(), Some("2024-05-01T00:00:00Z")).unwrap();
+        assert_eq!(
+            anomalies,
+            vec![
+                Anomaly::TimestampRegression {
+                    previous: "2024-05-01T00:00:00Z".to_string(),
+                    current: "2024-04-01T00:00:05Z".to_string(),
+                },
+                Anomaly::DuplicateLine {
+                    line: Some(5),
+                    gem: "sinatra".to_string(),
+                    md5: "ghi789".to_string(),
+                },
+                // 7.0.0,7.0.2 after the yank is a faithful relist; 7.0.0 alone is not
+                Anomaly::ShrunkVersions {
+                    line: Some(7),
+                    gem: "rails".to_string(),
+                    previous: 2,
+                    current: 1,
+                },
+            ]
+        );
+
+        // Appends and yanks alone are not anomalies
+        let clean: String = VERSIONS
+            .lines()
+            .take(6)
+            .map(|l| format!("{}\n", l))
+            .collect();
+        assert!(analyze_versions(clean.as_bytes(), None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_inline_in_pipeline() {
+        let detector = AnomalyDetector::new();
+        let mut output = Vec::new();
+        FilterPipeline::new()
+            .with(&detector)
+            .filter_versions(
+                VERSIONS.as_bytes(),
+                &mut output,
+                VersionOutput::Preserve,
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(output, VERSIONS.as_bytes());
+        assert_eq!(detector.anomalies().len(), 2);
+        assert!(matches!(
+            detector.anomalies()[0],
+            Anomaly::DuplicateLine { line: None, .. }
+        ));
+    }
+}
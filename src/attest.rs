@@ -0,0 +1,427 @@
+//! Provenance attestations for filtered artifacts
+//!
+//! A run is described by an [in-toto Statement v1] whose subject is the
+//! filtered output and whose predicate records the input digest, a digest of
+//! the filter configuration and when the run happened. The statement can be
+//! published as-is next to the artifact or wrapped and signed by cosign
+//! (`cosign attest-blob --type custom --predicate`), so auditors can tie a
+//! published index back to the upstream file and policy it came from.
+//!
+//! For tooling that only wants to know what an artifact is, [`ArtifactMeta`]
+//! is a plain JSON description (digest, size, gem line count, upstream
+//! `created_at`) written next to it as `<artifact>.meta`. It stays out of
+//! the versions file itself, which Bundler parses line by line and mirrors
+//! extend by appending.
+//!
+//! Computing the digests ([`HashingReader`], [`config_digest`],
+//! [`ConfigDigest`], [`ArtifactMeta::read`]) needs the `digest` feature.
+//!
+//! [in-toto Statement v1]: https://github.com/in-toto/attestation/blob/main/spec/v1/statement.md
+
+#[cfg(feature = "digest")]
+use crate::{FilterMode, VersionOutput, VersionRules};
+#[cfg(feature = "digest")]
+use sha2::{Digest, Sha256};
+use std::io::Write;
+#[cfg(feature = "digest")]
+use std::io::{BufRead, BufReader, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Statement type URI for in-toto v1
+pub const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+
+/// Predicate type URI identifying gem-index-filter provenance
+pub const PREDICATE_TYPE: &str = "https://github.com/gem-coop/gem-index-filter/provenance/v1";
+
+/// Everything recorded about a single filter run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// Name of the filtered artifact, usually its file name
+    pub artifact_name: String,
+    /// Hex SHA-256 of the upstream versions file as read
+    pub input_sha256: String,
+    /// Hex SHA-256 of the filtered output
+    pub output_sha256: String,
+    /// Hex SHA-256 of the filter configuration, see [`config_digest`]
+    pub config_sha256: String,
+    /// RFC 3339 UTC time of the run, see [`format_timestamp`]
+    pub timestamp: String,
+}
+
+impl Provenance {
+    /// Write the provenance as a single-line in-toto statement followed by a newline
+    pub fn write_statement<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        let statement = serde_json::json!({
+            "_type": STATEMENT_TYPE,
+            "subject": [{
+                "name": self.artifact_name,
+                "digest": { "sha256": self.output_sha256 },
+            }],
+            "predicateType": PREDICATE_TYPE,
+            "predicate": {
+                "builder": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "input": { "digest": { "sha256": self.input_sha256 } },
+                "config": { "digest": { "sha256": self.config_sha256 } },
+                "timestamp": self.timestamp,
+            },
+        });
+        serde_json::to_writer(&mut *output, &statement)?;
+        output.write_all(b"\n")
+    }
+}
+
+/// Self-description of a filtered artifact, published as `<artifact>.meta`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactMeta {
+    /// Name of the artifact, usually its file name
+    pub artifact_name: String,
+    /// Size of the artifact in bytes
+    pub bytes: u64,
+    /// Hex SHA-256 of the artifact
+    pub sha256: String,
+    /// Gem lines after the `---` separator
+    pub gem_lines: u64,
+    /// Value of the artifact's `created_at:` header line, if it has one
+    pub created_at: Option<String>,
+    /// Hex SHA-256 of the filter configuration, see [`config_digest`]
+    pub config_sha256: String,
+    /// RFC 3339 UTC time the artifact was generated, see [`format_timestamp`]
+    pub timestamp: String,
+}
+
+impl ArtifactMeta {
+    /// Describe the versions file read from `artifact`
+    #[cfg(feature = "digest")]
+    pub fn read<R: Read>(
+        artifact_name: impl Into<String>,
+        artifact: R,
+        config_sha256: impl Into<String>,
+        timestamp: impl Into<String>,
+    ) -> std::io::Result<Self> {
+        let mut reader = BufReader::new(artifact);
+        let mut hasher = Sha256::new();
+        let mut line = Vec::new();
+        let (mut bytes, mut gem_lines, mut created_at) = (0, 0, None);
+        let mut in_body = false;
+
+        loop {
+            line.clear();
+            let n = reader.read_until(b'\n', &mut line)?;
+            if n == 0 {
+                break;
+            }
+            bytes += n as u64;
+            hasher.update(&line);
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim();
+            if in_body {
+                gem_lines += u64::from(!text.is_empty());
+            } else if text == "---" {
+                in_body = true;
+            } else if let Some(value) = text.strip_prefix("created_at:") {
+                created_at = Some(value.trim().to_string());
+            }
+        }
+
+        Ok(ArtifactMeta {
+            artifact_name: artifact_name.into(),
+            bytes,
+            sha256: hex::encode(hasher.finalize()),
+            gem_lines,
+            created_at,
+            config_sha256: config_sha256.into(),
+            timestamp: timestamp.into(),
+        })
+    }
+
+    /// Write the description as pretty-printed JSON followed by a newline
+    pub fn write_json<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        let meta = serde_json::json!({
+            "name": self.artifact_name,
+            "size": self.bytes,
+            "digest": { "sha256": self.sha256 },
+            "gem_lines": self.gem_lines,
+            "created_at": self.created_at,
+            "generator": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "config": { "digest": { "sha256": self.config_sha256 } },
+            "generated_at": self.timestamp,
+        });
+        serde_json::to_writer_pretty(&mut *output, &meta)?;
+        output.write_all(b"\n")
+    }
+}
+
+/// Reader wrapper that computes the SHA-256 of everything read through it
+///
+/// Lets the input digest be taken while filtering, which matters when the
+/// input is stdin and can't be read twice.
+#[cfg(feature = "digest")]
+pub struct HashingReader<R: Read> {
+    inner: R,
+    hasher: Sha256,
+}
+
+#[cfg(feature = "digest")]
+impl<R: Read> HashingReader<R> {
+    /// Wrap `inner`, hashing all bytes read from it
+    pub fn new(inner: R) -> Self {
+        HashingReader {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Return the hex-encoded digest of the bytes read so far
+    pub fn finalize(self) -> String {
+        hex::encode(self.hasher.finalize())
+    }
+}
+
+#[cfg(feature = "digest")]
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Hex SHA-256 identifying a filter configuration
+///
+/// Hashes a canonical description (mode, version output, then the sorted gem
+/// list) so the digest doesn't depend on list file order, comments or which
+/// hash set iteration order the process happened to get. Runs with settings
+/// beyond those use [`ConfigDigest`].
+#[cfg(feature = "digest")]
+pub fn config_digest(mode: FilterMode, version_output: VersionOutput) -> String {
+    ConfigDigest::new(mode, version_output).finalize()
+}
+
+/// A filter configuration being hashed, setting by setting
+///
+/// Starts from what [`config_digest`] covers; every other setting that
+/// changes the output is added with [`setting`](Self::setting) or
+/// [`version_rules`](Self::version_rules), so two runs only share a digest
+/// when they would write the same output from the same input. A run with no
+/// extra settings gets the same digest as [`config_digest`].
+#[cfg(feature = "digest")]
+pub struct ConfigDigest {
+    hasher: Sha256,
+}
+
+#[cfg(feature = "digest")]
+impl ConfigDigest {
+    /// Hash the mode, version output and sorted gem list
+    pub fn new(mode: FilterMode, version_output: VersionOutput) -> Self {
+        let (mode_name, names) = match mode {
+            FilterMode::Passthrough => ("passthrough", None),
+            FilterMode::Allow(set) => ("allow", Some(set)),
+            FilterMode::Block(set) => ("block", Some(set)),
+        };
+        let versions = version_output.as_str();
+
+        let mut hasher = Sha256::new();
+        hasher.update(format!("mode {}\nversions {}\n", mode_name, versions));
+        if let Some(set) = names {
+            let mut sorted: Vec<&str> = set.iter().copied().collect();
+            sorted.sort_unstable();
+            for name in sorted {
+                hasher.update(name);
+                hasher.update(b"\n");
+            }
+        }
+        ConfigDigest { hasher }
+    }
+
+    /// Add a setting as `key "value"`
+    ///
+    /// The value is quoted, so no value can pass for a gem name or another
+    /// setting. Add settings in a fixed order.
+    pub fn setting(mut self, key: &str, value: impl std::fmt::Display) -> Self {
+        self.hasher
+            .update(format!("{} {:?}\n", key, value.to_string()));
+        self
+    }
+
+    /// Add version rules, unless they keep every version
+    pub fn version_rules(mut self, rules: &VersionRules) -> Self {
+        if rules.is_noop() {
+            return self;
+        }
+        self = self
+            .setting("yanked", rules.yanked)
+            .setting("prereleases", rules.prereleases);
+        if let Some(platforms) = &rules.platforms {
+            let mut platforms: Vec<&str> = platforms.iter().map(String::as_str).collect();
+            platforms.sort_unstable();
+            self = self.setting("platforms", platforms.join(","));
+        }
+        if let Some(max) = rules.max_versions {
+            self = self.setting("max_versions", max);
+        }
+        let mut constraints: Vec<_> = rules.constraints.iter().collect();
+        constraints.sort_unstable_by_key(|(name, _)| name.as_str());
+        for (name, requirement) in constraints {
+            self = self.setting("constraint", format!("{} {}", name, requirement));
+        }
+        self
+    }
+
+    /// The hex digest
+    pub fn finalize(self) -> String {
+        hex::encode(self.hasher.finalize())
+    }
+}
+
+/// Format a time as an RFC 3339 UTC timestamp with second precision
+pub fn format_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days (Howard Hinnant), valid for any date after the epoch
+    let z = days as i64 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "digest")]
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    #[test]
+    fn test_statement_fields() {
+        let provenance = Provenance {
+            artifact_name: "versions".to_string(),
+            input_sha256: "aa".to_string(),
+            output_sha256: "bb".to_string(),
+            config_sha256: "cc".to_string(),
+            timestamp: "2024-04-01T00:00:05Z".to_string(),
+        };
+        let mut output = Vec::new();
+        provenance.write_statement(&mut output).unwrap();
+
+        let statement: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(statement["_type"], STATEMENT_TYPE);
+        assert_eq!(statement["subject"][0]["name"], "versions");
+        assert_eq!(statement["subject"][0]["digest"]["sha256"], "bb");
+        assert_eq!(statement["predicateType"], PREDICATE_TYPE);
+        assert_eq!(statement["predicate"]["input"]["digest"]["sha256"], "aa");
+        assert_eq!(statement["predicate"]["config"]["digest"]["sha256"], "cc");
+        assert_eq!(statement["predicate"]["timestamp"], "2024-04-01T00:00:05Z");
+        assert!(output.ends_with(b"}\n"));
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn test_hashing_reader() {
+        let mut reader = HashingReader::new("abc".as_bytes());
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        assert_eq!(
+            reader.finalize(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn test_config_digest_is_canonical() {
+        let a: HashSet<&str> = ["rails", "sinatra", "puma"].into_iter().collect();
+        let b: HashSet<&str> = ["puma", "rails", "sinatra"].into_iter().collect();
+        let preserve = VersionOutput::Preserve;
+
+        assert_eq!(
+            config_digest(FilterMode::Allow(&a), preserve),
+            config_digest(FilterMode::Allow(&b), preserve)
+        );
+        assert_ne!(
+            config_digest(FilterMode::Allow(&a), preserve),
+            config_digest(FilterMode::Block(&a), preserve)
+        );
+        assert_ne!(
+            config_digest(FilterMode::Passthrough, preserve),
+            config_digest(FilterMode::Passthrough, VersionOutput::Strip)
+        );
+
+        // Settings beyond the mode change the digest; rules that keep everything don't
+        let plain = config_digest(FilterMode::Passthrough, preserve);
+        let with = |configure: fn(ConfigDigest) -> ConfigDigest| {
+            configure(ConfigDigest::new(FilterMode::Passthrough, preserve)).finalize()
+        };
+        assert_eq!(with(|c| c.version_rules(&VersionRules::new())), plain);
+        assert_ne!(with(|c| c.setting("grep", "^rails")), plain);
+        assert_ne!(
+            with(|c| c.setting("grep", "^rails")),
+            with(|c| c.setting("grep", "^rack"))
+        );
+        let mut rules = VersionRules::new();
+        rules.max_versions = Some(2);
+        let capped = ConfigDigest::new(FilterMode::Passthrough, preserve)
+            .version_rules(&rules)
+            .finalize();
+        assert_ne!(capped, plain);
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn test_artifact_meta_describes_versions_file() {
+        let versions =
+            "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\nsinatra 3.0.0 def456\n";
+        let meta = ArtifactMeta::read(
+            "versions",
+            versions.as_bytes(),
+            "cc",
+            "2024-04-02T00:00:00Z",
+        )
+        .unwrap();
+        assert_eq!(meta.bytes, versions.len() as u64);
+        assert_eq!(meta.gem_lines, 2);
+        assert_eq!(meta.created_at.as_deref(), Some("2024-04-01T00:00:05Z"));
+
+        let mut output = Vec::new();
+        meta.write_json(&mut output).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(json["digest"]["sha256"], meta.sha256);
+        assert_eq!(json["generated_at"], "2024-04-02T00:00:00Z");
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(1_711_929_605)),
+            "2024-04-01T00:00:05Z"
+        );
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00Z"
+        );
+    }
+}
@@ -0,0 +1,12 @@
+//! Allowlist compiled into the crate (`baked-allowlist` feature)
+//!
+//! For locked-down edge deployments whose gem set is fixed at build time:
+//! the build script reads the list named by `GEM_INDEX_FILTER_ALLOWLIST`
+//! (a path relative to the crate root, or absolute) and generates a `phf`
+//! perfect-hash set, so nothing is loaded at runtime. Patterns aren't
+//! accepted there; expand them into a plain list first.
+//!
+//! Filter with it through [`filter_versions_with_set`](crate::filter_versions_with_set)
+//! or [`SliceMode`](crate::SliceMode), both of which take any [`GemSet`](crate::GemSet).
+
+include!(concat!(env!("OUT_DIR"), "/baked_allowlist.rs"));
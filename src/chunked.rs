@@ -0,0 +1,275 @@
+//! Push-based filtering for runtimes that deliver input in chunks
+//!
+//! [`filter_versions_streaming`](crate::filter_versions_streaming) pulls from
+//! a `Read`, which doesn't fit event-driven hosts like Web Streams where the
+//! runtime hands over one chunk at a time. [`ChunkFilter`] keeps the same
+//! output byte for byte, carrying only the unfinished last line between chunks.
+//!
+//! Hosts with a hard limit on execution time (Workers, Lambda) can instead
+//! give a filter a budget with [`ChunkFilter::run_for`]. When the budget runs
+//! out the run stops after a complete line and returns an [`Incomplete`] with
+//! the [`Checkpoint`] to resume from in the next invocation, so a large input
+//! is split across invocations without a line lost or written twice.
+
+use crate::slice::{
+    Checkpoint, Incomplete, LineErrors, NamelessLines, SeparatorRules, SkippedLines, SliceFilter,
+};
+use crate::{FilterMode, VersionOutput};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+/// Bytes read from the input between checks of a run's budget
+const BUDGET_CHUNK: usize = 64 * 1024;
+
+/// Incremental versions-file filter fed with arbitrary byte chunks
+pub struct ChunkFilter<'a> {
+    inner: SliceFilter<'a, HashSet<&'a str>>,
+    buffer: Vec<u8>,
+}
+
+impl<'a> ChunkFilter<'a> {
+    /// Create a filter in the state before the first metadata line
+    pub fn new(mode: FilterMode<'a>, version_output: VersionOutput) -> Self {
+        ChunkFilter {
+            inner: SliceFilter::new(mode.into(), version_output),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Choose how gem lines without a space are handled
+    pub fn nameless_lines(mut self, nameless: NamelessLines) -> Self {
+        self.inner = self.inner.nameless_lines(nameless);
+        self
+    }
+
+    /// Choose how separator lines are recognized and repeated ones handled
+    pub fn separator_rules(mut self, separators: SeparatorRules) -> Self {
+        self.inner = self.inner.separator_rules(separators);
+        self
+    }
+
+    /// Choose whether lines the filter can't handle fail the run or are skipped
+    pub fn line_errors(mut self, line_errors: LineErrors) -> Self {
+        self.inner = self.inner.line_errors(line_errors);
+        self
+    }
+
+    /// Start from `checkpoint`, to be fed the input from its offset on
+    ///
+    /// The other options must match the run the checkpoint came from.
+    pub fn resume_from(mut self, checkpoint: Checkpoint) -> Self {
+        self.inner = self.inner.resume_from(checkpoint);
+        self
+    }
+
+    /// Filter a chunk, writing output for every line it completes
+    pub fn push<W: Write>(&mut self, chunk: &[u8], output: &mut W) -> std::io::Result<()> {
+        self.inner.push(chunk, &mut self.buffer)?;
+        output.write_all(&self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Flush the final unterminated line and check the file was complete
+    pub fn finish<W: Write>(self, output: &mut W) -> std::io::Result<()> {
+        self.finish_with_report(output).map(drop)
+    }
+
+    /// [`finish`](Self::finish), returning the lines skipped under [`LineErrors::Collect`]
+    pub fn finish_with_report<W: Write>(mut self, output: &mut W) -> std::io::Result<SkippedLines> {
+        let skipped = self.inner.finish_with_report(&mut self.buffer)?;
+        output.write_all(&self.buffer)?;
+        Ok(skipped)
+    }
+
+    /// Where to resume after the last complete line pushed so far
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.inner.checkpoint()
+    }
+
+    /// Filter the rest of `input`, stopping early once `budget` has passed
+    ///
+    /// `input` must start at the offset of the checkpoint the filter was
+    /// resumed from, if any. The budget is checked after each 64 KiB read,
+    /// so a run overshoots it by the time one read and its filtering take.
+    pub fn run_for<R: Read, W: Write>(
+        self,
+        input: R,
+        output: &mut W,
+        budget: Duration,
+    ) -> std::io::Result<RunOutcome> {
+        let start = Instant::now();
+        self.run_until(input, output, || start.elapsed() >= budget)
+    }
+
+    /// [`run_for`](Self::run_for) with the clock left to the caller
+    ///
+    /// `out_of_time` is asked after each read; hosts without
+    /// [`Instant`], such as `wasm32-unknown-unknown`, can check their own
+    /// clock there. A run always gets past at least one line before it
+    /// stops, so resuming makes progress however small the budget.
+    pub fn run_until<R: Read, W: Write>(
+        mut self,
+        mut input: R,
+        output: &mut W,
+        mut out_of_time: impl FnMut() -> bool,
+    ) -> std::io::Result<RunOutcome> {
+        let start = self.checkpoint().offset;
+        let mut chunk = vec![0; BUDGET_CHUNK];
+        loop {
+            let n = match input.read(&mut chunk) {
+                Ok(0) => return self.finish_with_report(output).map(RunOutcome::Complete),
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.push(&chunk[..n], output)?;
+            if self.checkpoint().offset > start && out_of_time() {
+                return Ok(RunOutcome::Incomplete(self.inner.stop()));
+            }
+        }
+    }
+}
+
+/// How a budgeted run ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The input was filtered to the end, with the lines skipped under
+    /// [`LineErrors::Collect`] since the run resumed
+    Complete(SkippedLines),
+    /// The budget ran out first; resume from the checkpoint
+    Incomplete(Incomplete),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter_versions_streaming;
+    use std::collections::HashSet;
+
+    const VERSIONS: &str = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0,7.0.1 abc123
+
+activerecord 7.0.0 def456 extra
+sinatra -3.0.0,3.0.1 ghi789
+rails 7.0.2 jkl000"#;
+
+    fn chunked(mode: FilterMode, version_output: VersionOutput, chunk_size: usize) -> Vec<u8> {
+        let mut filter = ChunkFilter::new(mode, version_output);
+        let mut output = Vec::new();
+        for chunk in VERSIONS.as_bytes().chunks(chunk_size) {
+            filter.push(chunk, &mut output).unwrap();
+        }
+        filter.finish(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_matches_streaming_filter_for_any_chunking() {
+        let gemlist: HashSet<&str> = ["rails", "sinatra"].into_iter().collect();
+        let modes = [
+            FilterMode::Passthrough,
+            FilterMode::Allow(&gemlist),
+            FilterMode::Block(&gemlist),
+        ];
+
+        for mode in modes {
+            for version_output in [VersionOutput::Preserve, VersionOutput::Strip] {
+                let mut expected = Vec::new();
+                filter_versions_streaming(
+                    VERSIONS.as_bytes(),
+                    &mut expected,
+                    mode,
+                    version_output,
+                    None,
+                )
+                .unwrap();
+
+                for chunk_size in [1, 2, 7, 16, VERSIONS.len()] {
+                    assert_eq!(
+                        chunked(mode, version_output, chunk_size),
+                        expected,
+                        "{:?} {:?} chunk size {}",
+                        mode,
+                        version_output,
+                        chunk_size
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_missing_separator_is_an_error() {
+        let mut filter = ChunkFilter::new(FilterMode::Passthrough, VersionOutput::Preserve);
+        let mut output = Vec::new();
+        filter
+            .push(b"created_at: x\nrails 7.0.0 abc\n", &mut output)
+            .unwrap();
+        let err = filter.finish(&mut output).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    /// Hands out at most `size` bytes per read, like a slow network body
+    struct Trickle<'a> {
+        data: &'a [u8],
+        size: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.size).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_runs_out_of_time_and_resumes() {
+        let gemlist: HashSet<&str> = ["rails", "sinatra"].into_iter().collect();
+        let mode = FilterMode::Allow(&gemlist);
+        let mut expected = Vec::new();
+        filter_versions_streaming(
+            VERSIONS.as_bytes(),
+            &mut expected,
+            mode,
+            VersionOutput::Strip,
+            None,
+        )
+        .unwrap();
+
+        // Out of time after every read: each invocation gets one line or so
+        let mut output = Vec::new();
+        let mut checkpoint = Checkpoint::default();
+        let mut invocations = 0;
+        loop {
+            invocations += 1;
+            let input = Trickle {
+                data: &VERSIONS.as_bytes()[checkpoint.offset as usize..],
+                size: 10,
+            };
+            let filter = ChunkFilter::new(mode, VersionOutput::Strip).resume_from(checkpoint);
+            match filter.run_until(input, &mut output, || true).unwrap() {
+                RunOutcome::Complete(_) => break,
+                RunOutcome::Incomplete(incomplete) => {
+                    assert!(incomplete.checkpoint.offset > checkpoint.offset);
+                    checkpoint = incomplete.checkpoint;
+                }
+            }
+        }
+        assert_eq!(output, expected);
+        assert!(invocations > 3);
+
+        // A generous budget finishes in one go
+        let filter = ChunkFilter::new(mode, VersionOutput::Strip);
+        let mut output = Vec::new();
+        let outcome = filter
+            .run_for(VERSIONS.as_bytes(), &mut output, Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(outcome, RunOutcome::Complete(SkippedLines::default()));
+        assert_eq!(output, expected);
+    }
+}
@@ -0,0 +1,278 @@
+//! Dropping versions released outside a date window
+//!
+//! Neither the versions file nor compact-index info files record when a
+//! version was released, so the dates come from a separate mapping. It can
+//! be loaded from a text file with one `name version date` line per release:
+//!
+//! ```text
+//! # name  version  released
+//! rails   7.1.0    2023-10-05
+//! rails   7.0.8    2023-09-09T20:13:35.912Z
+//! ```
+//!
+//! or from RubyGems API responses (`/api/v1/versions/<name>.json`), which
+//! carry a `created_at` per version. [`DateWindow`] is a
+//! [`GemFilter`](crate::GemFilter) that rewrites version lists to the
+//! releases inside the window, dropping lines left empty. Versions are
+//! matched by number, so every platform of a release shares its date.
+
+use crate::pipeline::GemFilter;
+use crate::slice::strip_bom;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Read};
+
+/// A calendar day, ordered chronologically
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ReleaseDate(u32);
+
+impl ReleaseDate {
+    /// Parse `YYYY-MM-DD`, ignoring anything after it such as an RFC 3339 time
+    pub fn parse(text: &str) -> Option<Self> {
+        let date = text.get(..10)?.as_bytes();
+        if date[4] != b'-' || date[7] != b'-' {
+            return None;
+        }
+        let number = |digits: &[u8]| -> Option<u32> {
+            digits.iter().try_fold(0, |value, &digit| {
+                digit
+                    .is_ascii_digit()
+                    .then(|| value * 10 + u32::from(digit - b'0'))
+            })
+        };
+        let (year, month, day) = (
+            number(&date[..4])?,
+            number(&date[5..7])?,
+            number(&date[8..])?,
+        );
+        ((1..=12).contains(&month) && (1..=31).contains(&day))
+            .then_some(ReleaseDate(year * 10_000 + month * 100 + day))
+    }
+}
+
+impl fmt::Display for ReleaseDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}",
+            self.0 / 10_000,
+            self.0 / 100 % 100,
+            self.0 % 100
+        )
+    }
+}
+
+/// Release dates by gem name and version number
+#[derive(Debug, Clone, Default)]
+pub struct ReleaseDates {
+    gems: HashMap<String, HashMap<String, ReleaseDate>>,
+}
+
+impl ReleaseDates {
+    /// An empty mapping
+    pub fn new() -> Self {
+        ReleaseDates::default()
+    }
+
+    /// Load `name version date` lines; blank lines and `#` comments are skipped
+    pub fn load<R: BufRead>(reader: R) -> std::io::Result<Self> {
+        let mut dates = ReleaseDates::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = if index == 0 { strip_bom(&line) } else { &line };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let parsed = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some(name), Some(version), Some(date), None) => {
+                    ReleaseDate::parse(date).map(|date| (name, version, date))
+                }
+                _ => None,
+            };
+            let Some((name, version, date)) = parsed else {
+                return Err(invalid(format!(
+                    "line {}: expected 'name version YYYY-MM-DD', got '{}'",
+                    index + 1,
+                    line
+                )));
+            };
+            dates.insert(name, strip_platform(version), date);
+        }
+        Ok(dates)
+    }
+
+    /// Add the versions listed in a RubyGems API `/api/v1/versions/<name>.json` response
+    pub fn add_api_versions<R: Read>(&mut self, name: &str, response: R) -> std::io::Result<()> {
+        let versions: serde_json::Value = serde_json::from_reader(response)?;
+        let versions = versions
+            .as_array()
+            .ok_or_else(|| invalid(format!("versions of {}: expected a JSON array", name)))?;
+        for version in versions {
+            let number = version["number"].as_str();
+            let date = version["created_at"].as_str().and_then(ReleaseDate::parse);
+            if let (Some(number), Some(date)) = (number, date) {
+                self.insert(name, number, date);
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the release date of one version
+    pub fn insert(&mut self, name: &str, number: &str, date: ReleaseDate) {
+        self.gems
+            .entry(name.to_string())
+            .or_default()
+            .insert(number.to_string(), date);
+    }
+
+    /// When `number` of gem `name` was released, if known
+    pub fn get(&self, name: &str, number: &str) -> Option<ReleaseDate> {
+        self.gems.get(name)?.get(number).copied()
+    }
+}
+
+/// Keeps only versions released between two dates, both inclusive
+#[derive(Debug, Clone)]
+pub struct DateWindow {
+    dates: ReleaseDates,
+    since: Option<ReleaseDate>,
+    until: Option<ReleaseDate>,
+    keep_unknown: bool,
+}
+
+impl DateWindow {
+    /// A window over `dates`; versions without a known date are kept
+    pub fn new(
+        dates: ReleaseDates,
+        since: Option<ReleaseDate>,
+        until: Option<ReleaseDate>,
+    ) -> Self {
+        DateWindow {
+            dates,
+            since,
+            until,
+            keep_unknown: true,
+        }
+    }
+
+    /// Drop versions without a known date instead of keeping them
+    pub fn drop_unknown(mut self) -> Self {
+        self.keep_unknown = false;
+        self
+    }
+
+    /// Whether version `number` of `name` is kept
+    pub fn keeps(&self, name: &str, number: &str) -> bool {
+        match self.dates.get(name, number) {
+            Some(date) => {
+                self.since.is_none_or(|since| date >= since)
+                    && self.until.is_none_or(|until| date <= until)
+            }
+            None => self.keep_unknown,
+        }
+    }
+}
+
+impl GemFilter for &DateWindow {
+    fn filter<'a>(&self, name: &str, line: Cow<'a, str>) -> Option<Cow<'a, str>> {
+        if self.keep_unknown && !self.dates.gems.contains_key(name) {
+            return Some(line);
+        }
+        let mut parts = line.splitn(3, ' ');
+        let (Some(_), Some(versions), Some(rest)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Some(line);
+        };
+
+        // Yank records (-1.0.0) follow the date of the version they yank
+        let kept: Vec<&str> = versions
+            .split(',')
+            .filter(|entry| {
+                let number = strip_platform(entry.strip_prefix('-').unwrap_or(entry));
+                self.keeps(name, number)
+            })
+            .collect();
+        if kept.is_empty() {
+            return None;
+        }
+        Some(Cow::Owned(format!("{} {} {}", name, kept.join(","), rest)))
+    }
+}
+
+/// The version number of a `number[-platform]` entry
+fn strip_platform(version: &str) -> &str {
+    version
+        .split_once('-')
+        .map_or(version, |(number, _)| number)
+}
+
+fn invalid(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FilterPipeline, VersionOutput};
+
+    #[test]
+    fn test_release_date_parse() {
+        let date = ReleaseDate::parse("2023-09-09T20:13:35.912Z").unwrap();
+        assert_eq!(date.to_string(), "2023-09-09");
+        assert!(date < ReleaseDate::parse("2023-10-05").unwrap());
+        assert_eq!(ReleaseDate::parse("2023-13-01"), None);
+        assert_eq!(ReleaseDate::parse("09/09/2023"), None);
+    }
+
+    #[test]
+    fn test_window_rewrites_version_lists() {
+        let mut dates = ReleaseDates::load(
+            "# name version released\n\
+             rails 6.1.0 2020-12-09\n\
+             rails 7.0.0 2021-12-15\n\
+             rails 7.1.0 2023-10-05T00:00:00Z\n\
+             old 1.0.0 2010-01-01\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        dates
+            .add_api_versions(
+                "puma",
+                r#"[{"number": "6.0.0", "platform": "ruby", "created_at": "2022-10-14T00:00:00Z"}]"#
+                    .as_bytes(),
+            )
+            .unwrap();
+        let window = DateWindow::new(dates, ReleaseDate::parse("2021-01-01"), None);
+
+        let input = "created_at: 2024-04-01T00:00:05Z\n---\n\
+            rails 6.1.0,7.0.0,7.0.0-java,-7.1.0 abc123\n\
+            old 1.0.0 def456\n\
+            puma 6.0.0,5.0.0 ghi789\n\
+            sinatra 3.0.0 jkl000\n";
+        let mut output = Vec::new();
+        FilterPipeline::new()
+            .with(&window)
+            .filter_versions(input.as_bytes(), &mut output, VersionOutput::Preserve, None)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "created_at: 2024-04-01T00:00:05Z\n---\n\
+             rails 7.0.0,7.0.0-java,-7.1.0 abc123\n\
+             puma 6.0.0,5.0.0 ghi789\n\
+             sinatra 3.0.0 jkl000\n"
+        );
+
+        let strict = window.clone().drop_unknown();
+        assert!(!strict.keeps("puma", "5.0.0"));
+        assert!(strict.keeps("puma", "6.0.0"));
+    }
+
+    #[test]
+    fn test_load_rejects_malformed_lines() {
+        let err = ReleaseDates::load("rails 7.0.0\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("line 1"));
+    }
+}
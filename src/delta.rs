@@ -0,0 +1,373 @@
+//! Binary deltas and snapshots for edge distribution
+//!
+//! The text [patch](crate::patch) spells every appended line out in full.
+//! A delta carries the same content, a kept prefix checked by SHA-256, the
+//! new metadata and the appended lines, in a binary layout. Lengths are
+//! varints and MD5s written as 32 hex digits take 16 bytes, so a refresh
+//! that appends a few hundred lines costs edge nodes a few kilobytes.
+//!
+//! A snapshot is the same format with no prefix to keep. It applies to any
+//! base, so nodes that are new, fell too far behind or failed to apply a
+//! delta can start again from the latest one. Publish one every so often
+//! beside the deltas.
+//!
+//! ```text
+//! "GIFD" 0x01     magic and format version
+//! kind            0 for a delta, 1 for a snapshot
+//! keep-lines      varint (deltas only)
+//! keep-sha256     32 bytes (deltas only)
+//! metadata        varint length, then the header through `---\n`
+//! records         one per appended gem line, until the end:
+//!   flags         bit 0: MD5 as 16 bytes, bit 1: extra fields follow
+//!   name          varint length, then bytes
+//!   versions      varint length, then bytes
+//!   md5           16 bytes, or varint length and bytes
+//!   extra         varint length, then the fields joined by spaces
+//! ```
+//!
+//! Applied lines come out trimmed and newline-terminated, as they are hashed.
+
+use crate::diff::{diff_with_prefix_hook, read_gem_line, read_metadata};
+use crate::patch::copy_kept_lines;
+use crate::VersionsDiff;
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// First bytes of every delta and snapshot, carrying the format version
+const DELTA_MAGIC: &[u8; 5] = b"GIFD\x01";
+
+const KIND_DELTA: u8 = 0;
+const KIND_SNAPSHOT: u8 = 1;
+
+const FLAG_BINARY_MD5: u8 = 1;
+const FLAG_EXTRA: u8 = 2;
+
+/// Whether `prefix`, the first bytes of a file, starts a delta or snapshot
+pub fn is_delta(prefix: &[u8]) -> bool {
+    prefix.starts_with(DELTA_MAGIC)
+}
+
+/// Write a delta that turns `old` into `new`
+///
+/// Returns the same summary as [`crate::diff_versions`].
+pub fn write_delta<R1: Read, R2: Read, W: Write>(
+    old: R1,
+    new: R2,
+    output: &mut W,
+) -> std::io::Result<VersionsDiff> {
+    let mut records = RecordWriter::new(output);
+    let diff = diff_with_prefix_hook(old, new, &mut records, true, |records, prefix| {
+        let sha256 = hex::decode(&prefix.sha256).expect("the prefix digest is hex");
+        let header = &mut records.inner;
+        header.write_all(DELTA_MAGIC)?;
+        header.write_all(&[KIND_DELTA])?;
+        write_varint(header, prefix.lines as u64)?;
+        header.write_all(&sha256)?;
+        write_bytes(header, prefix.new_metadata.as_bytes())
+    })?;
+    records.finish()?;
+    Ok(diff)
+}
+
+/// Write a snapshot of `new` that applies to any base, returning its gem line count
+pub fn write_snapshot<R: Read, W: Write>(new: R, output: &mut W) -> std::io::Result<usize> {
+    let mut new = BufReader::new(new);
+    let metadata = read_metadata(&mut new)?;
+    output.write_all(DELTA_MAGIC)?;
+    output.write_all(&[KIND_SNAPSHOT])?;
+    write_bytes(output, metadata.as_bytes())?;
+
+    let mut records = RecordWriter::new(output);
+    let mut line = String::new();
+    let mut lines = 0;
+    while read_gem_line(&mut new, &mut line)? {
+        records.write_all(line.as_bytes())?;
+        lines += 1;
+    }
+    records.finish()?;
+    Ok(lines)
+}
+
+/// Apply a delta from [`write_delta`] or a snapshot from [`write_snapshot`]
+/// to `base`, writing the result to `output`
+///
+/// Snapshots don't read `base`, so pass [`std::io::empty()`] when there is
+/// none. As with [`crate::apply_patch`], a delta's kept prefix is verified
+/// only once it has been streamed, so on error `output` holds a partial file
+/// and must be discarded.
+pub fn apply_delta<R1: Read, R2: Read, W: Write>(
+    base: R1,
+    delta: R2,
+    output: &mut W,
+) -> std::io::Result<()> {
+    let mut delta = BufReader::new(delta);
+    let mut magic = [0; DELTA_MAGIC.len() + 1];
+    read_exact(&mut delta, &mut magic)?;
+    if !is_delta(&magic) {
+        return Err(invalid_delta("Unrecognized delta header"));
+    }
+
+    match magic[DELTA_MAGIC.len()] {
+        KIND_DELTA => {
+            let keep_lines = read_varint(&mut delta)?;
+            let keep_lines = usize::try_from(keep_lines)
+                .map_err(|_| invalid_delta("Invalid keep-lines value in delta"))?;
+            let mut keep_sha256 = [0; 32];
+            read_exact(&mut delta, &mut keep_sha256)?;
+
+            // The delta carries the complete new metadata; the base's is replaced
+            output.write_all(&read_bytes(&mut delta)?)?;
+            let mut base = BufReader::new(base);
+            read_metadata(&mut base)?;
+            if copy_kept_lines(&mut base, keep_lines, output)? != keep_sha256 {
+                return Err(invalid_delta("Delta base does not match the delta digest"));
+            }
+        }
+        KIND_SNAPSHOT => output.write_all(&read_bytes(&mut delta)?)?,
+        _ => return Err(invalid_delta("Unknown delta kind")),
+    }
+
+    while !delta.fill_buf()?.is_empty() {
+        write_record(&mut delta, output)?;
+    }
+    Ok(())
+}
+
+/// Decode one record back into its gem line
+fn write_record<R: BufRead, W: Write>(delta: &mut R, output: &mut W) -> std::io::Result<()> {
+    let mut flags = [0];
+    read_exact(delta, &mut flags)?;
+    let name = read_bytes(delta)?;
+    let versions = read_bytes(delta)?;
+    let md5 = if flags[0] & FLAG_BINARY_MD5 != 0 {
+        let mut md5 = [0; 16];
+        read_exact(delta, &mut md5)?;
+        hex::encode(md5).into_bytes()
+    } else {
+        read_bytes(delta)?
+    };
+
+    output.write_all(&name)?;
+    output.write_all(b" ")?;
+    output.write_all(&versions)?;
+    output.write_all(b" ")?;
+    output.write_all(&md5)?;
+    if flags[0] & FLAG_EXTRA != 0 {
+        output.write_all(b" ")?;
+        output.write_all(&read_bytes(delta)?)?;
+    }
+    output.write_all(b"\n")
+}
+
+/// Writer turning the diff's appended lines into records
+struct RecordWriter<W: Write> {
+    inner: W,
+    line: Vec<u8>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    fn new(inner: W) -> Self {
+        RecordWriter {
+            inner,
+            line: Vec::new(),
+        }
+    }
+
+    /// Encode any unterminated last line
+    fn finish(mut self) -> std::io::Result<()> {
+        let line = std::mem::take(&mut self.line);
+        self.emit(&line)?;
+        self.inner.flush()
+    }
+
+    fn emit(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return Ok(());
+        }
+        let mut fields = line
+            .split(|byte| byte.is_ascii_whitespace())
+            .filter(|field| !field.is_empty());
+        let (Some(name), Some(versions), Some(md5)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid_delta(&format!(
+                "Not a gem line: '{}'",
+                String::from_utf8_lossy(line)
+            )));
+        };
+        let extra: Vec<&[u8]> = fields.collect();
+        let binary_md5 =
+            md5.len() == 32 && md5.iter().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+
+        let mut flags = 0;
+        if binary_md5 {
+            flags |= FLAG_BINARY_MD5;
+        }
+        if !extra.is_empty() {
+            flags |= FLAG_EXTRA;
+        }
+        self.inner.write_all(&[flags])?;
+        write_bytes(&mut self.inner, name)?;
+        write_bytes(&mut self.inner, versions)?;
+        if binary_md5 {
+            let md5 = hex::decode(md5).expect("checked to be hex");
+            self.inner.write_all(&md5)?;
+        } else {
+            write_bytes(&mut self.inner, md5)?;
+        }
+        if !extra.is_empty() {
+            write_bytes(&mut self.inner, &extra.join(&b' '))?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for RecordWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut rest = buf;
+        while let Some(newline) = memchr::memchr(b'\n', rest) {
+            let mut line = std::mem::take(&mut self.line);
+            line.extend_from_slice(&rest[..newline]);
+            self.emit(&line)?;
+            line.clear();
+            self.line = line;
+            rest = &rest[newline + 1..];
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn write_varint<W: Write>(output: &mut W, mut value: u64) -> std::io::Result<()> {
+    let mut bytes = [0; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes[len] = byte;
+            len += 1;
+            break;
+        }
+        bytes[len] = byte | 0x80;
+        len += 1;
+    }
+    output.write_all(&bytes[..len])
+}
+
+fn write_bytes<W: Write>(output: &mut W, bytes: &[u8]) -> std::io::Result<()> {
+    write_varint(output, bytes.len() as u64)?;
+    output.write_all(bytes)
+}
+
+fn read_varint<R: Read>(input: &mut R) -> std::io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        read_exact(input, &mut byte)?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_delta("Malformed length in delta"))
+}
+
+fn read_bytes<R: Read>(input: &mut R) -> std::io::Result<Vec<u8>> {
+    let len = read_varint(input)?;
+    // Read rather than allocate up front, so a corrupt length can't claim gigabytes
+    let mut bytes = Vec::new();
+    input.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(invalid_delta("Delta ends inside a record"));
+    }
+    Ok(bytes)
+}
+
+/// `read_exact`, reporting a short delta as invalid rather than a bare EOF
+fn read_exact<R: Read>(input: &mut R, buf: &mut [u8]) -> std::io::Result<()> {
+    input.read_exact(buf).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => invalid_delta("Delta ends inside a record"),
+        _ => e,
+    })
+}
+
+fn invalid_delta(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write_patch;
+
+    const BASE: &str = "created_at: 2024-04-01T00:00:05Z\n---\n\
+        rails 7.0.0 8f5ba4a1e4f24a7c05e0e8a9e1e7c2b3\n\
+        sinatra 3.0.0 def456\n";
+
+    fn apply(base: &str, delta: &[u8]) -> std::io::Result<String> {
+        let mut output = Vec::new();
+        apply_delta(base.as_bytes(), delta, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_delta_round_trip_is_smaller_than_patch() {
+        let new = format!(
+            "{}rails 7.0.1,7.0.2 0d1e2f3a4b5c6d7e8f9a0b1c2d3e4f5a\npuma 6.0.0 aaa111 extra\n",
+            BASE
+        );
+        let mut delta = Vec::new();
+        let diff = write_delta(BASE.as_bytes(), new.as_bytes(), &mut delta).unwrap();
+        assert!(diff.is_append_only());
+        assert!(is_delta(&delta));
+        assert_eq!(apply(BASE, &delta).unwrap(), new);
+
+        let mut patch = Vec::new();
+        write_patch(BASE.as_bytes(), new.as_bytes(), &mut patch).unwrap();
+        assert!(delta.len() < patch.len());
+
+        // A rewritten tail replaces the base's lines after the shared prefix
+        let rewritten = "created_at: 2024-05-01T00:00:05Z\n---\n\
+            rails 7.0.0 8f5ba4a1e4f24a7c05e0e8a9e1e7c2b3\n\
+            puma 6.0.0 aaa111\n";
+        let mut delta = Vec::new();
+        write_delta(BASE.as_bytes(), rewritten.as_bytes(), &mut delta).unwrap();
+        assert_eq!(apply(BASE, &delta).unwrap(), rewritten);
+    }
+
+    #[test]
+    fn test_snapshot_ignores_base() {
+        let mut snapshot = Vec::new();
+        assert_eq!(write_snapshot(BASE.as_bytes(), &mut snapshot).unwrap(), 2);
+
+        let mut output = Vec::new();
+        apply_delta(std::io::empty(), snapshot.as_slice(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), BASE);
+    }
+
+    #[test]
+    fn test_mismatched_base_and_truncated_delta_rejected() {
+        let new = format!("{}rails 7.0.1 fed321\n", BASE);
+        let mut delta = Vec::new();
+        write_delta(BASE.as_bytes(), new.as_bytes(), &mut delta).unwrap();
+
+        let other_base = "created_at: 2024-04-01T00:00:05Z\n---\n\
+            rails 7.0.0 8f5ba4a1e4f24a7c05e0e8a9e1e7c2b3\n\
+            puma 6.0.0 aaa111\n";
+        let err = apply(other_base, &delta).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Delta base does not match the delta digest"
+        );
+
+        let err = apply(BASE, &delta[..delta.len() - 2]).unwrap_err();
+        assert_eq!(err.to_string(), "Delta ends inside a record");
+
+        let err = apply(BASE, b"gem-index-filter-patch 1\n").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,343 @@
+//! Streaming comparison of two versions files
+//!
+//! The versions file is append-only, so two snapshots of the same index share
+//! a common prefix and differ only in the lines appended afterwards. The diff
+//! walks both files in lockstep until the first differing line; everything in
+//! the old file after that point counts as removed, and everything in the new
+//! file counts as added. For a genuine append this is the minimal delta, and
+//! for a rewritten file (upstream compaction or a filter change) it degrades
+//! to "replace the tail" without ever buffering either file.
+
+use crate::slice::gem_name;
+#[cfg(feature = "digest")]
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// Summary of the differences between two versions files
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionsDiff {
+    /// Whether the metadata section (everything up to `---`) differs
+    pub metadata_changed: bool,
+    /// Number of gem lines shared by both files before the first difference
+    pub common_lines: usize,
+    /// Number of gem lines in the old file after the first difference
+    pub removed_lines: usize,
+    /// Number of gem lines in the new file after the first difference
+    pub added_lines: usize,
+    /// Gems present in the new file but not in the old one, sorted by name
+    pub new_gems: Vec<String>,
+    /// Gems present in the old file but not in the new one, sorted by name
+    pub removed_gems: Vec<String>,
+}
+
+impl VersionsDiff {
+    /// Whether the new file only appends lines to the old one
+    ///
+    /// This is the property compact-index clients rely on when they fetch
+    /// updates with `Range` requests.
+    pub fn is_append_only(&self) -> bool {
+        !self.metadata_changed && self.removed_lines == 0
+    }
+}
+
+/// Compare two versions files, streaming the added lines to `added`
+///
+/// Lines from `new` that follow the first difference are written to `added`
+/// exactly as read. Pass [`std::io::sink()`] when only the summary is needed.
+///
+/// Tracking new and removed gems requires the set of distinct gem names seen
+/// in the shared prefix, so memory grows with the number of distinct gems
+/// (not with file size).
+pub fn diff_versions<R1: Read, R2: Read, W: Write>(
+    old: R1,
+    new: R2,
+    added: &mut W,
+) -> std::io::Result<VersionsDiff> {
+    diff_with_prefix_hook(old, new, added, false, |_, _| Ok(()))
+}
+
+/// The shared prefix of two versions files, as seen once the walk diverges
+// Only patches read it, and they need the digest feature
+#[cfg_attr(not(feature = "digest"), allow(dead_code))]
+pub(crate) struct SharedPrefix<'a> {
+    /// Number of shared gem lines
+    pub lines: usize,
+    /// Hex SHA-256 of the shared gem lines (each trimmed and newline-terminated),
+    /// or an empty string when hashing was not requested
+    pub sha256: String,
+    /// Metadata section of the new file, including the separator line
+    pub new_metadata: &'a str,
+}
+
+/// Diff engine shared by [`diff_versions`] and patch creation
+///
+/// `before_tail` runs once the shared prefix is known and before any added
+/// line is written, letting callers emit a header that depends on the prefix.
+pub(crate) fn diff_with_prefix_hook<R1, R2, W, F>(
+    old: R1,
+    new: R2,
+    added: &mut W,
+    hash_prefix: bool,
+    before_tail: F,
+) -> std::io::Result<VersionsDiff>
+where
+    R1: Read,
+    R2: Read,
+    W: Write,
+    F: FnOnce(&mut W, &SharedPrefix) -> std::io::Result<()>,
+{
+    let mut old = BufReader::new(old);
+    let mut new = BufReader::new(new);
+
+    let old_metadata = read_metadata(&mut old)?;
+    let new_metadata = read_metadata(&mut new)?;
+
+    let mut diff = VersionsDiff {
+        metadata_changed: old_metadata != new_metadata,
+        ..VersionsDiff::default()
+    };
+
+    let mut common_names: HashSet<String> = HashSet::new();
+    // Only patches hash the prefix, and they need the digest feature anyway
+    #[cfg(feature = "digest")]
+    let mut prefix_hasher = hash_prefix.then(Sha256::new);
+    #[cfg(not(feature = "digest"))]
+    let _ = hash_prefix;
+    let mut old_line = String::new();
+    let mut new_line = String::new();
+
+    // Walk the shared prefix; both buffers hold the first differing lines afterwards
+    let (old_pending, new_pending) = loop {
+        let old_more = read_gem_line(&mut old, &mut old_line)?;
+        let new_more = read_gem_line(&mut new, &mut new_line)?;
+        if !(old_more && new_more) || old_line.trim() != new_line.trim() {
+            break (old_more, new_more);
+        }
+        diff.common_lines += 1;
+        insert_name(&mut common_names, &old_line);
+        #[cfg(feature = "digest")]
+        if let Some(hasher) = prefix_hasher.as_mut() {
+            hash_gem_line(hasher, &old_line);
+        }
+    };
+
+    #[cfg(feature = "digest")]
+    let sha256 = prefix_hasher
+        .map(|hasher| hex::encode(hasher.finalize()))
+        .unwrap_or_default();
+    #[cfg(not(feature = "digest"))]
+    let sha256 = String::new();
+    before_tail(
+        added,
+        &SharedPrefix {
+            lines: diff.common_lines,
+            sha256,
+            new_metadata: &new_metadata,
+        },
+    )?;
+
+    let mut old_tail_names: HashSet<String> = HashSet::new();
+    let mut pending = old_pending;
+    while pending {
+        diff.removed_lines += 1;
+        if !contains_name(&common_names, &old_line) {
+            insert_name(&mut old_tail_names, &old_line);
+        }
+        pending = read_gem_line(&mut old, &mut old_line)?;
+    }
+
+    let mut new_tail_names: HashSet<String> = HashSet::new();
+    let mut pending = new_pending;
+    while pending {
+        diff.added_lines += 1;
+        added.write_all(new_line.as_bytes())?;
+        if !contains_name(&common_names, &new_line) {
+            insert_name(&mut new_tail_names, &new_line);
+        }
+        pending = read_gem_line(&mut new, &mut new_line)?;
+    }
+
+    diff.new_gems = new_tail_names
+        .iter()
+        .filter(|name| !old_tail_names.contains(*name))
+        .cloned()
+        .collect();
+    diff.removed_gems = old_tail_names
+        .iter()
+        .filter(|name| !new_tail_names.contains(*name))
+        .cloned()
+        .collect();
+    diff.new_gems.sort_unstable();
+    diff.removed_gems.sort_unstable();
+
+    Ok(diff)
+}
+
+/// Feed a gem line into a prefix hash, ignoring surrounding whitespace
+///
+/// Hashing the trimmed line keeps the digest stable when the final line of a
+/// file lacks its newline or a file was written with CRLF endings.
+#[cfg(feature = "digest")]
+#[inline]
+pub(crate) fn hash_gem_line(hasher: &mut Sha256, line: &str) {
+    hasher.update(line.trim().as_bytes());
+    hasher.update(b"\n");
+}
+
+/// Read the metadata section up to and including the "---" separator
+pub(crate) fn read_metadata<R: Read>(reader: &mut BufReader<R>) -> std::io::Result<String> {
+    let mut metadata = String::new();
+
+    loop {
+        let start = metadata.len();
+        let n = reader.read_line(&mut metadata)?;
+        if n == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "No separator found in versions file",
+            ));
+        }
+        if start == 0 && metadata.starts_with('\u{FEFF}') {
+            metadata.drain(..'\u{FEFF}'.len_utf8());
+        }
+
+        if metadata[start..].trim() == "---" {
+            break;
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Read the next non-empty gem line into `line`, returning false at EOF
+pub(crate) fn read_gem_line<R: Read>(
+    reader: &mut BufReader<R>,
+    line: &mut String,
+) -> std::io::Result<bool> {
+    loop {
+        line.clear();
+        if reader.read_line(line)? == 0 {
+            return Ok(false);
+        }
+        if !line.trim().is_empty() {
+            return Ok(true);
+        }
+    }
+}
+
+#[inline]
+fn contains_name(names: &HashSet<String>, line: &str) -> bool {
+    gem_name(line.trim()).is_some_and(|name| names.contains(name))
+}
+
+/// Insert the line's gem name, allocating only for names not yet seen
+#[inline]
+fn insert_name(names: &mut HashSet<String>, line: &str) {
+    if let Some(name) = gem_name(line.trim()) {
+        if !names.contains(name) {
+            names.insert(name.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_only_diff() {
+        let old = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0 abc123
+sinatra 3.0.0 def456
+"#;
+        let new = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0 abc123
+sinatra 3.0.0 def456
+rails 7.0.1 fed321
+puma 6.0.0 aaa111
+"#;
+
+        let mut added = Vec::new();
+        let diff = diff_versions(old.as_bytes(), new.as_bytes(), &mut added).unwrap();
+
+        assert!(diff.is_append_only());
+        assert_eq!(diff.common_lines, 2);
+        assert_eq!(diff.removed_lines, 0);
+        assert_eq!(diff.added_lines, 2);
+        assert_eq!(diff.new_gems, vec!["puma"]);
+        assert!(diff.removed_gems.is_empty());
+        assert_eq!(
+            String::from_utf8(added).unwrap(),
+            "rails 7.0.1 fed321\npuma 6.0.0 aaa111\n"
+        );
+    }
+
+    #[test]
+    fn test_filter_change_diff() {
+        // Same upstream filtered with two different allowlists
+        let old = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0 abc123
+activerecord 7.0.0 bbb222
+sinatra 3.0.0 def456
+"#;
+        let new = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0 abc123
+sinatra 3.0.0 def456
+puma 6.0.0 aaa111
+"#;
+
+        let mut added = Vec::new();
+        let diff = diff_versions(old.as_bytes(), new.as_bytes(), &mut added).unwrap();
+
+        assert!(!diff.is_append_only());
+        assert_eq!(diff.common_lines, 1);
+        assert_eq!(diff.removed_lines, 2);
+        assert_eq!(diff.added_lines, 2);
+        // sinatra moved into the tail of both files, so it is neither new nor removed
+        assert_eq!(diff.new_gems, vec!["puma"]);
+        assert_eq!(diff.removed_gems, vec!["activerecord"]);
+    }
+
+    #[test]
+    fn test_metadata_change_detected() {
+        let old = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+        let new = "created_at: 2024-05-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+
+        let diff = diff_versions(old.as_bytes(), new.as_bytes(), &mut std::io::sink()).unwrap();
+
+        assert!(diff.metadata_changed);
+        assert!(!diff.is_append_only());
+        assert_eq!(diff.common_lines, 1);
+        assert_eq!(diff.added_lines, 0);
+    }
+
+    #[test]
+    fn test_identical_files() {
+        let input =
+            "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n\nsinatra 3.0.0 def456\n";
+
+        let diff = diff_versions(input.as_bytes(), input.as_bytes(), &mut std::io::sink()).unwrap();
+
+        assert_eq!(
+            diff,
+            VersionsDiff {
+                common_lines: 2,
+                ..VersionsDiff::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_missing_separator_is_error() {
+        let old = "created_at: 2024-04-01T00:00:05Z\n---\n";
+        let new = "rails 7.0.0 abc123\n";
+
+        let err = diff_versions(old.as_bytes(), new.as_bytes(), &mut std::io::sink()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
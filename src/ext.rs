@@ -0,0 +1,148 @@
+//! Fluent filtering from any reader
+//!
+//! [`GemIndexReadExt`] turns the common "filter this input into that writer"
+//! call into one expression built on [`FilterOptions`]:
+//!
+//! ```no_run
+//! use gem_index_filter::{DigestAlgorithm, FilterMode, GemIndexReadExt};
+//! use std::collections::HashSet;
+//! use std::fs::File;
+//!
+//! let allowlist: HashSet<&str> = ["rails", "sinatra"].into_iter().collect();
+//! let mut output = File::create("versions.filtered")?;
+//! let stats = File::open("versions")?
+//!     .filter_gems(FilterMode::Allow(&allowlist))
+//!     .strip_versions()
+//!     .digest(DigestAlgorithm::Sha256)
+//!     .write_to(&mut output)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::file::FilterOptions;
+use crate::filter::filter_with_line_rules;
+use crate::slice::{LineErrors, NamelessLines, SeparatorRules};
+use crate::stats::FilterStats;
+use crate::{DigestAlgorithm, FilterMode};
+use std::io::{Read, Write};
+
+/// Filtering methods for every [`Read`]
+pub trait GemIndexReadExt: Read + Sized {
+    /// Start filtering this versions file with `mode`
+    fn filter_gems(self, mode: FilterMode<'_>) -> FilterGems<'_, Self> {
+        FilterGems {
+            input: self,
+            options: FilterOptions::new(mode),
+        }
+    }
+}
+
+impl<R: Read> GemIndexReadExt for R {}
+
+/// A pending filter run, created by [`GemIndexReadExt::filter_gems`]
+///
+/// Nothing is read until [`write_to`](Self::write_to).
+#[must_use = "nothing is filtered until write_to is called"]
+pub struct FilterGems<'a, R> {
+    input: R,
+    options: FilterOptions<'a>,
+}
+
+impl<'a, R: Read> FilterGems<'a, R> {
+    /// Replace version lists with `0`
+    pub fn strip_versions(mut self) -> Self {
+        self.options = self.options.strip_versions();
+        self
+    }
+
+    /// Report a digest of the output in [`FilterStats::digest`]
+    pub fn digest(mut self, algorithm: DigestAlgorithm) -> Self {
+        self.options = self.options.digest(algorithm);
+        self
+    }
+
+    /// Choose how gem lines without a space are handled
+    pub fn nameless_lines(mut self, nameless: NamelessLines) -> Self {
+        self.options = self.options.nameless_lines(nameless);
+        self
+    }
+
+    /// Choose how separator lines are recognized and repeated ones handled
+    pub fn separator_rules(mut self, separators: SeparatorRules) -> Self {
+        self.options = self.options.separator_rules(separators);
+        self
+    }
+
+    /// Choose whether lines the filter can't handle fail the run or are skipped
+    pub fn line_errors(mut self, line_errors: LineErrors) -> Self {
+        self.options = self.options.line_errors(line_errors);
+        self
+    }
+
+    /// Replace all options at once, keeping the input
+    pub fn options(mut self, options: FilterOptions<'a>) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Filter the input into `output`
+    pub fn write_to<W: Write>(self, output: &mut W) -> std::io::Result<FilterStats> {
+        let options = self.options;
+        filter_with_line_rules(
+            self.input,
+            output,
+            options.mode.into(),
+            options.version_output,
+            options.digest,
+            options.line_rules(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_fluent_filter() {
+        let input = "created_at: 2024-04-01T00:00:05Z\n---\n\
+            rails 7.0.0,7.0.1 abc123\n\
+            sinatra 3.0.0 ghi789\n";
+        let blocklist: HashSet<&str> = ["rails"].into_iter().collect();
+
+        let mut output = Vec::new();
+        let stats = input
+            .as_bytes()
+            .filter_gems(FilterMode::Block(&blocklist))
+            .strip_versions()
+            .write_to(&mut output)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "created_at: 2024-04-01T00:00:05Z\n---\nsinatra 0 ghi789\n"
+        );
+        assert_eq!(stats.input_bytes, input.len() as u64);
+        assert!(stats.digest.is_none());
+    }
+
+    #[test]
+    fn test_collected_line_errors_are_reported() {
+        let input = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\nmalformed\n";
+        let mut output = Vec::new();
+        let stats = input
+            .as_bytes()
+            .filter_gems(FilterMode::Passthrough)
+            .nameless_lines(NamelessLines::Error)
+            .line_errors(LineErrors::Collect)
+            .write_to(&mut output)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n"
+        );
+        assert_eq!(stats.skipped.count, 1);
+        assert_eq!(stats.skipped.lines[0].line, 4);
+    }
+}
@@ -0,0 +1,213 @@
+//! Bounds on upstream fetches (`http` feature)
+//!
+//! A refresh stuck on a stalled connection holds up everything waiting on
+//! it, and a body of unexpected size ends up on disk or in memory. [`FetchLimits`]
+//! caps how long connecting, waiting for the response and reading the body
+//! may take, and how large the body may be. Bodies served as HTML are
+//! refused too, since that is what a proxy's error or login page looks like
+//! in place of a versions file or list. A fetch that breaks a limit fails
+//! with a [`FetchError`].
+
+use std::fmt;
+use std::io::Read;
+use std::time::Duration;
+
+/// Timeouts and size cap applied to every request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchLimits {
+    /// Time to open the connection, TLS handshake included
+    pub connect_timeout: Option<Duration>,
+    /// Time from sending the request to receiving the response headers
+    pub response_timeout: Option<Duration>,
+    /// Time to receive the whole body
+    pub body_timeout: Option<Duration>,
+    /// Largest body accepted, in bytes
+    pub max_bytes: Option<u64>,
+}
+
+impl Default for FetchLimits {
+    /// 10 seconds to connect, 30 for the response, 5 minutes for the body, 256 MiB
+    fn default() -> Self {
+        FetchLimits {
+            connect_timeout: Some(Duration::from_secs(10)),
+            response_timeout: Some(Duration::from_secs(30)),
+            body_timeout: Some(Duration::from_secs(300)),
+            max_bytes: Some(256 << 20),
+        }
+    }
+}
+
+impl FetchLimits {
+    /// No timeouts and no size cap
+    pub fn unlimited() -> Self {
+        FetchLimits {
+            connect_timeout: None,
+            response_timeout: None,
+            body_timeout: None,
+            max_bytes: None,
+        }
+    }
+
+    /// An agent enforcing the timeouts
+    pub(crate) fn agent(&self, http_status_as_error: bool) -> ureq::Agent {
+        ureq::Agent::config_builder()
+            .http_status_as_error(http_status_as_error)
+            .timeout_connect(self.connect_timeout)
+            .timeout_recv_response(self.response_timeout)
+            .timeout_recv_body(self.body_timeout)
+            .build()
+            .into()
+    }
+
+    /// The body of `response`, refused if it is HTML or larger than the cap
+    ///
+    /// A declared `Content-Length` over the cap fails up front; otherwise the
+    /// returned reader fails once the cap is passed.
+    pub(crate) fn body(
+        &self,
+        url: &str,
+        response: ureq::http::Response<ureq::Body>,
+    ) -> std::io::Result<LimitedBody> {
+        let headers = response.headers();
+        let content_type = headers
+            .get("content-type")
+            .and_then(|value| value.to_str().ok());
+        if let Some(content_type) = content_type {
+            if content_type
+                .trim()
+                .to_ascii_lowercase()
+                .starts_with("text/html")
+            {
+                return Err(FetchError::HtmlBody {
+                    url: url.to_string(),
+                    content_type: content_type.to_string(),
+                }
+                .into());
+            }
+        }
+        let length = headers
+            .get("content-length")
+            .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+        if let (Some(length), Some(limit)) = (length, self.max_bytes) {
+            if length > limit {
+                return Err(FetchError::TooLarge {
+                    url: url.to_string(),
+                    limit,
+                }
+                .into());
+            }
+        }
+
+        Ok(LimitedBody {
+            inner: response.into_body().into_reader(),
+            url: url.to_string(),
+            limit: self.max_bytes,
+            read: 0,
+        })
+    }
+}
+
+/// A fetch broke one of its [`FetchLimits`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchError {
+    /// A timeout expired before the response or its body arrived
+    TimedOut {
+        /// URL being fetched
+        url: String,
+    },
+    /// The body is larger than `max_bytes`
+    TooLarge {
+        /// URL being fetched
+        url: String,
+        /// The cap that was passed
+        limit: u64,
+    },
+    /// The body is an HTML page rather than the file asked for
+    HtmlBody {
+        /// URL being fetched
+        url: String,
+        /// `Content-Type` the server sent
+        content_type: String,
+    },
+}
+
+impl FetchError {
+    /// The broken limit behind an I/O error from a fetch, if any
+    pub fn from_io_error(error: &std::io::Error) -> Option<&FetchError> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::TimedOut { url } => write!(f, "Timed out fetching {}", url),
+            FetchError::TooLarge { url, limit } => {
+                write!(f, "{} is larger than the {} byte limit", url, limit)
+            }
+            FetchError::HtmlBody { url, content_type } => {
+                write!(f, "{} returned an HTML page ({})", url, content_type)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+impl From<FetchError> for std::io::Error {
+    fn from(error: FetchError) -> Self {
+        let kind = match error {
+            FetchError::TimedOut { .. } => std::io::ErrorKind::TimedOut,
+            _ => std::io::ErrorKind::InvalidData,
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
+/// The error for a request to `url` that failed before a response arrived
+pub(crate) fn call_error(url: &str, error: ureq::Error) -> std::io::Error {
+    match error {
+        ureq::Error::Timeout(_) => FetchError::TimedOut {
+            url: url.to_string(),
+        }
+        .into(),
+        error => std::io::Error::other(format!("Failed to fetch {}: {}", url, error)),
+    }
+}
+
+/// A response body that fails once it passes the size cap
+pub(crate) struct LimitedBody {
+    inner: ureq::BodyReader<'static>,
+    url: String,
+    limit: Option<u64>,
+    read: u64,
+}
+
+impl Read for LimitedBody {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf).map_err(|error| {
+            let timed_out = error.kind() == std::io::ErrorKind::TimedOut
+                || error
+                    .get_ref()
+                    .and_then(|inner| inner.downcast_ref::<ureq::Error>())
+                    .is_some_and(|inner| matches!(inner, ureq::Error::Timeout(_)));
+            if timed_out {
+                FetchError::TimedOut {
+                    url: self.url.clone(),
+                }
+                .into()
+            } else {
+                error
+            }
+        })?;
+        self.read += n as u64;
+        match self.limit {
+            Some(limit) if self.read > limit => Err(FetchError::TooLarge {
+                url: self.url.clone(),
+                limit,
+            }
+            .into()),
+            _ => Ok(n),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Filtering files by path
+//!
+//! [`filter_file`] wraps the streaming filter in the scaffolding every
+//! file-based caller otherwise writes: opening and buffering, writing through
+//! a temporary file renamed into place so readers never see a partial index,
+//! and, with the `gzip` feature, transparent `.gz` input and output.
+
+use crate::filter::{filter_with_line_rules, LineRules};
+use crate::slice::{LineErrors, NamelessLines, SeparatorRules};
+use crate::stats::FilterStats;
+use crate::{DigestAlgorithm, FilterMode, VersionOutput};
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// How [`filter_file`] filters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilterOptions<'a> {
+    /// Which gems to keep
+    pub mode: FilterMode<'a>,
+    /// Whether to keep or strip version lists
+    pub version_output: VersionOutput,
+    /// Digest of the filtered output (before any compression) to report
+    pub digest: Option<DigestAlgorithm>,
+    /// What to do with gem lines that have no space
+    pub nameless_lines: NamelessLines,
+    /// How separator lines are recognized and repeated ones handled
+    pub separator_rules: SeparatorRules,
+    /// Whether lines the filter can't handle fail the run or are skipped
+    pub line_errors: LineErrors,
+}
+
+impl<'a> FilterOptions<'a> {
+    /// Filter with `mode`, preserving versions and computing no digest
+    pub fn new(mode: FilterMode<'a>) -> Self {
+        FilterOptions {
+            mode,
+            version_output: VersionOutput::Preserve,
+            digest: None,
+            nameless_lines: NamelessLines::default(),
+            separator_rules: SeparatorRules::default(),
+            line_errors: LineErrors::default(),
+        }
+    }
+
+    /// Replace version lists with `0`
+    pub fn strip_versions(mut self) -> Self {
+        self.version_output = VersionOutput::Strip;
+        self
+    }
+
+    /// Report a digest of the filtered output in [`FilterStats::digest`]
+    pub fn digest(mut self, algorithm: DigestAlgorithm) -> Self {
+        self.digest = Some(algorithm);
+        self
+    }
+
+    /// Choose how gem lines without a space are handled
+    pub fn nameless_lines(mut self, nameless: NamelessLines) -> Self {
+        self.nameless_lines = nameless;
+        self
+    }
+
+    /// Choose how separator lines are recognized and repeated ones handled
+    pub fn separator_rules(mut self, separators: SeparatorRules) -> Self {
+        self.separator_rules = separators;
+        self
+    }
+
+    /// Skip lines the filter can't handle instead of failing
+    ///
+    /// With [`LineErrors::Collect`], a mostly good upstream file still
+    /// produces output, and [`FilterStats::skipped`] lists what was dropped.
+    pub fn line_errors(mut self, line_errors: LineErrors) -> Self {
+        self.line_errors = line_errors;
+        self
+    }
+
+    pub(crate) fn line_rules(&self) -> LineRules {
+        LineRules {
+            nameless: self.nameless_lines,
+            separators: self.separator_rules,
+            errors: self.line_errors,
+        }
+    }
+}
+
+/// Filter `input` into `output` with every option of [`FilterOptions`]
+///
+/// The streaming counterpart of [`filter_file`], for callers who need to
+/// choose how lines without a space, repeated separators or bad lines are
+/// handled, which [`filter_versions_streaming`](crate::filter_versions_streaming)
+/// leaves at their defaults.
+pub fn filter_versions_with_options<R: Read, W: Write>(
+    input: R,
+    output: &mut W,
+    options: &FilterOptions,
+) -> std::io::Result<FilterStats> {
+    filter_with_line_rules(
+        input,
+        output,
+        options.mode.into(),
+        options.version_output,
+        options.digest,
+        options.line_rules(),
+    )
+}
+
+/// Filter the versions file at `input_path` into `output_path`
+///
+/// The output only replaces `output_path` once the run has succeeded. Paths
+/// ending in `.gz` are decompressed or compressed with the `gzip` feature,
+/// and rejected without it.
+pub fn filter_file(
+    input_path: impl AsRef<Path>,
+    output_path: impl AsRef<Path>,
+    options: &FilterOptions,
+) -> std::io::Result<FilterStats> {
+    let input_path = input_path.as_ref();
+    let output_path = output_path.as_ref();
+    let input = open_input(input_path)?;
+
+    write_atomically(output_path, |output| {
+        let filter = |input, mut output: &mut dyn Write| {
+            filter_versions_with_options(input, &mut output, options)
+        };
+        if !is_gzip(output_path) {
+            return filter(input, output);
+        }
+        #[cfg(feature = "gzip")]
+        {
+            let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+            let stats = filter(input, &mut encoder)?;
+            encoder.finish()?;
+            Ok(stats)
+        }
+        #[cfg(not(feature = "gzip"))]
+        Err(gzip_unsupported(output_path))
+    })
+}
+
+pub(crate) fn open_input(path: &Path) -> std::io::Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+    if !is_gzip(path) {
+        return Ok(Box::new(file));
+    }
+    #[cfg(feature = "gzip")]
+    return Ok(Box::new(flate2::read::MultiGzDecoder::new(
+        std::io::BufReader::new(file),
+    )));
+    #[cfg(not(feature = "gzip"))]
+    Err(gzip_unsupported(path))
+}
+
+fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "gz")
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip_unsupported(path: &Path) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!(
+            "{}: .gz files need gem-index-filter built with the gzip feature",
+            path.display()
+        ),
+    )
+}
+
+/// Write a file through a temporary sibling and rename it into place
+pub(crate) fn write_atomically<T, F>(path: &Path, write: F) -> std::io::Result<T>
+where
+    F: FnOnce(&mut BufWriter<File>) -> std::io::Result<T>,
+{
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let result = File::create(&tmp_path).and_then(|file| {
+        let mut output = BufWriter::new(file);
+        let value = write(&mut output)?;
+        output.flush()?;
+        Ok(value)
+    });
+
+    match result {
+        Ok(value) => fs::rename(&tmp_path, path).map(|()| value),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const VERSIONS: &str = "created_at: 2024-04-01T00:00:05Z\n---\n\
+        rails 7.0.0,7.0.1 abc123\n\
+        sinatra 3.0.0 ghi789\n";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("gem-index-filter-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_filter_file_replaces_output_only_on_success() {
+        let dir = temp_dir("filter-file");
+        let (input, output) = (dir.join("versions"), dir.join("versions.filtered"));
+        fs::write(&input, VERSIONS).unwrap();
+        fs::write(&output, "previous\n").unwrap();
+
+        let allowlist: HashSet<&str> = ["sinatra"].into_iter().collect();
+        let options = FilterOptions::new(FilterMode::Allow(&allowlist)).strip_versions();
+        #[cfg(feature = "digest")]
+        let options = options.digest(DigestAlgorithm::Sha256);
+        let stats = filter_file(&input, &output, &options).unwrap();
+
+        let filtered = fs::read_to_string(&output).unwrap();
+        assert_eq!(
+            filtered,
+            "created_at: 2024-04-01T00:00:05Z\n---\nsinatra 0 ghi789\n"
+        );
+        assert_eq!(stats.output_bytes, filtered.len() as u64);
+        #[cfg(feature = "digest")]
+        assert!(stats.digest.is_some());
+
+        // A failed run leaves the previous output and no temporary file
+        fs::write(&input, "no separator\n").unwrap();
+        assert!(filter_file(&input, &output, &options).is_err());
+        assert_eq!(fs::read_to_string(&output).unwrap(), filtered);
+        assert!(!dir.join("versions.filtered.tmp").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_streaming_with_options() {
+        let input = format!("{}puma\n", VERSIONS);
+        let run = |options: FilterOptions| {
+            let mut output = Vec::new();
+            filter_versions_with_options(input.as_bytes(), &mut output, &options).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(run(FilterOptions::new(FilterMode::Passthrough)), VERSIONS);
+        assert_eq!(
+            run(FilterOptions::new(FilterMode::Passthrough).nameless_lines(NamelessLines::Keep)),
+            input
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gz_paths_are_compressed() {
+        let dir = temp_dir("filter-file-gz");
+        let (input, output) = (dir.join("versions.gz"), dir.join("versions.filtered.gz"));
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&input).unwrap(), Default::default());
+        encoder.write_all(VERSIONS.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        filter_file(
+            &input,
+            &output,
+            &FilterOptions::new(FilterMode::Passthrough),
+        )
+        .unwrap();
+
+        let mut filtered = String::new();
+        flate2::read::GzDecoder::new(File::open(&output).unwrap())
+            .read_to_string(&mut filtered)
+            .unwrap();
+        assert_eq!(filtered, VERSIONS);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,13 @@
-use sha2::{Digest, Sha256, Sha512};
+use crate::pipeline::{LineTransform, StripVersions};
+pub use crate::slice::VersionOutput;
+use crate::slice::{
+    option_names, strip_bom, GemSet, LineErrors, NamelessLines, SeparatorRules, SliceFilter,
+    SliceMode,
+};
+use crate::stats::{FilterStats, MemoryProbe};
+#[cfg(feature = "digest")]
+pub use crate::writers::DigestWriter;
+use std::borrow::Cow;
 use std::collections::HashSet;
 use std::io::{BufRead, BufReader, Read, Write};
 
@@ -13,13 +22,14 @@ pub enum FilterMode<'a> {
     Block(&'a HashSet<&'a str>),
 }
 
-/// Version output mode
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum VersionOutput {
-    /// Preserve original version information
-    Preserve,
-    /// Strip versions, replacing with '0'
-    Strip,
+impl<'a> From<FilterMode<'a>> for SliceMode<'a, HashSet<&'a str>> {
+    fn from(mode: FilterMode<'a>) -> Self {
+        match mode {
+            FilterMode::Passthrough => SliceMode::Passthrough,
+            FilterMode::Allow(gemlist) => SliceMode::Allow(gemlist),
+            FilterMode::Block(gemlist) => SliceMode::Block(gemlist),
+        }
+    }
 }
 
 /// Supported digest algorithms for checksum computation
@@ -31,6 +41,11 @@ pub enum DigestAlgorithm {
     Sha512,
 }
 
+option_names!(DigestAlgorithm, "digest algorithm", {
+    Sha256 => "sha256" | "sha-256",
+    Sha512 => "sha512" | "sha-512",
+});
+
 impl DigestAlgorithm {
     /// Return the standard name of the digest algorithm
     pub fn name(&self) -> &'static str {
@@ -41,92 +56,95 @@ impl DigestAlgorithm {
     }
 }
 
-/// Internal enum for holding active digest state
-enum DigestState {
-    Sha256(Sha256),
-    Sha512(Sha512),
-}
-
-/// Writer wrapper that computes digest of data as it's written
-/// This enables streaming checksum computation with zero buffering
-pub struct DigestWriter<'a, W: Write> {
-    inner: &'a mut W,
-    state: DigestState,
+/// The error for a requested digest in a build without the `digest` feature
+#[cfg(not(feature = "digest"))]
+pub(crate) fn digest_unsupported(algorithm: DigestAlgorithm) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!(
+            "{} checksums need gem-index-filter built with the digest feature",
+            algorithm.name()
+        ),
+    )
 }
 
-impl<'a, W: Write> DigestWriter<'a, W> {
-    /// Create a new DigestWriter with the specified algorithm
-    pub fn new(inner: &'a mut W, algorithm: DigestAlgorithm) -> Self {
-        let state = match algorithm {
-            DigestAlgorithm::Sha256 => DigestState::Sha256(Sha256::new()),
-            DigestAlgorithm::Sha512 => DigestState::Sha512(Sha512::new()),
-        };
-        DigestWriter { inner, state }
-    }
-
-    /// Finalize the digest and return the hex-encoded checksum
-    pub fn finalize(self) -> String {
-        match self.state {
-            DigestState::Sha256(hasher) => hex::encode(hasher.finalize()),
-            DigestState::Sha512(hasher) => hex::encode(hasher.finalize()),
-        }
-    }
+/// Bytes of accepted lines gathered before each write to the caller's writer
+///
+/// Callers often pass an unbuffered `File` or stdout, where a write per line
+/// is a syscall per line. Kept well under the fuzz targets' memory budget.
+pub(crate) const OUTPUT_BATCH: usize = 32 * 1024;
+
+/// Per-line handling passed through to [`SliceFilter`]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LineRules {
+    pub nameless: NamelessLines,
+    pub separators: SeparatorRules,
+    pub errors: LineErrors,
 }
 
-impl<'a, W: Write> Write for DigestWriter<'a, W> {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        // Update digest with the data
-        match &mut self.state {
-            DigestState::Sha256(hasher) => hasher.update(buf),
-            DigestState::Sha512(hasher) => hasher.update(buf),
-        }
-        // Write to underlying writer
-        self.inner.write(buf)
-    }
-
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.inner.flush()
-    }
-}
 /// Execute the complete filtering pipeline: metadata pass-through + gem filtering
 ///
-/// This helper function encapsulates the core filtering logic and is used by both
-/// digest and non-digest code paths to eliminate duplication.
-fn execute_filter_pipeline<R: Read, W: Write>(
+/// Lines are filtered straight out of the read buffer by [`SliceFilter`],
+/// whose per-mode loops find newlines and name separators with `memchr`
+/// rather than copying every line into a `String` first. Output collects in
+/// a batch buffer that is written to `output` in [`OUTPUT_BATCH`]-sized runs.
+fn execute_filter_pipeline<R: Read, W: Write, S: GemSet + ?Sized>(
     reader: &mut BufReader<R>,
     output: &mut W,
-    mode: FilterMode,
+    mode: SliceMode<'_, S>,
     version_output: VersionOutput,
+    rules: LineRules,
+    stats: &mut FilterStats,
 ) -> std::io::Result<()> {
-    // Pass through metadata until separator "---"
-    pass_through_metadata(reader, output)?;
-
-    // Branch to specialized filter function based on mode
-    // This hoists the mode check outside the hot loop for performance
-    match mode {
-        FilterMode::Passthrough => process_passthrough(reader, output, version_output)?,
-        FilterMode::Allow(allowlist) => {
-            process_filtered(reader, output, allowlist, true, version_output)?
+    let mut filter = SliceFilter::new(mode, version_output)
+        .nameless_lines(rules.nameless)
+        .separator_rules(rules.separators)
+        .line_errors(rules.errors);
+    let mut batch = Vec::with_capacity(OUTPUT_BATCH);
+    stats.read_buffer_bytes = reader.capacity();
+
+    loop {
+        let chunk = reader.fill_buf()?;
+        if chunk.is_empty() {
+            break;
         }
-        FilterMode::Block(blocklist) => {
-            process_filtered(reader, output, blocklist, false, version_output)?
+        let consumed = chunk.len();
+        filter.push(chunk, &mut batch)?;
+        reader.consume(consumed);
+        stats.input_bytes += consumed as u64;
+        stats.peak_carry_bytes = stats.peak_carry_bytes.max(filter.pending_len());
+        stats.peak_batch_bytes = stats.peak_batch_bytes.max(batch.len());
+
+        // A chunk can write more than it read (a line carried over from earlier
+        // reads, a newline added to a stripped last line), so there's no room to
+        // reserve ahead; the batch is written once it fills instead
+        if batch.len() >= OUTPUT_BATCH {
+            output.write_all(&batch)?;
+            stats.output_bytes += batch.len() as u64;
+            batch.clear();
         }
     }
 
+    stats.skipped = filter.finish_with_report(&mut batch)?;
+    output.write_all(&batch)?;
+    stats.peak_batch_bytes = stats.peak_batch_bytes.max(batch.len());
+    stats.output_bytes += batch.len() as u64;
     Ok(())
 }
 
 /// Stream and filter versions file by first word (gem name) with zero memory retention
 ///
 /// This function:
-/// - Reads input line by line
+/// - Reads input a buffer at a time, finding line breaks with `memchr`
 /// - Passes through metadata until "---" separator
 /// - Applies filtering based on mode (Allow/Block/Passthrough)
-/// - Immediately writes matching lines to output
+/// - Writes matching lines to output in batches
 /// - Optionally strips version information, replacing with "0"
 /// - Optionally computes a checksum of the filtered output
 /// - Ignores everything after the first word until newline
-/// - Retains only the current line buffer in memory
+/// - Retains only the read buffer, the output batch and any line split across reads
+/// - Skips gem lines without a space, in every mode; see
+///   [`filter_versions_with_options`](crate::filter_versions_with_options) to choose otherwise
 ///
 /// Returns:
 /// - `Ok(None)` if no digest algorithm was specified
@@ -138,227 +156,143 @@ pub fn filter_versions_streaming<R: Read, W: Write>(
     version_output: VersionOutput,
     digest_algorithm: Option<DigestAlgorithm>,
 ) -> std::io::Result<Option<String>> {
-    let mut reader = BufReader::new(input);
-
-    // Wrap output in DigestWriter if checksum is requested
-    match digest_algorithm {
-        Some(algorithm) => {
-            // Wrap output writer to compute digest as data streams through
-            let mut digest_writer = DigestWriter::new(output, algorithm);
-            execute_filter_pipeline(&mut reader, &mut digest_writer, mode, version_output)?;
-            // Finalize digest and return hex string
-            Ok(Some(digest_writer.finalize()))
-        }
-        None => {
-            // No digest requested, use output directly
-            execute_filter_pipeline(&mut reader, output, mode, version_output)?;
-            Ok(None)
-        }
-    }
-}
-
-/// Pass through metadata lines until the "---" separator
-fn pass_through_metadata<R: Read, W: Write>(
-    reader: &mut BufReader<R>,
-    output: &mut W,
-) -> std::io::Result<()> {
-    let mut line = String::new();
-
-    loop {
-        line.clear();
-        let n = reader.read_line(&mut line)?;
-        if n == 0 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "No separator found in versions file",
-            ));
-        }
-
-        output.write_all(line.as_bytes())?;
-
-        if line.trim() == "---" {
-            break;
-        }
-    }
-
-    Ok(())
+    filter_versions_with_set(input, output, mode.into(), version_output, digest_algorithm)
 }
 
-/// Process all gems without filtering
+/// [`filter_versions_streaming`] over any [`GemSet`], not just a `HashSet`
 ///
-/// Hoists version_output check outside the hot loop for performance
-fn process_passthrough<R: Read, W: Write>(
-    reader: &mut BufReader<R>,
+/// For sets that aren't hash sets, such as a compile-time `phf::Set` (`phf`
+/// feature) or a sorted slice.
+pub fn filter_versions_with_set<R: Read, W: Write, S: GemSet + ?Sized>(
+    input: R,
     output: &mut W,
+    mode: SliceMode<'_, S>,
     version_output: VersionOutput,
-) -> std::io::Result<()> {
-    match version_output {
-        VersionOutput::Strip => process_passthrough_stripped(reader, output),
-        VersionOutput::Preserve => process_passthrough_preserved(reader, output),
-    }
-}
-
-/// Process all gems without filtering, preserving original format
-fn process_passthrough_preserved<R: Read, W: Write>(
-    reader: &mut BufReader<R>,
-    output: &mut W,
-) -> std::io::Result<()> {
-    let mut line = String::new();
-
-    loop {
-        line.clear();
-        let n = reader.read_line(&mut line)?;
-        if n == 0 {
-            break; // EOF
-        }
-
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            continue;
-        }
-
-        output.write_all(line.as_bytes())?;
-    }
-
-    Ok(())
-}
-
-/// Process all gems without filtering, stripping version information
-fn process_passthrough_stripped<R: Read, W: Write>(
-    reader: &mut BufReader<R>,
-    output: &mut W,
-) -> std::io::Result<()> {
-    let mut line = String::new();
-
-    loop {
-        line.clear();
-        let n = reader.read_line(&mut line)?;
-        if n == 0 {
-            break; // EOF
-        }
-
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            continue;
-        }
-
-        write_gem_line_stripped(trimmed, output)?;
-    }
-
-    Ok(())
+    digest_algorithm: Option<DigestAlgorithm>,
+) -> std::io::Result<Option<String>> {
+    filter_versions_with_stats(
+        input,
+        output,
+        mode,
+        version_output,
+        digest_algorithm,
+        NamelessLines::default(),
+    )
+    .map(|stats| stats.digest)
 }
 
-/// Process gems with filtering based on gemlist membership
+/// [`filter_versions_with_set`], reporting byte counts and buffer high-water marks
 ///
-/// Hoists version_output check outside the hot loop for performance
+/// `nameless` chooses what happens to gem lines without a space, which the
+/// other entry points skip.
 ///
-/// When `include_on_match` is true (Allow mode): includes gems where gemlist.contains(gemname) == true
-/// When `include_on_match` is false (Block mode): includes gems where gemlist.contains(gemname) == false
-fn process_filtered<R: Read, W: Write>(
-    reader: &mut BufReader<R>,
+/// [`FilterStats::memory`] is filled in only when the `instrument` feature's
+/// [`CountingAllocator`](crate::stats) is the global allocator.
+pub fn filter_versions_with_stats<R: Read, W: Write, S: GemSet + ?Sized>(
+    input: R,
     output: &mut W,
-    gemlist: &HashSet<&str>,
-    include_on_match: bool,
+    mode: SliceMode<'_, S>,
     version_output: VersionOutput,
-) -> std::io::Result<()> {
-    match version_output {
-        VersionOutput::Strip => {
-            process_filtered_stripped(reader, output, gemlist, include_on_match)
-        }
-        VersionOutput::Preserve => {
-            process_filtered_preserved(reader, output, gemlist, include_on_match)
-        }
-    }
+    digest_algorithm: Option<DigestAlgorithm>,
+    nameless: NamelessLines,
+) -> std::io::Result<FilterStats> {
+    filter_with_line_rules(
+        input,
+        output,
+        mode,
+        version_output,
+        digest_algorithm,
+        LineRules {
+            nameless,
+            ..LineRules::default()
+        },
+    )
 }
 
-/// Process gems with filtering, preserving original format
-fn process_filtered_preserved<R: Read, W: Write>(
-    reader: &mut BufReader<R>,
+/// [`filter_versions_with_stats`] with any [`LineRules`]
+pub(crate) fn filter_with_line_rules<R: Read, W: Write, S: GemSet + ?Sized>(
+    input: R,
     output: &mut W,
-    gemlist: &HashSet<&str>,
-    include_on_match: bool,
-) -> std::io::Result<()> {
-    let mut line = String::new();
-
-    loop {
-        line.clear();
-        let n = reader.read_line(&mut line)?;
-        if n == 0 {
-            break; // EOF
-        }
+    mode: SliceMode<'_, S>,
+    version_output: VersionOutput,
+    digest_algorithm: Option<DigestAlgorithm>,
+    rules: LineRules,
+) -> std::io::Result<FilterStats> {
+    let probe = MemoryProbe::start();
+    let mut stats = FilterStats::default();
+    let mut reader = BufReader::new(input);
 
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            continue;
+    // Wrap output in DigestWriter if checksum is requested
+    match digest_algorithm {
+        #[cfg(feature = "digest")]
+        Some(algorithm) => {
+            // Wrap output writer to compute digest as data streams through
+            let mut digest_writer = DigestWriter::new(output, algorithm);
+            execute_filter_pipeline(
+                &mut reader,
+                &mut digest_writer,
+                mode,
+                version_output,
+                rules,
+                &mut stats,
+            )?;
+            // Finalize digest and return hex string
+            stats.digest = Some(digest_writer.finalize());
         }
-
-        // Extract first word (gem name) and check gemlist
-        if let Some(gem_name) = extract_gem_name(trimmed) {
-            let is_in_list = gemlist.contains(gem_name);
-            if is_in_list == include_on_match {
-                output.write_all(line.as_bytes())?;
-            }
+        #[cfg(not(feature = "digest"))]
+        Some(algorithm) => return Err(digest_unsupported(algorithm)),
+        None => {
+            // No digest requested, use output directly
+            execute_filter_pipeline(&mut reader, output, mode, version_output, rules, &mut stats)?;
         }
     }
 
-    Ok(())
+    stats.memory = probe.finish();
+    Ok(stats)
 }
 
-/// Process gems with filtering, stripping version information
-fn process_filtered_stripped<R: Read, W: Write>(
+/// Pass through metadata lines until the first line `separators` takes for "---"
+pub(crate) fn pass_through_metadata<R: Read, W: Write>(
     reader: &mut BufReader<R>,
     output: &mut W,
-    gemlist: &HashSet<&str>,
-    include_on_match: bool,
+    separators: SeparatorRules,
 ) -> std::io::Result<()> {
     let mut line = String::new();
+    let mut first = true;
 
     loop {
         line.clear();
         let n = reader.read_line(&mut line)?;
         if n == 0 {
-            break; // EOF
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "No separator found in versions file",
+            ));
         }
 
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            continue;
-        }
+        let line = if first { strip_bom(&line) } else { &line };
+        first = false;
+        output.write_all(line.as_bytes())?;
 
-        // Extract first word (gem name) and check gemlist
-        if let Some(gem_name) = extract_gem_name(trimmed) {
-            let is_in_list = gemlist.contains(gem_name);
-            if is_in_list == include_on_match {
-                write_gem_line_stripped(trimmed, output)?;
-            }
+        if separators.is_separator(line) {
+            break;
         }
     }
 
     Ok(())
 }
 
-/// Extract gem name (first word) from a gem line
-#[inline]
-fn extract_gem_name(line: &str) -> Option<&str> {
-    line.find(' ').map(|space_pos| &line[..space_pos])
-}
-
 /// Write a gem line with stripped version info
 #[inline]
-fn write_gem_line_stripped<W: Write>(trimmed: &str, output: &mut W) -> std::io::Result<()> {
-    // Parse and reconstruct line: gemname versions md5 [extra...] -> gemname 0 md5 [extra...]
-    let parts: Vec<&str> = trimmed.split_whitespace().collect();
-    if parts.len() >= 3 {
-        // Write: gemname 0 md5 [any additional fields]
-        write!(output, "{} 0", parts[0])?;
-        for part in &parts[2..] {
-            write!(output, " {}", part)?;
-        }
-        writeln!(output)
-    } else {
-        // Fallback for malformed lines - write as-is with newline
-        writeln!(output, "{}", trimmed)
-    }
+pub(crate) fn write_gem_line_stripped<W: Write>(
+    trimmed: &str,
+    output: &mut W,
+) -> std::io::Result<()> {
+    writeln!(
+        output,
+        "{}",
+        StripVersions.transform(Cow::Borrowed(trimmed))
+    )
 }
 
 #[cfg(test)]
@@ -674,6 +608,7 @@ banana 1.0.0 ddd444
         assert_eq!(gem_lines[2], "banana 0 ddd444");
     }
 
+    #[cfg(feature = "digest")]
     #[test]
     fn test_digest_sha256() {
         let input = r#"created_at: 2024-04-01T00:00:05Z
@@ -711,6 +646,7 @@ sinatra 3.0.0 ghi789
         assert!(!result.contains("sinatra"));
     }
 
+    #[cfg(feature = "digest")]
     #[test]
     fn test_digest_sha512() {
         let input = r#"created_at: 2024-04-01T00:00:05Z
@@ -739,6 +675,7 @@ rails 7.0.0 abc123
         assert!(digest_hex.chars().all(|c| c.is_ascii_hexdigit()));
     }
 
+    #[cfg(feature = "digest")]
     #[test]
     fn test_digest_with_strip_versions() {
         let input = r#"created_at: 2024-04-01T00:00:05Z
@@ -780,6 +717,22 @@ sinatra 3.0.0 def456
         assert_ne!(digest.unwrap(), digest2.unwrap());
     }
 
+    #[cfg(not(feature = "digest"))]
+    #[test]
+    fn test_digest_needs_feature() {
+        let mut output = Vec::new();
+        let err = filter_versions_streaming(
+            "---\n".as_bytes(),
+            &mut output,
+            FilterMode::Passthrough,
+            VersionOutput::Preserve,
+            Some(DigestAlgorithm::Sha256),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    #[cfg(feature = "digest")]
     #[test]
     fn test_digest_consistency() {
         let input = r#"created_at: 2024-04-01T00:00:05Z
@@ -811,4 +764,118 @@ rails 7.0.0 abc123
         assert_eq!(digest1, digest2);
         assert_eq!(output1, output2);
     }
+
+    #[test]
+    fn test_filter_with_any_gem_set() {
+        let input =
+            "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\nsinatra 3.0.0 def456\n";
+        let sorted = ["puma", "rails"];
+        let mut output = Vec::new();
+        filter_versions_with_set(
+            input.as_bytes(),
+            &mut output,
+            SliceMode::Allow(&sorted[..]),
+            VersionOutput::Preserve,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n"
+        );
+    }
+
+    /// Counts calls reaching the underlying writer
+    #[cfg(feature = "digest")]
+    #[derive(Default)]
+    struct CountingWriter {
+        writes: usize,
+        bytes: Vec<u8>,
+    }
+
+    #[cfg(feature = "digest")]
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes += 1;
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn test_output_is_written_in_batches() {
+        let mut input = String::from("created_at: 2024-04-01T00:00:05Z\n---\n");
+        for i in 0..5000 {
+            input.push_str(&format!("gem{} 1.0.0,1.0.1 abc123\n", i));
+        }
+
+        for version_output in [VersionOutput::Preserve, VersionOutput::Strip] {
+            let mut output = CountingWriter::default();
+            filter_versions_streaming(
+                input.as_bytes(),
+                &mut output,
+                FilterMode::Passthrough,
+                version_output,
+                Some(DigestAlgorithm::Sha256),
+            )
+            .unwrap();
+
+            // Thousands of lines, but only a handful of writes
+            assert!(output.writes <= output.bytes.len() / (OUTPUT_BATCH / 2) + 1);
+            let last_line = match version_output {
+                VersionOutput::Preserve => "gem4999 1.0.0,1.0.1 abc123\n",
+                VersionOutput::Strip => "gem4999 0 abc123\n",
+            };
+            assert!(output.bytes.ends_with(last_line.as_bytes()));
+        }
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn test_stats_report_buffer_high_water_marks() {
+        // One line far longer than the read buffer has to be carried across reads
+        let long_versions = vec!["1.0.0"; 4000].join(",");
+        let input = format!(
+            "created_at: 2024-04-01T00:00:05Z\n---\nrails {} abc123\nsinatra 3.0.0 ghi789\n",
+            long_versions
+        );
+        let allowlist: HashSet<&str> = ["sinatra"].into_iter().collect();
+
+        let mut output = Vec::new();
+        let stats = filter_versions_with_stats(
+            input.as_bytes(),
+            &mut output,
+            FilterMode::Allow(&allowlist).into(),
+            VersionOutput::Preserve,
+            Some(DigestAlgorithm::Sha256),
+            NamelessLines::Skip,
+        )
+        .unwrap();
+
+        assert_eq!(stats.input_bytes, input.len() as u64);
+        assert_eq!(stats.output_bytes, output.len() as u64);
+        assert!(stats.digest.is_some());
+        assert!(stats.read_buffer_bytes > 0);
+        assert!(stats.peak_carry_bytes > stats.read_buffer_bytes);
+        assert!(stats.peak_carry_bytes < input.len());
+        assert_eq!(stats.peak_batch_bytes, output.len());
+        assert_eq!(stats.memory, None);
+    }
+
+    #[test]
+    fn test_option_names_round_trip() {
+        assert_eq!("SHA-512".parse(), Ok(DigestAlgorithm::Sha512));
+        assert_eq!(DigestAlgorithm::Sha256.to_string(), "sha256");
+        assert_eq!("strip".parse(), Ok(VersionOutput::Strip));
+        assert_eq!("whole-line".parse(), Ok(NamelessLines::WholeLine));
+        assert_eq!(
+            "md5".parse::<DigestAlgorithm>().unwrap_err().to_string(),
+            "Unknown digest algorithm 'md5'. Supported: sha256, sha512"
+        );
+    }
 }
@@ -0,0 +1,333 @@
+//! Per-gem metadata from the RubyGems API, for policy enrichment
+//!
+//! Some policies can't be decided from the versions file alone, like "only
+//! gems with at least N downloads" or "no GPL-3.0 gems". [`GemApiClient`] fetches
+//! `/api/v1/gems/<name>.json` for candidate gems, caching responses on disk
+//! and spacing out requests so repeated runs over the same candidates stay
+//! well inside the API's rate limits. The `select_*` functions turn that
+//! metadata into a list of gems to feed back into the filter as an allowlist.
+
+use crate::mirror::is_safe_file_name;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// API host used when none is configured
+pub const DEFAULT_API_BASE: &str = "https://rubygems.org";
+
+/// How many times a rate-limited (429) request is retried before giving up
+const MAX_RETRIES: u32 = 3;
+
+/// Where to fetch metadata from and how gently
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiOptions {
+    /// Base URL of the RubyGems API, without trailing slash
+    pub api_base: String,
+    /// Directory caching one response per gem; `None` disables caching
+    pub cache_dir: Option<PathBuf>,
+    /// Cached responses older than this are fetched again
+    pub cache_max_age: Duration,
+    /// Minimum time between two requests to the API
+    pub min_interval: Duration,
+}
+
+impl Default for ApiOptions {
+    fn default() -> Self {
+        ApiOptions {
+            api_base: DEFAULT_API_BASE.to_string(),
+            cache_dir: None,
+            cache_max_age: Duration::from_secs(24 * 60 * 60),
+            // rubygems.org allows 10 requests per second
+            min_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+/// The fields of a gem's API record that policies use
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GemMetadata {
+    /// Total downloads across all versions
+    pub downloads: u64,
+    /// License identifiers declared by the latest version (usually SPDX)
+    pub licenses: Vec<String>,
+}
+
+impl GemMetadata {
+    /// Parse an `/api/v1/gems/<name>.json` body; `null` (a cached 404) yields `None`
+    fn parse(body: &[u8]) -> std::io::Result<Option<Self>> {
+        let value: serde_json::Value = serde_json::from_slice(body)?;
+        if value.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(GemMetadata {
+            downloads: value["downloads"].as_u64().unwrap_or(0),
+            licenses: value["licenses"]
+                .as_array()
+                .map(|licenses| {
+                    licenses
+                        .iter()
+                        .filter_map(|l| l.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }))
+    }
+}
+
+/// Thresholds a gem's metadata must meet to be selected
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApiPolicy {
+    /// Minimum total downloads
+    pub min_downloads: Option<u64>,
+    /// Licenses a gem may declare; one match is enough for dual-licensed gems
+    pub allowed_licenses: Option<Vec<String>>,
+}
+
+impl ApiPolicy {
+    /// Whether `metadata` passes every configured threshold
+    pub fn allows(&self, metadata: &GemMetadata) -> bool {
+        if self
+            .min_downloads
+            .is_some_and(|min| metadata.downloads < min)
+        {
+            return false;
+        }
+        // Gems that declare no license fail a license policy rather than slip through
+        if let Some(allowed) = &self.allowed_licenses {
+            // SPDX identifiers are case-insensitive
+            let permitted = metadata
+                .licenses
+                .iter()
+                .any(|license| allowed.iter().any(|a| a.eq_ignore_ascii_case(license)));
+            if !permitted {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Rate-limited, caching client for gem metadata
+pub struct GemApiClient {
+    agent: ureq::Agent,
+    options: ApiOptions,
+    last_request: Option<Instant>,
+    requests: usize,
+}
+
+impl GemApiClient {
+    /// Create a client; the cache directory is created on first write
+    pub fn new(options: ApiOptions) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .build()
+            .into();
+        GemApiClient {
+            agent,
+            options,
+            last_request: None,
+            requests: 0,
+        }
+    }
+
+    /// Number of requests sent to the API so far (cache hits are not counted)
+    pub fn requests(&self) -> usize {
+        self.requests
+    }
+
+    /// Metadata for `name`, or `None` if the API doesn't know the gem
+    pub fn metadata(&mut self, name: &str) -> std::io::Result<Option<GemMetadata>> {
+        if !is_safe_file_name(name) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Refusing to look up gem name '{}'", name),
+            ));
+        }
+
+        let cache_path = self
+            .options
+            .cache_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.json", name)));
+        if let Some(body) = cache_path.as_ref().and_then(|p| self.read_fresh(p)) {
+            return GemMetadata::parse(&body);
+        }
+
+        let body = self.fetch(name)?;
+        if let Some(path) = &cache_path {
+            fs::create_dir_all(path.parent().expect("cache path has a parent"))?;
+            fs::write(path, &body)?;
+        }
+        GemMetadata::parse(&body)
+    }
+
+    /// Cached body for a path if it exists and is younger than the max age
+    fn read_fresh(&self, path: &Path) -> Option<Vec<u8>> {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age > self.options.cache_max_age {
+            return None;
+        }
+        fs::read(path).ok()
+    }
+
+    /// Fetch the raw API body, mapping 404 to `null` so misses can be cached too
+    fn fetch(&mut self, name: &str) -> std::io::Result<Vec<u8>> {
+        let url = format!(
+            "{}/api/v1/gems/{}.json",
+            self.options.api_base.trim_end_matches('/'),
+            name
+        );
+
+        let mut backoff = self.options.min_interval.max(Duration::from_secs(1));
+        for attempt in 0..=MAX_RETRIES {
+            self.wait_for_slot();
+            self.requests += 1;
+
+            let mut response =
+                self.agent.get(&url).call().map_err(|e| {
+                    std::io::Error::other(format!("Failed to fetch {}: {}", url, e))
+                })?;
+            match response.status().as_u16() {
+                200 => {
+                    return response
+                        .body_mut()
+                        .read_to_vec()
+                        .map_err(std::io::Error::other)
+                }
+                404 => return Ok(b"null".to_vec()),
+                429 if attempt < MAX_RETRIES => {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                status => {
+                    return Err(std::io::Error::other(format!(
+                        "Failed to fetch {}: HTTP {}",
+                        url, status
+                    )))
+                }
+            }
+        }
+        unreachable!("the final attempt always returns")
+    }
+
+    /// Sleep until at least `min_interval` has passed since the previous request
+    fn wait_for_slot(&mut self) {
+        if let Some(last) = self.last_request {
+            let elapsed = last.elapsed();
+            if elapsed < self.options.min_interval {
+                thread::sleep(self.options.min_interval - elapsed);
+            }
+        }
+        self.last_request = Some(Instant::now());
+    }
+}
+
+/// Keep the gems whose metadata satisfies `policy`
+///
+/// Gems the API doesn't know are dropped, since they can't be shown to comply.
+pub fn select_gems<'a, I>(
+    names: I,
+    policy: &ApiPolicy,
+    client: &mut GemApiClient,
+) -> std::io::Result<Vec<String>>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    let mut selected = Vec::new();
+    for name in names {
+        if client.metadata(name)?.is_some_and(|m| policy.allows(&m)) {
+            selected.push(name.clone());
+        }
+    }
+    Ok(selected)
+}
+
+/// Keep the gems with at least `min_downloads` downloads
+pub fn select_by_downloads<'a, I>(
+    names: I,
+    min_downloads: u64,
+    client: &mut GemApiClient,
+) -> std::io::Result<Vec<String>>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    let policy = ApiPolicy {
+        min_downloads: Some(min_downloads),
+        ..ApiPolicy::default()
+    };
+    select_gems(names, &policy, client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_metadata() {
+        let body = br#"{"name":"rails","downloads":123456789,"licenses":["MIT"]}"#;
+        assert_eq!(
+            GemMetadata::parse(body).unwrap(),
+            Some(GemMetadata {
+                downloads: 123456789,
+                licenses: vec!["MIT".to_string()],
+            })
+        );
+        assert_eq!(
+            GemMetadata::parse(br#"{"downloads":1,"licenses":null}"#).unwrap(),
+            Some(GemMetadata {
+                downloads: 1,
+                licenses: vec![],
+            })
+        );
+        assert_eq!(GemMetadata::parse(b"null").unwrap(), None);
+        assert!(GemMetadata::parse(b"<html>").is_err());
+    }
+
+    #[test]
+    fn test_fresh_cache_avoids_requests() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("gem-index-filter-api-cache-{}", std::process::id()));
+        fs::create_dir_all(&cache_dir).unwrap();
+        fs::write(cache_dir.join("rails.json"), br#"{"downloads":42}"#).unwrap();
+
+        // The API base is unroutable, so any request would fail the test
+        let mut client = GemApiClient::new(ApiOptions {
+            api_base: "http://127.0.0.1:9".to_string(),
+            cache_dir: Some(cache_dir.clone()),
+            ..ApiOptions::default()
+        });
+        let metadata = client.metadata("rails").unwrap();
+
+        assert_eq!(metadata.map(|m| m.downloads), Some(42));
+        assert_eq!(client.requests(), 0);
+        fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_license_policy() {
+        let policy = ApiPolicy {
+            allowed_licenses: Some(vec!["MIT".to_string(), "Apache-2.0".to_string()]),
+            ..ApiPolicy::default()
+        };
+        let gem = |licenses: &[&str]| GemMetadata {
+            downloads: 0,
+            licenses: licenses.iter().map(|l| l.to_string()).collect(),
+        };
+
+        assert!(policy.allows(&gem(&["mit"])));
+        assert!(policy.allows(&gem(&["GPL-3.0", "Apache-2.0"])));
+        assert!(!policy.allows(&gem(&["GPL-3.0"])));
+        assert!(!policy.allows(&gem(&[])));
+    }
+
+    #[test]
+    fn test_unsafe_names_rejected() {
+        let mut client = GemApiClient::new(ApiOptions::default());
+        assert!(client.metadata("../etc/passwd").is_err());
+        assert_eq!(client.requests(), 0);
+    }
+}
@@ -0,0 +1,135 @@
+//! Checking that a filter leaves its own output unchanged
+//!
+//! Filtering already-filtered output with the same settings should change
+//! nothing: the gems kept are still kept, stripped version lists stay `0`,
+//! and versions that met the constraints still meet them. A transformation
+//! that keeps rewriting its own output is a bug, and [`verify_idempotent`]
+//! is a cheap way to catch one after a run. It reports the first line that
+//! differs as a [`NotIdempotent`] error.
+
+use std::fmt;
+
+/// Re-filtering the output changed it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotIdempotent {
+    /// 1-based number of the first line that differs
+    pub line: usize,
+    /// That line in the output, or `None` if the re-filtered copy is longer
+    pub expected: Option<String>,
+    /// That line after re-filtering, or `None` if it was dropped
+    pub actual: Option<String>,
+}
+
+impl NotIdempotent {
+    /// The difference behind an I/O error returned by [`verify_idempotent`], if any
+    pub fn from_io_error(error: &std::io::Error) -> Option<&NotIdempotent> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for NotIdempotent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |line: &Option<String>| match line {
+            Some(line) => format!("'{}'", line),
+            None => "nothing".to_string(),
+        };
+        write!(
+            f,
+            "Filter is not idempotent: line {} was {}, re-filtered to {}",
+            self.line,
+            show(&self.expected),
+            show(&self.actual)
+        )
+    }
+}
+
+impl std::error::Error for NotIdempotent {}
+
+impl From<NotIdempotent> for std::io::Error {
+    fn from(difference: NotIdempotent) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, difference)
+    }
+}
+
+/// Run `refilter` over `output` and fail unless it writes `output` back unchanged
+///
+/// `refilter` should apply the same mode, rules and version output that
+/// produced `output`. Both copies are held in memory, so this costs about
+/// twice the size of the filtered index.
+pub fn verify_idempotent<F>(output: &[u8], refilter: F) -> std::io::Result<()>
+where
+    F: FnOnce(&[u8], &mut Vec<u8>) -> std::io::Result<()>,
+{
+    let mut again = Vec::with_capacity(output.len());
+    refilter(output, &mut again)?;
+    if again == output {
+        return Ok(());
+    }
+
+    let mut expected = output.split(|&b| b == b'\n');
+    let mut actual = again.split(|&b| b == b'\n');
+    for line in 1.. {
+        let (expected, actual) = (expected.next(), actual.next());
+        if expected != actual {
+            let text = |line: Option<&[u8]>| line.map(|l| String::from_utf8_lossy(l).into_owned());
+            return Err(NotIdempotent {
+                line,
+                expected: text(expected),
+                actual: text(actual),
+            }
+            .into());
+        }
+    }
+    unreachable!("copies that differ have a line that differs")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filter_versions_streaming, FilterMode, VersionOutput};
+
+    const VERSIONS: &str = "created_at: 2024-04-01T00:00:05Z\n---\n\
+        rails 7.0.0,7.1.0 abc123\n\
+        sinatra 3.0.0 def456\n";
+
+    #[test]
+    fn test_strip_versions_is_idempotent() {
+        let strip = |input: &[u8], output: &mut Vec<u8>| {
+            filter_versions_streaming(
+                input,
+                output,
+                FilterMode::Passthrough,
+                VersionOutput::Strip,
+                None,
+            )
+            .map(drop)
+        };
+        let mut stripped = Vec::new();
+        strip(VERSIONS.as_bytes(), &mut stripped).unwrap();
+        verify_idempotent(&stripped, strip).unwrap();
+    }
+
+    #[test]
+    fn test_reports_first_changed_line() {
+        // Drops the first version of every line, so each run loses one more
+        let err = verify_idempotent(VERSIONS.as_bytes(), |input, output| {
+            let text = std::str::from_utf8(input).unwrap();
+            for line in text.lines() {
+                let line = match line.split_once(' ') {
+                    Some((name, rest)) if line.contains(',') => {
+                        format!("{} {}", name, rest.split_once(',').unwrap().1)
+                    }
+                    _ => line.to_string(),
+                };
+                output.extend_from_slice(line.as_bytes());
+                output.push(b'\n');
+            }
+            Ok(())
+        })
+        .unwrap_err();
+
+        let difference = NotIdempotent::from_io_error(&err).unwrap();
+        assert_eq!(difference.line, 3);
+        assert_eq!(difference.actual.as_deref(), Some("rails 7.1.0 abc123"));
+    }
+}
@@ -0,0 +1,263 @@
+//! Concurrent `/info/<gem>` downloads (`http` feature)
+//!
+//! A mirror needs one info file per gem, so a few hundred gems fetched one
+//! after another spend most of the run waiting on round trips. [`InfoFetcher`]
+//! keeps a bounded number of requests in flight, retries the failures that
+//! tend to go away on their own (timeouts, dropped connections, `429` and
+//! `5xx` answers) with exponential backoff, and with an ETag directory asks
+//! upstream only for files that changed since the last run. Each finished
+//! gem is reported to a progress callback as it completes.
+//!
+//! [`build_mirror`](crate::build_mirror) fetches through it, and so can any
+//! other tool that keeps info files up to date.
+
+use crate::fetch::{call_error, FetchError, FetchLimits};
+use crate::file::write_atomically;
+use crate::mirror::{is_safe_file_name, DEFAULT_UPSTREAM};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Where info files come from and how hard to try for each
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InfoFetcher {
+    /// Base URL of the upstream compact index, without trailing slash
+    pub upstream: String,
+    /// Timeouts and size cap for every download
+    pub limits: FetchLimits,
+    /// Most downloads in flight at once
+    pub concurrency: usize,
+    /// Attempts after the first for a download that failed transiently
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after
+    pub backoff: Duration,
+    /// Directory keeping each gem's ETag, for conditional requests
+    ///
+    /// Kept apart from the info files so a static server doesn't publish them.
+    pub etag_dir: Option<PathBuf>,
+}
+
+impl InfoFetcher {
+    /// Fetch from `upstream` with 8 downloads in flight and 2 retries half a second apart
+    pub fn new(upstream: impl Into<String>) -> Self {
+        InfoFetcher {
+            upstream: upstream.into(),
+            limits: FetchLimits::default(),
+            concurrency: 8,
+            retries: 2,
+            backoff: Duration::from_millis(500),
+            etag_dir: None,
+        }
+    }
+
+    /// Fetch `/info/<name>` for each of `names` into `info_dir`
+    ///
+    /// Files are written through a temporary name and renamed into place.
+    /// The first gem that still fails after its retries fails the run: no
+    /// new downloads start, and the error is returned once those in flight
+    /// finish. Names that aren't safe file names are refused before anything
+    /// is fetched.
+    pub fn fetch_all<S, P>(
+        &self,
+        names: &[S],
+        info_dir: &Path,
+        progress: P,
+    ) -> std::io::Result<InfoStats>
+    where
+        S: AsRef<str> + Sync,
+        P: Fn(&InfoProgress) + Sync,
+    {
+        if let Some(name) = names.iter().find(|name| !is_safe_file_name(name.as_ref())) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Refusing to write info file for gem name '{}'",
+                    name.as_ref()
+                ),
+            ));
+        }
+        fs::create_dir_all(info_dir)?;
+        if let Some(dir) = &self.etag_dir {
+            fs::create_dir_all(dir)?;
+        }
+
+        let agent = self.limits.agent(false);
+        let next = AtomicUsize::new(0);
+        let done = AtomicUsize::new(0);
+        let not_modified = AtomicUsize::new(0);
+        let retries = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let error = Mutex::new(None);
+
+        std::thread::scope(|scope| {
+            for _ in 0..self.concurrency.clamp(1, names.len().max(1)) {
+                scope.spawn(|| {
+                    while !failed.load(Ordering::Relaxed) {
+                        let Some(name) = names.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                            return;
+                        };
+                        let name = name.as_ref();
+                        match self.fetch_one(&agent, name, info_dir, &retries) {
+                            Ok(outcome) => {
+                                if outcome == InfoOutcome::NotModified {
+                                    not_modified.fetch_add(1, Ordering::Relaxed);
+                                }
+                                progress(&InfoProgress {
+                                    name,
+                                    outcome,
+                                    done: done.fetch_add(1, Ordering::Relaxed) + 1,
+                                    total: names.len(),
+                                });
+                            }
+                            Err(e) => {
+                                failed.store(true, Ordering::Relaxed);
+                                error.lock().unwrap().get_or_insert(e);
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        if let Some(e) = error.into_inner().unwrap() {
+            return Err(e);
+        }
+        let not_modified = not_modified.into_inner();
+        Ok(InfoStats {
+            fetched: names.len() - not_modified,
+            not_modified,
+            retries: retries.into_inner(),
+        })
+    }
+
+    /// One gem's download, retried while it fails transiently
+    fn fetch_one(
+        &self,
+        agent: &ureq::Agent,
+        name: &str,
+        info_dir: &Path,
+        retries: &AtomicUsize,
+    ) -> std::io::Result<InfoOutcome> {
+        let mut attempt = 0;
+        loop {
+            match self.attempt(agent, name, info_dir) {
+                Ok(outcome) => return Ok(outcome),
+                Err(Failure::Transient(_)) if attempt < self.retries => {
+                    std::thread::sleep(self.backoff.saturating_mul(1 << attempt.min(16)));
+                    retries.fetch_add(1, Ordering::Relaxed);
+                    attempt += 1;
+                }
+                Err(Failure::Transient(e) | Failure::Permanent(e)) => return Err(e),
+            }
+        }
+    }
+
+    fn attempt(
+        &self,
+        agent: &ureq::Agent,
+        name: &str,
+        info_dir: &Path,
+    ) -> Result<InfoOutcome, Failure> {
+        let url = format!("{}/info/{}", self.upstream.trim_end_matches('/'), name);
+        let path = info_dir.join(name);
+        let etag_path = self.etag_dir.as_ref().map(|dir| dir.join(name));
+
+        let mut request = agent.get(&url);
+        // An ETag only counts while the file it describes is still there
+        if let Some(etag_path) = etag_path.as_ref().filter(|_| path.exists()) {
+            if let Ok(etag) = fs::read_to_string(etag_path) {
+                request = request.header("If-None-Match", etag.trim());
+            }
+        }
+        let response = request
+            .call()
+            .map_err(|e| Failure::Transient(call_error(&url, e)))?;
+
+        let status = response.status().as_u16();
+        match status {
+            200 => {}
+            304 if path.exists() => return Ok(InfoOutcome::NotModified),
+            _ => {
+                let error =
+                    std::io::Error::other(format!("Failed to fetch {}: HTTP {}", url, status));
+                return Err(match status {
+                    429 | 500..=599 => Failure::Transient(error),
+                    _ => Failure::Permanent(error),
+                });
+            }
+        }
+
+        let etag = response
+            .headers()
+            .get("etag")
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let mut body = self
+            .limits
+            .body(&url, response)
+            .map_err(Failure::Permanent)?;
+        if let Some(etag_path) = &etag_path {
+            // Without the file it names, a stale ETag would turn the next change into a 304
+            let _ = fs::remove_file(etag_path);
+        }
+        write_atomically(&path, |output| std::io::copy(&mut body, output).map(drop)).map_err(
+            |e| match FetchError::from_io_error(&e) {
+                Some(FetchError::TimedOut { .. }) | None => Failure::Transient(e),
+                Some(_) => Failure::Permanent(e),
+            },
+        )?;
+        if let (Some(etag_path), Some(etag)) = (&etag_path, etag) {
+            fs::write(etag_path, etag).map_err(Failure::Permanent)?;
+        }
+        Ok(InfoOutcome::Fetched)
+    }
+}
+
+impl Default for InfoFetcher {
+    /// [`InfoFetcher::new`] for [`DEFAULT_UPSTREAM`]
+    fn default() -> Self {
+        InfoFetcher::new(DEFAULT_UPSTREAM)
+    }
+}
+
+/// What happened to one gem's info file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfoOutcome {
+    /// Downloaded and written
+    Fetched,
+    /// Upstream answered `304 Not Modified`, so the local copy was kept
+    NotModified,
+}
+
+/// One finished gem, as reported to the progress callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InfoProgress<'a> {
+    /// The gem whose info file is done
+    pub name: &'a str,
+    /// Whether it was downloaded
+    pub outcome: InfoOutcome,
+    /// Gems done so far, this one included
+    pub done: usize,
+    /// Gems in the run
+    pub total: usize,
+}
+
+/// Totals of a completed [`InfoFetcher::fetch_all`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InfoStats {
+    /// Info files downloaded and written
+    pub fetched: usize,
+    /// Info files upstream reported unchanged
+    pub not_modified: usize,
+    /// Retries across all gems
+    pub retries: usize,
+}
+
+/// Why an attempt failed, and whether trying again could help
+enum Failure {
+    Transient(std::io::Error),
+    Permanent(std::io::Error),
+}
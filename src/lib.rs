@@ -12,6 +12,58 @@
 //! - **Fast filtering**: Uses HashSet for O(1) gem name lookups
 //! - **Version stripping**: Optionally replace version lists with `0` to reduce size
 //! - **Digest computation**: Optionally compute checksums (SHA-256, SHA-512) of filtered output
+//! - **File API**: [`filter_file`] filters one path into another through a temporary file
+//!   renamed into place, with `.gz` support (`gzip` feature)
+//! - **Chunked input**: Push-based filtering for hosts that deliver input chunk by chunk
+//! - **Time budgets**: Runs that stop at a wall-clock budget with a checkpoint to resume from,
+//!   for hosts with hard execution limits
+//! - **Async streams**: Filtered output as a `Stream` of `Bytes` from any `AsyncBufRead`
+//!   (`stream` feature)
+//! - **axum responses**: Filtered streams served with `Content-Type`, `Content-Length`, `ETag`
+//!   and digest headers (`server-support` feature)
+//! - **NDJSON output**: One JSON record per kept gem line, written in the same pass
+//! - **Protobuf output**: Length-delimited messages with binary MD5s, plus a reader for them
+//!   (`protobuf` feature)
+//! - **Parsing**: Typed, allocation-free entries streamed from a versions file ([`parser`])
+//! - **Diffing**: Compare two versions files and report appended lines, new and removed gems
+//! - **Patching**: Ship a small delta and apply it to a local copy instead of re-downloading
+//! - **Binary deltas**: A compact encoding of the same delta, plus snapshots that apply to any base
+//! - **Append-only updates**: Emit only the newly appended matching lines for an existing filtered file,
+//!   or just the lines of gems an allowlist edit added
+//! - **Mirror building**: Fetch and filter `versions`, `names` and `info/*` into a static directory
+//!   (`http` feature), with info files fetched concurrently, retried and revalidated by ETag
+//! - **API enrichment**: Popularity and license policies from RubyGems API metadata (`http` feature)
+//! - **Patterns**: Glob (and, with the `regex` feature, regex) list entries expanded to exact
+//!   names ahead of time, keeping lookups O(1)
+//! - **Unicode names**: NFC-normalized matching, or rejection of non-ASCII names, so a
+//!   differently encoded name can't slip past a blocklist (`unicode` feature)
+//! - **Policy files**: One `policy.toml` combining allow/block patterns with version constraints,
+//!   yank, prerelease, platform and max-versions rules, and a JSON Schema to validate them against
+//! - **Filter plugins**: Custom [`GemFilter`]s composed with the built-in ones in a
+//!   [`FilterPipeline`], and [`LineTransform`]s rewriting the lines it keeps
+//! - **List sources**: Allowlists from files, an HTTP policy service, an S3 object (`s3` feature), a Redis set
+//!   (`redis` feature) or a SQLite/Postgres table (`db` feature), refreshed while running
+//! - **Fetch limits**: Timeouts, a size cap and an HTML check on every download (`http` feature)
+//! - **Truncation detection**: Fail on inputs cut short (no final newline, partial last line,
+//!   fewer bytes than `Content-Length`) instead of publishing a partial index
+//! - **Anomaly warnings**: Repeated gem lines, shrinking version lists and `created_at`
+//!   regressions that point at upstream corruption, as a pass or inline in a pipeline
+//! - **Typo checks**: List entries that name no gem but nearly name one (`rest_client`)
+//! - **Policy simulation**: Compare what two policies would keep before rolling one out
+//! - **Idempotency checks**: Re-filter the output with the same settings and fail if it changes
+//! - **Mirror verification**: Check the compact-index invariants Bundler relies on
+//! - **Attestation**: in-toto provenance statements recording input, output and config digests
+//! - **SBOM**: CycloneDX listing of every gem a filtered index offers
+//! - **Signing**: Detached Ed25519 signatures of filtered output (`signing` feature)
+//! - **Compile-time allowlists**: `phf::Set` gem sets (`phf` feature), or a list baked in at
+//!   build time (`baked-allowlist` feature)
+//! - **Run statistics**: Byte counts and buffer high-water marks per run, plus allocation counts
+//!   and peak heap growth with the `instrument` feature's counting allocator
+//! - **`no_std` core**: [`slice`] filters byte slices with only `alloc` (disable the `std` feature)
+//! - **WebAssembly**: `filterVersions` bindings for Cloudflare Workers and other edge runtimes
+//!   (`wasm` feature)
+//! - **Cloudflare Workers**: Ready-made fetch handler streaming `/versions` through the filter
+//!   (`worker` feature)
 //!
 //! # Examples
 //!
@@ -43,7 +95,7 @@
 //! filter_versions_streaming(input, &mut output, FilterMode::Block(&blocklist), VersionOutput::Preserve, None).unwrap();
 //! ```
 //!
-//! **With digest computation**:
+//! **With digest computation** (`digest` feature, on by default):
 //!
 //! ```no_run
 //! # use gem_index_filter::{filter_versions_streaming, FilterMode, VersionOutput, DigestAlgorithm};
@@ -62,6 +114,193 @@
 //! }
 //! ```
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod anomaly;
+#[cfg(feature = "std")]
+pub mod attest;
+#[cfg(feature = "baked-allowlist")]
+pub mod baked;
+#[cfg(feature = "std")]
+pub mod chunked;
+#[cfg(feature = "std")]
+pub mod dates;
+#[cfg(feature = "digest")]
+pub mod delta;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod ext;
+#[cfg(feature = "http")]
+pub mod fetch;
+#[cfg(feature = "std")]
+pub mod file;
+#[cfg(feature = "std")]
 pub mod filter;
+#[cfg(feature = "http")]
+pub mod gem_api;
+#[cfg(feature = "std")]
+pub mod idempotence;
+#[cfg(feature = "http")]
+pub mod info;
+#[cfg(feature = "std")]
+pub mod lists;
+#[cfg(feature = "digest")]
+pub mod manifest;
+#[cfg(feature = "http")]
+pub mod mirror;
+#[cfg(feature = "std")]
+pub mod names;
+#[cfg(feature = "unicode")]
+pub mod normalize;
+#[cfg(feature = "std")]
+pub mod parser;
+#[cfg(feature = "digest")]
+pub mod patch;
+#[cfg(feature = "std")]
+pub mod pattern;
+#[cfg(feature = "digest")]
+pub mod pin;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod policy;
+#[cfg(feature = "protobuf")]
+pub mod proto;
+#[cfg(feature = "server-support")]
+pub mod response;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "std")]
+pub mod sbom;
+#[cfg(feature = "std")]
+pub mod setops;
+#[cfg(feature = "signing")]
+pub mod sign;
+#[cfg(feature = "std")]
+pub mod simulate;
+#[cfg(feature = "std")]
+pub mod sizes;
+pub mod slice;
+#[cfg(feature = "std")]
+pub mod source;
+#[cfg(feature = "specs")]
+pub mod specs;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "stream")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod truncation;
+#[cfg(feature = "std")]
+pub mod typos;
+#[cfg(feature = "std")]
+pub mod update;
+#[cfg(feature = "digest")]
+pub mod verify;
+#[cfg(feature = "std")]
+pub mod version;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "worker")]
+pub mod worker;
+#[cfg(feature = "std")]
+pub mod writers;
 
-pub use filter::{filter_versions_streaming, DigestAlgorithm, FilterMode, VersionOutput};
+#[cfg(feature = "std")]
+pub use anomaly::{analyze_versions, Anomaly, AnomalyDetector};
+#[cfg(feature = "std")]
+pub use attest::{ArtifactMeta, Provenance};
+#[cfg(feature = "std")]
+pub use chunked::{ChunkFilter, RunOutcome};
+#[cfg(feature = "std")]
+pub use dates::{DateWindow, ReleaseDate, ReleaseDates};
+#[cfg(feature = "digest")]
+pub use delta::{apply_delta, write_delta, write_snapshot};
+#[cfg(feature = "std")]
+pub use diff::{diff_versions, VersionsDiff};
+#[cfg(feature = "std")]
+pub use ext::{FilterGems, GemIndexReadExt};
+#[cfg(feature = "http")]
+pub use fetch::{FetchError, FetchLimits};
+#[cfg(feature = "std")]
+pub use file::{filter_file, filter_versions_with_options, FilterOptions};
+#[cfg(feature = "std")]
+pub use filter::{
+    filter_versions_streaming, filter_versions_with_set, filter_versions_with_stats,
+    DigestAlgorithm, FilterMode,
+};
+#[cfg(feature = "std")]
+pub use idempotence::{verify_idempotent, NotIdempotent};
+#[cfg(feature = "http")]
+pub use info::{InfoFetcher, InfoOutcome, InfoProgress, InfoStats};
+#[cfg(feature = "std")]
+pub use lists::{GemList, RuleHit, RuleHits};
+#[cfg(feature = "digest")]
+pub use manifest::{check_line_manifest, write_line_manifest, LineMismatch};
+#[cfg(all(feature = "http", feature = "signing"))]
+pub use mirror::SignedManifest;
+#[cfg(feature = "http")]
+pub use mirror::{build_mirror, build_mirror_with_progress, MirrorOptions, MirrorStats};
+#[cfg(feature = "std")]
+pub use names::{collect_gem_names, count_gem_lines, write_names};
+#[cfg(feature = "unicode")]
+pub use normalize::{NonAscii, NormalizedSet};
+#[cfg(feature = "std")]
+pub use parser::{Entry, GemLine, VersionEntry, VersionsReader};
+#[cfg(feature = "digest")]
+pub use patch::{apply_patch, write_patch};
+#[cfg(feature = "std")]
+pub use pattern::{CachedPatternList, PatternList, DECISION_CACHE_SIZE};
+#[cfg(feature = "digest")]
+pub use pin::{ChecksumMismatch, PinnedReader};
+#[cfg(feature = "regex")]
+pub use pipeline::LineRegex;
+#[cfg(feature = "std")]
+pub use pipeline::{FilterPipeline, GemFilter, LineTransform, StripVersions};
+#[cfg(feature = "std")]
+pub use policy::{filter_with_rules, GemSelection, Policy, VersionRules};
+#[cfg(feature = "protobuf")]
+pub use proto::{ProtoReader, ProtoWriter};
+#[cfg(feature = "server-support")]
+pub use response::FilteredResponse;
+#[cfg(feature = "std")]
+pub use setops::{intersect_versions, subtract_versions};
+#[cfg(feature = "signing")]
+pub use sign::SigningWriter;
+#[cfg(feature = "std")]
+pub use simulate::{simulate_policies, PolicyTotals, Simulation};
+#[cfg(feature = "std")]
+pub use sizes::{size_report, GemSize, SizeReport};
+pub use slice::{
+    filter_slice, Checkpoint, FilterError, GemSet, Incomplete, LineErrors, NamelessLines,
+    RepeatedSeparator, SeparatorMatch, SeparatorRules, SkippedLine, SkippedLines, SliceFilter,
+    SliceMode, VersionOutput,
+};
+#[cfg(feature = "db")]
+pub use source::DbSource;
+#[cfg(feature = "http")]
+pub use source::HttpSource;
+#[cfg(feature = "redis")]
+pub use source::RedisSource;
+#[cfg(feature = "std")]
+pub use source::{parse_gem_list, AllowlistSource, FileSource};
+#[cfg(feature = "std")]
+pub use stats::{FilterStats, MemoryStats};
+#[cfg(feature = "stream")]
+pub use stream::{filter_on_thread, filter_stream};
+#[cfg(feature = "std")]
+pub use truncation::{CompleteReader, Truncated};
+#[cfg(feature = "std")]
+pub use typos::{near_misses, NearMiss};
+#[cfg(feature = "std")]
+pub use update::{append_allowed_gems, append_new_lines, AllowlistUpdate, UpdateOutcome};
+#[cfg(feature = "digest")]
+pub use verify::{verify_mirror, VerifyReport, Violation};
+#[cfg(feature = "digest")]
+pub use writers::UploadChecksums;
+#[cfg(feature = "std")]
+pub use writers::{CanonicalWriter, CountingWriter, NdjsonWriter, TeeWriter};
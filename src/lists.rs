@@ -0,0 +1,381 @@
+//! Loading `--allow`/`--block` list files
+//!
+//! On top of one name per line and `#` comment lines, list files may use:
+//!
+//! ```text
+//! rails            # inline comments after whitespace
+//! aws-sdk-*        # globs and /regex/ entries, see crate::pattern
+//! !aws-sdk-legacy  # negation: never listed, whatever else matches it
+//! include team.txt # another list, relative to this file
+//! ```
+//!
+//! Negations win regardless of where they appear, so an included file can't
+//! re-add a gem the including file excludes. Gem names can't contain `#`,
+//! `!` or spaces, so none of this changes the meaning of a plain list.
+//!
+//! Long lists collect entries nothing matches any more. [`RuleHits`] counts
+//! the lines each entry matched during a run, so dead ones can be pruned.
+
+use crate::pattern::{DecisionCache, GemPattern, PatternList};
+use crate::pipeline::GemFilter;
+use crate::slice::strip_bom;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A loaded gem list: listed entries minus negated ones
+#[derive(Debug, Clone, Default)]
+pub struct GemList {
+    entries: PatternList,
+    negated: PatternList,
+}
+
+impl GemList {
+    /// True when expanding needs no index, because no entry is a pattern
+    ///
+    /// Negated patterns don't count: they are checked against names the
+    /// listed entries already produced.
+    pub fn is_exact(&self) -> bool {
+        self.entries.is_exact()
+    }
+
+    /// Whether `name` is listed and not negated
+    pub fn matches(&self, name: &str) -> bool {
+        self.entries.matches(name) && !self.negated.matches(name)
+    }
+
+    /// Entries listed by exact name, in no particular order
+    pub fn exact_entries(&self) -> impl Iterator<Item = &str> {
+        self.entries.exact().iter().map(String::as_str)
+    }
+
+    /// Resolve to an exact set, expanding patterns against `index`
+    ///
+    /// See [`PatternList::expand`]; `index` is only read when
+    /// [`is_exact`](Self::is_exact) is false.
+    pub fn expand<R: Read>(self, index: R) -> std::io::Result<HashSet<String>> {
+        let mut names = self.entries.expand(index)?;
+        names.retain(|name| !self.negated.matches(name));
+        Ok(names)
+    }
+}
+
+impl GemList {
+    /// Listed entries with no negations, such as a policy's `allow` or `block` array
+    pub fn from_entries<I: IntoIterator<Item = String>>(entries: I) -> std::io::Result<Self> {
+        Ok(GemList {
+            entries: PatternList::parse(entries)?,
+            negated: PatternList::default(),
+        })
+    }
+
+    /// A counter of the lines matching each entry, to register on a
+    /// [`FilterPipeline`](crate::FilterPipeline)
+    pub fn rule_hits(&self) -> RuleHits {
+        RuleHits {
+            listed: EntryCounts::new(&self.entries),
+            negated: EntryCounts::new(&self.negated),
+        }
+    }
+}
+
+/// How many gem lines one list entry matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleHit {
+    /// The entry as written, with a leading `!` for negations
+    pub entry: String,
+    /// Gem lines whose name matched the entry
+    pub lines: u64,
+}
+
+/// Per-entry match counts for a [`GemList`]
+///
+/// Registered on a pipeline, it passes every line on unchanged and counts
+/// it against each entry its gem name matches, whether or not other filters
+/// keep the line. A name can match an exact entry and several patterns, and
+/// counts for each. Register it first so it sees every input line.
+#[derive(Debug)]
+pub struct RuleHits {
+    listed: EntryCounts,
+    negated: EntryCounts,
+}
+
+impl RuleHits {
+    /// Every entry with its count, most matched first, then by entry
+    pub fn report(&self) -> Vec<RuleHit> {
+        let mut hits = self.listed.hits("");
+        hits.extend(self.negated.hits("!"));
+        hits.sort_unstable_by(|a, b| b.lines.cmp(&a.lines).then_with(|| a.entry.cmp(&b.entry)));
+        hits
+    }
+
+    /// Entries that matched no line, sorted
+    pub fn unmatched(&self) -> Vec<String> {
+        let mut dead: Vec<String> = self
+            .report()
+            .into_iter()
+            .filter(|hit| hit.lines == 0)
+            .map(|hit| hit.entry)
+            .collect();
+        dead.sort_unstable();
+        dead
+    }
+}
+
+impl GemFilter for &RuleHits {
+    fn filter<'a>(&self, name: &str, line: Cow<'a, str>) -> Option<Cow<'a, str>> {
+        self.listed.record(name);
+        self.negated.record(name);
+        Some(line)
+    }
+}
+
+/// Counters for the entries of one [`PatternList`]
+#[derive(Debug)]
+struct EntryCounts {
+    exact: HashMap<String, Cell<u64>>,
+    patterns: Vec<(GemPattern, Cell<u64>)>,
+    /// Indexes into `patterns` of the ones each recent name matched, shared
+    /// rather than copied on a hit (`Arc` keeps the counters `Send`)
+    matched: DecisionCache<Arc<[usize]>>,
+}
+
+impl EntryCounts {
+    fn new(list: &PatternList) -> Self {
+        EntryCounts {
+            exact: list
+                .exact()
+                .iter()
+                .map(|name| (name.clone(), Cell::new(0)))
+                .collect(),
+            patterns: list
+                .patterns()
+                .iter()
+                .map(|pattern| (pattern.clone(), Cell::new(0)))
+                .collect(),
+            matched: DecisionCache::new(),
+        }
+    }
+
+    fn record(&self, name: &str) {
+        let bump = |count: &Cell<u64>| count.set(count.get() + 1);
+        if let Some(count) = self.exact.get(name) {
+            bump(count);
+        }
+        if self.patterns.is_empty() {
+            return;
+        }
+        let matched = self.matched.decide(name, || {
+            (self.patterns.iter().enumerate())
+                .filter(|(_, (pattern, _))| pattern.matches(name))
+                .map(|(i, _)| i)
+                .collect()
+        });
+        for &i in matched.iter() {
+            bump(&self.patterns[i].1);
+        }
+    }
+
+    fn hits(&self, prefix: &str) -> Vec<RuleHit> {
+        let exact = self.exact.iter().map(|(name, count)| RuleHit {
+            entry: format!("{}{}", prefix, name),
+            lines: count.get(),
+        });
+        let patterns = self.patterns.iter().map(|(pattern, count)| RuleHit {
+            entry: format!("{}{}", prefix, pattern),
+            lines: count.get(),
+        });
+        exact.chain(patterns).collect()
+    }
+}
+
+impl From<HashSet<String>> for GemList {
+    /// A list of exact names, such as one fetched through an `AllowlistSource`
+    fn from(names: HashSet<String>) -> Self {
+        GemList {
+            entries: PatternList::from(names),
+            negated: PatternList::default(),
+        }
+    }
+}
+
+/// Load a list from `reader`, resolving includes against the working directory
+pub fn load<R: BufRead>(reader: R) -> std::io::Result<GemList> {
+    let mut loader = Loader::default();
+    loader.read(reader, Path::new(""))?;
+    loader.finish()
+}
+
+/// Load the list file at `path`, resolving includes against its directory
+pub fn load_file(path: impl AsRef<Path>) -> std::io::Result<GemList> {
+    let mut loader = Loader::default();
+    loader.include(path.as_ref())?;
+    loader.finish()
+}
+
+/// Entries gathered across a file and everything it includes
+#[derive(Default)]
+struct Loader {
+    entries: Vec<String>,
+    negated: Vec<String>,
+    /// Files currently being read, innermost last, to catch include cycles
+    including: Vec<PathBuf>,
+}
+
+impl Loader {
+    fn read<R: BufRead>(&mut self, reader: R, dir: &Path) -> std::io::Result<()> {
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = if index == 0 { strip_bom(&line) } else { &line };
+            let entry = strip_comment(line).trim();
+            if entry.is_empty() {
+                continue;
+            }
+
+            if let Some(("include", path)) = entry.split_once(char::is_whitespace) {
+                self.include(&dir.join(path.trim()))?;
+            } else if let Some(name) = entry.strip_prefix('!') {
+                self.negated.push(name.trim().to_string());
+            } else {
+                self.entries.push(entry.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn include(&mut self, path: &Path) -> std::io::Result<()> {
+        let with_path =
+            |e: std::io::Error| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+        let canonical = path.canonicalize().map_err(with_path)?;
+        if self.including.contains(&canonical) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: include cycle", path.display()),
+            ));
+        }
+
+        let file = File::open(path).map_err(with_path)?;
+        self.including.push(canonical);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        self.read(BufReader::new(file), dir)?;
+        self.including.pop();
+        Ok(())
+    }
+
+    fn finish(self) -> std::io::Result<GemList> {
+        Ok(GemList {
+            entries: PatternList::parse(self.entries)?,
+            negated: PatternList::parse(self.negated)?,
+        })
+    }
+}
+
+/// Cut a line at the first `#` that starts it or follows whitespace
+fn strip_comment(line: &str) -> &str {
+    let bytes = line.as_bytes();
+    let start = (0..bytes.len())
+        .find(|&i| bytes[i] == b'#' && (i == 0 || bytes[i - 1].is_ascii_whitespace()));
+    match start {
+        Some(start) => &line[..start],
+        None => line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const INDEX: &str = "created_at: 2024-04-01T00:00:05Z\n---\n\
+        aws-sdk-s3 1.0.0 a\n\
+        aws-sdk-legacy 1.0.0 b\n\
+        rails 7.0.0 c\n";
+
+    fn sorted(names: HashSet<String>) -> Vec<String> {
+        let mut names: Vec<String> = names.into_iter().collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_inline_comments_negation_and_patterns() {
+        let list =
+            load("rails # web\naws-sdk-*\n  !aws-sdk-legacy  # old\n!puma\npuma\n".as_bytes())
+                .unwrap();
+        assert!(!list.is_exact());
+        assert!(list.matches("aws-sdk-s3"));
+        assert!(!list.matches("aws-sdk-legacy"));
+        assert!(!list.matches("puma"));
+
+        assert_eq!(
+            sorted(list.expand(INDEX.as_bytes()).unwrap()),
+            vec!["aws-sdk-s3", "rails"]
+        );
+    }
+
+    #[test]
+    fn test_rule_hits_count_lines_per_entry() {
+        use crate::{FilterPipeline, VersionOutput};
+
+        let list = load("rails\naws-sdk-*\n!aws-sdk-legacy\npuma\n".as_bytes()).unwrap();
+        let hits = list.rule_hits();
+        let index = format!("{}rails 7.0.1 d\n", INDEX);
+        FilterPipeline::new()
+            .with(&hits)
+            .filter_versions(
+                index.as_bytes(),
+                &mut std::io::sink(),
+                VersionOutput::Preserve,
+                None,
+            )
+            .unwrap();
+
+        let report: Vec<(String, u64)> = hits
+            .report()
+            .into_iter()
+            .map(|hit| (hit.entry, hit.lines))
+            .collect();
+        assert_eq!(
+            report,
+            vec![
+                ("aws-sdk-*".to_string(), 2),
+                ("rails".to_string(), 2),
+                ("!aws-sdk-legacy".to_string(), 1),
+                ("puma".to_string(), 0),
+            ]
+        );
+        assert_eq!(hits.unmatched(), vec!["puma"]);
+    }
+
+    #[test]
+    fn test_includes_resolve_relative_to_the_file() {
+        let dir =
+            std::env::temp_dir().join(format!("gem-index-filter-lists-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("teams")).unwrap();
+        fs::write(
+            dir.join("allow.txt"),
+            "rails\ninclude teams/web.txt\n!puma\n",
+        )
+        .unwrap();
+        fs::write(dir.join("teams/web.txt"), "sinatra\npuma\n").unwrap();
+
+        let list = load_file(dir.join("allow.txt")).unwrap();
+        assert!(list.is_exact());
+        assert_eq!(
+            sorted(list.expand(std::io::empty()).unwrap()),
+            vec!["rails", "sinatra"]
+        );
+
+        // A file including itself, directly or not, is an error rather than a hang
+        fs::write(dir.join("teams/web.txt"), "include ../allow.txt\n").unwrap();
+        let err = load_file(dir.join("allow.txt")).unwrap_err();
+        assert!(err.to_string().contains("include cycle"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,24 +1,95 @@
-use gem_index_filter::filter::filter_versions_streaming;
-use gem_index_filter::{DigestAlgorithm, FilterMode, VersionOutput};
+use gem_index_filter::attest::{
+    format_timestamp, ArtifactMeta, ConfigDigest, HashingReader, Provenance,
+};
+use gem_index_filter::delta::is_delta;
+use gem_index_filter::policy::{GemSelection, Policy, VersionRules};
+use gem_index_filter::writers::DigestWriter;
+use gem_index_filter::{
+    apply_delta, apply_patch, diff_versions, filter_versions_with_stats, lists, verify_idempotent,
+    write_delta, write_line_manifest, write_patch, write_snapshot, CanonicalWriter, CompleteReader,
+    DateWindow, DigestAlgorithm, FilterMode, FilterPipeline, GemList, NamelessLines, NdjsonWriter,
+    ReleaseDate, ReleaseDates, RuleHits, TeeWriter, UploadChecksums, VersionOutput,
+};
 use std::collections::HashSet;
 use std::env;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// `--allow` value naming the allowlist compiled into the binary
+#[cfg(feature = "baked-allowlist")]
+const BAKED_LIST: &str = "baked:";
+
+/// Filter flags that take no value
+const SWITCHES: &[&str] = &[
+    "--strip-versions",
+    "--attest",
+    "--line-manifest",
+    "--require-complete",
+    "--canonical",
+    "--verify",
+    "--output-gzip",
+    "--meta",
+    "--upload-checksums",
+];
 
 fn main() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
 
+    // Subcommands take their own arguments, so dispatch before parsing filter flags
+    match args.get(1).map(String::as_str) {
+        Some("diff") => return run_diff(&args[2..]),
+        Some("patch") => return run_patch(&args[2..]),
+        #[cfg(feature = "http")]
+        Some("mirror") => return run_mirror(&args[2..]),
+        Some("verify") => return run_verify(&args[2..]),
+        Some("sbom") => return run_sbom(&args[2..]),
+        Some("gems") => return run_gems(&args[2..]),
+        Some("sizes") => return run_sizes(&args[2..]),
+        Some("simulate") => return run_simulate(&args[2..]),
+        Some("config") => return run_config(&args[2..]),
+        Some("update-allowlist") => return run_update_allowlist(&args[2..]),
+        Some("typos") => return run_typos(&args[2..]),
+        Some(operation @ ("intersect" | "subtract")) => return run_setop(operation, &args[2..]),
+        #[cfg(feature = "specs")]
+        Some("specs") => return run_specs(&args[2..]),
+        #[cfg(feature = "http")]
+        Some("enrich") => return run_enrich(&args[2..]),
+        _ => {}
+    }
+
     // Parse flags
     let version_output = if args.iter().any(|arg| arg == "--strip-versions") {
         VersionOutput::Strip
     } else {
         VersionOutput::Preserve
     };
+    let attest = args.iter().any(|arg| arg == "--attest");
+    let line_manifest = args.iter().any(|arg| arg == "--line-manifest");
+    let require_complete = args.iter().any(|arg| arg == "--require-complete");
+    let canonical = args.iter().any(|arg| arg == "--canonical");
+    let verify = args.iter().any(|arg| arg == "--verify");
+    let output_gzip = args.iter().any(|arg| arg == "--output-gzip");
+    let meta = args.iter().any(|arg| arg == "--meta");
+    let upload_checksums = args.iter().any(|arg| arg == "--upload-checksums");
 
     // Find --allow, --block, and --digest flags and extract their values
     let mut allowlist_file: Option<&str> = None;
     let mut blocklist_file: Option<&str> = None;
     let mut digest_algorithm: Option<DigestAlgorithm> = None;
+    let mut sign_key_source: Option<&str> = None;
+    let mut names_file: Option<&str> = None;
+    let mut policy_file: Option<&str> = None;
+    let mut grep_pattern: Option<&str> = None;
+    let mut rule_hits_file: Option<&str> = None;
+    let mut release_dates_file: Option<&str> = None;
+    let mut released_since: Option<&str> = None;
+    let mut released_until: Option<&str> = None;
+    let mut format: Option<&str> = None;
+    let mut nameless_lines: Option<&str> = None;
+    // Everything that is neither a flag nor a flag's value, in order
+    let mut positional_args: Vec<&String> = Vec::new();
     let mut i = 1; // Start after program name
     while i < args.len() {
         if args[i] == "--allow" {
@@ -39,15 +110,10 @@ fn main() -> io::Result<()> {
             }
         } else if args[i] == "--digest" {
             if i + 1 < args.len() {
-                let algo_str = args[i + 1].to_lowercase();
-                digest_algorithm = match algo_str.as_str() {
-                    "sha256" | "sha-256" => Some(DigestAlgorithm::Sha256),
-                    "sha512" | "sha-512" => Some(DigestAlgorithm::Sha512),
-                    _ => {
-                        eprintln!(
-                            "Error: Unknown digest algorithm '{}'. Supported: sha256, sha512",
-                            args[i + 1]
-                        );
+                digest_algorithm = match args[i + 1].parse() {
+                    Ok(algorithm) => Some(algorithm),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
                         std::process::exit(1);
                     }
                 };
@@ -56,37 +122,124 @@ fn main() -> io::Result<()> {
                 eprintln!("Error: --digest requires an algorithm (sha256, sha512)");
                 std::process::exit(1);
             }
+        } else if args[i] == "--sign-key" {
+            if i + 1 < args.len() {
+                sign_key_source = Some(&args[i + 1]);
+                i += 2;
+            } else {
+                eprintln!("Error: --sign-key requires a key file path or env:VAR");
+                std::process::exit(1);
+            }
+        } else if args[i] == "--names" {
+            if i + 1 < args.len() {
+                names_file = Some(&args[i + 1]);
+                i += 2;
+            } else {
+                eprintln!("Error: --names requires a file path");
+                std::process::exit(1);
+            }
+        } else if args[i] == "--policy" {
+            if i + 1 < args.len() {
+                policy_file = Some(&args[i + 1]);
+                i += 2;
+            } else {
+                eprintln!("Error: --policy requires a file path");
+                std::process::exit(1);
+            }
+        } else if args[i] == "--grep" {
+            if i + 1 < args.len() {
+                grep_pattern = Some(&args[i + 1]);
+                i += 2;
+            } else {
+                eprintln!("Error: --grep requires a regular expression");
+                std::process::exit(1);
+            }
+        } else if args[i] == "--rule-hits" {
+            if i + 1 < args.len() {
+                rule_hits_file = Some(&args[i + 1]);
+                i += 2;
+            } else {
+                eprintln!("Error: --rule-hits requires a file path");
+                std::process::exit(1);
+            }
+        } else if args[i] == "--release-dates" {
+            if i + 1 < args.len() {
+                release_dates_file = Some(&args[i + 1]);
+                i += 2;
+            } else {
+                eprintln!("Error: --release-dates requires a file path");
+                std::process::exit(1);
+            }
+        } else if args[i] == "--format" {
+            if i + 1 < args.len() {
+                format = Some(&args[i + 1]);
+                i += 2;
+            } else {
+                eprintln!("Error: --format requires text, ndjson or protobuf");
+                std::process::exit(1);
+            }
+        } else if args[i] == "--nameless-lines" {
+            if i + 1 < args.len() {
+                nameless_lines = Some(&args[i + 1]);
+                i += 2;
+            } else {
+                eprintln!("Error: --nameless-lines requires skip, keep, whole-line or error");
+                std::process::exit(1);
+            }
+        } else if args[i] == "--released-since" || args[i] == "--released-until" {
+            if i + 1 < args.len() {
+                if args[i] == "--released-since" {
+                    released_since = Some(&args[i + 1]);
+                } else {
+                    released_until = Some(&args[i + 1]);
+                }
+                i += 2;
+            } else {
+                eprintln!("Error: {} requires a date (YYYY-MM-DD)", args[i]);
+                std::process::exit(1);
+            }
         } else {
+            // Switches were read above
+            if !SWITCHES.contains(&args[i].as_str()) {
+                positional_args.push(&args[i]);
+            }
             i += 1;
         }
     }
 
-    // Get positional arguments (excluding program name and flags)
-    let digest_arg = digest_algorithm
-        .as_ref()
-        .map(|_| {
-            args.iter()
-                .position(|a| a == "--digest")
-                .and_then(|i| args.get(i + 1))
-        })
-        .flatten();
-
-    let positional_args: Vec<&String> = args
-        .iter()
-        .skip(1)
-        .filter(|arg| {
-            *arg != "--strip-versions"
-                && *arg != "--allow"
-                && *arg != "--block"
-                && *arg != "--digest"
-                && !allowlist_file.map_or(false, |f| *arg == f)
-                && !blocklist_file.map_or(false, |f| *arg == f)
-                && !digest_arg.map_or(false, |d| *arg == d)
-        })
-        .collect();
-
     if positional_args.is_empty() {
         eprintln!("Usage: gem-index-filter [OPTIONS] <versions-file> [output-file]");
+        eprintln!(
+            "       gem-index-filter diff [--patch | --delta] <old-file> <new-file> [output-file]"
+        );
+        eprintln!("       gem-index-filter diff --snapshot <new-file> <output-file>");
+        eprintln!("       gem-index-filter patch <base-file> <patch-file> [output-file]");
+        eprintln!("       gem-index-filter mirror [--allow <file>] [--block <file>] --dest <dir>");
+        eprintln!("       gem-index-filter verify [--previous <versions-file>] <mirror-dir>");
+        eprintln!("       gem-index-filter sbom [--versions] [--name <name>] <versions-file> [output-file]");
+        eprintln!("       gem-index-filter enrich [--min-downloads <n>] [--licenses <list>] <versions-file> [output-file]");
+        eprintln!();
+        eprintln!("Commands:");
+        eprintln!("  diff                 Compare two versions files and report what changed");
+        eprintln!("  patch                Apply a patch, delta or snapshot created by 'diff'");
+        eprintln!("  mirror               Build a static Bundler mirror (versions, names, info/*)");
+        eprintln!("  verify               Check a mirror directory for compact-index consistency");
+        eprintln!("  sbom                 Write a CycloneDX SBOM of the gems in a versions file");
+        eprintln!("  gems                 List the distinct gem names in a versions file");
+        eprintln!("  sizes                Rank gems by the space their lines take");
+        eprintln!("  simulate             Compare what two policies would keep");
+        eprintln!("  config schema        Print the JSON Schema for policy files");
+        eprintln!("  update-allowlist     Apply an allowlist edit to a filtered file, appending");
+        eprintln!("                       when the edit only adds gems");
+        eprintln!("  typos                List entries that nearly match a gem in a versions file");
+        eprintln!("  intersect            Keep lines whose gem also appears in a second file");
+        eprintln!("  subtract             Keep lines whose gem doesn't appear in a second file");
+        eprintln!(
+            "  specs                Filter a legacy specs.4.8.gz index (needs the specs feature)"
+        );
+        eprintln!(
+            "  enrich               Write an allowlist of gems meeting RubyGems API thresholds"
+        );
         eprintln!();
         eprintln!("Arguments:");
         eprintln!("  <versions-file>   Path to the versions file (or - for stdin)");
@@ -94,11 +247,54 @@ fn main() -> io::Result<()> {
         eprintln!();
         eprintln!("Options:");
         eprintln!(
-            "  --allow <file>       Filter to only gems in allowlist file (one name per line)"
+            "  --allow <file|url>   Filter to only gems in allowlist file (one name per line)"
+        );
+        eprintln!("  --block <file|url>   Filter out gems in blocklist file (one name per line)");
+        eprintln!(
+            "                       (url: http(s)://, or s3://bucket/key with the s3 feature)"
         );
-        eprintln!("  --block <file>       Filter out gems in blocklist file (one name per line)");
         eprintln!("  --strip-versions     Replace version lists with '0' in output");
         eprintln!("  --digest <algorithm> Compute checksum of filtered output (sha256, sha512)");
+        eprintln!("  --sign-key <source>  Write an Ed25519 signature to <output-file>.sig");
+        eprintln!("                       (source: key file or env:VAR, hex-encoded 32-byte seed)");
+        eprintln!("  --attest             Write an in-toto provenance statement to <output-file>.intoto.json");
+        eprintln!(
+            "  --line-manifest      Write per-line MD5/SHA-256 checksums to <output-file>.lines"
+        );
+        eprintln!("  --meta               Write the output's digest, size and header to <output-file>.meta");
+        eprintln!(
+            "  --names <file>       Names or versions file to expand list patterns (aws-sdk-*)"
+        );
+        eprintln!("                       against (default: the input versions file)");
+        eprintln!("  --policy <file>      Apply a policy.toml (gem patterns plus version rules)");
+        eprintln!("                       instead of --allow/--block");
+        eprintln!("  --grep <regex>       Keep only gem lines matching the regex anywhere in the");
+        eprintln!("                       line (needs the regex feature)");
+        eprintln!("  --rule-hits <file>   Write how many lines each allow/block entry matched");
+        eprintln!("  --release-dates <file>  'name version YYYY-MM-DD' lines giving release dates");
+        eprintln!("  --released-since <date> Drop versions released before the date (inclusive)");
+        eprintln!("  --released-until <date> Drop versions released after the date (inclusive)");
+        eprintln!("                       Versions missing from --release-dates are kept");
+        eprintln!(
+            "  --nameless-lines <choice>  What to do with gem lines without a space, in every"
+        );
+        eprintln!("                       mode: skip (default), keep, whole-line (match the whole");
+        eprintln!("                       line as the name) or error");
+        eprintln!("  --require-complete   Fail if the input looks truncated (no final newline or");
+        eprintln!("                       an incomplete last line)");
+        eprintln!("  --canonical          Normalize the output: LF line endings, no blank lines,");
+        eprintln!("                       trimmed lines and sorted header lines");
+        eprintln!("  --verify             Re-filter the output and fail unless it is unchanged");
+        eprintln!("  --output-gzip        Gzip the output (gzipped input is detected by itself;");
+        eprintln!("                       both need the gzip feature)");
+        eprintln!(
+            "  --format <format>    text (default), ndjson (one JSON record per gem line) or"
+        );
+        eprintln!("                       protobuf (length-delimited messages, protobuf feature)");
+        eprintln!("  --upload-checksums   Print Content-MD5 and x-amz-checksum-sha256 values of");
+        eprintln!(
+            "                       the bytes written, for an S3 upload to be verified against"
+        );
         eprintln!();
         eprintln!("Examples:");
         eprintln!("  gem-index-filter versions.txt                                      # Pass through all gems");
@@ -108,7 +304,11 @@ fn main() -> io::Result<()> {
         eprintln!(
             "  gem-index-filter --strip-versions versions.txt filtered.txt        # Strip versions"
         );
+        eprintln!("  gem-index-filter --policy policy.toml versions.txt filtered.txt    # Apply a policy file");
         eprintln!("  gem-index-filter --digest sha256 versions.txt filtered.txt         # Compute SHA-256 checksum");
+        eprintln!(
+            "  gem-index-filter --sign-key env:SIGNING_KEY versions.txt filtered.txt # Sign output"
+        );
         eprintln!(
             "  curl https://rubygems.org/versions | facet --allow allowlist.txt - > filtered.txt"
         );
@@ -118,14 +318,725 @@ fn main() -> io::Result<()> {
     let versions_file = positional_args[0].as_str();
     let output_file = positional_args.get(1).map(|s| s.as_str());
 
-    // Read filter lists if specified
-    let allowlist_owned = allowlist_file.map(read_gem_list).transpose()?;
-    let blocklist_owned = blocklist_file.map(read_gem_list).transpose()?;
+    if sign_key_source.is_some() && output_file.is_none() {
+        eprintln!("Error: --sign-key requires an output file to write the signature next to");
+        std::process::exit(1);
+    }
+    if attest && output_file.is_none() {
+        eprintln!("Error: --attest requires an output file to write the statement next to");
+        std::process::exit(1);
+    }
+    if line_manifest && output_file.is_none() {
+        eprintln!("Error: --line-manifest requires an output file to write the manifest next to");
+        std::process::exit(1);
+    }
+    if meta && output_file.is_none() {
+        eprintln!("Error: --meta requires an output file to write the description next to");
+        std::process::exit(1);
+    }
+    if verify && output_file.is_none() {
+        eprintln!("Error: --verify requires an output file to re-read");
+        std::process::exit(1);
+    }
+    let nameless = match nameless_lines.map(str::parse::<NamelessLines>) {
+        None => NamelessLines::default(),
+        Some(Ok(nameless)) => nameless,
+        Some(Err(e)) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let output_format = match format {
+        None | Some("text") => OutputFormat::Text,
+        Some("ndjson") => OutputFormat::Ndjson,
+        #[cfg(feature = "protobuf")]
+        Some("protobuf") => OutputFormat::Protobuf,
+        #[cfg(not(feature = "protobuf"))]
+        Some("protobuf") => {
+            eprintln!("Error: --format protobuf needs the protobuf feature");
+            std::process::exit(1);
+        }
+        Some(other) => {
+            eprintln!(
+                "Error: unknown --format '{}' (expected text, ndjson or protobuf)",
+                other
+            );
+            std::process::exit(1);
+        }
+    };
+    if output_format != OutputFormat::Text && (canonical || line_manifest || meta || verify) {
+        eprintln!(
+            "Error: --format {} can't be combined with --canonical, --line-manifest,",
+            format.unwrap_or_default()
+        );
+        eprintln!("       --meta or --verify, which expect a versions file");
+        std::process::exit(1);
+    }
+    if output_format != OutputFormat::Text
+        && matches!(nameless, NamelessLines::Keep | NamelessLines::WholeLine)
+    {
+        eprintln!(
+            "Error: --format {} can't be combined with --nameless-lines {}: records need",
+            format.unwrap_or_default(),
+            nameless
+        );
+        eprintln!("       a name, versions and MD5");
+        std::process::exit(1);
+    }
+    if output_gzip && (sign_key_source.is_some() || attest || line_manifest || meta || verify) {
+        eprintln!("Error: --output-gzip can't be combined with --sign-key, --attest,");
+        eprintln!(
+            "       --line-manifest, --meta or --verify, which work on the uncompressed output"
+        );
+        std::process::exit(1);
+    }
+    // Locked-down builds only ever allow what was compiled in
+    #[cfg(feature = "baked-allowlist")]
+    let allowlist_file = match (allowlist_file, policy_file) {
+        (None | Some(BAKED_LIST), None) => Some(BAKED_LIST),
+        _ => {
+            eprintln!(
+                "Error: this build has a baked-in allowlist; --allow and --policy are disabled"
+            );
+            std::process::exit(1);
+        }
+    };
+    if policy_file.is_some() && (allowlist_file.is_some() || blocklist_file.is_some()) {
+        eprintln!("Error: --policy replaces --allow and --block; put the lists in the policy");
+        std::process::exit(1);
+    }
+
+    // Counted against the entries as written, before patterns are expanded
+    let rule_hits = match rule_hits_file {
+        Some(_) => load_rule_hits(allowlist_file, blocklist_file, policy_file)?,
+        None => Vec::new(),
+    };
+
+    // Patterns can be resolved against the input itself when it can be read twice
+    let names_file = names_file.or((versions_file != "-").then_some(versions_file));
+    let (filter_set_owned, rules, version_output, allowlist_file, blocklist_file) =
+        match policy_file {
+            Some(path) => {
+                let policy = Policy::load(path)?;
+                let version_output = if policy.strip_versions {
+                    VersionOutput::Strip
+                } else {
+                    version_output
+                };
+                // The policy stands in for whichever list flag it replaces
+                let (set, allow, block) = match load_policy_set(&policy, names_file)? {
+                    GemSelection::All => (None, None, None),
+                    GemSelection::Allow(gems) => (Some(gems), Some(path), None),
+                    GemSelection::Block(gems) => (Some(gems), None, Some(path)),
+                };
+                (set, policy.versions, version_output, allow, block)
+            }
+            None => (
+                load_filter_set(allowlist_file, blocklist_file, names_file)?,
+                VersionRules::new(),
+                version_output,
+                allowlist_file,
+                blocklist_file,
+            ),
+        };
+    let filter_set_refs = borrow_filter_set(&filter_set_owned);
+    let mode = filter_mode(filter_set_refs.as_ref(), allowlist_file, blocklist_file);
+    let (window, release_dates_sha256) =
+        match load_date_window(release_dates_file, released_since, released_until)? {
+            Some((window, sha256)) => (Some(window), Some(sha256)),
+            None => (None, None),
+        };
+    let line_filters = LineFilters {
+        rule_hits: &rule_hits,
+        grep: grep_pattern,
+        window,
+        canonical,
+        format: output_format,
+        nameless,
+    };
+    // Everything that shapes the output, for the attestation and `--meta`
+    let mut config = ConfigDigest::new(mode, version_output).version_rules(&rules);
+    if let Some(pattern) = grep_pattern {
+        config = config.setting("grep", pattern);
+    }
+    if let Some(sha256) = &release_dates_sha256 {
+        config = config
+            .setting("release_dates", sha256)
+            .setting("released_since", released_since.unwrap_or_default())
+            .setting("released_until", released_until.unwrap_or_default());
+    }
+    if nameless != NamelessLines::default() {
+        config = config.setting("nameless_lines", nameless);
+    }
+    if canonical {
+        config = config.setting("canonical", true);
+    }
+    if output_format != OutputFormat::Text {
+        config = config.setting("format", format.unwrap_or_default());
+    }
+    let config_sha256 = config.finalize();
+
+    // Open input, hashing it on the way through when attesting
+    let mut input = open_input(versions_file)?;
+    if require_complete {
+        input = Box::new(CompleteReader::new(input));
+    }
+    let mut input = HashingReader::new(input);
+
+    // Stream and filter
+    // Checksums of the bytes actually written, compression included, for S3 to verify
+    let mut checksums = upload_checksums.then(UploadChecksums::new);
+    if let Some(output_path) = output_file {
+        let mut file = File::create(output_path)?;
+        let mut output = TeeWriter::new().with(&mut file);
+        if let Some(checksums) = &mut checksums {
+            output.push(checksums);
+        }
+        let digest = match sign_key_source {
+            Some(key_source) => {
+                filter_and_sign(&mut output, output_path, key_source, |mut signed| {
+                    filter_lines(
+                        &mut input,
+                        &mut signed,
+                        mode,
+                        &rules,
+                        &line_filters,
+                        version_output,
+                        digest_algorithm,
+                    )
+                })?
+            }
+            None if output_gzip => filter_gzipped(&mut output, |mut gzipped| {
+                filter_lines(
+                    &mut input,
+                    &mut gzipped,
+                    mode,
+                    &rules,
+                    &line_filters,
+                    version_output,
+                    digest_algorithm,
+                )
+            })?,
+            None => filter_lines(
+                &mut input,
+                &mut output,
+                mode,
+                &rules,
+                &line_filters,
+                version_output,
+                digest_algorithm,
+            )?,
+        };
+        drop(output);
+        eprintln!("Written to {}", output_path);
+        if let Some(checksum) = digest {
+            eprintln!("{}: {}", digest_algorithm.unwrap().name(), checksum);
+        }
+        print_upload_checksums(checksums);
+        if attest {
+            write_attestation(input, output_path, &config_sha256)?;
+        }
+        if line_manifest {
+            write_manifest(output_path)?;
+        }
+        if meta {
+            write_meta(output_path, &config_sha256)?;
+        }
+        if verify {
+            // Same filters without the hit counters, which would count every line twice
+            let recheck = LineFilters {
+                rule_hits: &[],
+                grep: line_filters.grep,
+                window: line_filters.window.clone(),
+                canonical: line_filters.canonical,
+                format: OutputFormat::Text,
+                nameless: line_filters.nameless,
+            };
+            let output = std::fs::read(output_path)?;
+            verify_idempotent(&output, |input, again| {
+                filter_lines(input, again, mode, &rules, &recheck, version_output, None).map(drop)
+            })?;
+            eprintln!("Verified: re-filtering {} leaves it unchanged", output_path);
+        }
+    } else {
+        let mut output = TeeWriter::new().with(io::stdout());
+        if let Some(checksums) = &mut checksums {
+            output.push(checksums);
+        }
+        let digest = if output_gzip {
+            filter_gzipped(&mut output, |mut gzipped| {
+                filter_lines(
+                    &mut input,
+                    &mut gzipped,
+                    mode,
+                    &rules,
+                    &line_filters,
+                    version_output,
+                    digest_algorithm,
+                )
+            })?
+        } else {
+            filter_lines(
+                &mut input,
+                &mut output,
+                mode,
+                &rules,
+                &line_filters,
+                version_output,
+                digest_algorithm,
+            )?
+        };
+        drop(output);
+        if let Some(checksum) = digest {
+            eprintln!("{}: {}", digest_algorithm.unwrap().name(), checksum);
+        }
+        print_upload_checksums(checksums);
+    }
+    if let Some(path) = rule_hits_file {
+        write_rule_hits(path, &rule_hits)?;
+    }
+
+    Ok(())
+}
+
+/// Line filters and options beyond the mode and version rules
+struct LineFilters<'a> {
+    /// `--rule-hits` counters, labelled by the list they count
+    rule_hits: &'a [(&'static str, RuleHits)],
+    /// `--grep` pattern
+    grep: Option<&'a str>,
+    /// `--released-since`/`--released-until` over `--release-dates`
+    window: Option<DateWindow>,
+    /// `--canonical`: normalize whatever the filters write
+    canonical: bool,
+    /// `--format`: versions lines, or the records they convert to
+    format: OutputFormat,
+    /// `--nameless-lines`: gem lines without a space
+    nameless: NamelessLines,
+}
+
+/// What `--format` asks the filtered lines to be written as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Ndjson,
+    #[cfg(feature = "protobuf")]
+    Protobuf,
+}
+
+/// The mode and version rules, adding the `--grep` and release date filters,
+/// `--nameless-lines`, `--canonical` and `--format ndjson`/`protobuf` when given
+fn filter_lines<R: io::Read, W: io::Write>(
+    input: R,
+    output: &mut W,
+    mode: FilterMode,
+    rules: &VersionRules,
+    extra: &LineFilters,
+    version_output: VersionOutput,
+    digest_algorithm: Option<DigestAlgorithm>,
+) -> io::Result<Option<String>> {
+    let filters = |input, mut output: &mut dyn io::Write| {
+        run_filters(input, &mut output, mode, rules, extra, version_output, None)
+    };
+    match extra.format {
+        OutputFormat::Text if !extra.canonical => run_filters(
+            input,
+            output,
+            mode,
+            rules,
+            extra,
+            version_output,
+            digest_algorithm,
+        ),
+        // The digest has to cover the canonical bytes, so it is taken after normalizing
+        OutputFormat::Text => filter_converted(
+            input,
+            output,
+            CanonicalWriter::new,
+            digest_algorithm,
+            filters,
+        ),
+        OutputFormat::Ndjson => {
+            filter_converted(input, output, NdjsonWriter::new, digest_algorithm, filters)
+        }
+        #[cfg(feature = "protobuf")]
+        OutputFormat::Protobuf => filter_converted(
+            input,
+            output,
+            gem_index_filter::ProtoWriter::new,
+            digest_algorithm,
+            filters,
+        ),
+    }
+}
+
+/// A writer converting the filtered lines, which holds back an unterminated
+/// last line until it is finished
+trait FinishWrite<W>: io::Write {
+    /// Convert what is held back, returning the inner writer
+    fn finish(self) -> io::Result<W>;
+}
+
+impl<W: io::Write> FinishWrite<W> for CanonicalWriter<W> {
+    fn finish(self) -> io::Result<W> {
+        CanonicalWriter::finish(self)
+    }
+}
+
+impl<W: io::Write> FinishWrite<W> for NdjsonWriter<W> {
+    fn finish(self) -> io::Result<W> {
+        NdjsonWriter::finish(self)
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<W: io::Write> FinishWrite<W> for gem_index_filter::ProtoWriter<W> {
+    fn finish(self) -> io::Result<W> {
+        gem_index_filter::ProtoWriter::finish(self)
+    }
+}
+
+/// The output, hashed on the way through when a digest was asked for
+enum DigestSink<W: io::Write> {
+    Plain(W),
+    Hashed(Box<DigestWriter<W>>),
+}
+
+impl<W: io::Write> io::Write for DigestSink<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            DigestSink::Plain(output) => output.write(buf),
+            DigestSink::Hashed(output) => output.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            DigestSink::Plain(output) => output.flush(),
+            DigestSink::Hashed(output) => output.flush(),
+        }
+    }
+}
+
+/// `filters` writing into the converter `convert` builds, with the digest
+/// taken over what the converter writes
+fn filter_converted<'o, R: io::Read, W: io::Write, C: FinishWrite<DigestSink<&'o mut W>>>(
+    input: R,
+    output: &'o mut W,
+    convert: impl FnOnce(DigestSink<&'o mut W>) -> C,
+    digest_algorithm: Option<DigestAlgorithm>,
+    filters: impl FnOnce(R, &mut dyn io::Write) -> io::Result<Option<String>>,
+) -> io::Result<Option<String>> {
+    let sink = match digest_algorithm {
+        Some(algorithm) => DigestSink::Hashed(Box::new(DigestWriter::new(output, algorithm))),
+        None => DigestSink::Plain(output),
+    };
+    let mut converted = convert(sink);
+    filters(input, &mut converted)?;
+    Ok(match converted.finish()? {
+        DigestSink::Plain(_) => None,
+        DigestSink::Hashed(digest) => Some(digest.finalize()),
+    })
+}
+
+/// The plain streaming filter, or a pipeline when anything needs one
+fn run_filters<R: io::Read, W: io::Write>(
+    input: R,
+    output: &mut W,
+    mode: FilterMode,
+    rules: &VersionRules,
+    extra: &LineFilters,
+    version_output: VersionOutput,
+    digest_algorithm: Option<DigestAlgorithm>,
+) -> io::Result<Option<String>> {
+    if extra.rule_hits.is_empty()
+        && extra.grep.is_none()
+        && extra.window.is_none()
+        && rules.is_noop()
+    {
+        return filter_versions_with_stats(
+            input,
+            output,
+            mode.into(),
+            version_output,
+            digest_algorithm,
+            extra.nameless,
+        )
+        .map(|stats| stats.digest);
+    }
+    let mut pipeline = FilterPipeline::new().nameless_lines(extra.nameless);
+    // Count before anything drops the line
+    for (_, hits) in extra.rule_hits {
+        pipeline.register(hits);
+    }
+    pipeline.register(mode);
+    if let Some(pattern) = extra.grep {
+        // Match before the rules rewrite version lists, so --grep sees the upstream line
+        #[cfg(feature = "regex")]
+        pipeline.register(gem_index_filter::LineRegex::new(pattern)?);
+        #[cfg(not(feature = "regex"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "--grep {} requires gem-index-filter to be built with the regex feature",
+                pattern
+            ),
+        ));
+    }
+    if let Some(window) = &extra.window {
+        pipeline.register(window);
+    }
+    pipeline
+        .with(rules.clone())
+        .filter_versions(input, output, version_output, digest_algorithm)
+}
+
+/// Counters for `--rule-hits`, one for each list in use
+fn load_rule_hits(
+    allowlist_file: Option<&str>,
+    blocklist_file: Option<&str>,
+    policy_file: Option<&str>,
+) -> io::Result<Vec<(&'static str, RuleHits)>> {
+    let lists = match policy_file {
+        Some(path) => {
+            let policy = Policy::load(path)?;
+            let allow = policy.allow.map(GemList::from_entries).transpose()?;
+            vec![
+                ("allow", allow),
+                ("block", Some(GemList::from_entries(policy.block)?)),
+            ]
+        }
+        None => vec![
+            ("allow", allowlist_file.map(read_gem_list).transpose()?),
+            ("block", blocklist_file.map(read_gem_list).transpose()?),
+        ],
+    };
+    let hits: Vec<(&'static str, RuleHits)> = lists
+        .into_iter()
+        .filter_map(|(label, list)| Some((label, list?.rule_hits())))
+        .collect();
+    if hits.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--rule-hits needs --allow, --block or --policy",
+        ));
+    }
+    Ok(hits)
+}
+
+/// Write each list's `--rule-hits` report as `<lines> <entry>` lines under a `# <list>` heading
+fn write_rule_hits(path: &str, rule_hits: &[(&str, RuleHits)]) -> io::Result<()> {
+    use std::io::Write;
+
+    let mut output = io::BufWriter::new(File::create(path)?);
+    let mut unmatched = 0;
+    for (label, hits) in rule_hits {
+        writeln!(output, "# {}", label)?;
+        for hit in hits.report() {
+            writeln!(output, "{} {}", hit.lines, hit.entry)?;
+        }
+        unmatched += hits.unmatched().len();
+    }
+    output.flush()?;
+    eprintln!(
+        "Rule hits written to {} ({} entries matched no line)",
+        path, unmatched
+    );
+    Ok(())
+}
+
+/// Build the release date window from the `--release-dates` and `--released-*` flags
+///
+/// Returns the SHA-256 of the dates file with the window, for the config digest.
+fn load_date_window(
+    dates_file: Option<&str>,
+    since: Option<&str>,
+    until: Option<&str>,
+) -> io::Result<Option<(DateWindow, String)>> {
+    let parse = |flag: &str, date: Option<&str>| -> io::Result<Option<ReleaseDate>> {
+        date.map(|date| {
+            ReleaseDate::parse(date).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} expects a date as YYYY-MM-DD, got '{}'", flag, date),
+                )
+            })
+        })
+        .transpose()
+    };
+    let (since, until) = (
+        parse("--released-since", since)?,
+        parse("--released-until", until)?,
+    );
+    match dates_file {
+        Some(path) => {
+            let mut file = HashingReader::new(open_input(path)?);
+            let dates = ReleaseDates::load(io::BufReader::new(&mut file))
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+            Ok(Some((
+                DateWindow::new(dates, since, until),
+                file.finalize(),
+            )))
+        }
+        None if since.is_some() || until.is_some() => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--released-since and --released-until need --release-dates",
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Write `<output_path>.lines` listing the checksums of each gem line in `output_path`
+fn write_manifest(output_path: &str) -> io::Result<()> {
+    use std::io::Write;
+
+    let manifest_path = format!("{}.lines", output_path);
+    let mut manifest = io::BufWriter::new(File::create(&manifest_path)?);
+    write_line_manifest(File::open(output_path)?, &mut manifest)?;
+    manifest.flush()?;
+    eprintln!("Line manifest written to {}", manifest_path);
+    Ok(())
+}
+
+/// Print `--upload-checksums` as header lines
+fn print_upload_checksums(checksums: Option<UploadChecksums>) {
+    for (name, value) in checksums.into_iter().flat_map(UploadChecksums::headers) {
+        eprintln!("{}: {}", name, value);
+    }
+}
+
+/// Describe the written output in `<output>.meta` for `--meta`
+fn write_meta(output_path: &str, config_sha256: &str) -> io::Result<()> {
+    let meta = ArtifactMeta::read(
+        artifact_name(output_path),
+        File::open(output_path)?,
+        config_sha256.to_string(),
+        format_timestamp(SystemTime::now()),
+    )?;
+    let meta_path = format!("{}.meta", output_path);
+    let mut file = File::create(&meta_path)?;
+    meta.write_json(&mut file)?;
+    eprintln!("Metadata written to {}", meta_path);
+    Ok(())
+}
+
+/// The file name of `output_path`, for naming it inside sidecar files
+fn artifact_name(output_path: &str) -> String {
+    Path::new(output_path).file_name().map_or_else(
+        || output_path.to_string(),
+        |n| n.to_string_lossy().into_owned(),
+    )
+}
+
+/// Write `<output_path>.intoto.json` describing the run that produced `output_path`
+fn write_attestation<R: io::Read>(
+    input: HashingReader<R>,
+    output_path: &str,
+    config_sha256: &str,
+) -> io::Result<()> {
+    // Hash the output from disk rather than threading another writer through the filter
+    let mut output_hash = HashingReader::new(File::open(output_path)?);
+    io::copy(&mut output_hash, &mut io::sink())?;
+
+    let provenance = Provenance {
+        artifact_name: artifact_name(output_path),
+        input_sha256: input.finalize(),
+        output_sha256: output_hash.finalize(),
+        config_sha256: config_sha256.to_string(),
+        timestamp: format_timestamp(SystemTime::now()),
+    };
+
+    let statement_path = format!("{}.intoto.json", output_path);
+    let mut statement = File::create(&statement_path)?;
+    provenance.write_statement(&mut statement)?;
+    eprintln!("Attestation written to {}", statement_path);
+    Ok(())
+}
+
+/// Filter into `output` while signing it, writing the signature to `<output_path>.sig`
+///
+/// `filter` writes the filtered index to the writer it is given and returns its digest.
+#[cfg(feature = "signing")]
+fn filter_and_sign(
+    output: &mut TeeWriter,
+    output_path: &str,
+    key_source: &str,
+    filter: impl FnOnce(&mut dyn io::Write) -> io::Result<Option<String>>,
+) -> io::Result<Option<String>> {
+    use gem_index_filter::sign::{parse_signing_key, public_key_hex};
+    use gem_index_filter::SigningWriter;
+
+    // Keys come from a file or, for CI secrets, an environment variable
+    let key_hex = match key_source.strip_prefix("env:") {
+        Some(var) => env::var(var).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Environment variable {} is not set", var),
+            )
+        })?,
+        None => std::fs::read_to_string(key_source)?,
+    };
+    let key = parse_signing_key(&key_hex)?;
+
+    let mut signing_writer = SigningWriter::new(output);
+    let digest = filter(&mut signing_writer)?;
+    let signature = signing_writer.sign(&key);
+
+    let signature_path = format!("{}.sig", output_path);
+    std::fs::write(&signature_path, format!("{}\n", signature))?;
+    eprintln!(
+        "Signature written to {} (public key {})",
+        signature_path,
+        public_key_hex(&key)
+    );
+
+    Ok(digest)
+}
 
-    // Determine filter mode with preprocessing optimization:
-    // If both allow and block are specified, preprocess by removing blocked gems from allowlist
-    // This reduces to just 2 runtime modes: Allow or Block (or Passthrough)
-    let filter_set_owned: Option<HashSet<String>> = match (allowlist_owned, blocklist_owned) {
+#[cfg(not(feature = "signing"))]
+fn filter_and_sign(
+    _output: &mut TeeWriter,
+    _output_path: &str,
+    _key_source: &str,
+    _filter: impl FnOnce(&mut dyn io::Write) -> io::Result<Option<String>>,
+) -> io::Result<Option<String>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--sign-key requires gem-index-filter to be built with the signing feature",
+    ))
+}
+
+/// Read --allow/--block lists and preprocess them into a single filter set
+///
+/// If both allow and block are specified, blocked gems are removed from the
+/// allowlist up front. This reduces to just 2 runtime modes: Allow or Block
+/// (or Passthrough). Pattern entries are expanded against `names_file` first,
+/// so the filter itself only ever sees exact names.
+fn load_filter_set(
+    allowlist_file: Option<&str>,
+    blocklist_file: Option<&str>,
+    names_file: Option<&str>,
+) -> io::Result<Option<HashSet<String>>> {
+    let load = |path: &str| -> io::Result<HashSet<String>> {
+        let list = read_gem_list(path)?;
+        if list.is_exact() {
+            return list.expand(io::empty());
+        }
+        let Some(names_file) = names_file else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} contains patterns; pass --names <file> to expand them",
+                    path
+                ),
+            ));
+        };
+        list.expand(open_input(names_file)?)
+    };
+    let allowlist_owned = allowlist_file.map(load).transpose()?;
+    let blocklist_owned = blocklist_file.map(load).transpose()?;
+
+    let filter_set_owned = match (allowlist_owned, blocklist_owned) {
         (Some(mut allow), Some(block)) => {
             // Optimization: allowlist - blocklist, then use Allow mode
             let original_count = allow.len();
@@ -149,62 +1060,1038 @@ fn main() -> io::Result<()> {
         (None, None) => None,
     };
 
-    // Create the filter mode by converting String references to &str
-    // Keep owned set and converted set separate to manage lifetimes
-    let filter_set_refs: Option<HashSet<&str>> = filter_set_owned
+    Ok(filter_set_owned)
+}
+
+/// Resolve a policy's gem lists to exact names, as [`load_filter_set`] does for files
+fn load_policy_set(policy: &Policy, names_file: Option<&str>) -> io::Result<GemSelection> {
+    let index = names_file.map(open_input).transpose()?;
+    let selection = policy.gem_selection(index)?;
+    match &selection {
+        GemSelection::All => {}
+        GemSelection::Allow(gems) => eprintln!("Loaded {} gems from policy allowlist", gems.len()),
+        GemSelection::Block(gems) => eprintln!("Loaded {} gems from policy blocklist", gems.len()),
+    }
+    Ok(selection)
+}
+
+/// Convert the owned filter set to the borrowed form FilterMode expects
+///
+/// Keep owned set and converted set separate to manage lifetimes
+fn borrow_filter_set(filter_set_owned: &Option<HashSet<String>>) -> Option<HashSet<&str>> {
+    filter_set_owned
         .as_ref()
-        .map(|set| set.iter().map(|s| s.as_str()).collect());
+        .map(|set| set.iter().map(|s| s.as_str()).collect())
+}
 
-    // Determine which mode to use based on what was specified
-    let mode = match (&filter_set_refs, allowlist_file, blocklist_file) {
+/// Determine which mode to use based on which lists were specified
+fn filter_mode<'a>(
+    filter_set: Option<&'a HashSet<&'a str>>,
+    allowlist_file: Option<&str>,
+    blocklist_file: Option<&str>,
+) -> FilterMode<'a> {
+    match (filter_set, allowlist_file, blocklist_file) {
         (Some(set), Some(_), Some(_)) => FilterMode::Allow(set), // Both: use Allow with preprocessed set
         (Some(set), Some(_), None) => FilterMode::Allow(set),    // Allow only
         (Some(set), None, Some(_)) => FilterMode::Block(set),    // Block only
         _ => FilterMode::Passthrough,                            // Neither
-    };
+    }
+}
 
-    // Open input
-    let input: Box<dyn io::Read> = if versions_file == "-" {
-        Box::new(io::stdin())
-    } else {
-        Box::new(File::open(versions_file)?)
-    };
+/// Compare two versions files: `diff [--patch | --delta] <old-file> <new-file> [output-file]`
+///
+/// Prints a summary to stdout. The optional output file receives the added
+/// lines, or a patch for the `patch` subcommand when `--patch` or `--delta`
+/// is given. `diff --snapshot <new-file> <output-file>` writes a snapshot,
+/// which `patch` applies to any base.
+fn run_diff(args: &[String]) -> io::Result<()> {
+    let format = args
+        .first()
+        .map(String::as_str)
+        .filter(|arg| ["--patch", "--delta", "--snapshot"].contains(arg));
+    let args = if format.is_some() { &args[1..] } else { args };
 
-    // Stream and filter
-    if let Some(output_path) = output_file {
-        let mut output = File::create(output_path)?;
-        let digest =
-            filter_versions_streaming(input, &mut output, mode, version_output, digest_algorithm)?;
-        eprintln!("Written to {}", output_path);
-        if let Some(checksum) = digest {
-            eprintln!("{}: {}", digest_algorithm.unwrap().name(), checksum);
+    if format == Some("--snapshot") {
+        if args.len() != 2 {
+            eprintln!("Usage: gem-index-filter diff --snapshot <new-file> <output-file>");
+            std::process::exit(1);
         }
-    } else {
-        let mut output = io::stdout();
-        let digest =
-            filter_versions_streaming(input, &mut output, mode, version_output, digest_algorithm)?;
-        if let Some(checksum) = digest {
-            eprintln!("{}: {}", digest_algorithm.unwrap().name(), checksum);
+        let lines = write_snapshot(open_input(&args[0])?, &mut File::create(&args[1])?)?;
+        println!("snapshot lines: {}", lines);
+        return Ok(());
+    }
+
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!(
+            "Usage: gem-index-filter diff [--patch | --delta] <old-file> <new-file> [output-file]"
+        );
+        eprintln!("       gem-index-filter diff --snapshot <new-file> <output-file>");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  <old-file>     Previous versions file (or - for stdin)");
+        eprintln!("  <new-file>     Newer versions file (or - for stdin)");
+        eprintln!("  [output-file]  Optional file receiving the lines added in <new-file>");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --patch        Write a patch (applied with 'patch') instead of raw lines");
+        eprintln!("  --delta        Write the patch in the compact binary delta format");
+        eprintln!("  --snapshot     Write all of <new-file> as a delta that applies to any base");
+        std::process::exit(1);
+    }
+
+    let old = open_input(&args[0])?;
+    let new = open_input(&args[1])?;
+
+    let diff = match (args.get(2), format) {
+        (Some(output_path), Some("--patch")) => {
+            write_patch(old, new, &mut File::create(output_path)?)?
+        }
+        (Some(output_path), Some(_)) => write_delta(old, new, &mut File::create(output_path)?)?,
+        (Some(output_path), None) => diff_versions(old, new, &mut File::create(output_path)?)?,
+        (None, _) => diff_versions(old, new, &mut io::sink())?,
+    };
+
+    println!(
+        "metadata: {}",
+        if diff.metadata_changed {
+            "changed"
+        } else {
+            "unchanged"
+        }
+    );
+    println!("append-only: {}", diff.is_append_only());
+    println!("common lines: {}", diff.common_lines);
+    println!("removed lines: {}", diff.removed_lines);
+    println!("added lines: {}", diff.added_lines);
+    println!("new gems: {}", diff.new_gems.len());
+    for gem in &diff.new_gems {
+        println!("  + {}", gem);
+    }
+    println!("removed gems: {}", diff.removed_gems.len());
+    for gem in &diff.removed_gems {
+        println!("  - {}", gem);
+    }
+
+    Ok(())
+}
+
+/// Apply a patch: `patch <base-file> <patch-file> [output-file]`
+fn run_patch(args: &[String]) -> io::Result<()> {
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("Usage: gem-index-filter patch <base-file> <patch-file> [output-file]");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  <base-file>    Local versions file the patch was created against");
+        eprintln!("  <patch-file>   Patch written by 'diff --patch', '--delta' or '--snapshot'");
+        eprintln!("                 (or - for stdin)");
+        eprintln!("  [output-file]  Optional output file (defaults to stdout)");
+        std::process::exit(1);
+    }
+
+    let base = open_input(&args[0])?;
+    let mut patch = io::BufReader::new(open_input(&args[1])?);
+    let delta = is_delta(io::BufRead::fill_buf(&mut patch)?);
+
+    let mut output: Box<dyn io::Write> = match args.get(2) {
+        Some(output_path) => Box::new(File::create(output_path)?),
+        None => Box::new(io::stdout()),
+    };
+    if delta {
+        apply_delta(base, patch, &mut output)?;
+    } else {
+        apply_patch(base, patch, &mut output)?;
+    }
+    if let Some(output_path) = args.get(2) {
+        eprintln!("Written to {}", output_path);
+    }
+
+    Ok(())
+}
+
+/// Build a static mirror:
+/// `mirror [--allow <file>] [--block <file>] [--names <file>] --dest <dir> [--upstream <url>]`
+#[cfg(feature = "http")]
+fn run_mirror(args: &[String]) -> io::Result<()> {
+    use gem_index_filter::{build_mirror_with_progress, InfoOutcome, MirrorOptions};
+
+    let mut allowlist_file: Option<&str> = None;
+    let mut blocklist_file: Option<&str> = None;
+    let mut names_file: Option<&str> = None;
+    let mut dest: Option<&str> = None;
+    let mut upstream: Option<&str> = None;
+    let mut upstream_digest: Option<&str> = None;
+    let mut upstream_manifest: Option<&str> = None;
+    let mut manifest_key: Option<&str> = None;
+    let mut snapshot_dir: Option<&str> = None;
+    let mut limits = gem_index_filter::FetchLimits::default();
+    let mut concurrency: Option<u64> = None;
+    let mut retries: Option<u64> = None;
+    let mut progress = false;
+    let number = |flag: &str, value: Option<&str>| -> u64 {
+        match required_value(flag, value).parse() {
+            Ok(n) => n,
+            Err(_) => {
+                eprintln!("Error: {} requires a number", flag);
+                std::process::exit(1);
+            }
+        }
+    };
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).map(String::as_str);
+        match args[i].as_str() {
+            "--allow" => allowlist_file = Some(required_value("--allow", value)),
+            "--block" => blocklist_file = Some(required_value("--block", value)),
+            "--names" => names_file = Some(required_value("--names", value)),
+            "--dest" => dest = Some(required_value("--dest", value)),
+            "--upstream" => upstream = Some(required_value("--upstream", value)),
+            "--upstream-digest" => {
+                upstream_digest = Some(required_value("--upstream-digest", value))
+            }
+            "--upstream-manifest" => {
+                upstream_manifest = Some(required_value("--upstream-manifest", value))
+            }
+            "--manifest-key" => manifest_key = Some(required_value("--manifest-key", value)),
+            "--snapshot-dir" => snapshot_dir = Some(required_value("--snapshot-dir", value)),
+            "--connect-timeout" => {
+                let secs = number("--connect-timeout", value);
+                limits.connect_timeout = Some(std::time::Duration::from_secs(secs));
+            }
+            "--download-timeout" => {
+                let secs = number("--download-timeout", value);
+                limits.body_timeout = Some(std::time::Duration::from_secs(secs));
+            }
+            "--max-download" => limits.max_bytes = Some(number("--max-download", value)),
+            "--concurrency" => concurrency = Some(number("--concurrency", value)),
+            "--retries" => retries = Some(number("--retries", value)),
+            "--progress" => {
+                progress = true;
+                i += 1;
+                continue;
+            }
+            other => {
+                eprintln!("Error: Unknown mirror argument '{}'", other);
+                std::process::exit(1);
+            }
+        }
+        i += 2;
+    }
+
+    // Mirroring the whole index means one info request per gem; require an explicit policy
+    let Some(dest) = dest.filter(|_| allowlist_file.is_some() || blocklist_file.is_some()) else {
+        eprintln!(
+            "Usage: gem-index-filter mirror [--allow <file>] [--block <file>] --dest <dir> [--upstream <url>]"
+        );
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --allow <file>     Mirror only gems in allowlist file");
+        eprintln!("  --block <file>     Leave out gems in blocklist file");
+        eprintln!("  --names <file>     Names or versions file to expand list patterns against");
+        eprintln!("  --dest <dir>       Directory receiving versions, names and info/*");
+        eprintln!("  --upstream <url>   Compact index to mirror (default: https://rubygems.org)");
+        eprintln!(
+            "  --upstream-digest <hex>    SHA-256 or SHA-512 the upstream versions must match"
+        );
+        eprintln!("  --upstream-manifest <url>  Checksum manifest listing the upstream versions,");
+        eprintln!("                             signed at <url>.sig by --manifest-key <hex>");
+        eprintln!("  --snapshot-dir <dir>       Keep the raw upstream versions and its ETag here,");
+        eprintln!("                             downloading it again only when it changed");
+        eprintln!("  --connect-timeout <secs>   Time allowed to connect (default: 10)");
+        eprintln!("  --download-timeout <secs>  Time allowed to receive each body (default: 300)");
+        eprintln!("  --max-download <bytes>     Largest body accepted (default: 268435456)");
+        eprintln!("  --concurrency <n>          Info files downloaded at once (default: 8)");
+        eprintln!("  --retries <n>              Retries of an info download after a timeout,");
+        eprintln!("                             dropped connection, 429 or 5xx (default: 2)");
+        eprintln!("  --progress                 Print each info file as it completes");
+        eprintln!();
+        eprintln!("At least one of --allow or --block is required.");
+        std::process::exit(1);
+    };
+
+    let filter_set_owned = load_filter_set(allowlist_file, blocklist_file, names_file)?;
+    let filter_set_refs = borrow_filter_set(&filter_set_owned);
+    let mode = filter_mode(filter_set_refs.as_ref(), allowlist_file, blocklist_file);
+
+    let mut options = MirrorOptions::new(dest);
+    if let Some(upstream) = upstream {
+        options.upstream = upstream.to_string();
+    }
+    options.upstream_digest = upstream_digest.map(str::to_string);
+    options.snapshot_dir = snapshot_dir.map(Into::into);
+    options.limits = limits;
+    if let Some(concurrency) = concurrency {
+        options.info_concurrency = concurrency as usize;
+    }
+    if let Some(retries) = retries {
+        options.info_retries = retries as u32;
+    }
+    match (upstream_manifest, manifest_key) {
+        #[cfg(feature = "signing")]
+        (Some(url), Some(key)) => {
+            options.upstream_manifest = Some(gem_index_filter::SignedManifest {
+                url: url.to_string(),
+                public_key: key.to_string(),
+            })
+        }
+        #[cfg(not(feature = "signing"))]
+        (Some(_), Some(_)) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "--upstream-manifest requires gem-index-filter to be built with the signing feature",
+            ));
+        }
+        (None, None) => {}
+        _ => {
+            eprintln!("Error: --upstream-manifest and --manifest-key must be given together");
+            std::process::exit(1);
+        }
+    }
+
+    let stats = build_mirror_with_progress(mode, &options, |info| {
+        if progress {
+            let outcome = match info.outcome {
+                InfoOutcome::Fetched => "fetched",
+                InfoOutcome::NotModified => "unchanged",
+            };
+            eprintln!(
+                "[{}/{}] info/{} {}",
+                info.done, info.total, info.name, outcome
+            );
+        }
+    })?;
+    eprintln!(
+        "Mirrored {} gems into {} (versions: {} bytes, {} info files, {} unchanged, {} removed)",
+        stats.gems,
+        dest,
+        stats.versions_bytes,
+        stats.info_files,
+        stats.info_unchanged,
+        stats.info_removed
+    );
+
+    Ok(())
+}
+
+/// Check a mirror directory: `verify [--previous <versions-file>] <mirror-dir>`
+///
+/// Prints each violation as `path:line: message` and exits non-zero if any are found.
+fn run_verify(args: &[String]) -> io::Result<()> {
+    let (previous, rest) = match args.first().map(String::as_str) {
+        Some("--previous") => (
+            Some(required_value(
+                "--previous",
+                args.get(1).map(String::as_str),
+            )),
+            &args[2..],
+        ),
+        _ => (None, args),
+    };
+
+    if rest.len() != 1 {
+        eprintln!("Usage: gem-index-filter verify [--previous <versions-file>] <mirror-dir>");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  <mirror-dir>                 Directory containing versions, names and info/*");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --previous <versions-file>   Earlier versions snapshot that must be a prefix");
+        std::process::exit(1);
+    }
+
+    let report = gem_index_filter::verify_mirror(Path::new(&rest[0]), previous.map(Path::new))?;
+    for violation in &report.violations {
+        println!("{}", violation);
+    }
+    eprintln!(
+        "Checked {} gems and {} info files: {} violations",
+        report.gems,
+        report.info_files_checked,
+        report.violations.len()
+    );
+
+    if !report.is_ok() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Write an SBOM: `sbom [--versions] [--name <name>] <versions-file> [output-file]`
+fn run_sbom(args: &[String]) -> io::Result<()> {
+    use gem_index_filter::sbom::{write_sbom, SbomOptions};
+
+    let mut include_versions = false;
+    let mut name: Option<&str> = None;
+    let mut positional = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--versions" => include_versions = true,
+            "--name" => {
+                name = Some(required_value(
+                    "--name",
+                    args.get(i + 1).map(String::as_str),
+                ));
+                i += 1;
+            }
+            _ => positional.push(args[i].as_str()),
         }
+        i += 1;
     }
 
+    if positional.is_empty() || positional.len() > 2 {
+        eprintln!(
+            "Usage: gem-index-filter sbom [--versions] [--name <name>] <versions-file> [output-file]"
+        );
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  <versions-file>  Filtered versions file (or - for stdin)");
+        eprintln!("  [output-file]    Optional output file (defaults to stdout)");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --versions       List every published version instead of one entry per gem");
+        eprintln!("  --name <name>    Name of the mirror described (default: the input file name)");
+        std::process::exit(1);
+    }
+
+    let options = SbomOptions {
+        include_versions,
+        ..SbomOptions::new(name.unwrap_or(positional[0]))
+    };
+    let input = open_input(positional[0])?;
+
+    let components = if let Some(output_path) = positional.get(1) {
+        let components = write_sbom(input, &mut File::create(output_path)?, &options)?;
+        eprintln!("Written to {}", output_path);
+        components
+    } else {
+        write_sbom(input, &mut io::stdout(), &options)?
+    };
+    eprintln!("SBOM components: {}", components);
+
     Ok(())
 }
 
-/// Read gem list from file (one gem name per line, supports comments with #)
-fn read_gem_list(path: &str) -> io::Result<HashSet<String>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let mut gems = HashSet::new();
+/// List gem names: `gems [--counts] <versions-file> [output-file]`
+fn run_gems(args: &[String]) -> io::Result<()> {
+    use gem_index_filter::count_gem_lines;
+    use std::io::Write;
+
+    let counts = args.iter().any(|arg| arg == "--counts");
+    let positional: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "--counts")
+        .collect();
+
+    if positional.is_empty() || positional.len() > 2 {
+        eprintln!("Usage: gem-index-filter gems [--counts] <versions-file> [output-file]");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  <versions-file>  Versions file (or - for stdin)");
+        eprintln!("  [output-file]    Optional output file (defaults to stdout)");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --counts         Follow each name with its number of lines");
+        std::process::exit(1);
+    }
+
+    // One name per line, sorted, so the output diffs cleanly against a list file
+    let gems = count_gem_lines(open_input(positional[0])?)?;
+    let mut output: Box<dyn Write> = match positional.get(1) {
+        Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+        None => Box::new(io::BufWriter::new(io::stdout().lock())),
+    };
+    for (name, lines) in &gems {
+        if counts {
+            writeln!(output, "{} {}", name, lines)?;
+        } else {
+            writeln!(output, "{}", name)?;
+        }
+    }
+    output.flush()?;
+    eprintln!("Gems: {}", gems.len());
+
+    Ok(())
+}
+
+/// Rank gems by size: `sizes [--top <n>] [--cap <n>] [--json] <versions-file> [output-file]`
+fn run_sizes(args: &[String]) -> io::Result<()> {
+    use gem_index_filter::size_report;
+    use std::io::Write;
+
+    let number = |flag: &str, value: Option<&String>| -> usize {
+        match required_value(flag, value.map(String::as_str)).parse() {
+            Ok(n) => n,
+            Err(_) => {
+                eprintln!("Error: {} requires a number", flag);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let mut top = 20;
+    let mut cap: Option<usize> = None;
+    let mut json = false;
+    let mut positional = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--top" => {
+                top = number("--top", args.get(i + 1));
+                i += 1;
+            }
+            "--cap" => {
+                cap = Some(number("--cap", args.get(i + 1)));
+                i += 1;
+            }
+            "--json" => json = true,
+            _ => positional.push(args[i].as_str()),
+        }
+        i += 1;
+    }
+
+    if positional.is_empty() || positional.len() > 2 {
+        eprintln!(
+            "Usage: gem-index-filter sizes [--top <n>] [--cap <n>] [--json] <versions-file> [output-file]"
+        );
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  <versions-file>  Versions file (or - for stdin)");
+        eprintln!("  [output-file]    Optional output file (defaults to stdout)");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --top <n>        Number of gems to list (default: 20)");
+        eprintln!("  --cap <n>        Also estimate the bytes saved by max_versions = <n>");
+        eprintln!("  --json           Write JSON instead of a text table");
+        std::process::exit(1);
+    }
+
+    let report = size_report(open_input(positional[0])?, cap)?;
+    let mut output: Box<dyn Write> = match positional.get(1) {
+        Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+        None => Box::new(io::BufWriter::new(io::stdout().lock())),
+    };
+    if json {
+        report.write_json(top, &mut output)?;
+    } else {
+        report.write_text(top, &mut output)?;
+    }
+    output.flush()?;
+    eprintln!(
+        "Gems: {}, gem line bytes: {}",
+        report.gems.len(),
+        report.total_bytes
+    );
+
+    Ok(())
+}
+
+/// Print the policy file schema: `config schema [output-file]`
+fn run_config(args: &[String]) -> io::Result<()> {
+    use gem_index_filter::Policy;
+    use std::io::Write;
+
+    let (Some("schema"), 1..=2) = (args.first().map(String::as_str), args.len()) else {
+        eprintln!("Usage: gem-index-filter config schema [output-file]");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  [output-file]    Optional output file (defaults to stdout)");
+        std::process::exit(1);
+    };
+
+    let mut output: Box<dyn Write> = match args.get(1) {
+        Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+        None => Box::new(io::BufWriter::new(io::stdout().lock())),
+    };
+    serde_json::to_writer_pretty(&mut output, &Policy::schema())?;
+    writeln!(output)?;
+    output.flush()
+}
+
+/// Compare two policies: `simulate --policy-a <file> --policy-b <file> <versions-file>`
+fn run_simulate(args: &[String]) -> io::Result<()> {
+    use gem_index_filter::simulate_policies;
+    use std::io::Write;
+
+    let mut policy_a: Option<&str> = None;
+    let mut policy_b: Option<&str> = None;
+    let mut positional = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--policy-a" => {
+                policy_a = Some(required_value(
+                    "--policy-a",
+                    args.get(i + 1).map(String::as_str),
+                ));
+                i += 1;
+            }
+            "--policy-b" => {
+                policy_b = Some(required_value(
+                    "--policy-b",
+                    args.get(i + 1).map(String::as_str),
+                ));
+                i += 1;
+            }
+            _ => positional.push(args[i].as_str()),
+        }
+        i += 1;
+    }
+
+    let (Some(policy_a), Some(policy_b), [input]) = (policy_a, policy_b, &positional[..]) else {
+        eprintln!(
+            "Usage: gem-index-filter simulate --policy-a <file> --policy-b <file> <versions-file>"
+        );
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  <versions-file>    Versions file (or - for stdin)");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --policy-a <file>  Current policy");
+        eprintln!("  --policy-b <file>  Proposed policy, compared against --policy-a");
+        std::process::exit(1);
+    };
+
+    let simulation = simulate_policies(
+        open_input(input)?,
+        &Policy::load(policy_a)?,
+        &Policy::load(policy_b)?,
+    )?;
+    let mut output = io::BufWriter::new(io::stdout().lock());
+    simulation.write_text(&mut output)?;
+    output.flush()
+}
+
+/// Apply an allowlist edit: `update-allowlist [--strip-versions] [--names <file>]
+/// <old-allowlist> <new-allowlist> <upstream-file> <filtered-file>`
+fn run_update_allowlist(args: &[String]) -> io::Result<()> {
+    use gem_index_filter::{append_allowed_gems, filter_file, AllowlistUpdate, FilterOptions};
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    let mut version_output = VersionOutput::Preserve;
+    let mut names_file: Option<&str> = None;
+    let mut positional = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).map(String::as_str);
+        match args[i].as_str() {
+            "--strip-versions" => version_output = VersionOutput::Strip,
+            "--names" => {
+                names_file = Some(required_value("--names", value));
+                i += 1;
+            }
+            other => positional.push(other),
+        }
+        i += 1;
+    }
+
+    let [old_path, new_path, upstream_path, filtered_path] = positional[..] else {
+        eprintln!(
+            "Usage: gem-index-filter update-allowlist [--strip-versions] [--names <file>] \
+             <old-allowlist> <new-allowlist> <upstream-file> <filtered-file>"
+        );
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  <old-allowlist>   Allowlist <filtered-file> was filtered with");
+        eprintln!("  <new-allowlist>   Edited allowlist");
+        eprintln!("  <upstream-file>   Upstream copy <filtered-file> was filtered from");
+        eprintln!("  <filtered-file>   Filtered file, appended to or re-filtered in place");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --strip-versions  The filtered file has its version lists stripped");
+        eprintln!("  --names <file>    Names or versions file to expand list patterns against");
+        eprintln!("                    (defaults to <upstream-file>)");
+        std::process::exit(1);
+    };
+
+    let names_file = Some(names_file.unwrap_or(upstream_path));
+    let old = load_filter_set(Some(old_path), None, names_file)?.unwrap_or_default();
+    let new = load_filter_set(Some(new_path), None, names_file)?.unwrap_or_default();
+    let old: HashSet<&str> = old.iter().map(String::as_str).collect();
+    let new: HashSet<&str> = new.iter().map(String::as_str).collect();
+
+    let mut appended = Vec::new();
+    let update = append_allowed_gems(
+        open_input(upstream_path)?,
+        &mut appended,
+        &old,
+        &new,
+        version_output,
+    )?;
+    match update {
+        AllowlistUpdate::Appended { gems, lines, bytes } => {
+            let mut filtered = std::fs::OpenOptions::new()
+                .read(true)
+                .append(true)
+                .open(filtered_path)?;
+            // A filtered file can end without a newline, as its upstream did
+            if filtered.metadata()?.len() > 0 {
+                let mut last = [0];
+                filtered.seek(SeekFrom::End(-1))?;
+                filtered.read_exact(&mut last)?;
+                if last[0] != b'\n' {
+                    filtered.write_all(b"\n")?;
+                }
+            }
+            filtered.write_all(&appended)?;
+            eprintln!(
+                "Appended {} lines ({} bytes) for {} added gems to {}",
+                lines,
+                bytes,
+                gems.len(),
+                filtered_path
+            );
+        }
+        AllowlistUpdate::Removed { gems } => {
+            eprintln!(
+                "{} gems removed ({}), re-filtering {}",
+                gems.len(),
+                gems.join(", "),
+                filtered_path
+            );
+            let mut options = FilterOptions::new(FilterMode::Allow(&new));
+            options.version_output = version_output;
+            let stats = filter_file(upstream_path, filtered_path, &options)?;
+            eprintln!("Written to {}", filtered_path);
+            eprintln!("Output size: {} bytes", stats.output_bytes);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check a list for typos: `typos <list-file> <versions-file>`
+///
+/// Prints each near miss as `entry: suggestion, ...` and exits non-zero if any are found.
+fn run_typos(args: &[String]) -> io::Result<()> {
+    use gem_index_filter::{collect_gem_names, near_misses};
+
+    let [list_path, versions_path] = args else {
+        eprintln!("Usage: gem-index-filter typos <list-file> <versions-file>");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  <list-file>      Allow or block list to check (file or URL)");
+        eprintln!("  <versions-file>  Versions or names file holding the real gem names");
+        eprintln!("                   (or - for stdin)");
+        std::process::exit(1);
+    };
+
+    let list = read_gem_list(list_path)?;
+    let names = collect_gem_names(open_input(versions_path)?)?;
+    let mut entries: Vec<&str> = list.exact_entries().collect();
+    entries.sort_unstable();
+    let misses = near_misses(entries, &names);
+    for miss in &misses {
+        println!("{}: {}", miss.entry, miss.suggestions.join(", "));
+    }
+    eprintln!(
+        "{} entries of {} nearly match a gem",
+        misses.len(),
+        list_path
+    );
+    if !misses.is_empty() {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Compare two files by gem name: `intersect|subtract <versions-file> <other-file> [output-file]`
+fn run_setop(operation: &str, args: &[String]) -> io::Result<()> {
+    use gem_index_filter::{intersect_versions, subtract_versions};
+    use std::io::Write;
+
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!(
+            "Usage: gem-index-filter {} <versions-file> <other-file> [output-file]",
+            operation
+        );
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  <versions-file>  File whose lines are kept or dropped (or - for stdin)");
+        eprintln!("  <other-file>     File whose gem names are compared against");
+        eprintln!("  [output-file]    Optional output file (defaults to stdout)");
+        std::process::exit(1);
+    }
+
+    let input = open_input(&args[0])?;
+    let other = File::open(&args[1])?;
+    let mut output: Box<dyn io::Write> = match args.get(2) {
+        Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+        None => Box::new(io::BufWriter::new(io::stdout().lock())),
+    };
+    let stats = match operation {
+        "intersect" => intersect_versions(input, other, &mut output)?,
+        _ => subtract_versions(input, other, &mut output)?,
+    };
+    output.flush()?;
+    if let Some(path) = args.get(2) {
+        eprintln!("Written to {}", path);
+    }
+    eprintln!("Output size: {} bytes", stats.output_bytes);
+
+    Ok(())
+}
+
+/// Filter a legacy Marshal index: `specs [--allow <file>] [--block <file>]
+/// [--names <file>] <specs-file> [output-file]`
+#[cfg(feature = "specs")]
+fn run_specs(args: &[String]) -> io::Result<()> {
+    use gem_index_filter::specs::filter_specs;
+    use std::io::Write;
+
+    let mut allowlist_file: Option<&str> = None;
+    let mut blocklist_file: Option<&str> = None;
+    let mut names_file: Option<&str> = None;
+    let mut positional = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).map(String::as_str);
+        match args[i].as_str() {
+            "--allow" => allowlist_file = Some(required_value("--allow", value)),
+            "--block" => blocklist_file = Some(required_value("--block", value)),
+            "--names" => names_file = Some(required_value("--names", value)),
+            other => {
+                positional.push(other);
+                i += 1;
+                continue;
+            }
+        }
+        i += 2;
+    }
+
+    if positional.is_empty() || positional.len() > 2 {
+        eprintln!(
+            "Usage: gem-index-filter specs [--allow <file>] [--block <file>] <specs-file> [output-file]"
+        );
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!(
+            "  <specs-file>     specs.4.8.gz, latest_specs.4.8.gz or prerelease_specs.4.8.gz"
+        );
+        eprintln!("                   (or - for stdin)");
+        eprintln!("  [output-file]    Optional gzipped output file (defaults to stdout)");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --allow <file>   Keep only gems in allowlist file");
+        eprintln!("  --block <file>   Drop gems in blocklist file");
+        eprintln!("  --names <file>   Names or versions file to expand list patterns against");
+        std::process::exit(1);
+    }
+
+    let filter_set_owned = load_filter_set(allowlist_file, blocklist_file, names_file)?;
+    let filter_set_refs = borrow_filter_set(&filter_set_owned);
+    let mode = filter_mode(filter_set_refs.as_ref(), allowlist_file, blocklist_file);
+
+    let input = open_input(positional[0])?;
+    let mut output: Box<dyn io::Write> = match positional.get(1) {
+        Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+        None => Box::new(io::BufWriter::new(io::stdout().lock())),
+    };
+    let stats = filter_specs(input, &mut output, mode)?;
+    output.flush()?;
+    if let Some(path) = positional.get(1) {
+        eprintln!("Written to {}", path);
+    }
+    eprintln!("Kept {} of {} specs", stats.kept, stats.specs);
+
+    Ok(())
+}
+
+/// Build an allowlist from API metadata: `enrich [--min-downloads <n>]
+/// [--licenses <list>] [--cache-dir <dir>] [--api <url>] <versions-file> [output-file]`
+#[cfg(feature = "http")]
+fn run_enrich(args: &[String]) -> io::Result<()> {
+    use gem_index_filter::collect_gem_names;
+    use gem_index_filter::gem_api::{select_gems, ApiOptions, ApiPolicy, GemApiClient};
+    use std::io::Write;
+
+    let mut options = ApiOptions::default();
+    let mut policy = ApiPolicy::default();
+    let mut positional = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).map(String::as_str);
+        match args[i].as_str() {
+            "--min-downloads" => {
+                let value = required_value("--min-downloads", value);
+                policy.min_downloads = Some(value.parse().unwrap_or_else(|_| {
+                    eprintln!("Error: --min-downloads expects a number, got '{}'", value);
+                    std::process::exit(1);
+                }));
+                i += 1;
+            }
+            "--licenses" => {
+                let value = required_value("--licenses", value);
+                policy.allowed_licenses = Some(
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|l| !l.is_empty())
+                        .map(String::from)
+                        .collect(),
+                );
+                i += 1;
+            }
+            "--cache-dir" => {
+                options.cache_dir = Some(required_value("--cache-dir", value).into());
+                i += 1;
+            }
+            "--api" => {
+                options.api_base = required_value("--api", value).to_string();
+                i += 1;
+            }
+            _ => positional.push(args[i].as_str()),
+        }
+        i += 1;
+    }
+
+    let has_policy = policy.min_downloads.is_some() || policy.allowed_licenses.is_some();
+    if !has_policy || !(1..=2).contains(&positional.len()) {
+        eprintln!(
+            "Usage: gem-index-filter enrich [--min-downloads <n>] [--licenses <list>] [--cache-dir <dir>] <versions-file> [output-file]"
+        );
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!(
+            "  <versions-file>       Versions file whose gems are candidates (or - for stdin)"
+        );
+        eprintln!("  [output-file]         Allowlist of gems that pass (defaults to stdout)");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --min-downloads <n>   Drop gems with fewer total downloads");
+        eprintln!(
+            "  --licenses <list>     Drop gems declaring none of these licenses (comma-separated)"
+        );
+        eprintln!("  --cache-dir <dir>     Cache API responses here (refreshed after a day)");
+        eprintln!("  --api <url>           RubyGems API host (default: https://rubygems.org)");
+        eprintln!();
+        eprintln!("At least one of --min-downloads or --licenses is required.");
+        std::process::exit(1);
+    }
+
+    let candidates = collect_gem_names(open_input(positional[0])?)?;
+    eprintln!("Checking {} candidate gems", candidates.len());
+
+    let mut client = GemApiClient::new(options);
+    let selected = select_gems(&candidates, &policy, &mut client)?;
+
+    let mut output: Box<dyn Write> = match positional.get(1) {
+        Some(output_path) => Box::new(io::BufWriter::new(File::create(output_path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    for name in &selected {
+        writeln!(output, "{}", name)?;
+    }
+    output.flush()?;
+
+    eprintln!(
+        "Selected {} of {} gems ({} API requests)",
+        selected.len(),
+        candidates.len(),
+        client.requests()
+    );
+    Ok(())
+}
+
+/// Return a flag's value or exit with an error if it is missing
+fn required_value<'a>(flag: &str, value: Option<&'a str>) -> &'a str {
+    value.unwrap_or_else(|| {
+        eprintln!("Error: {} requires a value", flag);
+        std::process::exit(1);
+    })
+}
+
+/// Open a file for reading, treating "-" as stdin
+fn open_input(path: &str) -> io::Result<Box<dyn io::Read>> {
+    use io::BufRead;
+
+    let input: Box<dyn io::Read> = if path == "-" {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path)?)
+    };
+    // Piped input has no extension to go by, so look for the gzip magic bytes
+    let mut input = io::BufReader::new(input);
+    if !input.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        return Ok(Box::new(input));
+    }
+    #[cfg(feature = "gzip")]
+    return Ok(Box::new(flate2::read::MultiGzDecoder::new(input)));
+    #[cfg(not(feature = "gzip"))]
+    Err(gzip_unsupported(&format!("{} is gzipped", path)))
+}
+
+/// Run `filter` writing into a gzip stream over `output`, for `--output-gzip`
+///
+/// `filter` sees the uncompressed output, so a digest it returns covers that.
+fn filter_gzipped<W: io::Write>(
+    output: W,
+    filter: impl FnOnce(&mut dyn io::Write) -> io::Result<Option<String>>,
+) -> io::Result<Option<String>> {
+    #[cfg(feature = "gzip")]
+    {
+        let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+        let digest = filter(&mut encoder)?;
+        encoder.finish()?.flush()?;
+        Ok(digest)
+    }
+    #[cfg(not(feature = "gzip"))]
+    {
+        let _ = (output, filter);
+        Err(gzip_unsupported("--output-gzip"))
+    }
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip_unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "{}: gzip needs gem-index-filter built with the gzip feature",
+            what
+        ),
+    )
+}
+
+/// Read gem list from a file (see `gem_index_filter::lists` for the syntax)
+/// or, with the `http` feature, a URL of plain names
+///
+/// `baked:` names the allowlist compiled in with the `baked-allowlist` feature.
+fn read_gem_list(path: &str) -> io::Result<GemList> {
+    #[cfg(feature = "baked-allowlist")]
+    if path == BAKED_LIST {
+        let baked = &gem_index_filter::baked::BAKED_ALLOWLIST;
+        let names: HashSet<String> = baked.iter().map(|gem| gem.to_string()).collect();
+        return Ok(names.into());
+    }
+
+    #[cfg(feature = "http")]
+    if path.starts_with("http://") || path.starts_with("https://") {
+        use gem_index_filter::AllowlistSource;
+        let mut source = gem_index_filter::HttpSource::new(path, std::time::Duration::ZERO);
+        return source.gems().map(|gems| gems.as_ref().clone().into());
+    }
 
-    for line in reader.lines() {
-        let line = line?;
-        let gem_name = line.trim();
-        // Skip empty lines and comments
-        if !gem_name.is_empty() && !gem_name.starts_with('#') {
-            gems.insert(gem_name.to_string());
+    if path.starts_with("s3://") {
+        #[cfg(feature = "s3")]
+        {
+            use gem_index_filter::s3::S3Config;
+            use gem_index_filter::AllowlistSource;
+            let config = S3Config::from_env()?;
+            let mut source =
+                gem_index_filter::HttpSource::s3(path, config, std::time::Duration::ZERO)?;
+            return source.gems().map(|gems| gems.as_ref().clone().into());
         }
+        #[cfg(not(feature = "s3"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "{} requires gem-index-filter to be built with the s3 feature",
+                path
+            ),
+        ));
     }
 
-    Ok(gems)
+    lists::load_file(path)
 }
@@ -0,0 +1,242 @@
+//! Per-line checksum manifests for versions files
+//!
+//! A whole-file digest says a copy was altered, not where. The manifest
+//! records every gem line's position and checksums, so a verifier holding a
+//! copy of the filtered index can name the exact lines that changed:
+//!
+//! ```text
+//! # line name md5 sha256
+//! 3 rails 1f3a... 9b2c...
+//! 4 sinatra 77de... 0e41...
+//! ```
+//!
+//! Positions are 1-based line numbers in the whole file, header included, and
+//! both checksums cover the line without its newline. Header lines aren't
+//! listed; `--digest` or a signature covers the file as a whole.
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// First line of every manifest
+const MANIFEST_HEADER: &str = "# line name md5 sha256";
+
+/// A gem line that doesn't match its manifest entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineMismatch {
+    /// The line at a listed position has different content
+    Changed { line: usize, name: String },
+    /// A listed line is past the end of the file
+    Missing { line: usize, name: String },
+    /// A gem line the manifest doesn't list
+    Unexpected { line: usize, name: String },
+}
+
+impl fmt::Display for LineMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LineMismatch::Changed { line, name } => {
+                write!(f, "line {}: {} does not match the manifest", line, name)
+            }
+            LineMismatch::Missing { line, name } => {
+                write!(f, "line {}: {} is missing", line, name)
+            }
+            LineMismatch::Unexpected { line, name } => {
+                write!(f, "line {}: {} is not in the manifest", line, name)
+            }
+        }
+    }
+}
+
+/// Write the manifest of every gem line in `versions`, returning how many were listed
+pub fn write_line_manifest<R: Read, W: Write>(
+    versions: R,
+    output: &mut W,
+) -> std::io::Result<usize> {
+    writeln!(output, "{}", MANIFEST_HEADER)?;
+    let mut listed = 0;
+    for_each_gem_line(versions, |position, name, line| {
+        listed += 1;
+        writeln!(
+            output,
+            "{} {} {} {}",
+            position,
+            name,
+            hex::encode(Md5::digest(line)),
+            hex::encode(Sha256::digest(line))
+        )
+    })?;
+    Ok(listed)
+}
+
+/// Compare `versions` against a manifest written by [`write_line_manifest`],
+/// returning every mismatch in file order; empty means the copy is intact
+pub fn check_line_manifest<R1: Read, R2: Read>(
+    versions: R1,
+    manifest: R2,
+) -> std::io::Result<Vec<LineMismatch>> {
+    let expected = read_manifest(manifest)?;
+    let mut entries = expected.iter().peekable();
+    let mut mismatches = Vec::new();
+
+    for_each_gem_line(versions, |position, name, line| {
+        // Listed positions that no longer hold a gem line have changed
+        while let Some(entry) = entries.next_if(|entry| entry.line < position) {
+            mismatches.push(LineMismatch::Changed {
+                line: entry.line,
+                name: entry.name.clone(),
+            });
+        }
+        match entries.next_if(|entry| entry.line == position) {
+            Some(entry) => {
+                let intact = entry.name == name
+                    && entry.md5 == hex::encode(Md5::digest(line))
+                    && entry.sha256 == hex::encode(Sha256::digest(line));
+                if !intact {
+                    mismatches.push(LineMismatch::Changed {
+                        line: position,
+                        name: entry.name.clone(),
+                    });
+                }
+            }
+            None => mismatches.push(LineMismatch::Unexpected {
+                line: position,
+                name: name.to_string(),
+            }),
+        }
+        Ok(())
+    })?;
+
+    mismatches.extend(entries.map(|entry| LineMismatch::Missing {
+        line: entry.line,
+        name: entry.name.clone(),
+    }));
+    Ok(mismatches)
+}
+
+/// One listed line
+struct ManifestEntry {
+    line: usize,
+    name: String,
+    md5: String,
+    sha256: String,
+}
+
+fn read_manifest<R: Read>(manifest: R) -> std::io::Result<Vec<ManifestEntry>> {
+    let mut entries: Vec<ManifestEntry> = Vec::new();
+    for (index, line) in BufReader::new(manifest).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(' ').collect();
+        let entry = match fields[..] {
+            [position, name, md5, sha256] => position.parse().ok().map(|line| ManifestEntry {
+                line,
+                name: name.to_string(),
+                md5: md5.to_string(),
+                sha256: sha256.to_string(),
+            }),
+            _ => None,
+        };
+        // Checking relies on positions ascending, as write_line_manifest emits them
+        match entry {
+            Some(entry) if entries.last().is_none_or(|last| last.line < entry.line) => {
+                entries.push(entry)
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid manifest line {}: '{}'", index + 1, line),
+                ))
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Call `visit` with the position, name and bytes of each gem line after `---`
+fn for_each_gem_line<R: Read>(
+    versions: R,
+    mut visit: impl FnMut(usize, &str, &[u8]) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(versions);
+    let mut buf = Vec::new();
+    let mut position = 0;
+    let mut in_header = true;
+
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            return Ok(());
+        }
+        position += 1;
+        let line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+        if in_header {
+            in_header = line != b"---";
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let name = memchr::memchr(b' ', line).map_or(&line[..0], |space| &line[..space]);
+        visit(position, &String::from_utf8_lossy(name), line)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSIONS: &str = "created_at: 2024-04-01T00:00:05Z\n---\n\
+        rails 7.0.0 abc123\n\
+        sinatra 3.0.0 def456\n\
+        puma 6.0.0 aaa111\n";
+
+    #[test]
+    fn test_manifest_pinpoints_tampered_lines() {
+        let mut manifest = Vec::new();
+        assert_eq!(
+            write_line_manifest(VERSIONS.as_bytes(), &mut manifest).unwrap(),
+            3
+        );
+        let manifest = String::from_utf8(manifest).unwrap();
+        assert!(manifest.starts_with("# line name md5 sha256\n3 rails "));
+
+        let intact = check_line_manifest(VERSIONS.as_bytes(), manifest.as_bytes()).unwrap();
+        assert!(intact.is_empty());
+
+        let tampered = VERSIONS
+            .replace("sinatra 3.0.0", "sinatra 3.0.1")
+            .replace("puma 6.0.0 aaa111\n", "");
+        let tampered = format!("{}rack 3.0.0 bbb222\n", tampered);
+        let mismatches = check_line_manifest(tampered.as_bytes(), manifest.as_bytes()).unwrap();
+        assert_eq!(
+            mismatches,
+            vec![
+                LineMismatch::Changed {
+                    line: 4,
+                    name: "sinatra".to_string()
+                },
+                LineMismatch::Changed {
+                    line: 5,
+                    name: "puma".to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            mismatches[0].to_string(),
+            "line 4: sinatra does not match the manifest"
+        );
+
+        let truncated = VERSIONS.replace("puma 6.0.0 aaa111\n", "");
+        assert_eq!(
+            check_line_manifest(truncated.as_bytes(), manifest.as_bytes()).unwrap(),
+            vec![LineMismatch::Missing {
+                line: 5,
+                name: "puma".to_string()
+            }]
+        );
+    }
+}
@@ -0,0 +1,418 @@
+//! One-command private mirror builder
+//!
+//! Downloads and filters the upstream `/versions` file, derives `/names` from
+//! the result and fetches `/info/<gem>` for every gem that survived the
+//! filter, producing a directory any static web server can serve as a
+//! Bundler source:
+//!
+//! ```text
+//! mirror/
+//!   versions
+//!   names
+//!   info/rails
+//!   info/sinatra
+//! ```
+//!
+//! Info files are copied verbatim so the MD5 checksums recorded in the
+//! versions file stay valid for Bundler's consistency checks. They are
+//! fetched several at a time, with retries, by an [`InfoFetcher`].
+//!
+//! With [`MirrorOptions::snapshot_dir`] set, the raw upstream `versions` is
+//! kept on disk with its ETag, gzipped with the `gzip` feature. Later runs
+//! revalidate it with `If-None-Match`, so a policy-only change re-filters the
+//! local copy instead of downloading 20 MB again, and the raw file is at hand
+//! to compare with the filtered one when something looks wrong. The info
+//! files' ETags are kept there too, so unchanged ones aren't downloaded again.
+//!
+//! Info files, and their ETags, for gems no longer in `names` are removed
+//! once the fetch succeeds, so a gem dropped from the policy stops being served.
+
+use crate::fetch::{call_error, FetchLimits};
+use crate::file::{open_input, write_atomically};
+use crate::filter::filter_versions_streaming;
+use crate::info::{InfoFetcher, InfoProgress};
+use crate::names::{collect_gem_names, write_names};
+#[cfg(feature = "signing")]
+use crate::pin::manifest_digest;
+#[cfg(feature = "digest")]
+use crate::pin::PinnedReader;
+use crate::truncation::CompleteReader;
+use crate::{FilterMode, VersionOutput};
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Upstream used when none is configured
+pub const DEFAULT_UPSTREAM: &str = "https://rubygems.org";
+
+/// Where to fetch the index from and where to write the mirror
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorOptions {
+    /// Base URL of the upstream compact index, without trailing slash
+    pub upstream: String,
+    /// Directory receiving `versions`, `names` and `info/`
+    pub dest: PathBuf,
+    /// Directory keeping the last raw upstream `versions` and its ETag
+    pub snapshot_dir: Option<PathBuf>,
+    /// Timeouts and size cap for every download
+    pub limits: FetchLimits,
+    /// Most info downloads in flight at once
+    pub info_concurrency: usize,
+    /// Attempts after the first for an info download that failed transiently
+    pub info_retries: u32,
+    /// Hex SHA-256 or SHA-512 the upstream `versions` must match
+    #[cfg(feature = "digest")]
+    pub upstream_digest: Option<String>,
+    /// Signed checksum manifest the upstream `versions` must match
+    #[cfg(feature = "signing")]
+    pub upstream_manifest: Option<SignedManifest>,
+}
+
+/// A `sha256sum`-style manifest with a detached signature at `<url>.sig`
+#[cfg(feature = "signing")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedManifest {
+    /// URL of the manifest listing `versions`
+    pub url: String,
+    /// Hex Ed25519 public key the signature must verify against
+    pub public_key: String,
+}
+
+impl MirrorOptions {
+    /// Mirror [`DEFAULT_UPSTREAM`] into `dest`
+    pub fn new(dest: impl Into<PathBuf>) -> Self {
+        MirrorOptions {
+            upstream: DEFAULT_UPSTREAM.to_string(),
+            dest: dest.into(),
+            snapshot_dir: None,
+            limits: FetchLimits::default(),
+            info_concurrency: 8,
+            info_retries: 2,
+            #[cfg(feature = "digest")]
+            upstream_digest: None,
+            #[cfg(feature = "signing")]
+            upstream_manifest: None,
+        }
+    }
+}
+
+/// Summary of a completed mirror run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MirrorStats {
+    /// Size of the filtered versions file
+    pub versions_bytes: u64,
+    /// Number of distinct gems in the mirror
+    pub gems: usize,
+    /// Number of info files written
+    pub info_files: usize,
+    /// Number of info files upstream reported unchanged
+    pub info_unchanged: usize,
+    /// Number of info files removed because their gem left the mirror
+    pub info_removed: usize,
+}
+
+/// Build or refresh a mirror of the gems selected by `mode`
+///
+/// Each file is written to a temporary name and renamed into place, so a
+/// static server never serves a partially written file. With a pinned
+/// upstream digest, a `versions` download that doesn't match fails the run
+/// and leaves the mirror as it was.
+pub fn build_mirror(mode: FilterMode, options: &MirrorOptions) -> std::io::Result<MirrorStats> {
+    build_mirror_with_progress(mode, options, |_| {})
+}
+
+/// [`build_mirror`], reporting each info file as it completes
+///
+/// `progress` is called from the download threads, in completion order.
+pub fn build_mirror_with_progress(
+    mode: FilterMode,
+    options: &MirrorOptions,
+    progress: impl Fn(&InfoProgress) + Sync,
+) -> std::io::Result<MirrorStats> {
+    let upstream = options.upstream.trim_end_matches('/');
+    fs::create_dir_all(&options.dest)?;
+
+    let agent = options.limits.agent(true);
+    let limits = &options.limits;
+    let mut stats = MirrorStats::default();
+
+    let versions_path = options.dest.join("versions");
+    let versions_url = format!("{}/versions", upstream);
+    let body: Box<dyn Read> = match &options.snapshot_dir {
+        Some(dir) => fetch_snapshot(&agent, limits, &versions_url, dir)?,
+        None => Box::new(fetch_complete(&agent, limits, &versions_url)?),
+    };
+    #[cfg(feature = "digest")]
+    let body = pin_versions(&agent, options, body)?;
+    write_atomically(&versions_path, |output| {
+        filter_versions_streaming(body, output, mode, VersionOutput::Preserve, None).map(|_| ())
+    })?;
+    stats.versions_bytes = fs::metadata(&versions_path)?.len();
+
+    let names = collect_gem_names(File::open(&versions_path)?)?;
+    write_atomically(&options.dest.join("names"), |output| {
+        write_names(&names, output)
+    })?;
+    stats.gems = names.len();
+
+    let fetcher = InfoFetcher {
+        upstream: upstream.to_string(),
+        limits: *limits,
+        concurrency: options.info_concurrency,
+        retries: options.info_retries,
+        etag_dir: options
+            .snapshot_dir
+            .as_ref()
+            .map(|dir| dir.join("info-etags")),
+        ..InfoFetcher::default()
+    };
+    let info_dir = options.dest.join("info");
+    let listed: Vec<&String> = names.iter().collect();
+    let info = fetcher.fetch_all(&listed, &info_dir, progress)?;
+    stats.info_files = info.fetched;
+    stats.info_unchanged = info.not_modified;
+
+    stats.info_removed = remove_unlisted(&info_dir, &names)?;
+    if let Some(etag_dir) = &fetcher.etag_dir {
+        remove_unlisted(etag_dir, &names)?;
+    }
+
+    Ok(stats)
+}
+
+/// Delete the files in `dir` not named in `names`, returning how many went
+///
+/// A missing `dir` has nothing to delete.
+fn remove_unlisted(dir: &Path, names: &BTreeSet<String>) -> std::io::Result<usize> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let listed = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| names.contains(name));
+        if !listed && entry.file_type()?.is_file() {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Start a GET request and return the response body as a reader
+#[cfg(feature = "signing")]
+fn fetch(agent: &ureq::Agent, limits: &FetchLimits, url: &str) -> std::io::Result<impl Read> {
+    let response = agent.get(url).call().map_err(|e| call_error(url, e))?;
+    limits.body(url, response)
+}
+
+/// [`fetch`] for the versions file, failing on a truncated body
+///
+/// The length is only checked when the body isn't content-encoded, since
+/// `Content-Length` then counts compressed bytes.
+fn fetch_complete(
+    agent: &ureq::Agent,
+    limits: &FetchLimits,
+    url: &str,
+) -> std::io::Result<CompleteReader<impl Read>> {
+    let response = agent.get(url).call().map_err(|e| call_error(url, e))?;
+    complete_body(limits, url, response)
+}
+
+/// The body of a `versions` response, failing on truncation
+fn complete_body(
+    limits: &FetchLimits,
+    url: &str,
+    response: ureq::http::Response<ureq::Body>,
+) -> std::io::Result<CompleteReader<impl Read>> {
+    let headers = response.headers();
+    let expected_len = match headers.get("content-encoding") {
+        Some(_) => None,
+        None => headers
+            .get("content-length")
+            .and_then(|value| value.to_str().ok()?.parse().ok()),
+    };
+
+    let body = CompleteReader::new(limits.body(url, response)?);
+    Ok(match expected_len {
+        Some(len) => body.expect_len(len),
+        None => body,
+    })
+}
+
+/// The upstream `versions` read from the snapshot in `dir`, refreshed first if its ETag changed
+///
+/// A new download is written to the snapshot in full before it is read, so a
+/// truncated download fails the run and leaves the previous snapshot in place.
+fn fetch_snapshot(
+    agent: &ureq::Agent,
+    limits: &FetchLimits,
+    url: &str,
+    dir: &Path,
+) -> std::io::Result<Box<dyn Read>> {
+    let snapshot_path = dir.join(SNAPSHOT_NAME);
+    let etag_path = dir.join("versions.etag");
+    fs::create_dir_all(dir)?;
+
+    let mut request = agent.get(url);
+    if snapshot_path.exists() {
+        if let Ok(etag) = fs::read_to_string(&etag_path) {
+            request = request.header("If-None-Match", etag.trim());
+        }
+    }
+    let response = request.call().map_err(|e| call_error(url, e))?;
+
+    match response.status().as_u16() {
+        304 => {}
+        200 => {
+            let etag = response
+                .headers()
+                .get("etag")
+                .and_then(|value| value.to_str().ok())
+                .map(String::from);
+            let mut body = complete_body(limits, url, response)?;
+            // Without a snapshot to match, a stale ETag would skip the next download
+            let _ = fs::remove_file(&etag_path);
+            write_atomically(&snapshot_path, |output| {
+                #[cfg(feature = "gzip")]
+                {
+                    let mut encoder =
+                        flate2::write::GzEncoder::new(output, flate2::Compression::default());
+                    std::io::copy(&mut body, &mut encoder)?;
+                    encoder.finish().map(drop)
+                }
+                #[cfg(not(feature = "gzip"))]
+                std::io::copy(&mut body, output).map(drop)
+            })?;
+            if let Some(etag) = etag {
+                fs::write(&etag_path, etag)?;
+            }
+        }
+        status => {
+            return Err(std::io::Error::other(format!(
+                "Failed to fetch {}: HTTP {}",
+                url, status
+            )))
+        }
+    }
+    open_input(&snapshot_path)
+}
+
+/// File name of the raw upstream `versions` in the snapshot directory
+#[cfg(feature = "gzip")]
+const SNAPSHOT_NAME: &str = "versions.gz";
+#[cfg(not(feature = "gzip"))]
+const SNAPSHOT_NAME: &str = "versions";
+
+/// Wrap the `versions` download in a [`PinnedReader`] for each configured digest
+#[cfg(feature = "digest")]
+fn pin_versions<'a>(
+    agent: &ureq::Agent,
+    options: &MirrorOptions,
+    body: impl Read + 'a,
+) -> std::io::Result<Box<dyn Read + 'a>> {
+    let mut body: Box<dyn Read + 'a> = Box::new(body);
+    if let Some(digest) = &options.upstream_digest {
+        body = Box::new(PinnedReader::new(body, digest)?);
+    }
+    #[cfg(feature = "signing")]
+    if let Some(manifest) = &options.upstream_manifest {
+        let digest = fetch_manifest_digest(agent, &options.limits, manifest)?;
+        body = Box::new(PinnedReader::new(body, &digest)?);
+    }
+    #[cfg(not(feature = "signing"))]
+    let _ = agent;
+    Ok(body)
+}
+
+/// Fetch a signed manifest, check its signature and return the digest it lists for `versions`
+#[cfg(feature = "signing")]
+fn fetch_manifest_digest(
+    agent: &ureq::Agent,
+    limits: &FetchLimits,
+    manifest: &SignedManifest,
+) -> std::io::Result<String> {
+    use crate::sign::{parse_verifying_key, verify_signature};
+
+    let key = parse_verifying_key(&manifest.public_key)?;
+    let mut text = String::new();
+    fetch(agent, limits, &manifest.url)?.read_to_string(&mut text)?;
+    let mut signature = String::new();
+    fetch(agent, limits, &format!("{}.sig", manifest.url))?.read_to_string(&mut signature)?;
+
+    if !verify_signature(text.as_bytes(), &signature, &key)? {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "Signature of {} does not match the manifest key",
+                manifest.url
+            ),
+        ));
+    }
+    manifest_digest(&text, "versions")
+}
+
+/// Gem names become file names under `info/`, so reject anything that could escape it
+pub(crate) fn is_safe_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "gem-index-filter-mirror-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_remove_unlisted() {
+        let dir = temp_dir("prune");
+        for name in ["rails", "sinatra", "puma"] {
+            fs::write(dir.join(name), "---\n").unwrap();
+        }
+        fs::create_dir(dir.join("nested")).unwrap();
+
+        let names: BTreeSet<String> = ["rails", "rack"].map(String::from).into();
+        assert_eq!(remove_unlisted(&dir, &names).unwrap(), 2);
+
+        let mut left: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["nested", "rails"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_unlisted_missing_dir() {
+        let dir = temp_dir("prune-missing").join("info");
+        assert_eq!(remove_unlisted(&dir, &BTreeSet::new()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_is_safe_file_name() {
+        assert!(is_safe_file_name("rails"));
+        assert!(is_safe_file_name("net-http_2.0"));
+        assert!(!is_safe_file_name(""));
+        assert!(!is_safe_file_name("."));
+        assert!(!is_safe_file_name(".."));
+        assert!(!is_safe_file_name("../etc"));
+        assert!(!is_safe_file_name("a\\b"));
+    }
+}
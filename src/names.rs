@@ -0,0 +1,113 @@
+//! Gem name extraction and the compact-index `names` file
+//!
+//! The `/names` endpoint lists every gem the index offers, one per line after
+//! a `---` separator, sorted by name. Mirrors derive it from their filtered
+//! versions file so the two never disagree.
+
+use crate::diff::{read_gem_line, read_metadata};
+use crate::slice::gem_name;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufReader, Read, Write};
+
+/// Collect the distinct gem names present in a versions file, sorted by name
+///
+/// Memory grows with the number of distinct gems, so this is intended for
+/// filtered files rather than the full upstream index.
+pub fn collect_gem_names<R: Read>(input: R) -> std::io::Result<BTreeSet<String>> {
+    let mut reader = BufReader::new(input);
+    read_metadata(&mut reader)?;
+
+    let mut names = BTreeSet::new();
+    let mut line = String::new();
+    while read_gem_line(&mut reader, &mut line)? {
+        if let Some(name) = gem_name(line.trim()) {
+            if !names.contains(name) {
+                names.insert(name.to_string());
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+/// Count the lines each gem has in a versions file, sorted by name
+///
+/// Every release appends a line, so the count is roughly how often a gem was
+/// published or yanked while it was in the file.
+pub fn count_gem_lines<R: Read>(input: R) -> std::io::Result<BTreeMap<String, usize>> {
+    let mut reader = BufReader::new(input);
+    read_metadata(&mut reader)?;
+
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    let mut line = String::new();
+    while read_gem_line(&mut reader, &mut line)? {
+        if let Some(name) = gem_name(line.trim()) {
+            match counts.get_mut(name) {
+                Some(count) => *count += 1,
+                None => {
+                    counts.insert(name.to_string(), 1);
+                }
+            }
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Write gem names in the compact-index `names` format
+pub fn write_names<'a, I, W>(names: I, output: &mut W) -> std::io::Result<()>
+where
+    I: IntoIterator<Item = &'a String>,
+    W: Write,
+{
+    output.write_all(b"---\n")?;
+    for name in names {
+        writeln!(output, "{}", name)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_and_write_names() {
+        let input = r#"created_at: 2024-04-01T00:00:05Z
+---
+sinatra 3.0.0 def456
+rails 7.0.0 abc123
+
+rails 7.0.1 fed321
+-A 0.0.0 8b1527991f0022e46140907a7fc4cfd4
+"#;
+
+        let names = collect_gem_names(input.as_bytes()).unwrap();
+        assert_eq!(
+            names.iter().map(String::as_str).collect::<Vec<_>>(),
+            vec!["-A", "rails", "sinatra"]
+        );
+        let counts = count_gem_lines(input.as_bytes()).unwrap();
+        assert_eq!(
+            counts.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("-A".to_string(), 1),
+                ("rails".to_string(), 2),
+                ("sinatra".to_string(), 1)
+            ]
+        );
+
+        let mut output = Vec::new();
+        write_names(&names, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "---\n-A\nrails\nsinatra\n"
+        );
+    }
+
+    #[test]
+    fn test_collect_requires_separator() {
+        let err = collect_gem_names("rails 7.0.0 abc123\n".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
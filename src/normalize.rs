@@ -0,0 +1,143 @@
+//! Unicode-aware gem name matching (`unicode` feature)
+//!
+//! Gem lists are matched byte for byte, so `café` written with a combining
+//! accent is a different name from `café` written precomposed, and a
+//! blocklist entry in one form doesn't stop the other. [`NormalizedSet`]
+//! NFC-normalizes both the list and every non-ASCII name it is asked about.
+//!
+//! NFC doesn't unify look-alikes from different scripts (Latin `a` and
+//! Cyrillic `а` stay distinct). Since real gem names are ASCII,
+//! [`NonAscii::Reject`] drops every non-ASCII name instead, whichever list it
+//! is or isn't on.
+
+use crate::slice::{option_names, GemSet, SliceMode};
+use std::collections::HashSet;
+use unicode_normalization::UnicodeNormalization;
+
+/// What to do with gem names that aren't plain ASCII
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonAscii {
+    /// Compare them in NFC form
+    Normalize,
+    /// Drop their lines, in allow and block mode alike
+    Reject,
+}
+
+option_names!(NonAscii, "non-ASCII handling", {
+    Normalize => "normalize",
+    Reject => "reject",
+});
+
+/// Whether the set is used to keep or to drop the gems it lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ListKind {
+    Allow,
+    Block,
+}
+
+/// A gem list matched after NFC normalization
+///
+/// Build it as an allowlist or a blocklist and filter with [`mode`](Self::mode),
+/// since a rejected name has to count as absent from an allowlist but
+/// present in a blocklist.
+#[derive(Debug, Clone)]
+pub struct NormalizedSet {
+    names: HashSet<String>,
+    kind: ListKind,
+    non_ascii: NonAscii,
+}
+
+impl NormalizedSet {
+    /// Keep only the listed gems
+    pub fn allowlist<'a>(names: impl IntoIterator<Item = &'a str>, non_ascii: NonAscii) -> Self {
+        Self::new(names, ListKind::Allow, non_ascii)
+    }
+
+    /// Drop the listed gems
+    pub fn blocklist<'a>(names: impl IntoIterator<Item = &'a str>, non_ascii: NonAscii) -> Self {
+        Self::new(names, ListKind::Block, non_ascii)
+    }
+
+    fn new<'a>(
+        names: impl IntoIterator<Item = &'a str>,
+        kind: ListKind,
+        non_ascii: NonAscii,
+    ) -> Self {
+        NormalizedSet {
+            names: names.into_iter().map(|name| name.nfc().collect()).collect(),
+            kind,
+            non_ascii,
+        }
+    }
+
+    /// The filter mode this set was built for
+    pub fn mode(&self) -> SliceMode<'_, Self> {
+        match self.kind {
+            ListKind::Allow => SliceMode::Allow(self),
+            ListKind::Block => SliceMode::Block(self),
+        }
+    }
+}
+
+impl GemSet for NormalizedSet {
+    #[inline]
+    fn contains_gem(&self, name: &str) -> bool {
+        // ASCII is already in NFC, which keeps real-world names on the fast path
+        if name.is_ascii() {
+            return self.names.contains(name);
+        }
+        match self.non_ascii {
+            NonAscii::Normalize => self.names.contains(&name.nfc().collect::<String>()),
+            NonAscii::Reject => self.kind == ListKind::Block,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filter_versions_with_set, VersionOutput};
+
+    // "café" precomposed (U+00E9) in the list, decomposed (e + U+0301) in the index
+    const VERSIONS: &str = "created_at: 2024-04-01T00:00:05Z\n---\n\
+        rails 7.0.0 abc123\n\
+        cafe\u{301} 1.0.0 def456\n\
+        \u{440}ails 7.0.0 ghi789\n";
+
+    fn filter(set: &NormalizedSet) -> String {
+        let mut output = Vec::new();
+        filter_versions_with_set(
+            VERSIONS.as_bytes(),
+            &mut output,
+            set.mode(),
+            VersionOutput::Preserve,
+            None,
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_blocklist_matches_other_normal_form() {
+        let blocked = NormalizedSet::blocklist(["caf\u{e9}"], NonAscii::Normalize);
+        let output = filter(&blocked);
+        assert!(output.contains("rails 7.0.0"));
+        assert!(!output.contains("def456"));
+        // A look-alike in another script is a different name to NFC
+        assert!(output.contains("ghi789"));
+    }
+
+    #[test]
+    fn test_reject_drops_non_ascii_in_both_modes() {
+        let blocked = NormalizedSet::blocklist(["puma"], NonAscii::Reject);
+        let allowed =
+            NormalizedSet::allowlist(["rails", "caf\u{e9}", "\u{440}ails"], NonAscii::Reject);
+
+        for set in [blocked, allowed] {
+            assert_eq!(
+                filter(&set),
+                "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n"
+            );
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Typed parsing of versions files
+//!
+//! The filter itself only needs the first word of each line, but tools built
+//! around it (diffs, reports, verification) want the whole structure:
+//!
+//! ```text
+//! created_at: 2024-04-01T00:00:05Z     <- Entry::Metadata
+//! ---                                  <- Entry::Separator
+//! rails 7.0.0,-7.0.1,7.0.2-java abc123 <- Entry::Gem
+//! puma                                 <- Entry::Malformed
+//! ```
+//!
+//! [`VersionsReader`] streams entries out of any reader, reusing one line
+//! buffer, and [`GemLine::parse`] and [`VersionEntry::parse`] work on single
+//! lines and version list entries. Entries borrow from the line they came
+//! from, so nothing is allocated per line.
+
+use crate::slice::{strip_bom, FilterError, RepeatedSeparator, SeparatorRules};
+use std::io::{BufRead, BufReader, Read};
+
+/// One non-blank line of a versions file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entry<'a> {
+    /// A header line before the separator, such as `created_at: ...`
+    Metadata(&'a str),
+    /// The `---` line ending the header
+    Separator,
+    /// A well-formed gem line
+    Gem(GemLine<'a>),
+    /// A line after the separator with fewer than three fields
+    Malformed {
+        /// The line, trimmed
+        line: &'a str,
+        /// The line as read, its line ending included
+        raw: &'a str,
+    },
+}
+
+/// A `name versions md5 [extra...]` line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GemLine<'a> {
+    /// Gem name
+    pub name: &'a str,
+    /// Comma-separated version list, as written
+    pub versions: &'a str,
+    /// MD5 of the gem's `info` file
+    pub md5: &'a str,
+    /// Fields after the MD5, as written; empty for most lines
+    pub extra: &'a str,
+    /// The whole line, trimmed
+    pub line: &'a str,
+    /// The line as given to [`GemLine::parse`]; from a [`VersionsReader`],
+    /// the bytes as read, line ending included, as the filter preserves them
+    pub raw: &'a str,
+}
+
+impl<'a> GemLine<'a> {
+    /// Parse a gem line, or `None` if it has fewer than three fields
+    pub fn parse(raw: &'a str) -> Option<Self> {
+        let line = raw.trim_ascii();
+        let mut rest = line;
+        let mut field = || {
+            let (field, tail) = rest
+                .split_once(|c: char| c.is_ascii_whitespace())
+                .unwrap_or((rest, ""));
+            rest = tail.trim_ascii_start();
+            (!field.is_empty()).then_some(field)
+        };
+        let (name, versions, md5) = (field()?, field()?, field()?);
+        Some(GemLine {
+            name,
+            versions,
+            md5,
+            extra: rest,
+            line,
+            raw,
+        })
+    }
+
+    /// The fields after the MD5, one by one
+    pub fn extra_fields(&self) -> impl Iterator<Item = &'a str> + 'a {
+        self.extra.split_ascii_whitespace()
+    }
+
+    /// The entries of the version list, in file order
+    pub fn version_entries(&self) -> impl Iterator<Item = VersionEntry<'a>> + 'a {
+        self.versions.split(',').map(VersionEntry::parse)
+    }
+}
+
+/// One `[-]number[-platform]` entry of a version list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionEntry<'a> {
+    /// Version number, such as `7.0.0` or `7.1.0.rc1`
+    pub number: &'a str,
+    /// Platform suffix; `None` for the default `ruby` platform
+    pub platform: Option<&'a str>,
+    /// Whether the entry records a yank (`-7.0.0`)
+    pub yanked: bool,
+}
+
+impl<'a> VersionEntry<'a> {
+    /// Split an entry into its parts; version numbers never contain `-`
+    pub fn parse(entry: &'a str) -> Self {
+        let (yanked, entry) = match entry.strip_prefix('-') {
+            Some(entry) => (true, entry),
+            None => (false, entry),
+        };
+        let (number, platform) = match entry.split_once('-') {
+            Some((number, platform)) => (number, Some(platform)),
+            None => (entry, None),
+        };
+        VersionEntry {
+            number,
+            platform,
+            yanked,
+        }
+    }
+
+    /// The platform, with `ruby` for entries that don't name one
+    pub fn platform_or_ruby(&self) -> &'a str {
+        self.platform.unwrap_or("ruby")
+    }
+}
+
+/// Streaming parser yielding one [`Entry`] per non-blank line
+pub struct VersionsReader<R: Read> {
+    reader: BufReader<R>,
+    line: String,
+    line_number: usize,
+    in_body: bool,
+    separators: SeparatorRules,
+}
+
+impl<R: Read> VersionsReader<R> {
+    /// Parse `input` from its first line
+    pub fn new(input: R) -> Self {
+        VersionsReader {
+            reader: BufReader::new(input),
+            line: String::new(),
+            line_number: 0,
+            in_body: false,
+            separators: SeparatorRules::default(),
+        }
+    }
+
+    /// Recognize the header's end, and handle later separators, as the
+    /// filter does with the same rules
+    pub fn separator_rules(mut self, separators: SeparatorRules) -> Self {
+        self.separators = separators;
+        self
+    }
+
+    /// The next entry, or `None` at end of input
+    ///
+    /// The entry borrows the reader's line buffer, so it must be dropped
+    /// before the next call.
+    pub fn next_entry(&mut self) -> std::io::Result<Option<Entry<'_>>> {
+        loop {
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+            self.line_number += 1;
+            if self.line.trim().is_empty() {
+                continue;
+            }
+            if !self.in_body || !self.separators.is_separator(&self.line) {
+                break;
+            }
+            match self.separators.repeated {
+                RepeatedSeparator::Body => break,
+                RepeatedSeparator::Skip => {}
+                RepeatedSeparator::Error => return Err(FilterError::RepeatedSeparator.into()),
+            }
+        }
+
+        let raw = match self.line_number {
+            1 => strip_bom(&self.line),
+            _ => &self.line,
+        };
+        let line = raw.trim();
+        if !self.in_body {
+            self.in_body = self.separators.is_separator(raw);
+            return Ok(Some(if self.in_body {
+                Entry::Separator
+            } else {
+                Entry::Metadata(line)
+            }));
+        }
+        Ok(Some(match GemLine::parse(raw) {
+            Some(gem) => Entry::Gem(gem),
+            None => Entry::Malformed { line, raw },
+        }))
+    }
+
+    /// 1-based line number of the entry last returned
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reader_yields_typed_entries() {
+        let input = "\u{FEFF}created_at: 2024-04-01T00:00:05Z\n---\n\
+            rails 7.0.0,-7.0.1,7.0.2-java abc123\n\n\
+            activerecord 7.0.0 def456 extra\r\n";
+        let mut reader = VersionsReader::new(input.as_bytes());
+
+        assert_eq!(
+            reader.next_entry().unwrap(),
+            Some(Entry::Metadata("created_at: 2024-04-01T00:00:05Z"))
+        );
+        assert_eq!(reader.next_entry().unwrap(), Some(Entry::Separator));
+
+        let Some(Entry::Gem(rails)) = reader.next_entry().unwrap() else {
+            panic!("expected a gem line");
+        };
+        assert_eq!((rails.name, rails.md5), ("rails", "abc123"));
+        let entries: Vec<_> = rails.version_entries().collect();
+        assert_eq!(
+            entries,
+            vec![
+                VersionEntry {
+                    number: "7.0.0",
+                    platform: None,
+                    yanked: false
+                },
+                VersionEntry {
+                    number: "7.0.1",
+                    platform: None,
+                    yanked: true
+                },
+                VersionEntry {
+                    number: "7.0.2",
+                    platform: Some("java"),
+                    yanked: false
+                },
+            ]
+        );
+        assert_eq!(reader.line_number(), 3);
+
+        // Blank lines are skipped but still counted; fields after the hash are kept
+        assert_eq!(
+            reader.next_entry().unwrap(),
+            Some(Entry::Gem(GemLine {
+                name: "activerecord",
+                versions: "7.0.0",
+                md5: "def456",
+                extra: "extra",
+                line: "activerecord 7.0.0 def456 extra",
+                raw: "activerecord 7.0.0 def456 extra\r\n",
+            }))
+        );
+        assert_eq!(reader.line_number(), 5);
+        assert_eq!(reader.next_entry().unwrap(), None);
+
+        assert_eq!(GemLine::parse("rails 7.0.0"), None);
+    }
+
+    #[test]
+    fn test_reader_follows_separator_rules() {
+        use crate::slice::SeparatorMatch;
+
+        let input = "created_at: 2024-04-01T00:00:05Z\n--- \nrails 7.0.0 abc123\n---\n";
+        let entries = |separators| {
+            let mut reader = VersionsReader::new(input.as_bytes()).separator_rules(separators);
+            let mut kinds = Vec::new();
+            while let Some(entry) = reader.next_entry()? {
+                kinds.push(match entry {
+                    Entry::Metadata(_) => "metadata",
+                    Entry::Separator => "separator",
+                    Entry::Gem(_) => "gem",
+                    Entry::Malformed { .. } => "malformed",
+                });
+            }
+            std::io::Result::Ok(kinds)
+        };
+
+        assert_eq!(
+            entries(SeparatorRules::default()).unwrap(),
+            ["metadata", "separator", "gem", "malformed"]
+        );
+        let skip = SeparatorRules {
+            repeated: RepeatedSeparator::Skip,
+            ..SeparatorRules::default()
+        };
+        assert_eq!(entries(skip).unwrap(), ["metadata", "separator", "gem"]);
+        let exact = SeparatorRules {
+            matching: SeparatorMatch::Exact,
+            repeated: RepeatedSeparator::Error,
+        };
+        assert_eq!(
+            entries(exact).unwrap(),
+            ["metadata", "metadata", "metadata", "separator"]
+        );
+        let error = SeparatorRules {
+            repeated: RepeatedSeparator::Error,
+            ..SeparatorRules::default()
+        };
+        assert_eq!(
+            entries(error).unwrap_err().to_string(),
+            "Separator line repeated after the header"
+        );
+    }
+}
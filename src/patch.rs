@@ -0,0 +1,221 @@
+//! Patch format for updating a local versions file from a small delta
+//!
+//! A patch records how many gem lines of the base file to keep, a SHA-256 of
+//! those lines so a mismatched base is detected, the new metadata section and
+//! the lines to append:
+//!
+//! ```text
+//! gem-index-filter-patch 1
+//! keep-lines: 2
+//! keep-sha256: 5f0c...e1
+//! created_at: 2024-04-01T00:00:05Z
+//! ---
+//! rails 7.0.1 fed321
+//! ```
+//!
+//! For an append-only update the patch is just the appended lines plus a few
+//! header lines, so edge nodes can refresh their copy without downloading the
+//! whole index. Both creating and applying a patch stream their inputs.
+
+use crate::diff::{diff_with_prefix_hook, hash_gem_line, read_gem_line, read_metadata};
+use crate::VersionsDiff;
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// First line of every patch, carrying the format version
+const PATCH_MAGIC: &str = "gem-index-filter-patch 1";
+
+/// Write a patch that turns `old` into `new`
+///
+/// Returns the same summary as [`crate::diff_versions`].
+pub fn write_patch<R1: Read, R2: Read, W: Write>(
+    old: R1,
+    new: R2,
+    output: &mut W,
+) -> std::io::Result<VersionsDiff> {
+    diff_with_prefix_hook(old, new, output, true, |output, prefix| {
+        writeln!(output, "{}", PATCH_MAGIC)?;
+        writeln!(output, "keep-lines: {}", prefix.lines)?;
+        writeln!(output, "keep-sha256: {}", prefix.sha256)?;
+        output.write_all(prefix.new_metadata.as_bytes())
+    })
+}
+
+/// Apply a patch produced by [`write_patch`] to `base`, writing the result to `output`
+///
+/// The kept prefix of `base` is verified against the patch's digest only once
+/// it has been streamed, so on error `output` holds a partial file and must be
+/// discarded (write to a temporary file and rename on success).
+pub fn apply_patch<R1: Read, R2: Read, W: Write>(
+    base: R1,
+    patch: R2,
+    output: &mut W,
+) -> std::io::Result<()> {
+    let mut base = BufReader::new(base);
+    let mut patch = BufReader::new(patch);
+
+    let mut line = String::new();
+    patch.read_line(&mut line)?;
+    if line.trim() != PATCH_MAGIC {
+        return Err(invalid_patch("Unrecognized patch header"));
+    }
+
+    let keep_lines: usize = read_header_field(&mut patch, &mut line, "keep-lines")?
+        .parse()
+        .map_err(|_| invalid_patch("Invalid keep-lines value in patch"))?;
+    let keep_sha256 = read_header_field(&mut patch, &mut line, "keep-sha256")?.to_string();
+
+    // The patch carries the complete new metadata; the base's is replaced
+    let metadata = read_metadata(&mut patch)?;
+    output.write_all(metadata.as_bytes())?;
+    read_metadata(&mut base)?;
+
+    if hex::encode(copy_kept_lines(&mut base, keep_lines, output)?) != keep_sha256 {
+        return Err(invalid_patch("Patch base does not match the patch digest"));
+    }
+
+    std::io::copy(&mut patch, output)?;
+    Ok(())
+}
+
+/// Copy the next `keep_lines` gem lines of `base`, whose metadata has been
+/// read, to `output`, returning the SHA-256 a patch records for them
+pub(crate) fn copy_kept_lines<R: Read, W: Write>(
+    base: &mut BufReader<R>,
+    keep_lines: usize,
+    output: &mut W,
+) -> std::io::Result<[u8; 32]> {
+    let mut line = String::new();
+    let mut hasher = Sha256::new();
+    for _ in 0..keep_lines {
+        if !read_gem_line(base, &mut line)? {
+            return Err(invalid_patch(
+                "Patch base is shorter than the patch expects",
+            ));
+        }
+        hash_gem_line(&mut hasher, &line);
+        output.write_all(line.as_bytes())?;
+        // A final base line without newline must not merge with the first appended line
+        if !line.ends_with('\n') {
+            output.write_all(b"\n")?;
+        }
+    }
+    Ok(hasher.finalize().into())
+}
+
+/// Read a `name: value` header line from the patch, returning the value
+fn read_header_field<'a, R: Read>(
+    patch: &mut BufReader<R>,
+    line: &'a mut String,
+    name: &str,
+) -> std::io::Result<&'a str> {
+    line.clear();
+    patch.read_line(line)?;
+    line.trim()
+        .strip_prefix(name)
+        .and_then(|rest| rest.strip_prefix(':'))
+        .map(str::trim)
+        .ok_or_else(|| invalid_patch(&format!("Missing {} in patch header", name)))
+}
+
+fn invalid_patch(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0 abc123
+sinatra 3.0.0 def456
+"#;
+
+    fn round_trip(old: &str, new: &str) -> (VersionsDiff, String) {
+        let mut patch = Vec::new();
+        let diff = write_patch(old.as_bytes(), new.as_bytes(), &mut patch).unwrap();
+
+        let mut output = Vec::new();
+        apply_patch(old.as_bytes(), patch.as_slice(), &mut output).unwrap();
+        (diff, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_append_patch_round_trip() {
+        let new = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0 abc123
+sinatra 3.0.0 def456
+rails 7.0.1 fed321
+"#;
+
+        let (diff, output) = round_trip(BASE, new);
+        assert!(diff.is_append_only());
+        assert_eq!(output, new);
+    }
+
+    #[test]
+    fn test_rewrite_patch_round_trip() {
+        let new = r#"created_at: 2024-05-01T00:00:05Z
+---
+rails 7.0.0 abc123
+puma 6.0.0 aaa111
+"#;
+
+        let (diff, output) = round_trip(BASE, new);
+        assert!(!diff.is_append_only());
+        assert_eq!(output, new);
+    }
+
+    #[test]
+    fn test_patch_contains_only_tail() {
+        let new = format!("{}rails 7.0.1 fed321\n", BASE);
+
+        let mut patch = Vec::new();
+        write_patch(BASE.as_bytes(), new.as_bytes(), &mut patch).unwrap();
+        let patch = String::from_utf8(patch).unwrap();
+
+        assert!(patch.starts_with("gem-index-filter-patch 1\nkeep-lines: 2\nkeep-sha256: "));
+        assert!(patch.ends_with("created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.1 fed321\n"));
+        assert!(!patch.contains("sinatra"));
+    }
+
+    #[test]
+    fn test_base_without_trailing_newline() {
+        let base = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123";
+        let new = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\nrails 7.0.1 fed321\n";
+
+        let mut patch = Vec::new();
+        write_patch(base.as_bytes(), new.as_bytes(), &mut patch).unwrap();
+
+        let mut output = Vec::new();
+        apply_patch(base.as_bytes(), patch.as_slice(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), new);
+    }
+
+    #[test]
+    fn test_mismatched_base_rejected() {
+        let new = format!("{}rails 7.0.1 fed321\n", BASE);
+        let mut patch = Vec::new();
+        write_patch(BASE.as_bytes(), new.as_bytes(), &mut patch).unwrap();
+
+        let other_base =
+            "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\npuma 6.0.0 aaa111\n";
+        let err =
+            apply_patch(other_base.as_bytes(), patch.as_slice(), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        let short_base = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+        let err =
+            apply_patch(short_base.as_bytes(), patch.as_slice(), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_invalid_patch_header() {
+        let err =
+            apply_patch(BASE.as_bytes(), "not a patch\n".as_bytes(), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
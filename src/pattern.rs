@@ -0,0 +1,398 @@
+//! Pattern entries in gem lists, expanded to exact names ahead of time
+//!
+//! People want to write `aws-sdk-*` rather than list three hundred gems, but
+//! the hot loop only does exact `HashSet` lookups and should stay that way.
+//! [`PatternList`] splits list entries into exact names and patterns, then
+//! [`expand`](PatternList::expand) resolves the patterns once against a
+//! `/names` file or a versions file, producing the plain set the filter uses.
+//!
+//! Entries containing `*`, `?` or `[` are globs; entries written as `/.../`
+//! are regular expressions (`regex` feature). Gem names can't contain any of
+//! those characters, so no existing exact entry changes meaning.
+//!
+//! Where patterns do run per line, popular gems appear hundreds of times in
+//! a versions file. [`CachedPatternList`] and expansion remember the decision
+//! for each name seen recently, so the patterns run about once per distinct
+//! name rather than once per line.
+
+use crate::diff::{read_gem_line, read_metadata};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{BufReader, Read};
+
+/// One non-exact list entry
+#[derive(Debug, Clone)]
+pub enum GemPattern {
+    /// Shell-style glob: `*`, `?` and `[a-z]` / `[!a-z]` classes
+    Glob(String),
+    /// Unanchored regular expression (`regex` feature)
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+}
+
+impl GemPattern {
+    /// Whether `name` matches this pattern
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            GemPattern::Glob(glob) => glob_match(glob, name),
+            #[cfg(feature = "regex")]
+            GemPattern::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// The entry as written in a list file
+impl fmt::Display for GemPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GemPattern::Glob(glob) => f.write_str(glob),
+            #[cfg(feature = "regex")]
+            GemPattern::Regex(regex) => write!(f, "/{}/", regex.as_str()),
+        }
+    }
+}
+
+/// Gem list entries split into exact names and patterns
+#[derive(Debug, Clone, Default)]
+pub struct PatternList {
+    exact: HashSet<String>,
+    patterns: Vec<GemPattern>,
+}
+
+impl PatternList {
+    /// Classify list entries, e.g. the output of [`parse_gem_list`](crate::parse_gem_list)
+    pub fn parse<I: IntoIterator<Item = String>>(entries: I) -> std::io::Result<Self> {
+        let mut list = PatternList::default();
+        for entry in entries {
+            if entry.len() > 1 && entry.starts_with('/') && entry.ends_with('/') {
+                list.patterns
+                    .push(regex_pattern(&entry[1..entry.len() - 1])?);
+            } else if entry.contains(['*', '?', '[']) {
+                list.patterns.push(GemPattern::Glob(entry));
+            } else {
+                list.exact.insert(entry);
+            }
+        }
+        Ok(list)
+    }
+
+    /// True when there is nothing to expand
+    pub fn is_exact(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Entries listed by exact name
+    pub(crate) fn exact(&self) -> &HashSet<String> {
+        &self.exact
+    }
+
+    /// Glob and regex entries
+    pub(crate) fn patterns(&self) -> &[GemPattern] {
+        &self.patterns
+    }
+
+    /// Whether `name` is listed exactly or matches any pattern
+    pub fn matches(&self, name: &str) -> bool {
+        self.exact.contains(name) || self.patterns.iter().any(|p| p.matches(name))
+    }
+
+    /// Resolve patterns against every gem name in `index`, returning an exact set
+    ///
+    /// `index` may be a `/names` file or a versions file: both have a `---`
+    /// separator followed by lines starting with a gem name. Exact entries
+    /// are kept even if the index doesn't mention them. Only matching names
+    /// are retained, so memory stays proportional to the result.
+    pub fn expand<R: Read>(self, index: R) -> std::io::Result<HashSet<String>> {
+        let PatternList {
+            mut exact,
+            patterns,
+        } = self;
+        if patterns.is_empty() {
+            return Ok(exact);
+        }
+
+        let mut reader = BufReader::new(index);
+        read_metadata(&mut reader)?;
+        let mut line = String::new();
+        // Matched names land in `exact`; this spares repeats of the rest
+        let rejected = DecisionCache::new();
+        while read_gem_line(&mut reader, &mut line)? {
+            let Some(name) = line.split_whitespace().next() else {
+                continue;
+            };
+            if !exact.contains(name)
+                && rejected.decide(name, || patterns.iter().any(|p| p.matches(name)))
+            {
+                exact.insert(name.to_string());
+            }
+        }
+        Ok(exact)
+    }
+
+    /// This list with a per-run memo of pattern decisions, for matching line by line
+    pub fn cached(self) -> CachedPatternList {
+        CachedPatternList {
+            list: self,
+            decisions: DecisionCache::new(),
+        }
+    }
+}
+
+/// A [`PatternList`] that remembers which names its patterns matched
+///
+/// Meant for one pass over a versions file, where the same names come up
+/// again and again: the patterns run once per distinct name among the last
+/// [`DECISION_CACHE_SIZE`] or so, and exact entries are looked up as usual.
+/// Build one with [`PatternList::cached`].
+#[derive(Debug)]
+pub struct CachedPatternList {
+    list: PatternList,
+    decisions: DecisionCache<bool>,
+}
+
+impl CachedPatternList {
+    /// Whether `name` is listed exactly or matches any pattern
+    pub fn matches(&self, name: &str) -> bool {
+        if self.list.exact.contains(name) {
+            return true;
+        }
+        if self.list.patterns.is_empty() {
+            return false;
+        }
+        self.decisions
+            .decide(name, || self.list.patterns.iter().any(|p| p.matches(name)))
+    }
+
+    /// The list without its cache
+    pub fn into_inner(self) -> PatternList {
+        self.list
+    }
+}
+
+/// Distinct names a decision cache holds before it starts over
+///
+/// Big enough for the gems that repeat in the appended part of a versions
+/// file, small enough not to matter next to the filter's own memory.
+pub const DECISION_CACHE_SIZE: usize = 4096;
+
+/// Name to decision memo, emptied whenever it fills up
+///
+/// Starting over is cruder than evicting the least recently used name, but
+/// costs nothing per lookup, and names repeated often are cached again
+/// straight away.
+#[derive(Debug)]
+pub(crate) struct DecisionCache<T = bool> {
+    decisions: RefCell<HashMap<Box<str>, T>>,
+}
+
+impl<T: Clone> DecisionCache<T> {
+    pub(crate) fn new() -> Self {
+        DecisionCache {
+            decisions: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// The decision for `name`, running `decide` unless it is remembered
+    pub(crate) fn decide(&self, name: &str, decide: impl FnOnce() -> T) -> T {
+        if let Some(decision) = self.decisions.borrow().get(name) {
+            return decision.clone();
+        }
+        let decision = decide();
+        let mut decisions = self.decisions.borrow_mut();
+        if decisions.len() >= DECISION_CACHE_SIZE {
+            decisions.clear();
+        }
+        decisions.insert(name.into(), decision.clone());
+        decision
+    }
+}
+
+impl From<HashSet<String>> for PatternList {
+    /// A list of exact names only
+    fn from(exact: HashSet<String>) -> Self {
+        PatternList {
+            exact,
+            patterns: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "regex")]
+fn regex_pattern(source: &str) -> std::io::Result<GemPattern> {
+    regex::Regex::new(source)
+        .map(GemPattern::Regex)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(not(feature = "regex"))]
+fn regex_pattern(source: &str) -> std::io::Result<GemPattern> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!(
+            "Regex entry /{}/ requires gem-index-filter to be built with the regex feature",
+            source
+        ),
+    ))
+}
+
+/// Match a shell-style glob against the whole of `name`
+pub fn glob_match(glob: &str, name: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut g, mut n) = (0, 0);
+    // Where to resume after the most recent `*` if the current attempt fails
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        let step = match glob.get(g) {
+            Some('*') => {
+                backtrack = Some((g, n));
+                g += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match_class(&glob[g..], name[n]),
+            Some(&c) if c == name[n] => Some(1),
+            _ => None,
+        };
+        match (step, backtrack) {
+            (Some(width), _) => {
+                g += width;
+                n += 1;
+            }
+            // Let the last `*` swallow one more character and retry
+            (None, Some((star, matched))) => {
+                backtrack = Some((star, matched + 1));
+                g = star + 1;
+                n = matched + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// Match `c` against the class at the start of `glob`, returning the class width
+///
+/// An unterminated `[` is treated as a literal, as shells do.
+fn match_class(glob: &[char], c: char) -> Option<usize> {
+    let Some(end) = glob.iter().skip(2).position(|&ch| ch == ']').map(|i| i + 2) else {
+        return (c == '[').then_some(1);
+    };
+    let (negated, body) = match glob[1] {
+        '!' | '^' => (true, &glob[2..end]),
+        _ => (false, &glob[1..end]),
+    };
+
+    let mut found = false;
+    let mut i = 0;
+    while i < body.len() {
+        if i + 2 < body.len() && body[i + 1] == '-' {
+            found |= (body[i]..=body[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= body[i] == c;
+            i += 1;
+        }
+    }
+    (found != negated).then_some(end + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("aws-sdk-*", "aws-sdk-s3"));
+        assert!(glob_match("aws-sdk-*", "aws-sdk-"));
+        assert!(!glob_match("aws-sdk-*", "aws-sigv4"));
+        assert!(glob_match("*-rails", "rspec-rails"));
+        assert!(glob_match("rack*", "rack"));
+        assert!(glob_match("net-?ttp", "net-http"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(!glob_match("*a*b", "xxaxxbxx"));
+        assert!(glob_match("rails[0-9]", "rails5"));
+        assert!(!glob_match("rails[!0-9]", "rails5"));
+        assert!(glob_match("[_.]*", "_foo"));
+        assert!(!glob_match("rails", "rails-html"));
+        assert!(glob_match("a[b", "a[b"));
+    }
+
+    #[test]
+    fn test_expand_against_versions_and_names_files() {
+        let entries = ["rails", "aws-sdk-*", "*-rails", "gone"].map(String::from);
+
+        let versions = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0 abc123
+aws-sdk-s3 1.0.0 def456
+aws-sigv4 1.0.0 ghi789
+rspec-rails 6.0.0 jkl000
+aws-sdk-s3 1.0.1 mno111
+"#;
+        let list = PatternList::parse(entries.clone()).unwrap();
+        assert!(!list.is_exact());
+        assert!(list.matches("aws-sdk-ec2"));
+        let mut gems: Vec<_> = list
+            .expand(versions.as_bytes())
+            .unwrap()
+            .into_iter()
+            .collect();
+        gems.sort();
+        assert_eq!(gems, ["aws-sdk-s3", "gone", "rails", "rspec-rails"]);
+
+        let names = "---\naws-sdk-ec2\naws-sdk-s3\nrails\n";
+        let list = PatternList::parse(entries).unwrap();
+        let mut gems: Vec<_> = list.expand(names.as_bytes()).unwrap().into_iter().collect();
+        gems.sort();
+        assert_eq!(gems, ["aws-sdk-ec2", "aws-sdk-s3", "gone", "rails"]);
+    }
+
+    #[test]
+    fn test_decisions_are_cached_per_name() {
+        let cache = DecisionCache::new();
+        let calls = std::cell::Cell::new(0);
+        let decide = |name: &str| {
+            cache.decide(name, || {
+                calls.set(calls.get() + 1);
+                glob_match("aws-*", name)
+            })
+        };
+        for name in ["aws-sdk-s3", "rails", "aws-sdk-s3", "rails", "aws-sdk-s3"] {
+            assert_eq!(decide(name), name.starts_with("aws-"));
+        }
+        assert_eq!(calls.get(), 2);
+
+        for i in 0..DECISION_CACHE_SIZE {
+            decide(&format!("gem-{}", i));
+        }
+        assert!(cache.decisions.borrow().len() <= DECISION_CACHE_SIZE);
+        assert!(!decide("rails"));
+
+        let entries = ["rails", "aws-sdk-*"].map(String::from);
+        let list = PatternList::parse(entries).unwrap().cached();
+        assert!(list.matches("rails"));
+        assert!(list.matches("aws-sdk-s3"));
+        assert!(list.matches("aws-sdk-s3"));
+        assert!(!list.matches("puma"));
+        assert!(!list.into_inner().is_exact());
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[test]
+    fn test_regex_entries_need_the_feature() {
+        let err = PatternList::parse(["/^aws-/".to_string()]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex_entries() {
+        let list = PatternList::parse(["/^aws-sdk-(s3|ec2)$/".to_string()]).unwrap();
+        assert!(list.matches("aws-sdk-s3"));
+        assert!(!list.matches("aws-sdk-s3control"));
+    }
+}
@@ -0,0 +1,208 @@
+//! Checking a download against a pinned checksum
+//!
+//! A compromised upstream or intercepting proxy can serve a well-formed
+//! versions file with altered lines, which the filter would pass straight
+//! through. [`PinnedReader`] hashes the input as it streams and turns the end
+//! of input into a [`ChecksumMismatch`] error when the digest differs from the
+//! expected one. The filter fails before its output is committed, so the
+//! previous good copy stays in place.
+//!
+//! The expected digest can be given directly or taken from a checksum
+//! manifest (`sha256sum` output) published next to the file, optionally
+//! signed with the `.sig` format of [`sign`](crate::sign).
+
+use crate::writers::DigestWriter;
+use crate::DigestAlgorithm;
+use std::fmt;
+use std::io::{Read, Write};
+
+/// The input's digest differs from the pinned one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    /// Algorithm both digests were computed with
+    pub algorithm: DigestAlgorithm,
+    /// Hex digest that was pinned
+    pub expected: String,
+    /// Hex digest of what was read
+    pub actual: String,
+}
+
+impl ChecksumMismatch {
+    /// The mismatch behind an I/O error returned by a filter run, if any
+    pub fn from_io_error(error: &std::io::Error) -> Option<&ChecksumMismatch> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Input {} mismatch: expected {}, got {}",
+            self.algorithm.name(),
+            self.expected,
+            self.actual
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+impl From<ChecksumMismatch> for std::io::Error {
+    fn from(mismatch: ChecksumMismatch) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, mismatch)
+    }
+}
+
+/// Reader wrapper that fails at end of input unless the input matches a pinned digest
+pub struct PinnedReader<R: Read> {
+    inner: R,
+    expected: String,
+    algorithm: DigestAlgorithm,
+    /// Taken when the digest is checked, so a repeated read at EOF doesn't re-check
+    digest: Option<DigestWriter<std::io::Sink>>,
+}
+
+impl<R: Read> PinnedReader<R> {
+    /// Wrap `inner`, expecting the hex `digest`; SHA-256 or SHA-512 is picked by its length
+    pub fn new(inner: R, digest: &str) -> std::io::Result<Self> {
+        let expected = digest.trim().to_ascii_lowercase();
+        let algorithm = match expected.len() {
+            64 => DigestAlgorithm::Sha256,
+            128 => DigestAlgorithm::Sha512,
+            _ => {
+                return Err(invalid(format!(
+                    "'{}' is not a SHA-256 or SHA-512 hex digest",
+                    digest
+                )))
+            }
+        };
+        if !expected.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid(format!("'{}' is not a hex digest", digest)));
+        }
+        Ok(PinnedReader {
+            inner,
+            expected,
+            algorithm,
+            digest: Some(DigestWriter::new(std::io::sink(), algorithm)),
+        })
+    }
+
+    fn check(&mut self) -> Result<(), ChecksumMismatch> {
+        let Some(digest) = self.digest.take() else {
+            return Ok(());
+        };
+        let actual = digest.finalize();
+        if actual == self.expected {
+            return Ok(());
+        }
+        Err(ChecksumMismatch {
+            algorithm: self.algorithm,
+            expected: self.expected.clone(),
+            actual,
+        })
+    }
+}
+
+impl<R: Read> Read for PinnedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.check()?;
+        }
+        if let Some(digest) = &mut self.digest {
+            digest.write_all(&buf[..n])?;
+        }
+        Ok(n)
+    }
+}
+
+/// The digest listed for `file_name` in a `sha256sum`-style manifest
+///
+/// A manifest with a single entry may name any file, so a bare digest works too.
+pub fn manifest_digest(manifest: &str, file_name: &str) -> std::io::Result<String> {
+    let entries: Vec<(&str, Option<&str>)> = manifest
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.split_whitespace();
+            let digest = fields.next().unwrap_or_default();
+            // sha256sum marks binary mode with '*' before the name
+            (
+                digest,
+                fields.next().map(|name| name.trim_start_matches('*')),
+            )
+        })
+        .collect();
+    let found = match entries[..] {
+        [(digest, _)] => Some(digest),
+        _ => entries
+            .iter()
+            .find(|(_, name)| name.is_some_and(|name| name.rsplit('/').next() == Some(file_name)))
+            .map(|(digest, _)| *digest),
+    };
+    found
+        .map(str::to_string)
+        .ok_or_else(|| invalid(format!("Checksum manifest has no entry for {}", file_name)))
+}
+
+fn invalid(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filter_versions_streaming, FilterMode, VersionOutput};
+
+    const VERSIONS: &str = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+    const VERSIONS_SHA256: &str =
+        "8b3ecdd38845689b6742975916f2358f8db22b0562e332dea181cd5d0b69998e";
+
+    fn filter(input: &str, digest: &str) -> std::io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        filter_versions_streaming(
+            PinnedReader::new(input.as_bytes(), digest)?,
+            &mut output,
+            FilterMode::Passthrough,
+            VersionOutput::Preserve,
+            None,
+        )?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_pinned_digest_must_match() {
+        assert_eq!(
+            filter(VERSIONS, VERSIONS_SHA256).unwrap(),
+            VERSIONS.as_bytes()
+        );
+
+        let tampered = VERSIONS.replace("7.0.0", "7.0.1");
+        let err = filter(&tampered, VERSIONS_SHA256).unwrap_err();
+        let mismatch = ChecksumMismatch::from_io_error(&err).unwrap();
+        assert_eq!(mismatch.expected, VERSIONS_SHA256);
+
+        assert!(filter(VERSIONS, "abc").is_err());
+    }
+
+    #[test]
+    fn test_manifest_digest() {
+        let manifest = format!(
+            "{}  versions\n{}  *names\n",
+            VERSIONS_SHA256,
+            "0".repeat(64)
+        );
+        assert_eq!(
+            manifest_digest(&manifest, "versions").unwrap(),
+            VERSIONS_SHA256
+        );
+        assert_eq!(manifest_digest(&manifest, "names").unwrap(), "0".repeat(64));
+        assert!(manifest_digest(&manifest, "info").is_err());
+        assert_eq!(
+            manifest_digest(VERSIONS_SHA256, "versions").unwrap(),
+            VERSIONS_SHA256
+        );
+    }
+}
@@ -0,0 +1,492 @@
+//! Composable per-line filters for integrators
+//!
+//! [`filter_versions_streaming`](crate::filter_versions_streaming) covers
+//! name-based selection with specialized loops. Anything else, such as a
+//! house rule that drops gems by naming convention or rewrites version
+//! lists, implements [`GemFilter`] and is registered on a [`FilterPipeline`],
+//! which runs each line through the filters in order. The built-in
+//! [`FilterMode`] and [`VersionRules`] are filters too, so custom ones
+//! compose with them, as is `LineRegex` with the `regex` feature.
+//!
+//! Lines the filters keep then pass through any [`LineTransform`]s on their
+//! way out, after the version list is stripped if it is, so output can be
+//! rewritten (tagged, say) without re-implementing the stripping.
+//! [`StripVersions`] is that stripping, as a transform of its own.
+//!
+//! The pipeline pays for a dynamic call per filter per line; use the plain
+//! streaming filter when name selection is all that's needed.
+
+#[cfg(not(feature = "digest"))]
+use crate::filter::digest_unsupported;
+#[cfg(feature = "digest")]
+use crate::filter::DigestWriter;
+use crate::filter::{pass_through_metadata, OUTPUT_BATCH};
+use crate::policy::VersionRules;
+use crate::slice::{
+    gem_name, stripped_fields, FilterError, NamelessLines, RepeatedSeparator, SeparatorRules,
+};
+use crate::{DigestAlgorithm, FilterMode, VersionOutput};
+use std::borrow::Cow;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+/// A decision about each gem line, optionally rewriting it
+pub trait GemFilter {
+    /// Return `None` to drop `line`, or the line to pass on, rewritten or not
+    ///
+    /// `line` is trimmed and has no newline; `name` is its first field.
+    /// Rewrites must keep the gem name, since later filters are handed the
+    /// same `name`.
+    fn filter<'a>(&self, name: &str, line: Cow<'a, str>) -> Option<Cow<'a, str>>;
+}
+
+/// Plain predicates on the gem name keep or drop lines unchanged
+impl<F: Fn(&str) -> bool> GemFilter for F {
+    fn filter<'a>(&self, name: &str, line: Cow<'a, str>) -> Option<Cow<'a, str>> {
+        self(name).then_some(line)
+    }
+}
+
+impl GemFilter for FilterMode<'_> {
+    fn filter<'a>(&self, name: &str, line: Cow<'a, str>) -> Option<Cow<'a, str>> {
+        let keep = match self {
+            FilterMode::Passthrough => true,
+            FilterMode::Allow(gems) => gems.contains(name),
+            FilterMode::Block(gems) => !gems.contains(name),
+        };
+        keep.then_some(line)
+    }
+}
+
+impl GemFilter for VersionRules {
+    fn filter<'a>(&self, _name: &str, line: Cow<'a, str>) -> Option<Cow<'a, str>> {
+        if self.is_noop() {
+            return Some(line);
+        }
+        let mut rewritten = String::with_capacity(line.len());
+        self.apply(&line, &mut rewritten)
+            .then_some(Cow::Owned(rewritten))
+    }
+}
+
+/// A rewrite of each kept line, applied just before it is written
+pub trait LineTransform {
+    /// Return `line` as it should be written
+    ///
+    /// `line` is trimmed and has no newline. It has already had its version
+    /// list stripped, if the output strips them, and been through the
+    /// transforms registered before this one. Dropping lines is for filters.
+    fn transform<'a>(&self, line: Cow<'a, str>) -> Cow<'a, str>;
+}
+
+/// Replaces the version list with `0`, as [`VersionOutput::Strip`] does
+///
+/// Lines with fewer than three fields are left as they are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StripVersions;
+
+impl LineTransform for StripVersions {
+    fn transform<'a>(&self, line: Cow<'a, str>) -> Cow<'a, str> {
+        let stripped = stripped_fields(&line).map(|fields| fields.collect::<Vec<_>>().join(" "));
+        stripped.map_or(line, Cow::Owned)
+    }
+}
+
+/// Keeps gem lines where a regular expression matches anywhere in the line
+/// (`regex` feature)
+///
+/// Unlike list patterns, which only see the name, this sees the version list
+/// and MD5 too, like `grep` over the gem lines that leaves the header alone.
+#[cfg(feature = "regex")]
+#[derive(Debug, Clone)]
+pub struct LineRegex(regex::Regex);
+
+#[cfg(feature = "regex")]
+impl LineRegex {
+    /// Compile `pattern`, unanchored
+    pub fn new(pattern: &str) -> std::io::Result<Self> {
+        regex::Regex::new(pattern)
+            .map(LineRegex)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+    }
+}
+
+#[cfg(feature = "regex")]
+impl GemFilter for LineRegex {
+    fn filter<'a>(&self, _name: &str, line: Cow<'a, str>) -> Option<Cow<'a, str>> {
+        self.0.is_match(&line).then_some(line)
+    }
+}
+
+/// Filters applied in registration order; a line is written only if all keep it
+///
+/// Kept lines are then rewritten by the registered transforms, in order.
+#[derive(Default)]
+pub struct FilterPipeline<'f> {
+    filters: Vec<Box<dyn GemFilter + 'f>>,
+    transforms: Vec<Box<dyn LineTransform + 'f>>,
+    nameless: NamelessLines,
+    separators: SeparatorRules,
+}
+
+impl<'f> FilterPipeline<'f> {
+    /// An empty pipeline, which passes every line through
+    pub fn new() -> Self {
+        FilterPipeline {
+            filters: Vec::new(),
+            transforms: Vec::new(),
+            nameless: NamelessLines::default(),
+            separators: SeparatorRules::default(),
+        }
+    }
+
+    /// Choose how gem lines without a space are handled
+    ///
+    /// With [`NamelessLines::WholeLine`] the filters are handed the whole
+    /// line as the name; the other choices bypass them.
+    pub fn nameless_lines(mut self, nameless: NamelessLines) -> Self {
+        self.nameless = nameless;
+        self
+    }
+
+    /// Choose how separator lines are recognized and repeated ones handled
+    pub fn separator_rules(mut self, separators: SeparatorRules) -> Self {
+        self.separators = separators;
+        self
+    }
+
+    /// Add a filter after those already registered
+    pub fn register(&mut self, filter: impl GemFilter + 'f) -> &mut Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Builder form of [`register`](Self::register)
+    pub fn with(mut self, filter: impl GemFilter + 'f) -> Self {
+        self.register(filter);
+        self
+    }
+
+    /// Add a transform after those already registered
+    pub fn register_transform(&mut self, transform: impl LineTransform + 'f) -> &mut Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Builder form of [`register_transform`](Self::register_transform)
+    pub fn with_transform(mut self, transform: impl LineTransform + 'f) -> Self {
+        self.register_transform(transform);
+        self
+    }
+
+    /// Number of registered filters
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// True when no filter is registered
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Run one trimmed gem line through every filter
+    pub fn filter_line<'a>(&self, line: &'a str) -> Option<Cow<'a, str>> {
+        self.run_filters(gem_name(line)?, line)
+    }
+
+    fn run_filters<'a>(&self, name: &str, line: &'a str) -> Option<Cow<'a, str>> {
+        self.filters
+            .iter()
+            .try_fold(Cow::Borrowed(line), |line, filter| {
+                filter.filter(name, line)
+            })
+    }
+
+    /// A trimmed line without a space, handled as [`nameless_lines`](Self::nameless_lines) says
+    fn filter_nameless<'a>(&self, line: &'a str) -> Result<Option<Cow<'a, str>>, FilterError> {
+        match self.nameless {
+            NamelessLines::Skip => Ok(None),
+            NamelessLines::Keep => Ok(Some(Cow::Borrowed(line))),
+            NamelessLines::WholeLine => Ok(self.run_filters(line, line)),
+            NamelessLines::Error => Err(FilterError::NamelessLine),
+        }
+    }
+
+    /// Stream a versions file through the pipeline
+    ///
+    /// Metadata is passed through as by
+    /// [`filter_versions_streaming`](crate::filter_versions_streaming), and the
+    /// return value is the same optional digest of what was written.
+    pub fn filter_versions<R: Read, W: Write>(
+        &self,
+        input: R,
+        output: &mut W,
+        version_output: VersionOutput,
+        digest_algorithm: Option<DigestAlgorithm>,
+    ) -> std::io::Result<Option<String>> {
+        let mut reader = BufReader::new(input);
+        match digest_algorithm {
+            #[cfg(feature = "digest")]
+            Some(algorithm) => {
+                let mut digest_writer = DigestWriter::new(output, algorithm);
+                self.process(&mut reader, &mut digest_writer, version_output)?;
+                Ok(Some(digest_writer.finalize()))
+            }
+            #[cfg(not(feature = "digest"))]
+            Some(algorithm) => Err(digest_unsupported(algorithm)),
+            None => {
+                self.process(&mut reader, output, version_output)?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn process<R: Read, W: Write>(
+        &self,
+        reader: &mut BufReader<R>,
+        output: &mut W,
+        version_output: VersionOutput,
+    ) -> std::io::Result<()> {
+        let output = &mut BufWriter::with_capacity(OUTPUT_BATCH, output);
+        pass_through_metadata(reader, output, self.separators)?;
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return output.flush();
+            }
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            let kept = match gem_name(trimmed) {
+                Some(name) => self.run_filters(name, trimmed),
+                None => match self.separators.repeated {
+                    RepeatedSeparator::Body => self.filter_nameless(trimmed)?,
+                    _ if !self.separators.is_separator(&line) => self.filter_nameless(trimmed)?,
+                    RepeatedSeparator::Skip => None,
+                    RepeatedSeparator::Error => return Err(FilterError::RepeatedSeparator.into()),
+                },
+            };
+            let Some(kept) = kept else {
+                continue;
+            };
+
+            let kept = match version_output {
+                VersionOutput::Preserve => kept,
+                VersionOutput::Strip => StripVersions.transform(kept),
+            };
+            let kept = self
+                .transforms
+                .iter()
+                .fold(kept, |line, transform| transform.transform(line));
+            // A line nothing rewrote keeps its bytes, line ending and all
+            if version_output == VersionOutput::Preserve && kept == trimmed {
+                output.write_all(line.as_bytes())?;
+            } else {
+                writeln!(output, "{}", kept)?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const VERSIONS: &str = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0,7.1.0.rc1 abc123
+rails-html-sanitizer 1.6.0 def456
+sinatra 3.0.0 ghi789
+internal-billing 0.1.0 jkl000
+"#;
+
+    /// Keeps only the first listed version, to check later filters see the rewrite
+    struct FirstVersion;
+
+    impl GemFilter for FirstVersion {
+        fn filter<'a>(&self, name: &str, line: Cow<'a, str>) -> Option<Cow<'a, str>> {
+            let mut fields = line.split(' ').skip(1);
+            let first = fields.next()?.split(',').next()?;
+            let rest: Vec<&str> = fields.collect();
+            Some(Cow::Owned(format!("{} {} {}", name, first, rest.join(" "))))
+        }
+    }
+
+    fn run(pipeline: &FilterPipeline) -> String {
+        let mut output = Vec::new();
+        pipeline
+            .filter_versions(
+                VERSIONS.as_bytes(),
+                &mut output,
+                VersionOutput::Preserve,
+                None,
+            )
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_filters_compose_in_order() {
+        let blocked: HashSet<&str> = ["sinatra"].into_iter().collect();
+        let mut rules = VersionRules::new();
+        rules.prereleases = false;
+
+        let mut pipeline = FilterPipeline::new()
+            .with(FilterMode::Block(&blocked))
+            .with(|name: &str| !name.starts_with("internal-"));
+        pipeline.register(rules);
+        assert_eq!(pipeline.len(), 3);
+
+        assert_eq!(
+            run(&pipeline),
+            "created_at: 2024-04-01T00:00:05Z\n---\n\
+             rails 7.0.0 abc123\n\
+             rails-html-sanitizer 1.6.0 def456\n"
+        );
+    }
+
+    #[test]
+    fn test_rewrites_are_passed_on() {
+        let pipeline = FilterPipeline::new()
+            .with(FirstVersion)
+            .with(|name: &str| name != "sinatra")
+            .with(VersionRules {
+                max_versions: Some(1),
+                ..VersionRules::new()
+            });
+        assert_eq!(
+            pipeline.filter_line("rails 7.0.0,7.1.0 abc123").as_deref(),
+            Some("rails 7.0.0 abc123")
+        );
+        assert_eq!(pipeline.filter_line("sinatra 3.0.0 ghi789"), None);
+
+        // An empty pipeline is a passthrough
+        assert_eq!(run(&FilterPipeline::new()), VERSIONS);
+    }
+
+    /// Marks lines for an internal audit, after any stripping
+    struct Tag(&'static str);
+
+    impl LineTransform for Tag {
+        fn transform<'a>(&self, line: Cow<'a, str>) -> Cow<'a, str> {
+            Cow::Owned(format!("{} {}", line, self.0))
+        }
+    }
+
+    #[test]
+    fn test_transforms_follow_stripping() {
+        let pipeline = FilterPipeline::new()
+            .with(|name: &str| name.starts_with("rails"))
+            .with_transform(Tag("audited"));
+        let mut output = Vec::new();
+        pipeline
+            .filter_versions(VERSIONS.as_bytes(), &mut output, VersionOutput::Strip, None)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "created_at: 2024-04-01T00:00:05Z\n---\n\
+             rails 0 abc123 audited\n\
+             rails-html-sanitizer 0 def456 audited\n"
+        );
+
+        assert_eq!(
+            StripVersions.transform("sinatra 3.0.0  ghi789 extra".into()),
+            "sinatra 0 ghi789 extra"
+        );
+        assert_eq!(
+            StripVersions.transform("sinatra 3.0.0".into()),
+            "sinatra 3.0.0"
+        );
+    }
+
+    #[test]
+    fn test_unchanged_lines_keep_their_bytes() {
+        let input = "---\r\nrails 7.0.0 abc123\r\n  sinatra 3.0.0 ghi789\npuma 6.0.0 mno111";
+        let run = |pipeline: FilterPipeline| {
+            let mut output = Vec::new();
+            pipeline
+                .filter_versions(input.as_bytes(), &mut output, VersionOutput::Preserve, None)
+                .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(run(FilterPipeline::new()), input);
+        assert_eq!(
+            run(FilterPipeline::new().with_transform(Tag("audited"))),
+            "---\r\nrails 7.0.0 abc123 audited\nsinatra 3.0.0 ghi789 audited\n\
+             puma 6.0.0 mno111 audited\n"
+        );
+    }
+
+    #[test]
+    fn test_separator_rules() {
+        use crate::slice::SeparatorMatch;
+
+        let input = "created_at: 2024-04-01T00:00:05Z\n--- \nrails 7.0.0 abc123\n---\n";
+        let run = |separators| {
+            let mut output = Vec::new();
+            FilterPipeline::new()
+                .separator_rules(separators)
+                .filter_versions(input.as_bytes(), &mut output, VersionOutput::Preserve, None)
+                .map(|_| String::from_utf8(output).unwrap())
+        };
+
+        assert_eq!(
+            run(SeparatorRules::default()).unwrap(),
+            "created_at: 2024-04-01T00:00:05Z\n--- \nrails 7.0.0 abc123\n"
+        );
+        let err = run(SeparatorRules {
+            repeated: RepeatedSeparator::Error,
+            ..SeparatorRules::default()
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Separator line repeated after the header");
+
+        // Matched exactly, only the last line ends the header
+        let exact = SeparatorRules {
+            matching: SeparatorMatch::Exact,
+            repeated: RepeatedSeparator::Error,
+        };
+        assert_eq!(run(exact).unwrap(), input);
+    }
+
+    #[test]
+    fn test_nameless_lines() {
+        let input = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\npuma\nsinatra\n";
+        let run = |nameless| {
+            let mut output = Vec::new();
+            FilterPipeline::new()
+                .nameless_lines(nameless)
+                .with(|name: &str| name != "sinatra")
+                .filter_versions(input.as_bytes(), &mut output, VersionOutput::Preserve, None)
+                .map(|_| String::from_utf8(output).unwrap())
+        };
+        let header = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+
+        assert_eq!(run(NamelessLines::Skip).unwrap(), header);
+        assert_eq!(
+            run(NamelessLines::Keep).unwrap(),
+            format!("{}puma\nsinatra\n", header)
+        );
+        assert_eq!(
+            run(NamelessLines::WholeLine).unwrap(),
+            format!("{}puma\n", header)
+        );
+        let err = run(NamelessLines::Error).unwrap_err();
+        assert_eq!(err.to_string(), "Gem line has no space after the name");
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_line_regex_sees_whole_line() {
+        let pipeline = FilterPipeline::new().with(LineRegex::new(r"\brc\d|^sinatra ").unwrap());
+        assert_eq!(
+            run(&pipeline),
+            "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0,7.1.0.rc1 abc123\nsinatra 3.0.0 ghi789\n"
+        );
+        assert!(LineRegex::new("(").is_err());
+    }
+}
@@ -0,0 +1,545 @@
+//! Declarative filter policy (`policy.toml`)
+//!
+//! Gem selection and version-level rules in one file instead of a handful of
+//! flags and environment variables:
+//!
+//! ```toml
+//! [gems]
+//! allow = ["rails", "aws-sdk-*"]     # omit to allow everything not blocked
+//! block = ["rails-html-sanitizer"]   # patterns as in `--allow` files
+//!
+//! [versions]
+//! yanked = "keep"                    # or "drop"
+//! prerelease = "drop"                # or "keep" (default)
+//! platforms = ["ruby", "x86_64-linux"]
+//! max_versions = 20                  # per line, newest kept
+//! strip = false
+//!
+//! [versions.constraints]
+//! rails = ">= 7.0, < 8"
+//! ```
+//!
+//! Version rules rewrite each line's version list; a line left with no
+//! versions is dropped. They act on the versions file only, which is what
+//! Bundler reads to decide what exists: `info/<gem>` files are untouched.
+//! `max_versions` counts within a line, because keeping a per-gem tally
+//! across the file would mean holding state for every gem.
+
+use crate::parser::VersionEntry;
+use crate::pattern::PatternList;
+use crate::pipeline::FilterPipeline;
+use crate::slice::{option_names, strip_bom};
+use crate::version::{GemVersion, Requirement};
+use crate::{filter_versions_streaming, DigestAlgorithm, FilterMode, VersionOutput};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// What to do with yank records (`-1.0.0` entries)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum YankHandling {
+    /// Pass them through so clients learn about yanks
+    #[default]
+    Keep,
+    /// Remove them; only for consumers building a fresh index that never saw the version
+    Drop,
+}
+
+option_names!(YankHandling, "yank handling", {
+    Keep => "keep",
+    Drop => "drop",
+});
+
+/// Per-version rules applied to every line that passes gem selection
+#[derive(Debug, Clone, Default)]
+pub struct VersionRules {
+    /// Yank record handling
+    pub yanked: YankHandling,
+    /// Keep prerelease versions (any version containing letters)
+    pub prereleases: bool,
+    /// Platforms to keep, `ruby` meaning no platform; `None` keeps all
+    pub platforms: Option<HashSet<String>>,
+    /// Keep at most this many versions per line, the last ones listed
+    pub max_versions: Option<usize>,
+    /// Requirements by gem name; versions outside them are removed
+    pub constraints: HashMap<String, Requirement>,
+}
+
+impl VersionRules {
+    /// Rules that keep every version
+    pub fn new() -> Self {
+        VersionRules {
+            prereleases: true,
+            ..VersionRules::default()
+        }
+    }
+
+    /// True when no rule can change a line
+    pub fn is_noop(&self) -> bool {
+        self.yanked == YankHandling::Keep
+            && self.prereleases
+            && self.platforms.is_none()
+            && self.max_versions.is_none()
+            && self.constraints.is_empty()
+    }
+
+    /// Rewrite a trimmed gem line into `out`, returning false if no version survives
+    ///
+    /// Malformed lines are copied unchanged, as the plain filter does.
+    pub fn apply(&self, line: &str, out: &mut String) -> bool {
+        out.clear();
+        let mut parts = line.splitn(3, ' ');
+        let (Some(name), Some(versions), Some(rest)) = (parts.next(), parts.next(), parts.next())
+        else {
+            out.push_str(line);
+            return true;
+        };
+
+        let constraint = self.constraints.get(name);
+        let kept: Vec<&str> = versions
+            .split(',')
+            .filter(|entry| self.keeps(entry, constraint))
+            .collect();
+        let skip = self
+            .max_versions
+            .map_or(0, |max| kept.len().saturating_sub(max));
+        if kept.len() == skip {
+            return false;
+        }
+
+        out.push_str(name);
+        out.push(' ');
+        for (i, entry) in kept[skip..].iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str(entry);
+        }
+        out.push(' ');
+        out.push_str(rest);
+        true
+    }
+
+    /// Whether one `[-]version[-platform]` entry survives the rules
+    fn keeps(&self, entry: &str, constraint: Option<&Requirement>) -> bool {
+        let entry = VersionEntry::parse(entry);
+        if entry.yanked && self.yanked == YankHandling::Drop {
+            return false;
+        }
+        if let Some(platforms) = &self.platforms {
+            if !platforms.contains(entry.platform_or_ruby()) {
+                return false;
+            }
+        }
+        if self.prereleases && constraint.is_none() {
+            return true;
+        }
+        // Entries we can't parse are kept: dropping data we don't understand is worse
+        let Some(version) = GemVersion::parse(entry.number) else {
+            return true;
+        };
+        (self.prereleases || !version.is_prerelease())
+            && constraint.is_none_or(|requirement| requirement.matches(&version))
+    }
+}
+
+/// A complete filter policy
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// Allowlist entries (names or patterns); `None` allows every gem not blocked
+    pub allow: Option<Vec<String>>,
+    /// Blocklist entries (names or patterns)
+    pub block: Vec<String>,
+    /// Per-version rules
+    pub versions: VersionRules,
+    /// Replace surviving version lists with `0`
+    pub strip_versions: bool,
+}
+
+/// Gem selection resolved to exact names
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GemSelection {
+    /// No gem-level filtering
+    All,
+    /// Only these gems
+    Allow(HashSet<String>),
+    /// Every gem except these
+    Block(HashSet<String>),
+}
+
+impl Policy {
+    /// Read and parse a policy file
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        Policy::from_toml(strip_bom(&text))
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    /// Parse policy TOML; unknown keys are errors so typos don't silently widen a policy
+    pub fn from_toml(text: &str) -> std::io::Result<Self> {
+        let root: toml::Table = text.parse().map_err(invalid)?;
+        check_keys(&root, "", ROOT_KEYS)?;
+
+        let gems = table(&root, "gems")?;
+        check_keys(gems, "gems.", GEMS_KEYS)?;
+        let allow = gems
+            .get("allow")
+            .map(|value| strings(value, "gems.allow"))
+            .transpose()?;
+        let block = gems
+            .get("block")
+            .map(|value| strings(value, "gems.block"))
+            .transpose()?
+            .unwrap_or_default();
+
+        let versions = table(&root, "versions")?;
+        check_keys(versions, "versions.", VERSIONS_KEYS)?;
+        let mut rules = VersionRules::new();
+        rules.yanked = match choice(versions, "yanked")? {
+            None => YankHandling::Keep,
+            Some(value) => value
+                .parse()
+                .map_err(|_| bad_choice("versions.yanked", value))?,
+        };
+        rules.prereleases = match choice(versions, "prerelease")? {
+            None | Some("keep") => true,
+            Some("drop") => false,
+            Some(other) => return Err(bad_choice("versions.prerelease", other)),
+        };
+        rules.platforms = versions
+            .get("platforms")
+            .map(|value| strings(value, "versions.platforms"))
+            .transpose()?
+            .map(|platforms| platforms.into_iter().collect());
+        rules.max_versions = match versions.get("max_versions") {
+            None => None,
+            Some(toml::Value::Integer(max)) if *max > 0 => Some(*max as usize),
+            Some(_) => return Err(invalid("versions.max_versions must be a positive integer")),
+        };
+        for (name, requirement) in table(versions, "constraints")? {
+            let parsed = requirement
+                .as_str()
+                .and_then(Requirement::parse)
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "versions.constraints.{} is not a valid requirement",
+                        name
+                    ))
+                })?;
+            rules.constraints.insert(name.clone(), parsed);
+        }
+        let strip_versions = match versions.get("strip") {
+            None => false,
+            Some(toml::Value::Boolean(strip)) => *strip,
+            Some(_) => return Err(invalid("versions.strip must be true or false")),
+        };
+
+        Ok(Policy {
+            allow,
+            block,
+            versions: rules,
+            strip_versions,
+        })
+    }
+
+    /// JSON Schema (draft 2020-12) for policy files, as parsed into JSON
+    ///
+    /// For validating policies before they are deployed, with any tool that
+    /// reads TOML as JSON. It rejects unknown keys and mistyped values as
+    /// [`from_toml`](Self::from_toml) does, but can't check that patterns
+    /// compile or that constraints are valid requirements.
+    pub fn schema() -> serde_json::Value {
+        let entries = |description: &str| {
+            serde_json::json!({
+                "type": "array",
+                "items": {"type": "string"},
+                "description": description,
+            })
+        };
+        let keep_or_drop = [YankHandling::Keep.as_str(), YankHandling::Drop.as_str()];
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "gem-index-filter policy",
+            "type": "object",
+            "additionalProperties": false,
+            "properties": {
+                "gems": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "properties": {
+                        "allow": entries("Names or patterns to keep; omit to keep every gem not blocked"),
+                        "block": entries("Names or patterns to drop"),
+                    },
+                },
+                "versions": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "properties": {
+                        "yanked": {
+                            "enum": keep_or_drop,
+                            "default": "keep",
+                            "description": "Whether yank records (-1.0.0) are kept",
+                        },
+                        "prerelease": {
+                            "enum": keep_or_drop,
+                            "default": "keep",
+                            "description": "Whether versions containing letters are kept",
+                        },
+                        "platforms": entries("Platforms to keep, \"ruby\" being the platform-less version"),
+                        "max_versions": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "Versions kept per line, the last listed",
+                        },
+                        "strip": {
+                            "type": "boolean",
+                            "default": false,
+                            "description": "Replace surviving version lists with 0",
+                        },
+                        "constraints": {
+                            "type": "object",
+                            "additionalProperties": {"type": "string"},
+                            "description": "RubyGems requirements by gem name, such as \">= 7.0, < 8\"",
+                        },
+                    },
+                },
+            },
+        })
+    }
+
+    /// Resolve allow/block entries to exact names, allowlist minus blocklist
+    ///
+    /// `index` (a names or versions file) is read only when a pattern needs
+    /// expanding; it is an error to need it and not provide it.
+    pub fn gem_selection<R: Read>(&self, index: Option<R>) -> std::io::Result<GemSelection> {
+        let block = PatternList::parse(self.block.iter().cloned())?;
+        let needs_index = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "policy contains patterns; a names or versions file is needed to expand them",
+            )
+        };
+
+        match &self.allow {
+            Some(allow) => {
+                let allow = PatternList::parse(allow.iter().cloned())?;
+                let mut gems = if allow.is_exact() {
+                    allow.expand(std::io::empty())?
+                } else {
+                    allow.expand(index.ok_or_else(needs_index)?)?
+                };
+                // Blocked patterns only need testing against what was allowed
+                gems.retain(|gem| !block.matches(gem));
+                Ok(GemSelection::Allow(gems))
+            }
+            None if self.block.is_empty() => Ok(GemSelection::All),
+            None if block.is_exact() => Ok(GemSelection::Block(block.expand(std::io::empty())?)),
+            None => Ok(GemSelection::Block(
+                block.expand(index.ok_or_else(needs_index)?)?,
+            )),
+        }
+    }
+}
+
+/// [`filter_versions_streaming`] with version rules applied to each kept line
+///
+/// Falls through to the plain filter when the rules are a no-op, so a policy
+/// without version rules costs nothing extra.
+pub fn filter_with_rules<R: Read, W: Write>(
+    input: R,
+    output: &mut W,
+    mode: FilterMode,
+    rules: &VersionRules,
+    version_output: VersionOutput,
+    digest_algorithm: Option<DigestAlgorithm>,
+) -> std::io::Result<Option<String>> {
+    if rules.is_noop() {
+        return filter_versions_streaming(input, output, mode, version_output, digest_algorithm);
+    }
+    FilterPipeline::new()
+        .with(mode)
+        .with(rules.clone())
+        .filter_versions(input, output, version_output, digest_algorithm)
+}
+
+/// Keys accepted at each level, shared by the parser and the schema
+const ROOT_KEYS: &[&str] = &["gems", "versions"];
+const GEMS_KEYS: &[&str] = &["allow", "block"];
+const VERSIONS_KEYS: &[&str] = &[
+    "yanked",
+    "prerelease",
+    "platforms",
+    "max_versions",
+    "strip",
+    "constraints",
+];
+
+fn table<'a>(parent: &'a toml::Table, key: &str) -> std::io::Result<&'a toml::Table> {
+    static EMPTY: std::sync::OnceLock<toml::Table> = std::sync::OnceLock::new();
+    match parent.get(key) {
+        None => Ok(EMPTY.get_or_init(toml::Table::new)),
+        Some(toml::Value::Table(table)) => Ok(table),
+        Some(_) => Err(invalid(format!("{} must be a table", key))),
+    }
+}
+
+fn check_keys(table: &toml::Table, prefix: &str, known: &[&str]) -> std::io::Result<()> {
+    match table.keys().find(|key| !known.contains(&key.as_str())) {
+        Some(key) => Err(invalid(format!("unknown key {}{}", prefix, key))),
+        None => Ok(()),
+    }
+}
+
+fn strings(value: &toml::Value, key: &str) -> std::io::Result<Vec<String>> {
+    value
+        .as_array()
+        .and_then(|items| {
+            items
+                .iter()
+                .map(|item| item.as_str().map(|s| s.trim().to_string()))
+                .collect()
+        })
+        .ok_or_else(|| invalid(format!("{} must be an array of strings", key)))
+}
+
+fn choice<'a>(table: &'a toml::Table, key: &str) -> std::io::Result<Option<&'a str>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(toml::Value::String(value)) => Ok(Some(value)),
+        Some(_) => Err(invalid(format!("versions.{} must be a string", key))),
+    }
+}
+
+fn bad_choice(key: &str, value: &str) -> std::io::Error {
+    invalid(format!(
+        "{} must be \"keep\" or \"drop\", not \"{}\"",
+        key, value
+    ))
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"
+[gems]
+allow = ["rails", "aws-sdk-*", "nokogiri"]
+block = ["aws-sdk-legacy"]
+
+[versions]
+prerelease = "drop"
+platforms = ["ruby", "x86_64-linux"]
+max_versions = 2
+
+[versions.constraints]
+rails = ">= 7.0, < 8"
+"#;
+
+    const VERSIONS: &str = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 6.1.7,7.0.0,7.1.0.rc1,7.1.0,8.0.0 abc123
+aws-sdk-s3 1.0.0,1.1.0 def456
+aws-sdk-legacy 1.0.0 ghi789
+nokogiri 1.16.0-x86_64-linux,1.16.0-java,1.16.0,1.16.1.rc1 jkl000
+sinatra 3.0.0 mno111
+rails 8.1.0 pqr222
+"#;
+
+    #[test]
+    fn test_policy_filters_gems_and_versions() {
+        let policy = Policy::from_toml(POLICY).unwrap();
+        let GemSelection::Allow(gems) = policy.gem_selection(Some(VERSIONS.as_bytes())).unwrap()
+        else {
+            panic!("expected an allowlist");
+        };
+        let gems: HashSet<&str> = gems.iter().map(String::as_str).collect();
+
+        let mut output = Vec::new();
+        filter_with_rules(
+            VERSIONS.as_bytes(),
+            &mut output,
+            FilterMode::Allow(&gems),
+            &policy.versions,
+            VersionOutput::Preserve,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "created_at: 2024-04-01T00:00:05Z\n---\n\
+             rails 7.0.0,7.1.0 abc123\n\
+             aws-sdk-s3 1.0.0,1.1.0 def456\n\
+             nokogiri 1.16.0-x86_64-linux,1.16.0 jkl000\n"
+        );
+    }
+
+    #[test]
+    fn test_yank_records_are_kept_by_default() {
+        let mut rules = VersionRules::new();
+        rules.prereleases = false;
+        let mut out = String::new();
+        assert!(rules.apply("rails -7.0.0,7.0.1.rc1 abc123", &mut out));
+        assert_eq!(out, "rails -7.0.0 abc123");
+
+        rules.yanked = YankHandling::Drop;
+        assert!(!rules.apply("rails -7.0.0 abc123", &mut out));
+    }
+
+    #[test]
+    fn test_schema_lists_the_accepted_keys() {
+        let schema = Policy::schema();
+        let keys = |pointer: &str| -> Vec<String> {
+            let mut keys: Vec<String> = schema
+                .pointer(pointer)
+                .and_then(|properties| properties.as_object())
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect();
+            keys.sort_unstable();
+            keys
+        };
+        let sorted = |known: &[&str]| {
+            let mut known: Vec<String> = known.iter().map(|key| key.to_string()).collect();
+            known.sort_unstable();
+            known
+        };
+
+        assert_eq!(keys("/properties"), sorted(ROOT_KEYS));
+        assert_eq!(keys("/properties/gems/properties"), sorted(GEMS_KEYS));
+        assert_eq!(
+            keys("/properties/versions/properties"),
+            sorted(VERSIONS_KEYS)
+        );
+        assert_eq!(
+            schema["properties"]["versions"]["properties"]["yanked"]["enum"],
+            serde_json::json!(["keep", "drop"])
+        );
+    }
+
+    #[test]
+    fn test_invalid_policies_are_rejected() {
+        for text in [
+            "[gems]\nallowed = [\"rails\"]",
+            "[versions]\nprerelease = \"maybe\"",
+            "[versions]\nmax_versions = 0",
+            "[versions.constraints]\nrails = \">= seven\"",
+        ] {
+            let err = Policy::from_toml(text).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData, "{}", text);
+        }
+        assert_eq!(
+            Policy::from_toml("")
+                .unwrap()
+                .gem_selection(None::<&[u8]>)
+                .unwrap(),
+            GemSelection::All
+        );
+    }
+}
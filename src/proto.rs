@@ -0,0 +1,341 @@
+//! Compact binary encoding of filtered output (`protobuf` feature)
+//!
+//! Edge workers that only look gems up spend most of their time splitting
+//! the text format. [`ProtoWriter`] writes the same content as protobuf
+//! messages instead: a [`Header`] holding the lines before `---`, then one
+//! [`Gem`] per gem line, each prefixed with its length as a varint. MD5s
+//! written as 32 hex digits are stored as their 16 bytes, and readers get
+//! every field without tokenizing. Like [`NdjsonWriter`](crate::NdjsonWriter)
+//! it converts lines as they are written, so filtering into it encodes in
+//! the same pass.
+//!
+//! The schema is `proto/versions.proto` in this crate, so workers in any
+//! language can generate a reader. [`ProtoReader`] reads the format back
+//! here, and [`ProtoReader::write_text`] turns it back into versions lines.
+
+use crate::parser::GemLine;
+use prost::Message;
+use std::io::{BufReader, Read, Write};
+
+/// The lines before `---`
+#[derive(Clone, PartialEq, Message)]
+pub struct Header {
+    /// Header lines such as `created_at: 2024-04-01T00:00:05Z`, in order
+    #[prost(string, repeated, tag = "1")]
+    pub metadata: Vec<String>,
+}
+
+/// One `name versions md5 [extra...]` line
+#[derive(Clone, PartialEq, Message)]
+pub struct Gem {
+    /// Gem name
+    #[prost(string, tag = "1")]
+    pub name: String,
+    /// Comma-separated version list, as written
+    #[prost(string, tag = "2")]
+    pub versions: String,
+    /// The MD5 as 16 bytes, when the line has it as 32 lowercase hex digits
+    #[prost(bytes = "vec", tag = "3")]
+    pub md5: Vec<u8>,
+    /// The MD5 field as written, for lines where it isn't
+    #[prost(string, tag = "4")]
+    pub md5_text: String,
+    /// Fields after the MD5
+    #[prost(string, repeated, tag = "5")]
+    pub extra: Vec<String>,
+}
+
+impl Gem {
+    /// Parse a gem line with [`GemLine::parse`], or `None` if it isn't one
+    pub fn from_line(line: &str) -> Option<Gem> {
+        GemLine::parse(line).map(Gem::from)
+    }
+
+    /// The MD5 field as the line had it
+    pub fn md5_hex(&self) -> String {
+        if !self.md5_text.is_empty() {
+            return self.md5_text.clone();
+        }
+        self.md5
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// The gem line, without its newline
+    pub fn to_line(&self) -> String {
+        let mut line = format!("{} {} {}", self.name, self.versions, self.md5_hex());
+        for field in &self.extra {
+            line.push(' ');
+            line.push_str(field);
+        }
+        line
+    }
+}
+
+impl From<GemLine<'_>> for Gem {
+    fn from(gem: GemLine<'_>) -> Self {
+        let (md5, md5_text) = match md5_bytes(gem.md5) {
+            Some(bytes) => (bytes, String::new()),
+            None => (Vec::new(), gem.md5.to_string()),
+        };
+        Gem {
+            name: gem.name.to_string(),
+            versions: gem.versions.to_string(),
+            md5,
+            md5_text,
+            extra: gem.extra_fields().map(String::from).collect(),
+        }
+    }
+}
+
+/// 32 lowercase hex digits as bytes; other spellings wouldn't survive the round trip
+fn md5_bytes(text: &str) -> Option<Vec<u8>> {
+    if text.len() != 32 {
+        return None;
+    }
+    let digit = |byte: u8| match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        _ => None,
+    };
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| Some(digit(pair[0])? << 4 | digit(pair[1])?))
+        .collect()
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Writer turning versions lines into length-delimited protobuf messages
+///
+/// The header lines become one [`Header`] once `---` is written, and each
+/// line after it a [`Gem`]. A final line may lack its newline, so call
+/// [`finish`](ProtoWriter::finish) once everything is written.
+pub struct ProtoWriter<W: Write> {
+    inner: W,
+    line: Vec<u8>,
+    /// Header lines seen so far, until `---` writes them out
+    header: Option<Vec<String>>,
+    message: Vec<u8>,
+}
+
+impl<W: Write> ProtoWriter<W> {
+    /// Wrap `inner`, expecting the start of a versions file
+    pub fn new(inner: W) -> Self {
+        ProtoWriter {
+            inner,
+            line: Vec::new(),
+            header: Some(Vec::new()),
+            message: Vec::new(),
+        }
+    }
+
+    /// Encode any unterminated last line, returning the inner writer
+    ///
+    /// Output that never reached `---` still gets its [`Header`], so a
+    /// reader always finds one.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let line = std::mem::take(&mut self.line);
+        self.emit(&line)?;
+        if let Some(metadata) = self.header.take() {
+            self.write_message(&Header { metadata })?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn emit(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return Ok(());
+        }
+        let line = std::str::from_utf8(line).map_err(|_| {
+            invalid_data(format!(
+                "Line is not UTF-8: '{}'",
+                String::from_utf8_lossy(line)
+            ))
+        })?;
+        if let Some(metadata) = &mut self.header {
+            if line != "---" {
+                metadata.push(line.to_string());
+                return Ok(());
+            }
+            let header = Header {
+                metadata: std::mem::take(metadata),
+            };
+            self.header = None;
+            return self.write_message(&header);
+        }
+        let gem = Gem::from_line(line)
+            .ok_or_else(|| invalid_data(format!("Not a gem line: '{}'", line)))?;
+        self.write_message(&gem)
+    }
+
+    fn write_message(&mut self, message: &impl Message) -> std::io::Result<()> {
+        self.message.clear();
+        message
+            .encode_length_delimited(&mut self.message)
+            .expect("a Vec grows to fit any message");
+        self.inner.write_all(&self.message)
+    }
+}
+
+impl<W: Write> Write for ProtoWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut rest = buf;
+        while let Some(newline) = memchr::memchr(b'\n', rest) {
+            let mut line = std::mem::take(&mut self.line);
+            line.extend_from_slice(&rest[..newline]);
+            self.emit(&line)?;
+            line.clear();
+            self.line = line;
+            rest = &rest[newline + 1..];
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reader of what [`ProtoWriter`] wrote, one [`Gem`] at a time
+pub struct ProtoReader<R: Read> {
+    input: BufReader<R>,
+    header: Header,
+    message: Vec<u8>,
+}
+
+impl<R: Read> ProtoReader<R> {
+    /// Read the [`Header`], failing if `input` doesn't start with one
+    pub fn new(input: R) -> std::io::Result<Self> {
+        let mut reader = ProtoReader {
+            input: BufReader::new(input),
+            header: Header::default(),
+            message: Vec::new(),
+        };
+        reader.header = reader
+            .next_message()?
+            .ok_or_else(|| invalid_data("Protobuf input is empty".to_string()))?;
+        Ok(reader)
+    }
+
+    /// The header read when the reader was created
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The next gem, or `None` at the end of the input
+    pub fn next_gem(&mut self) -> std::io::Result<Option<Gem>> {
+        self.next_message()
+    }
+
+    /// Write the rest of the input as a versions file, header first
+    ///
+    /// Lines come out trimmed with single spaces between fields, as the
+    /// writer saw them, and blank lines are gone.
+    pub fn write_text<W: Write>(mut self, output: &mut W) -> std::io::Result<()> {
+        for line in &self.header.metadata {
+            writeln!(output, "{}", line)?;
+        }
+        output.write_all(b"---\n")?;
+        while let Some(gem) = self.next_gem()? {
+            writeln!(output, "{}", gem.to_line())?;
+        }
+        Ok(())
+    }
+
+    fn next_message<M: Message + Default>(&mut self) -> std::io::Result<Option<M>> {
+        let Some(length) = self.read_length()? else {
+            return Ok(None);
+        };
+        // Read rather than allocate up front, so a corrupt length can't claim gigabytes
+        self.message.clear();
+        (&mut self.input)
+            .take(length)
+            .read_to_end(&mut self.message)?;
+        if self.message.len() as u64 != length {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Protobuf input ends inside a message",
+            ));
+        }
+        M::decode(self.message.as_slice())
+            .map(Some)
+            .map_err(|e| invalid_data(format!("Failed to decode protobuf message: {}", e)))
+    }
+
+    /// A varint length prefix, or `None` at a clean end of input
+    fn read_length(&mut self) -> std::io::Result<Option<u64>> {
+        let mut length = 0;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0];
+            match self.input.read_exact(&mut byte) {
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && shift == 0 => {
+                    return Ok(None)
+                }
+                result => result?,
+            }
+            length |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(length));
+            }
+        }
+        Err(invalid_data("Malformed protobuf length prefix".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSIONS: &str = "created_at: 2024-04-01T00:00:05Z\n---\n\
+        rails 7.0.0,7.0.1 8f5ba4a1e4f24a7c05e0e8a9e1e7c2b3\n\
+        sinatra 3.0.0 def456 extra\n\
+        rack 3.0.8,-3.0.9 0d1e2f3a4b5c6d7e8f9a0b1c2d3e4f5a";
+
+    #[test]
+    fn test_round_trip_is_smaller_than_text() {
+        let mut writer = ProtoWriter::new(Vec::new());
+        // Split mid-line, as a filter's writes would be
+        for chunk in VERSIONS.as_bytes().chunks(9) {
+            writer.write_all(chunk).unwrap();
+        }
+        let encoded = writer.finish().unwrap();
+        assert!(encoded.len() < VERSIONS.len());
+
+        let mut reader = ProtoReader::new(encoded.as_slice()).unwrap();
+        assert_eq!(
+            reader.header().metadata,
+            vec!["created_at: 2024-04-01T00:00:05Z"]
+        );
+        let rails = reader.next_gem().unwrap().unwrap();
+        assert_eq!(rails.md5.len(), 16);
+        assert_eq!(
+            rails.to_line(),
+            "rails 7.0.0,7.0.1 8f5ba4a1e4f24a7c05e0e8a9e1e7c2b3"
+        );
+        let sinatra = reader.next_gem().unwrap().unwrap();
+        assert_eq!(
+            (sinatra.md5_text.as_str(), sinatra.extra),
+            ("def456", vec!["extra".to_string()])
+        );
+
+        let mut text = Vec::new();
+        ProtoReader::new(encoded.as_slice())
+            .unwrap()
+            .write_text(&mut text)
+            .unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), format!("{}\n", VERSIONS));
+
+        let truncated = &encoded[..encoded.len() - 3];
+        let mut reader = ProtoReader::new(truncated).unwrap();
+        reader.next_gem().unwrap();
+        reader.next_gem().unwrap();
+        assert!(reader.next_gem().is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! Filtered output as an axum response (`server-support` feature)
+//!
+//! Every server that hands out a filtered index has to repeat the same
+//! headers: the content type Bundler expects, a length when one is known,
+//! an `ETag` for conditional requests and the checksum headers Bundler
+//! checks the download against. [`FilteredResponse`] wraps a filtered stream,
+//! such as one from [`filter_stream`](crate::filter_stream) or
+//! [`filter_on_thread`](crate::filter_on_thread), and sets them in one place,
+//! so servers built on this crate answer the same way.
+//!
+//! A stream's digest is only known once it has been read to the end, after
+//! the headers are gone. Responses served from a file filtered ahead of time
+//! pass the digest recorded with it, from `--digest` or a `.meta` file, and
+//! responses filtered on the fly go out without one.
+
+use crate::writers::base64;
+use crate::DigestAlgorithm;
+use axum_core::body::Body;
+use axum_core::response::{IntoResponse, Response};
+use axum_core::BoxError;
+use bytes::Bytes;
+use futures_util::TryStream;
+use http::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, ETAG};
+
+/// The content type of versions files, as rubygems.org serves them
+pub const VERSIONS_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// A filtered stream and the headers to serve it with
+///
+/// A file filtered ahead of time, with the length and digest recorded for it:
+///
+/// ```ignore
+/// async fn versions(State(index): State<Arc<Published>>) -> Response {
+///     let file = tokio::fs::File::open(&index.path).await.unwrap();
+///     FilteredResponse::new(tokio_util::io::ReaderStream::new(file))
+///         .content_length(index.size)
+///         .digest(DigestAlgorithm::Sha256, &index.sha256)
+///         .unwrap()
+///         .into_response()
+/// }
+/// ```
+pub struct FilteredResponse<S> {
+    body: S,
+    content_type: &'static str,
+    content_length: Option<u64>,
+    etag: Option<String>,
+    digest: Option<(DigestAlgorithm, Vec<u8>)>,
+}
+
+impl<S> FilteredResponse<S> {
+    /// Serve `body` as a versions file, with no length, `ETag` or digest
+    pub fn new(body: S) -> Self {
+        FilteredResponse {
+            body,
+            content_type: VERSIONS_CONTENT_TYPE,
+            content_length: None,
+            etag: None,
+            digest: None,
+        }
+    }
+
+    /// Override the content type, such as `application/x-ndjson` for NDJSON output
+    pub fn content_type(mut self, content_type: &'static str) -> Self {
+        self.content_type = content_type;
+        self
+    }
+
+    /// Send `Content-Length`, which must be exactly what the stream yields
+    ///
+    /// Without it the body is sent chunked.
+    pub fn content_length(mut self, length: u64) -> Self {
+        self.content_length = Some(length);
+        self
+    }
+
+    /// Send `etag` as a strong `ETag`, quoted
+    ///
+    /// Overrides the one derived from [`digest`](Self::digest).
+    pub fn etag(mut self, etag: impl Into<String>) -> Self {
+        self.etag = Some(etag.into());
+        self
+    }
+
+    /// Send the hex `digest` of the body as `Repr-Digest` and `Digest` headers
+    ///
+    /// Bundler checks downloads against either header. The digest also
+    /// becomes the `ETag` unless one is set, since it changes exactly when
+    /// the content does.
+    pub fn digest(mut self, algorithm: DigestAlgorithm, digest: &str) -> std::io::Result<Self> {
+        let bytes = hex::decode(digest).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid {} digest '{}'", algorithm, digest),
+            )
+        })?;
+        self.digest = Some((algorithm, bytes));
+        Ok(self)
+    }
+}
+
+/// The algorithm's name in the HTTP digest field registry
+fn http_algorithm(algorithm: DigestAlgorithm) -> &'static str {
+    match algorithm {
+        DigestAlgorithm::Sha256 => "sha-256",
+        DigestAlgorithm::Sha512 => "sha-512",
+    }
+}
+
+impl<S> IntoResponse for FilteredResponse<S>
+where
+    S: TryStream + Send + 'static,
+    S::Ok: Into<Bytes>,
+    S::Error: Into<BoxError>,
+{
+    fn into_response(self) -> Response {
+        let mut response = Response::new(Body::from_stream(self.body));
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(self.content_type));
+        if let Some(length) = self.content_length {
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+        }
+
+        let etag = self
+            .etag
+            .or_else(|| self.digest.as_ref().map(|(_, bytes)| hex::encode(bytes)));
+        // An ETag that can't be a header value is dropped rather than failing the response
+        if let Some(etag) = etag.and_then(|etag| format!("\"{}\"", etag).try_into().ok()) {
+            headers.insert(ETAG, etag);
+        }
+
+        if let Some((algorithm, bytes)) = &self.digest {
+            let name = http_algorithm(*algorithm);
+            let encoded = base64(bytes);
+            let repr_digest = format!("{}=:{}:", name, encoded);
+            let digest = format!("{}={}", name, encoded);
+            // Both are base64 and a fixed name, always valid header values
+            headers.insert("repr-digest", repr_digest.try_into().unwrap());
+            headers.insert("digest", digest.try_into().unwrap());
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+
+    const BODY: &str = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+    // SHA-256 of BODY
+    const SHA256: &str = "8b3ecdd38845689b6742975916f2358f8db22b0562e332dea181cd5d0b69998e";
+
+    fn chunks() -> impl futures_util::Stream<Item = std::io::Result<Bytes>> {
+        futures_util::stream::iter(
+            BODY.as_bytes()
+                .chunks(10)
+                .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+        response
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    #[test]
+    fn test_headers_and_body() {
+        let response = FilteredResponse::new(chunks())
+            .content_length(BODY.len() as u64)
+            .digest(DigestAlgorithm::Sha256, SHA256)
+            .unwrap()
+            .into_response();
+
+        assert_eq!(
+            header(&response, "content-type"),
+            Some(VERSIONS_CONTENT_TYPE)
+        );
+        assert_eq!(header(&response, "content-length"), Some("56"));
+        assert_eq!(
+            header(&response, "etag").unwrap(),
+            format!("\"{}\"", SHA256)
+        );
+        assert_eq!(
+            header(&response, "repr-digest"),
+            Some("sha-256=:iz7N04hFaJtnQpdZFvI1j42yKwVi4zLeoYHNXQtpmY4=:")
+        );
+        assert_eq!(
+            header(&response, "digest"),
+            Some("sha-256=iz7N04hFaJtnQpdZFvI1j42yKwVi4zLeoYHNXQtpmY4=")
+        );
+
+        let body: Vec<u8> = futures_executor::block_on(
+            response
+                .into_body()
+                .into_data_stream()
+                .map(|chunk| chunk.unwrap().to_vec())
+                .concat(),
+        );
+        assert_eq!(body, BODY.as_bytes());
+    }
+
+    #[test]
+    fn test_stream_without_digest() {
+        let response = FilteredResponse::new(chunks())
+            .content_type("application/x-ndjson")
+            .etag("v42")
+            .into_response();
+        assert_eq!(
+            header(&response, "content-type"),
+            Some("application/x-ndjson")
+        );
+        assert_eq!(header(&response, "etag"), Some("\"v42\""));
+        assert_eq!(header(&response, "content-length"), None);
+        assert_eq!(header(&response, "repr-digest"), None);
+
+        let err = FilteredResponse::new(chunks())
+            .digest(DigestAlgorithm::Sha256, "not-hex")
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "Invalid sha256 digest 'not-hex'");
+    }
+}
@@ -0,0 +1,196 @@
+//! Software bill of materials for a filtered index
+//!
+//! Emits a [CycloneDX 1.5] JSON document listing every gem a (filtered)
+//! versions file offers, one `library` component per gem with a
+//! `pkg:gem/<name>` package URL. With [`SbomOptions::include_versions`] each
+//! published, non-yanked version becomes its own component instead, with the
+//! platform carried as a purl qualifier.
+//!
+//! [CycloneDX 1.5]: https://cyclonedx.org/docs/1.5/json/
+
+use crate::attest::format_timestamp;
+use crate::diff::{read_gem_line, read_metadata};
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read, Write};
+use std::time::SystemTime;
+
+/// What to include in the SBOM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SbomOptions {
+    /// Name of the mirror, recorded as the SBOM's subject component
+    pub name: String,
+    /// Emit one component per gem version instead of one per gem
+    pub include_versions: bool,
+}
+
+impl SbomOptions {
+    /// One component per gem, describing a mirror called `name`
+    pub fn new(name: impl Into<String>) -> Self {
+        SbomOptions {
+            name: name.into(),
+            include_versions: false,
+        }
+    }
+}
+
+/// Read a versions file and write a CycloneDX SBOM of its gems
+///
+/// Returns the number of components written. Memory grows with the number of
+/// distinct gems (and versions, when included), so this is intended for
+/// filtered files rather than the full upstream index.
+pub fn write_sbom<R: Read, W: Write>(
+    input: R,
+    output: &mut W,
+    options: &SbomOptions,
+) -> std::io::Result<usize> {
+    let gems = collect_gem_versions(input)?;
+
+    let mut components = Vec::new();
+    for (name, versions) in &gems {
+        if !options.include_versions {
+            components.push(serde_json::json!({
+                "type": "library",
+                "bom-ref": format!("pkg:gem/{}", name),
+                "name": name,
+                "purl": format!("pkg:gem/{}", name),
+            }));
+            continue;
+        }
+        for version in versions {
+            let purl = version_purl(name, version);
+            components.push(serde_json::json!({
+                "type": "library",
+                "bom-ref": purl,
+                "name": name,
+                "version": version,
+                "purl": purl,
+            }));
+        }
+    }
+
+    let count = components.len();
+    let bom = serde_json::json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": format_timestamp(SystemTime::now()),
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+            "component": { "type": "data", "name": options.name },
+        },
+        "components": components,
+    });
+
+    serde_json::to_writer_pretty(&mut *output, &bom)?;
+    output.write_all(b"\n")?;
+    Ok(count)
+}
+
+/// Collect each gem's currently published versions, in the order they were added
+///
+/// Later lines for a gem append versions, and `-<version>` entries mark a
+/// yank that removes an earlier one.
+fn collect_gem_versions<R: Read>(input: R) -> std::io::Result<BTreeMap<String, Vec<String>>> {
+    let mut reader = BufReader::new(input);
+    read_metadata(&mut reader)?;
+
+    let mut gems: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut line = String::new();
+    while read_gem_line(&mut reader, &mut line)? {
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some(version_list)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+
+        if !gems.contains_key(name) {
+            gems.insert(name.to_string(), Vec::new());
+        }
+        let versions = gems.get_mut(name).expect("inserted above");
+        for version in version_list.split(',') {
+            match version.strip_prefix('-') {
+                Some(yanked) => versions.retain(|v| v != yanked),
+                None => versions.push(version.to_string()),
+            }
+        }
+    }
+
+    Ok(gems)
+}
+
+/// Package URL for one gem version, moving any platform suffix into a qualifier
+///
+/// RubyGems versions never contain `-`, so the first one starts the platform.
+fn version_purl(name: &str, version: &str) -> String {
+    match version.split_once('-') {
+        Some((number, platform)) => format!("pkg:gem/{}@{}?platform={}", name, number, platform),
+        None => format!("pkg:gem/{}@{}", name, version),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSIONS: &str = r#"created_at: 2024-04-01T00:00:05Z
+---
+sinatra 3.0.0 def456
+rails 7.0.0,7.0.1 abc123
+nokogiri 1.15.0,1.15.0-x86_64-linux ghi789
+rails -7.0.0 fed321
+"#;
+
+    fn sbom(include_versions: bool) -> (usize, serde_json::Value) {
+        let options = SbomOptions {
+            include_versions,
+            ..SbomOptions::new("internal-mirror")
+        };
+        let mut output = Vec::new();
+        let count = write_sbom(VERSIONS.as_bytes(), &mut output, &options).unwrap();
+        (count, serde_json::from_slice(&output).unwrap())
+    }
+
+    fn purls(bom: &serde_json::Value) -> Vec<&str> {
+        bom["components"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["purl"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_one_component_per_gem() {
+        let (count, bom) = sbom(false);
+
+        assert_eq!(count, 3);
+        assert_eq!(bom["bomFormat"], "CycloneDX");
+        assert_eq!(bom["specVersion"], "1.5");
+        assert_eq!(bom["metadata"]["component"]["name"], "internal-mirror");
+        assert_eq!(
+            purls(&bom),
+            vec!["pkg:gem/nokogiri", "pkg:gem/rails", "pkg:gem/sinatra"]
+        );
+    }
+
+    #[test]
+    fn test_versions_skip_yanked_and_qualify_platforms() {
+        let (count, bom) = sbom(true);
+
+        assert_eq!(count, 4);
+        assert_eq!(
+            purls(&bom),
+            vec![
+                "pkg:gem/nokogiri@1.15.0",
+                "pkg:gem/nokogiri@1.15.0?platform=x86_64-linux",
+                "pkg:gem/rails@7.0.1",
+                "pkg:gem/sinatra@3.0.0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Set operations between two versions files
+//!
+//! Both operations work on gem names: every line of the first file is kept or
+//! dropped depending on whether its gem appears anywhere in the second. The
+//! output keeps the first file's metadata and line order, so it is a valid
+//! versions file in its own right. Only the second file's names are held in
+//! memory; the first file is streamed.
+
+use crate::filter::filter_versions_with_stats;
+use crate::names::collect_gem_names;
+use crate::slice::NamelessLines;
+use crate::stats::FilterStats;
+use crate::{FilterMode, VersionOutput};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+/// Keep the lines of `input` whose gem also appears in `other`
+pub fn intersect_versions<R1: Read, R2: Read, W: Write>(
+    input: R1,
+    other: R2,
+    output: &mut W,
+) -> std::io::Result<FilterStats> {
+    filter_by_names(input, other, output, true)
+}
+
+/// Keep the lines of `input` whose gem doesn't appear in `other`
+pub fn subtract_versions<R1: Read, R2: Read, W: Write>(
+    input: R1,
+    other: R2,
+    output: &mut W,
+) -> std::io::Result<FilterStats> {
+    filter_by_names(input, other, output, false)
+}
+
+fn filter_by_names<R1: Read, R2: Read, W: Write>(
+    input: R1,
+    other: R2,
+    output: &mut W,
+    shared: bool,
+) -> std::io::Result<FilterStats> {
+    let names = collect_gem_names(other)?;
+    let names: HashSet<&str> = names.iter().map(String::as_str).collect();
+    let mode = if shared {
+        FilterMode::Allow(&names)
+    } else {
+        FilterMode::Block(&names)
+    };
+    filter_versions_with_stats(
+        input,
+        output,
+        mode.into(),
+        VersionOutput::Preserve,
+        None,
+        NamelessLines::Skip,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRST: &str = "created_at: 2024-04-01T00:00:05Z\n---\n\
+        rails 7.0.0 abc123\n\
+        sinatra 3.0.0 def456\n\
+        rails 7.0.1 fed321\n";
+    const SECOND: &str = "created_at: 2024-03-01T00:00:00Z\n---\n\
+        puma 6.0.0 aaa111\n\
+        rails 6.1.0 bbb222\n";
+
+    #[test]
+    fn test_intersect_and_subtract_keep_first_file_lines() {
+        let mut shared = Vec::new();
+        intersect_versions(FIRST.as_bytes(), SECOND.as_bytes(), &mut shared).unwrap();
+        assert_eq!(
+            String::from_utf8(shared).unwrap(),
+            "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\nrails 7.0.1 fed321\n"
+        );
+
+        let mut only_first = Vec::new();
+        subtract_versions(FIRST.as_bytes(), SECOND.as_bytes(), &mut only_first).unwrap();
+        assert_eq!(
+            String::from_utf8(only_first).unwrap(),
+            "created_at: 2024-04-01T00:00:05Z\n---\nsinatra 3.0.0 def456\n"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Detached Ed25519 signatures for filtered artifacts
+//!
+//! Signatures use Ed25519ph (RFC 8032): the output is hashed with SHA-512 as
+//! it streams through a [`SigningWriter`] and only the 64-byte prehash is
+//! signed, so signing never needs the whole file in memory. Keys and
+//! signatures are exchanged as hex strings:
+//!
+//! - secret key: the 32-byte seed, 64 hex characters
+//! - public key: 32 bytes, 64 hex characters
+//! - signature: 64 bytes, 128 hex characters (the contents of `<file>.sig`)
+
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha512};
+use std::io::{Read, Write};
+
+/// Domain-separation context, so these signatures can't be replayed for another purpose
+const SIGNATURE_CONTEXT: &[u8] = b"gem-index-filter";
+
+/// Writer wrapper that hashes everything written for a detached signature
+pub struct SigningWriter<'a, W: Write> {
+    inner: &'a mut W,
+    hasher: Sha512,
+}
+
+impl<'a, W: Write> SigningWriter<'a, W> {
+    /// Wrap `inner`, hashing all bytes written through it
+    pub fn new(inner: &'a mut W) -> Self {
+        SigningWriter {
+            inner,
+            hasher: Sha512::new(),
+        }
+    }
+
+    /// Sign everything written so far, returning the hex-encoded signature
+    pub fn sign(self, key: &SigningKey) -> String {
+        let signature = key
+            .sign_prehashed(self.hasher, Some(SIGNATURE_CONTEXT))
+            .expect("context is shorter than 256 bytes");
+        hex::encode(signature.to_bytes())
+    }
+}
+
+impl<'a, W: Write> Write for SigningWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // Only hash what the inner writer actually accepted
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Parse a hex-encoded 32-byte secret key seed (surrounding whitespace is ignored)
+pub fn parse_signing_key(hex_seed: &str) -> std::io::Result<SigningKey> {
+    let seed: [u8; 32] = decode_hex_array(hex_seed, "signing key")?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Parse a hex-encoded 32-byte public key
+pub fn parse_verifying_key(hex_key: &str) -> std::io::Result<VerifyingKey> {
+    let bytes: [u8; 32] = decode_hex_array(hex_key, "public key")?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| invalid_data("Invalid public key"))
+}
+
+/// Hex-encoded public key matching a signing key, for publishing to consumers
+pub fn public_key_hex(key: &SigningKey) -> String {
+    hex::encode(key.verifying_key().to_bytes())
+}
+
+/// Check a detached hex signature against the data it claims to sign
+///
+/// Returns `Ok(false)` for a well-formed signature that does not match.
+pub fn verify_signature<R: Read>(
+    mut data: R,
+    signature_hex: &str,
+    key: &VerifyingKey,
+) -> std::io::Result<bool> {
+    let signature = Signature::from_bytes(&decode_hex_array(signature_hex, "signature")?);
+
+    let mut hasher = Sha512::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let n = data.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(key
+        .verify_prehashed(hasher, Some(SIGNATURE_CONTEXT), &signature)
+        .is_ok())
+}
+
+fn decode_hex_array<const N: usize>(value: &str, what: &str) -> std::io::Result<[u8; N]> {
+    let bytes =
+        hex::decode(value.trim()).map_err(|_| invalid_data(&format!("Invalid hex in {}", what)))?;
+    bytes
+        .try_into()
+        .map_err(|_| invalid_data(&format!("{} must be {} bytes", what, N)))
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+
+    fn sign(data: &[u8]) -> (String, VerifyingKey) {
+        let key = parse_signing_key(SEED).unwrap();
+        let mut output = Vec::new();
+        let mut writer = SigningWriter::new(&mut output);
+        writer.write_all(data).unwrap();
+        let signature = writer.sign(&key);
+        assert_eq!(output, data);
+        (signature, key.verifying_key())
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let data = b"created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+        let (signature, key) = sign(data);
+
+        assert_eq!(signature.len(), 128);
+        assert!(verify_signature(&data[..], &signature, &key).unwrap());
+    }
+
+    #[test]
+    fn test_tampered_data_fails() {
+        let (signature, key) = sign(b"rails 7.0.0 abc123\n");
+        assert!(!verify_signature(&b"rails 7.0.0 abc124\n"[..], &signature, &key).unwrap());
+    }
+
+    #[test]
+    fn test_public_key_round_trip() {
+        let key = parse_signing_key(&format!("{}\n", SEED)).unwrap();
+        let public = parse_verifying_key(&public_key_hex(&key)).unwrap();
+        assert_eq!(public, key.verifying_key());
+    }
+
+    #[test]
+    fn test_invalid_keys_rejected() {
+        assert!(parse_signing_key("not hex").is_err());
+        assert!(parse_signing_key("abcd").is_err());
+        assert!(verify_signature(
+            &b""[..],
+            "abcd",
+            &parse_signing_key(SEED).unwrap().verifying_key()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! Comparing what two policies would publish
+//!
+//! Before rolling out a policy change it helps to know what it does to the
+//! index: which gems appear or disappear, and how many lines and bytes the
+//! output gains or loses. [`simulate_policies`] runs two policies over one
+//! versions file in a single pass and totals what each would write, without
+//! writing anything.
+//!
+//! Patterns are matched against each gem name as it streams past instead of
+//! being expanded first, so no names file is needed, and each name's decision
+//! is cached for its repeats. Memory grows with the number of distinct gems
+//! either policy keeps.
+
+use crate::parser::{Entry, VersionsReader};
+use crate::pattern::{CachedPatternList, PatternList};
+use crate::policy::{Policy, VersionRules};
+use crate::slice::stripped_fields;
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+/// What one side of a simulation keeps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PolicyTotals {
+    /// Distinct gems with at least one line kept
+    pub gems: usize,
+    /// Gem lines kept
+    pub lines: usize,
+    /// Bytes of the kept gem lines after version rules, newlines included
+    pub bytes: u64,
+}
+
+/// Outcome of running two policies over the same input
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Simulation {
+    /// The input's gem lines, unfiltered
+    pub input: PolicyTotals,
+    /// What the current policy keeps
+    pub a: PolicyTotals,
+    /// What the proposed policy keeps
+    pub b: PolicyTotals,
+    /// Gems kept by `b` but not by `a`, sorted
+    pub added: Vec<String>,
+    /// Gems kept by `a` but not by `b`, sorted
+    pub removed: Vec<String>,
+}
+
+/// Totals for policy `a` and policy `b` over `input`, plus the gems only one keeps
+pub fn simulate_policies<R: Read>(input: R, a: &Policy, b: &Policy) -> std::io::Result<Simulation> {
+    let mut reader = VersionsReader::new(input);
+    let mut sides = [Side::new(a)?, Side::new(b)?];
+    let mut input_gems = HashSet::new();
+    let mut input_totals = PolicyTotals::default();
+    let mut rewritten = String::new();
+
+    while let Some(entry) = reader.next_entry()? {
+        let Entry::Gem(gem) = entry else {
+            continue;
+        };
+
+        input_totals.lines += 1;
+        input_totals.bytes += gem.line.len() as u64 + 1;
+        if !input_gems.contains(gem.name) {
+            input_gems.insert(gem.name.to_string());
+        }
+        for side in &mut sides {
+            side.visit(gem.name, gem.line, &mut rewritten);
+        }
+    }
+    input_totals.gems = input_gems.len();
+
+    let [a, b] = sides;
+    let mut added: Vec<String> = b.kept.difference(&a.kept).cloned().collect();
+    let mut removed: Vec<String> = a.kept.difference(&b.kept).cloned().collect();
+    added.sort_unstable();
+    removed.sort_unstable();
+    Ok(Simulation {
+        input: input_totals,
+        a: a.totals(),
+        b: b.totals(),
+        added,
+        removed,
+    })
+}
+
+impl Simulation {
+    /// Write both totals, their difference and the gems that change side
+    pub fn write_text<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        writeln!(
+            output,
+            "{:<8} {:>10} {:>10} {:>14}",
+            "", "gems", "lines", "bytes"
+        )?;
+        for (label, totals) in [("input", self.input), ("a", self.a), ("b", self.b)] {
+            writeln!(
+                output,
+                "{:<8} {:>10} {:>10} {:>14}",
+                label, totals.gems, totals.lines, totals.bytes
+            )?;
+        }
+        writeln!(
+            output,
+            "{:<8} {:>+10} {:>+10} {:>+14}",
+            "b - a",
+            self.b.gems as i64 - self.a.gems as i64,
+            self.b.lines as i64 - self.a.lines as i64,
+            self.b.bytes as i64 - self.a.bytes as i64
+        )?;
+        for gem in &self.added {
+            writeln!(output, "+ {}", gem)?;
+        }
+        for gem in &self.removed {
+            writeln!(output, "- {}", gem)?;
+        }
+        Ok(())
+    }
+}
+
+/// One policy's matchers and running totals
+struct Side<'p> {
+    allow: Option<CachedPatternList>,
+    block: CachedPatternList,
+    rules: Option<&'p VersionRules>,
+    strip_versions: bool,
+    kept: HashSet<String>,
+    lines: usize,
+    bytes: u64,
+}
+
+impl<'p> Side<'p> {
+    fn new(policy: &'p Policy) -> std::io::Result<Self> {
+        Ok(Side {
+            allow: policy
+                .allow
+                .as_ref()
+                .map(|allow| PatternList::parse(allow.iter().cloned()).map(PatternList::cached))
+                .transpose()?,
+            block: PatternList::parse(policy.block.iter().cloned())?.cached(),
+            rules: (!policy.versions.is_noop()).then_some(&policy.versions),
+            strip_versions: policy.strip_versions,
+            kept: HashSet::new(),
+            lines: 0,
+            bytes: 0,
+        })
+    }
+
+    /// Count `line` if the policy keeps it, as it would be written
+    fn visit(&mut self, name: &str, line: &str, rewritten: &mut String) {
+        let listed = self.allow.as_ref().is_none_or(|allow| allow.matches(name));
+        if !listed || self.block.matches(name) {
+            return;
+        }
+        let line = match self.rules {
+            Some(rules) if !rules.apply(line, rewritten) => return,
+            Some(_) => rewritten.as_str(),
+            None => line,
+        };
+        let length = match stripped_fields(line).filter(|_| self.strip_versions) {
+            // name 0 md5 [extra...]
+            Some(fields) => fields.map(|field| field.len() + 1).sum::<usize>() - 1,
+            None => line.len(),
+        };
+
+        self.lines += 1;
+        self.bytes += length as u64 + 1;
+        if !self.kept.contains(name) {
+            self.kept.insert(name.to_string());
+        }
+    }
+
+    fn totals(&self) -> PolicyTotals {
+        PolicyTotals {
+            gems: self.kept.len(),
+            lines: self.lines,
+            bytes: self.bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSIONS: &str = "created_at: 2024-04-01T00:00:05Z\n---\n\
+        rails 7.0.0,7.1.0.rc1 abc123\n\
+        aws-sdk-s3 1.0.0 def456\n\
+        sinatra 3.0.0 ghi789\n\
+        rails 7.1.0 jkl000\n";
+
+    #[test]
+    fn test_simulation_totals_both_policies() {
+        let old = Policy::from_toml("[gems]\nallow = [\"rails\", \"sinatra\"]\n").unwrap();
+        let new = Policy::from_toml(
+            "[gems]\nallow = [\"rails\", \"aws-sdk-*\"]\n[versions]\nprerelease = \"drop\"\n",
+        )
+        .unwrap();
+        let simulation = simulate_policies(VERSIONS.as_bytes(), &old, &new).unwrap();
+
+        assert_eq!(
+            simulation.input,
+            PolicyTotals {
+                gems: 3,
+                lines: 4,
+                bytes: 93
+            }
+        );
+        assert_eq!(
+            simulation.a,
+            PolicyTotals {
+                gems: 2,
+                lines: 3,
+                bytes: 69
+            }
+        );
+        assert_eq!(
+            simulation.b,
+            PolicyTotals {
+                gems: 2,
+                lines: 3,
+                bytes: 62
+            }
+        );
+        assert_eq!(simulation.added, vec!["aws-sdk-s3"]);
+        assert_eq!(simulation.removed, vec!["sinatra"]);
+
+        let mut text = Vec::new();
+        simulation.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("b - a"));
+        assert!(text.ends_with("+ aws-sdk-s3\n- sinatra\n"));
+    }
+
+    #[test]
+    fn test_simulation_counts_lines_as_written() {
+        let input = "---\nrails 7.0.0  abc123 extra\n";
+        let keep = Policy::from_toml("").unwrap();
+        let strip = Policy::from_toml("[versions]\nstrip = true\n").unwrap();
+        let simulation = simulate_policies(input.as_bytes(), &keep, &strip).unwrap();
+
+        assert_eq!((simulation.input.lines, simulation.input.bytes), (1, 26));
+        assert_eq!(simulation.a.bytes, 26);
+        // rails 0 abc123 extra
+        assert_eq!(simulation.b.bytes, 21);
+    }
+}
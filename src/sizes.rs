@@ -0,0 +1,204 @@
+//! Which gems take up the most room in a versions file
+//!
+//! [`size_report`] totals, per gem, the bytes of its lines and the version
+//! entries they list, and estimates what blocking the gem or capping its
+//! version lists would save. Sorted largest first, it is the short list of
+//! blocklist or `max_versions` candidates.
+//!
+//! Memory grows with the number of distinct gems, about 200k entries for the
+//! full upstream index.
+
+use crate::parser::{Entry, GemLine, VersionsReader};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+/// Space one gem takes in a versions file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GemSize {
+    /// Gem name
+    pub name: String,
+    /// Number of lines for the gem
+    pub lines: usize,
+    /// Version entries across those lines, yanks included
+    pub versions: usize,
+    /// Bytes of those lines, newlines included; also what blocking saves
+    pub bytes: u64,
+    /// Bytes saved by keeping only the last `cap` versions of each line,
+    /// as `max_versions` does; zero when no cap was given
+    pub capped_savings: u64,
+}
+
+/// Per-gem sizes of a versions file, largest first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeReport {
+    /// Bytes of all gem lines, the header excluded
+    pub total_bytes: u64,
+    /// The cap `capped_savings` was estimated for
+    pub cap: Option<usize>,
+    /// Every gem, by descending `bytes`, then by name
+    pub gems: Vec<GemSize>,
+}
+
+/// Measure every gem in `input`, estimating savings for a per-line cap of
+/// `cap` versions if given
+pub fn size_report<R: Read>(input: R, cap: Option<usize>) -> std::io::Result<SizeReport> {
+    let mut reader = VersionsReader::new(input);
+    let mut sizes: HashMap<String, GemSize> = HashMap::new();
+    let mut total_bytes = 0;
+
+    while let Some(entry) = reader.next_entry()? {
+        let Entry::Gem(gem) = entry else {
+            continue;
+        };
+        let bytes = gem.line.len() as u64 + 1;
+        total_bytes += bytes;
+
+        let size = match sizes.get_mut(gem.name) {
+            Some(size) => size,
+            None => sizes.entry(gem.name.to_string()).or_insert(GemSize {
+                name: gem.name.to_string(),
+                lines: 0,
+                versions: 0,
+                bytes: 0,
+                capped_savings: 0,
+            }),
+        };
+        size.lines += 1;
+        size.versions += gem.versions.split(',').count();
+        size.bytes += bytes;
+        size.capped_savings += cap.map_or(0, |cap| capped_savings(&gem, cap));
+    }
+
+    let mut gems: Vec<GemSize> = sizes.into_values().collect();
+    gems.sort_unstable_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+    Ok(SizeReport {
+        total_bytes,
+        cap,
+        gems,
+    })
+}
+
+/// Bytes dropped from one line's version list by keeping its last `cap` entries
+fn capped_savings(gem: &GemLine, cap: usize) -> u64 {
+    let cap = cap.max(1);
+    match gem.versions.rmatch_indices(',').nth(cap - 1) {
+        // Everything up to and including the comma before the kept entries
+        Some((comma, _)) => (comma + 1) as u64,
+        None => 0,
+    }
+}
+
+impl SizeReport {
+    /// Write the `top` largest gems as an aligned text table
+    pub fn write_text<W: Write>(&self, top: usize, output: &mut W) -> std::io::Result<()> {
+        let capped = match self.cap {
+            Some(cap) => format!("  cap {} saves", cap),
+            None => String::new(),
+        };
+        writeln!(
+            output,
+            "{:>4}  {:<32} {:>6} {:>8} {:>12} {:>6}{}",
+            "rank", "gem", "lines", "versions", "bytes", "share", capped
+        )?;
+        for (rank, gem) in self.gems.iter().take(top).enumerate() {
+            write!(
+                output,
+                "{:>4}  {:<32} {:>6} {:>8} {:>12} {:>5.1}%",
+                rank + 1,
+                gem.name,
+                gem.lines,
+                gem.versions,
+                gem.bytes,
+                self.share(gem.bytes)
+            )?;
+            if self.cap.is_some() {
+                write!(output, "  {:>11}", gem.capped_savings)?;
+            }
+            writeln!(output)?;
+        }
+        Ok(())
+    }
+
+    /// Write the `top` largest gems as a JSON document
+    pub fn write_json<W: Write>(&self, top: usize, output: &mut W) -> std::io::Result<()> {
+        let gems: Vec<serde_json::Value> = self
+            .gems
+            .iter()
+            .take(top)
+            .map(|gem| {
+                let mut value = serde_json::json!({
+                    "name": gem.name,
+                    "lines": gem.lines,
+                    "versions": gem.versions,
+                    "bytes": gem.bytes,
+                    "share": self.share(gem.bytes) / 100.0,
+                });
+                if self.cap.is_some() {
+                    value["capped_savings"] = gem.capped_savings.into();
+                }
+                value
+            })
+            .collect();
+        let report = serde_json::json!({
+            "total_bytes": self.total_bytes,
+            "gem_count": self.gems.len(),
+            "cap": self.cap,
+            "gems": gems,
+        });
+        serde_json::to_writer_pretty(&mut *output, &report)?;
+        output.write_all(b"\n")
+    }
+
+    /// Percentage of all gem line bytes
+    fn share(&self, bytes: u64) -> f64 {
+        if self.total_bytes == 0 {
+            return 0.0;
+        }
+        bytes as f64 * 100.0 / self.total_bytes as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSIONS: &str = "created_at: 2024-04-01T00:00:05Z\n---\n\
+        rails 7.0.0,7.0.1,7.0.2 abc123\n\
+        rack 3.0.0 def456\n\
+        rails -7.0.1,7.1.0 fed321\n";
+
+    #[test]
+    fn test_gems_ranked_by_bytes_with_cap_savings() {
+        let report = size_report(VERSIONS.as_bytes(), Some(1)).unwrap();
+        assert_eq!(report.total_bytes, 31 + 18 + 26);
+
+        let rails = &report.gems[0];
+        assert_eq!(
+            (
+                rails.name.as_str(),
+                rails.lines,
+                rails.versions,
+                rails.bytes
+            ),
+            ("rails", 2, 5, 57)
+        );
+        // "7.0.0,7.0.1," and "-7.0.1,"
+        assert_eq!(rails.capped_savings, 12 + 7);
+        assert_eq!(report.gems[1].capped_savings, 0);
+
+        let mut json = Vec::new();
+        report.write_json(1, &mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["gem_count"], 2);
+        assert_eq!(json["gems"][0]["name"], "rails");
+        assert_eq!(json["gems"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_lines_measured_as_written() {
+        let input = "---\nrails 7.0.0  abc123 extra\n";
+        let report = size_report(input.as_bytes(), None).unwrap();
+        assert_eq!(report.total_bytes, 26);
+        assert_eq!(report.gems[0].bytes, 26);
+    }
+}
@@ -0,0 +1,1016 @@
+//! Byte-slice filtering core that needs only `alloc`
+//!
+//! Everything else in the crate is built on `std::io`. This module is what
+//! remains with the `std` feature turned off: [`SliceFilter`] takes input as
+//! byte chunks and appends output to a `Vec<u8>`, so it runs in WASI
+//! snapshots, embedded proxies and other runtimes without `std::fs` or
+//! `std::io`. Output is byte-for-byte the same as the streaming filter.
+
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use memchr::{memchr, memchr_iter, memrchr};
+
+/// An option value that isn't one of the accepted names
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptionError {
+    what: &'static str,
+    value: String,
+    expected: &'static [&'static str],
+}
+
+impl ParseOptionError {
+    #[doc(hidden)]
+    pub fn new(what: &'static str, value: &str, expected: &'static [&'static str]) -> Self {
+        ParseOptionError {
+            what,
+            value: value.into(),
+            expected,
+        }
+    }
+}
+
+impl fmt::Display for ParseOptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown {} '{}'. Supported: ", self.what, self.value)?;
+        for (i, name) in self.expected.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            f.write_str(name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseOptionError {}
+
+#[cfg(feature = "std")]
+impl From<ParseOptionError> for std::io::Error {
+    fn from(error: ParseOptionError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, error)
+    }
+}
+
+/// One table of canonical names for an option enum
+///
+/// Generates `as_str`, `Display` and case-insensitive `FromStr` (accepting
+/// the listed aliases too), and `clap::ValueEnum` with the `clap` feature, so
+/// the CLI, config files and bindings all spell options the same way.
+macro_rules! option_names {
+    ($ty:ident, $what:literal, { $($variant:ident => $name:literal $(| $alias:literal)*),+ $(,)? }) => {
+        impl $ty {
+            /// Canonical name, as printed by `Display` and accepted by `FromStr`
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $($ty::$variant => $name),+
+                }
+            }
+        }
+
+        impl core::fmt::Display for $ty {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl core::str::FromStr for $ty {
+            type Err = $crate::slice::ParseOptionError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                $(
+                    if s.eq_ignore_ascii_case($name) $(|| s.eq_ignore_ascii_case($alias))* {
+                        return Ok($ty::$variant);
+                    }
+                )+
+                Err($crate::slice::ParseOptionError::new($what, s, &[$($name),+]))
+            }
+        }
+
+        #[cfg(feature = "clap")]
+        impl clap::ValueEnum for $ty {
+            fn value_variants<'a>() -> &'a [Self] {
+                &[$($ty::$variant),+]
+            }
+
+            fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+                Some(match self {
+                    $($ty::$variant => clap::builder::PossibleValue::new($name)$(.alias($alias))*),+
+                })
+            }
+        }
+    };
+}
+#[cfg(feature = "std")]
+pub(crate) use option_names;
+
+/// Version output mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionOutput {
+    /// Preserve original version information
+    Preserve,
+    /// Strip versions, replacing with '0'
+    Strip,
+}
+
+option_names!(VersionOutput, "version output", {
+    Preserve => "preserve",
+    Strip => "strip",
+});
+
+/// A set of gem names the filter can test membership against
+pub trait GemSet {
+    /// Whether `name` is in the set
+    fn contains_gem(&self, name: &str) -> bool;
+}
+
+impl GemSet for BTreeSet<&str> {
+    #[inline]
+    fn contains_gem(&self, name: &str) -> bool {
+        self.contains(name)
+    }
+}
+
+impl GemSet for BTreeSet<String> {
+    #[inline]
+    fn contains_gem(&self, name: &str) -> bool {
+        self.contains(name)
+    }
+}
+
+/// Sorted slices use binary search, so sort them before filtering
+impl GemSet for [&str] {
+    #[inline]
+    fn contains_gem(&self, name: &str) -> bool {
+        self.binary_search(&name).is_ok()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: core::hash::BuildHasher> GemSet for std::collections::HashSet<&str, S> {
+    #[inline]
+    fn contains_gem(&self, name: &str) -> bool {
+        self.contains(name)
+    }
+}
+
+/// Perfect-hash sets generated at build time (`phf` feature)
+#[cfg(feature = "phf")]
+impl GemSet for phf::Set<&'static str> {
+    #[inline]
+    fn contains_gem(&self, name: &str) -> bool {
+        self.contains(name)
+    }
+}
+
+/// Filtering mode over any [`GemSet`]
+#[derive(Debug)]
+pub enum SliceMode<'a, S: GemSet + ?Sized> {
+    /// Pass through all gems (no filtering)
+    Passthrough,
+    /// Include only gems in the set
+    Allow(&'a S),
+    /// Exclude gems in the set
+    Block(&'a S),
+}
+
+impl<S: GemSet + ?Sized> Clone for SliceMode<'_, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S: GemSet + ?Sized> Copy for SliceMode<'_, S> {}
+
+/// What to do with a gem line that has no space, and so no separate name
+///
+/// The same choice applies in every mode, so allowing every gem is
+/// equivalent to passthrough.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NamelessLines {
+    /// Drop the line
+    #[default]
+    Skip,
+    /// Keep the line, whatever the mode
+    Keep,
+    /// Treat the whole line as the gem name
+    WholeLine,
+    /// Fail with [`FilterError::NamelessLine`]
+    Error,
+}
+
+option_names!(NamelessLines, "nameless line handling", {
+    Skip => "skip",
+    Keep => "keep",
+    WholeLine => "whole-line",
+    Error => "error",
+});
+
+/// How the `---` line ending the header is recognized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SeparatorMatch {
+    /// `---` with any whitespace around it, such as the `--- ` some mirrors write
+    #[default]
+    Trimmed,
+    /// `---` alone on its line, before a `\n` or `\r\n`
+    Exact,
+}
+
+option_names!(SeparatorMatch, "separator match", {
+    Trimmed => "trimmed",
+    Exact => "exact",
+});
+
+/// What to do with a separator line inside the body, after the first one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RepeatedSeparator {
+    /// Handle it like any other line without a space, under [`NamelessLines`]
+    #[default]
+    Body,
+    /// Drop the line, whatever the mode
+    Skip,
+    /// Fail with [`FilterError::RepeatedSeparator`]
+    Error,
+}
+
+option_names!(RepeatedSeparator, "repeated separator handling", {
+    Body => "body",
+    Skip => "skip",
+    Error => "error",
+});
+
+/// How separator lines are recognized, and what happens to repeated ones
+///
+/// Only the first separator ends the header. The default accepts `--- `
+/// and passes a second `---` through the body as any nameless line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SeparatorRules {
+    /// How a line is recognized as a separator
+    pub matching: SeparatorMatch,
+    /// What happens to separators after the first
+    pub repeated: RepeatedSeparator,
+}
+
+impl SeparatorRules {
+    /// Whether `line`, with or without its line ending, is a separator
+    pub fn is_separator(&self, line: &str) -> bool {
+        match self.matching {
+            SeparatorMatch::Trimmed => line.trim_ascii() == "---",
+            SeparatorMatch::Exact => {
+                let line = line.strip_suffix('\n').unwrap_or(line);
+                line.strip_suffix('\r').unwrap_or(line) == "---"
+            }
+        }
+    }
+}
+
+/// What a line the filter can't handle does to the run
+///
+/// Such lines are ones that aren't UTF-8, and those that
+/// [`NamelessLines::Error`] or [`RepeatedSeparator::Error`] reject. A
+/// missing separator always fails, since without one there is no body.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineErrors {
+    /// Fail with the line's [`FilterError`]
+    #[default]
+    Fail,
+    /// Drop the line and record it in the run's [`SkippedLines`]
+    Collect,
+}
+
+option_names!(LineErrors, "line error handling", {
+    Fail => "fail",
+    Collect => "collect",
+});
+
+/// How many [`SkippedLine`]s a report keeps; later ones are only counted
+pub const SKIPPED_LINES_KEPT: usize = 100;
+
+/// Longest line text a [`SkippedLine`] keeps, in bytes
+const SKIPPED_TEXT_BYTES: usize = 256;
+
+/// A line dropped under [`LineErrors::Collect`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedLine {
+    /// Line number in the input, counting from 1
+    pub line: u64,
+    /// Why the line was dropped
+    pub error: FilterError,
+    /// The start of the line, without its newline and with invalid UTF-8 replaced
+    pub text: String,
+}
+
+/// The lines a run dropped under [`LineErrors::Collect`]
+///
+/// Only the first [`SKIPPED_LINES_KEPT`] are kept, so a file of garbage
+/// can't grow the report without bound.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SkippedLines {
+    /// Number of lines dropped
+    pub count: u64,
+    /// The first lines dropped, in input order
+    pub lines: Vec<SkippedLine>,
+}
+
+/// Where a [`SliceFilter`] stopped, for picking the same input up again later
+///
+/// Taken between chunks with [`SliceFilter::checkpoint`]. A filter resumed
+/// from it with [`SliceFilter::resume_from`] and fed the input from
+/// `offset` on writes exactly what the original would have written after
+/// it, so the outputs of the two runs concatenate to one filtered file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Input bytes filtered, always the end of a complete line
+    pub offset: u64,
+    /// Whether the metadata separator was behind `offset`
+    pub in_body: bool,
+    /// Lines behind `offset`, counted only under [`LineErrors::Collect`] to
+    /// number skipped lines
+    pub lines: u64,
+}
+
+/// A run stopped before the end of its input
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Incomplete {
+    /// Where to resume
+    pub checkpoint: Checkpoint,
+    /// The lines skipped under [`LineErrors::Collect`] so far in this run
+    pub skipped: SkippedLines,
+}
+
+/// Errors from filtering malformed input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterError {
+    /// The input ended before the `---` separator
+    MissingSeparator,
+    /// A line was not valid UTF-8
+    InvalidUtf8,
+    /// A gem line had no space, with [`NamelessLines::Error`]
+    NamelessLine,
+    /// A separator line followed the first, with [`RepeatedSeparator::Error`]
+    RepeatedSeparator,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::MissingSeparator => f.write_str("No separator found in versions file"),
+            FilterError::InvalidUtf8 => f.write_str("stream did not contain valid UTF-8"),
+            FilterError::NamelessLine => f.write_str("Gem line has no space after the name"),
+            FilterError::RepeatedSeparator => {
+                f.write_str("Separator line repeated after the header")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FilterError {}
+
+#[cfg(feature = "std")]
+impl From<FilterError> for std::io::Error {
+    fn from(error: FilterError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// Filter a complete versions file held in memory, appending to `output`
+pub fn filter_slice<S: GemSet + ?Sized>(
+    input: &[u8],
+    output: &mut Vec<u8>,
+    mode: SliceMode<'_, S>,
+    version_output: VersionOutput,
+) -> Result<(), FilterError> {
+    let mut filter = SliceFilter::new(mode, version_output);
+    filter.push(input, output)?;
+    filter.finish(output)
+}
+
+/// Incremental versions-file filter fed with arbitrary byte chunks
+pub struct SliceFilter<'a, S: GemSet + ?Sized> {
+    mode: SliceMode<'a, S>,
+    version_output: VersionOutput,
+    nameless: NamelessLines,
+    separators: SeparatorRules,
+    line_errors: LineErrors,
+    in_body: bool,
+    started: bool,
+    partial: Vec<u8>,
+    /// Input bytes pushed, including any resumed from
+    consumed: u64,
+    /// Lines before the current run, counted only when collecting errors
+    lines_before: u64,
+    skipped: SkippedLines,
+}
+
+impl<'a, S: GemSet + ?Sized> SliceFilter<'a, S> {
+    /// Create a filter in the state before the first metadata line
+    pub fn new(mode: SliceMode<'a, S>, version_output: VersionOutput) -> Self {
+        SliceFilter {
+            mode,
+            version_output,
+            nameless: NamelessLines::default(),
+            separators: SeparatorRules::default(),
+            line_errors: LineErrors::default(),
+            in_body: false,
+            started: false,
+            partial: Vec::new(),
+            consumed: 0,
+            lines_before: 0,
+            skipped: SkippedLines::default(),
+        }
+    }
+
+    /// Start from `checkpoint`, to be fed the input from its offset on
+    ///
+    /// The other options must match the run the checkpoint came from.
+    pub fn resume_from(mut self, checkpoint: Checkpoint) -> Self {
+        self.consumed = checkpoint.offset;
+        self.in_body = checkpoint.in_body;
+        self.started = checkpoint.offset > 0;
+        self.lines_before = checkpoint.lines;
+        self
+    }
+
+    /// Choose how gem lines without a space are handled
+    pub fn nameless_lines(mut self, nameless: NamelessLines) -> Self {
+        self.nameless = nameless;
+        self
+    }
+
+    /// Choose how separator lines are recognized and repeated ones handled
+    pub fn separator_rules(mut self, separators: SeparatorRules) -> Self {
+        self.separators = separators;
+        self
+    }
+
+    /// Choose whether lines the filter can't handle fail the run or are skipped
+    pub fn line_errors(mut self, line_errors: LineErrors) -> Self {
+        self.line_errors = line_errors;
+        self
+    }
+
+    /// Filter a chunk, appending output for every line it completes
+    pub fn push(&mut self, chunk: &[u8], output: &mut Vec<u8>) -> Result<(), FilterError> {
+        self.consumed += chunk.len() as u64;
+        let mut rest = chunk;
+
+        // Complete the line carried over from the previous chunk first
+        if !self.partial.is_empty() {
+            match memchr(b'\n', rest) {
+                None => {
+                    self.partial.extend_from_slice(rest);
+                    return Ok(());
+                }
+                Some(i) => {
+                    self.partial.extend_from_slice(&rest[..=i]);
+                    let line = core::mem::take(&mut self.partial);
+                    self.process_lines(&line, output)?;
+                    rest = &rest[i + 1..];
+                }
+            }
+        }
+
+        // Whole lines are filtered straight from the chunk without copying
+        let end = memrchr(b'\n', rest).map_or(0, |i| i + 1);
+        self.process_lines(&rest[..end], output)?;
+        self.partial.extend_from_slice(&rest[end..]);
+        Ok(())
+    }
+
+    /// Bytes of an incomplete line held until a later chunk completes it
+    pub fn pending_len(&self) -> usize {
+        self.partial.len()
+    }
+
+    /// Where to resume after the last complete line pushed so far
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            offset: self.consumed - self.partial.len() as u64,
+            in_body: self.in_body,
+            lines: self.lines_before,
+        }
+    }
+
+    /// Give up on the rest of the input, dropping any incomplete line
+    ///
+    /// The dropped bytes lie after the checkpoint, so a resumed run reads
+    /// them again.
+    pub fn stop(self) -> Incomplete {
+        Incomplete {
+            checkpoint: self.checkpoint(),
+            skipped: self.skipped,
+        }
+    }
+
+    /// Flush the final unterminated line and check the file was complete
+    pub fn finish(self, output: &mut Vec<u8>) -> Result<(), FilterError> {
+        self.finish_with_report(output).map(drop)
+    }
+
+    /// [`finish`](Self::finish), returning the lines skipped under [`LineErrors::Collect`]
+    pub fn finish_with_report(mut self, output: &mut Vec<u8>) -> Result<SkippedLines, FilterError> {
+        let line = core::mem::take(&mut self.partial);
+        self.process_lines(&line, output)?;
+
+        if !self.in_body {
+            return Err(FilterError::MissingSeparator);
+        }
+        Ok(self.skipped)
+    }
+
+    /// Filter a run of complete lines (the last may lack its newline only in `finish`)
+    fn process_lines(&mut self, mut lines: &[u8], output: &mut Vec<u8>) -> Result<(), FilterError> {
+        // Metadata is copied verbatim up to and including the separator
+        while !self.in_body && !lines.is_empty() {
+            let end = memchr(b'\n', lines).map_or(lines.len(), |i| i + 1);
+            let (raw, rest) = lines.split_at(end);
+            lines = rest;
+            if self.line_errors == LineErrors::Collect {
+                self.lines_before += 1;
+            }
+            let mut line = match to_str(raw) {
+                Ok(line) => line,
+                Err(error) => {
+                    self.skip(self.lines_before, raw, error)?;
+                    continue;
+                }
+            };
+            if !self.started {
+                line = strip_bom(line);
+                self.started = true;
+            }
+            output.extend_from_slice(line.as_bytes());
+            self.in_body = self.separators.is_separator(line);
+        }
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        // One validation pass over the whole run, rather than one per line
+        let text = match to_str(lines) {
+            Ok(text) => text,
+            Err(_) if self.line_errors == LineErrors::Collect => {
+                return self.process_lines_singly(lines, output)
+            }
+            Err(error) => return Err(error),
+        };
+
+        // Hoist the mode checks out of the per-line loops
+        let keep_all = matches!(
+            self.nameless,
+            NamelessLines::Keep | NamelessLines::WholeLine
+        ) && self.separators.repeated == RepeatedSeparator::Body;
+        match (self.mode, self.version_output) {
+            (SliceMode::Passthrough, VersionOutput::Preserve) if keep_all => {
+                for line in lines_inclusive(text) {
+                    if !line.trim_ascii().is_empty() {
+                        output.extend_from_slice(line.as_bytes());
+                    }
+                }
+            }
+            (SliceMode::Passthrough, VersionOutput::Strip) if keep_all => {
+                for line in lines_inclusive(text) {
+                    let trimmed = line.trim_ascii();
+                    if !trimmed.is_empty() {
+                        push_gem_line_stripped(trimmed, output);
+                    }
+                }
+            }
+            (SliceMode::Passthrough, VersionOutput::Preserve) => {
+                for line in lines_inclusive(text) {
+                    let trimmed = line.trim_ascii();
+                    if trimmed.is_empty() {
+                        continue;
+                    }
+                    if gem_name(trimmed).is_some() || self.keep_nameless(text, line, trimmed)? {
+                        output.extend_from_slice(line.as_bytes());
+                    }
+                }
+            }
+            (SliceMode::Passthrough, VersionOutput::Strip) => {
+                for line in lines_inclusive(text) {
+                    let trimmed = line.trim_ascii();
+                    if trimmed.is_empty() {
+                        continue;
+                    }
+                    if gem_name(trimmed).is_some() || self.keep_nameless(text, line, trimmed)? {
+                        push_gem_line_stripped(trimmed, output);
+                    }
+                }
+            }
+            (SliceMode::Allow(gemlist) | SliceMode::Block(gemlist), VersionOutput::Preserve) => {
+                let include_on_match = matches!(self.mode, SliceMode::Allow(_));
+                for line in lines_inclusive(text) {
+                    let trimmed = line.trim_ascii();
+                    let keep = match gem_name(trimmed) {
+                        Some(gem_name) => gemlist.contains_gem(gem_name) == include_on_match,
+                        None => !trimmed.is_empty() && self.keep_nameless(text, line, trimmed)?,
+                    };
+                    if keep {
+                        output.extend_from_slice(line.as_bytes());
+                    }
+                }
+            }
+            (SliceMode::Allow(gemlist) | SliceMode::Block(gemlist), VersionOutput::Strip) => {
+                let include_on_match = matches!(self.mode, SliceMode::Allow(_));
+                for line in lines_inclusive(text) {
+                    let trimmed = line.trim_ascii();
+                    let keep = match gem_name(trimmed) {
+                        Some(gem_name) => gemlist.contains_gem(gem_name) == include_on_match,
+                        None => !trimmed.is_empty() && self.keep_nameless(text, line, trimmed)?,
+                    };
+                    if keep {
+                        push_gem_line_stripped(trimmed, output);
+                    }
+                }
+            }
+        }
+
+        if self.line_errors == LineErrors::Collect {
+            self.lines_before += memchr_iter(b'\n', lines).count() as u64;
+        }
+        Ok(())
+    }
+
+    /// Filter a run that isn't all UTF-8 line by line, skipping the lines that aren't
+    #[cold]
+    fn process_lines_singly(
+        &mut self,
+        mut lines: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<(), FilterError> {
+        while !lines.is_empty() {
+            let end = memchr(b'\n', lines).map_or(lines.len(), |i| i + 1);
+            let (line, rest) = lines.split_at(end);
+            lines = rest;
+            if let Err(error) = to_str(line) {
+                self.lines_before += 1;
+                self.skip(self.lines_before, line, error)?;
+            } else {
+                self.process_lines(line, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail with `error`, or record `line` as skipped under [`LineErrors::Collect`]
+    #[cold]
+    fn skip(&mut self, number: u64, line: &[u8], error: FilterError) -> Result<(), FilterError> {
+        if self.line_errors == LineErrors::Fail {
+            return Err(error);
+        }
+        self.skipped.count += 1;
+        if self.skipped.lines.len() < SKIPPED_LINES_KEPT {
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let text = &line[..line.len().min(SKIPPED_TEXT_BYTES)];
+            self.skipped.lines.push(SkippedLine {
+                line: number,
+                error,
+                text: String::from_utf8_lossy(text).into_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// [`skip`](Self::skip) for `line`, a line of the run `text`, returning that it isn't kept
+    #[cold]
+    fn reject(&mut self, text: &str, line: &str, error: FilterError) -> Result<bool, FilterError> {
+        let offset = line.as_ptr() as usize - text.as_ptr() as usize;
+        let number =
+            self.lines_before + memchr_iter(b'\n', &text.as_bytes()[..offset]).count() as u64 + 1;
+        self.skip(number, line.as_bytes(), error).map(|()| false)
+    }
+
+    /// Whether to keep a non-empty line of the run `text` that has no space, given trimmed too
+    #[cold]
+    fn keep_nameless(
+        &mut self,
+        text: &str,
+        line: &str,
+        trimmed: &str,
+    ) -> Result<bool, FilterError> {
+        // A separator never has a space after trimming, so repeats all land here
+        match self.separators.repeated {
+            RepeatedSeparator::Body => {}
+            _ if !self.separators.is_separator(line) => {}
+            RepeatedSeparator::Skip => return Ok(false),
+            RepeatedSeparator::Error => {
+                return self.reject(text, line, FilterError::RepeatedSeparator)
+            }
+        }
+        match self.nameless {
+            NamelessLines::Skip => Ok(false),
+            NamelessLines::Keep => Ok(true),
+            NamelessLines::WholeLine => Ok(match self.mode {
+                SliceMode::Passthrough => true,
+                SliceMode::Allow(gemlist) => gemlist.contains_gem(trimmed),
+                SliceMode::Block(gemlist) => !gemlist.contains_gem(trimmed),
+            }),
+            NamelessLines::Error => self.reject(text, line, FilterError::NamelessLine),
+        }
+    }
+}
+
+/// Split `text` into lines that keep their `\n`, the last one possibly without
+///
+/// `memchr` scans a word or SIMD register at a time, and slicing at an ASCII
+/// byte always lands on a char boundary.
+#[inline]
+fn lines_inclusive(mut text: &str) -> impl Iterator<Item = &str> {
+    core::iter::from_fn(move || {
+        if text.is_empty() {
+            return None;
+        }
+        let end = memchr(b'\n', text.as_bytes()).map_or(text.len(), |i| i + 1);
+        let (line, rest) = text.split_at(end);
+        text = rest;
+        Some(line)
+    })
+}
+
+/// Extract gem name (first word) from a gem line
+#[inline]
+pub(crate) fn gem_name(line: &str) -> Option<&str> {
+    memchr(b' ', line.as_bytes()).map(|space_pos| &line[..space_pos])
+}
+
+/// Append a gem line with its version list replaced by `0`
+#[inline]
+fn push_gem_line_stripped(trimmed: &str, output: &mut Vec<u8>) {
+    match stripped_fields(trimmed) {
+        Some(fields) => {
+            for (i, field) in fields.enumerate() {
+                if i > 0 {
+                    output.push(b' ');
+                }
+                output.extend_from_slice(field.as_bytes());
+            }
+        }
+        // Malformed lines are written as-is
+        None => output.extend_from_slice(trimmed.as_bytes()),
+    }
+    output.push(b'\n');
+}
+
+/// The fields of a gem line with its version list replaced by `0`
+///
+/// `gemname versions md5 [extra...]` becomes `gemname 0 md5 [extra...]`,
+/// split on ASCII whitespace like the rest of the index. `None` for lines
+/// with fewer than three fields. Every strip of a version list goes through
+/// here, so all output paths agree on what a stripped line looks like.
+#[inline]
+pub(crate) fn stripped_fields(trimmed: &str) -> Option<impl Iterator<Item = &str>> {
+    let mut parts = trimmed.split_ascii_whitespace();
+    let name = parts.next()?;
+    parts.next()?;
+    let mut rest = parts.peekable();
+    rest.peek()?;
+    Some([name, "0"].into_iter().chain(rest))
+}
+
+/// Drop a leading UTF-8 byte order mark, as written by some Windows tools
+///
+/// Left in place it would become part of the first metadata key or gem name.
+pub(crate) fn strip_bom(text: &str) -> &str {
+    text.strip_prefix('\u{FEFF}').unwrap_or(text)
+}
+
+fn to_str(bytes: &[u8]) -> Result<&str, FilterError> {
+    core::str::from_utf8(bytes).map_err(|_| FilterError::InvalidUtf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSIONS: &str = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0,7.0.1 abc123
+
+activerecord 7.0.0 def456 extra
+sinatra -3.0.0,3.0.1 ghi789
+malformed
+rails 7.0.2 jkl000"#;
+
+    #[test]
+    fn test_filter_slice_with_sorted_slice_set() {
+        let gems = ["rails", "sinatra"];
+        let mut output = Vec::new();
+        filter_slice(
+            VERSIONS.as_bytes(),
+            &mut output,
+            SliceMode::Allow(&gems[..]),
+            VersionOutput::Strip,
+        )
+        .unwrap();
+
+        assert_eq!(
+            core::str::from_utf8(&output).unwrap(),
+            "created_at: 2024-04-01T00:00:05Z\n---\nrails 0 abc123\nsinatra 0 ghi789\nrails 0 jkl000\n"
+        );
+    }
+
+    #[test]
+    fn test_line_errors_collect() {
+        let mut input = b"created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n".to_vec();
+        input.extend_from_slice(b"sinatra 3.0.0 \xff\xfe\n");
+        input.extend_from_slice(b"puma\nrack 3.0.8 def456\n---\nnokogiri 1.16.0 ghi789\n");
+        let rules = SeparatorRules {
+            repeated: RepeatedSeparator::Error,
+            ..SeparatorRules::default()
+        };
+
+        // Line numbers must come out the same however the input is split
+        for chunk_size in [1, 7, input.len()] {
+            let mut filter =
+                SliceFilter::new(SliceMode::<[&str]>::Passthrough, VersionOutput::Preserve)
+                    .nameless_lines(NamelessLines::Error)
+                    .separator_rules(rules)
+                    .line_errors(LineErrors::Collect);
+            let mut output = Vec::new();
+            for chunk in input.chunks(chunk_size) {
+                filter.push(chunk, &mut output).unwrap();
+            }
+            let skipped = filter.finish_with_report(&mut output).unwrap();
+
+            assert_eq!(
+                core::str::from_utf8(&output).unwrap(),
+                "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n\
+                 rack 3.0.8 def456\nnokogiri 1.16.0 ghi789\n"
+            );
+            assert_eq!(skipped.count, 3);
+            let found: Vec<(u64, FilterError, &str)> = skipped
+                .lines
+                .iter()
+                .map(|skipped| (skipped.line, skipped.error, skipped.text.as_str()))
+                .collect();
+            assert_eq!(
+                found,
+                [
+                    (
+                        4,
+                        FilterError::InvalidUtf8,
+                        "sinatra 3.0.0 \u{FFFD}\u{FFFD}"
+                    ),
+                    (5, FilterError::NamelessLine, "puma"),
+                    (7, FilterError::RepeatedSeparator, "---"),
+                ]
+            );
+        }
+
+        // By default the first bad line still fails the run
+        let mut output = Vec::new();
+        let err = filter_slice(
+            &input,
+            &mut output,
+            SliceMode::<[&str]>::Passthrough,
+            VersionOutput::Preserve,
+        )
+        .unwrap_err();
+        assert_eq!(err, FilterError::InvalidUtf8);
+    }
+
+    #[test]
+    fn test_chunked_block_mode() {
+        let gems: BTreeSet<&str> = ["rails"].into_iter().collect();
+        let mut filter = SliceFilter::new(SliceMode::Block(&gems), VersionOutput::Preserve);
+        let mut output = Vec::new();
+        for chunk in VERSIONS.as_bytes().chunks(5) {
+            filter.push(chunk, &mut output).unwrap();
+        }
+        filter.finish(&mut output).unwrap();
+
+        assert_eq!(
+            core::str::from_utf8(&output).unwrap(),
+            "created_at: 2024-04-01T00:00:05Z\n---\nactiverecord 7.0.0 def456 extra\nsinatra -3.0.0,3.0.1 ghi789\n"
+        );
+    }
+
+    #[test]
+    fn test_nameless_lines_are_handled_alike_in_every_mode() {
+        let all = ["activerecord", "malformed", "rails", "sinatra"];
+        let none: [&str; 0] = [];
+        let modes: [SliceMode<'_, [&str]>; 3] = [
+            SliceMode::Passthrough,
+            SliceMode::Allow(&all[..]),
+            SliceMode::Block(&none[..]),
+        ];
+        let run = |mode, nameless| {
+            let mut output = Vec::new();
+            let mut filter =
+                SliceFilter::new(mode, VersionOutput::Preserve).nameless_lines(nameless);
+            filter.push(VERSIONS.as_bytes(), &mut output)?;
+            filter.finish(&mut output)?;
+            Ok(String::from_utf8(output).unwrap())
+        };
+
+        for mode in modes {
+            assert!(!run(mode, NamelessLines::Skip)
+                .unwrap()
+                .contains("malformed"));
+            assert!(run(mode, NamelessLines::Keep)
+                .unwrap()
+                .contains("\nmalformed\n"));
+            assert!(run(mode, NamelessLines::WholeLine)
+                .unwrap()
+                .contains("\nmalformed\n"));
+            assert_eq!(
+                run(mode, NamelessLines::Error),
+                Err(FilterError::NamelessLine)
+            );
+        }
+
+        // As a name, the whole line is matched against the list
+        let rails = ["rails"];
+        let output = run(SliceMode::Allow(&rails[..]), NamelessLines::WholeLine).unwrap();
+        assert!(!output.contains("malformed"));
+        let output = run(SliceMode::Block(&rails[..]), NamelessLines::WholeLine).unwrap();
+        assert!(output.contains("malformed"));
+    }
+
+    #[test]
+    fn test_separator_rules() {
+        let input = "created_at: 2024-04-01T00:00:05Z\n--- \r\n\
+            rails 7.0.0 abc123\n\
+            ---\n\
+            sinatra 3.0.0 def456\n";
+        let rails = ["rails", "sinatra"];
+        let run = |mode, nameless, separators| {
+            let mut output = Vec::new();
+            let mut filter = SliceFilter::new(mode, VersionOutput::Preserve)
+                .nameless_lines(nameless)
+                .separator_rules(separators);
+            filter.push(input.as_bytes(), &mut output)?;
+            filter.finish(&mut output)?;
+            Ok(String::from_utf8(output).unwrap())
+        };
+        let rules = |matching, repeated| SeparatorRules { matching, repeated };
+
+        // Matched exactly, `--- ` is a header line and the bare `---` ends the header
+        let exact = rules(SeparatorMatch::Exact, RepeatedSeparator::Error);
+        let output = run(SliceMode::Allow(&rails[..]), NamelessLines::Skip, exact).unwrap();
+        assert_eq!(output, input);
+        assert!(exact.is_separator("---\r\n"));
+
+        let modes: [SliceMode<'_, [&str]>; 2] =
+            [SliceMode::Passthrough, SliceMode::Allow(&rails[..])];
+        for mode in modes {
+            let trimmed = |repeated| rules(SeparatorMatch::Trimmed, repeated);
+            let body = run(mode, NamelessLines::Keep, trimmed(RepeatedSeparator::Body)).unwrap();
+            assert_eq!(body.matches("---").count(), 2);
+            let skip = run(mode, NamelessLines::Keep, trimmed(RepeatedSeparator::Skip)).unwrap();
+            assert_eq!(
+                skip,
+                "created_at: 2024-04-01T00:00:05Z\n--- \r\nrails 7.0.0 abc123\nsinatra 3.0.0 def456\n"
+            );
+            assert_eq!(
+                run(mode, NamelessLines::Keep, trimmed(RepeatedSeparator::Error)),
+                Err(FilterError::RepeatedSeparator)
+            );
+        }
+    }
+
+    #[test]
+    fn test_leading_bom_is_stripped() {
+        let input = alloc::format!("\u{FEFF}{}\n", VERSIONS);
+        let none: SliceMode<'_, [&str]> = SliceMode::Passthrough;
+
+        // Even when the mark itself is split across chunks
+        let mut filter = SliceFilter::new(none, VersionOutput::Preserve);
+        let mut output = Vec::new();
+        for chunk in input.as_bytes().chunks(1) {
+            filter.push(chunk, &mut output).unwrap();
+        }
+        filter.finish(&mut output).unwrap();
+
+        assert!(output.starts_with(b"created_at: "));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut output = Vec::new();
+        let none: SliceMode<'_, [&str]> = SliceMode::Passthrough;
+        assert_eq!(
+            filter_slice(
+                b"rails 7.0.0 abc\n",
+                &mut output,
+                none,
+                VersionOutput::Preserve
+            ),
+            Err(FilterError::MissingSeparator)
+        );
+        assert_eq!(
+            filter_slice(
+                &[b'-', b'-', b'-', b'\n', 0xff, b'\n'],
+                &mut output,
+                none,
+                VersionOutput::Preserve
+            ),
+            Err(FilterError::InvalidUtf8)
+        );
+    }
+}
@@ -0,0 +1,574 @@
+//! Where allow/block lists come from, and keeping them current
+//!
+//! A one-shot CLI run reads its lists once, but a long-running server should
+//! pick up policy changes without a restart or a new image. An
+//! [`AllowlistSource`] hands out the current list and decides for itself when
+//! to look again: [`FileSource`] rereads when the file's mtime changes,
+//! [`HttpSource`] polls a URL, or with the `s3` feature an S3 object, on an
+//! interval with `If-None-Match`, so an unchanged list costs a 304 rather
+//! than a download, [`RedisSource`]
+//! reads a Redis set, reloading as soon as a keyspace notification arrives,
+//! and [`DbSource`] reads a SQLite or Postgres table an admin UI can edit.
+
+use crate::slice::strip_bom;
+#[cfg(feature = "db")]
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+#[cfg(feature = "redis")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+#[cfg(any(feature = "http", feature = "redis", feature = "db"))]
+use std::time::{Duration, Instant};
+
+/// A gem list that may change while the process runs
+pub trait AllowlistSource {
+    /// The current list, refreshed first if the source is due for it
+    ///
+    /// The list is shared, so callers can hold on to it across a request
+    /// while a later call swaps in a newer one.
+    fn gems(&mut self) -> std::io::Result<Arc<HashSet<String>>>;
+}
+
+/// Parse a gem list: one name per line, blank lines and `#` comments skipped
+pub fn parse_gem_list<R: BufRead>(reader: R) -> std::io::Result<HashSet<String>> {
+    let mut gems = HashSet::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = if index == 0 { strip_bom(&line) } else { &line };
+        let gem_name = line.trim();
+        if !gem_name.is_empty() && !gem_name.starts_with('#') {
+            gems.insert(gem_name.to_string());
+        }
+    }
+    Ok(gems)
+}
+
+/// A list file on disk, reread whenever its modification time changes
+pub struct FileSource {
+    path: PathBuf,
+    loaded: Option<(SystemTime, Arc<HashSet<String>>)>,
+}
+
+impl FileSource {
+    /// Create a source; the file is first read on the first `gems()` call
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileSource {
+            path: path.into(),
+            loaded: None,
+        }
+    }
+}
+
+impl AllowlistSource for FileSource {
+    fn gems(&mut self) -> std::io::Result<Arc<HashSet<String>>> {
+        let modified = std::fs::metadata(&self.path)?.modified()?;
+        if let Some((loaded_at, gems)) = &self.loaded {
+            if *loaded_at == modified {
+                return Ok(Arc::clone(gems));
+            }
+        }
+
+        let gems = Arc::new(parse_gem_list(BufReader::new(File::open(&self.path)?))?);
+        self.loaded = Some((modified, Arc::clone(&gems)));
+        Ok(gems)
+    }
+}
+
+/// A list served over HTTP(S), polled every `refresh` (`http` feature)
+///
+/// A failed refresh keeps serving the last list that loaded, so a policy
+/// service outage doesn't take mirrors down with it; [`last_error`](Self::last_error)
+/// reports the failure. Only the very first fetch can return an error.
+#[cfg(feature = "http")]
+pub struct HttpSource {
+    agent: ureq::Agent,
+    limits: crate::fetch::FetchLimits,
+    url: String,
+    refresh: Duration,
+    etag: Option<String>,
+    checked_at: Option<Instant>,
+    gems: Option<Arc<HashSet<String>>>,
+    last_error: Option<std::io::Error>,
+    /// Signs every request when the list lives in S3
+    #[cfg(feature = "s3")]
+    s3: Option<crate::s3::S3Config>,
+}
+
+#[cfg(feature = "http")]
+impl HttpSource {
+    /// Create a source; the URL is first fetched on the first `gems()` call
+    pub fn new(url: impl Into<String>, refresh: Duration) -> Self {
+        let limits = crate::fetch::FetchLimits::default();
+        HttpSource {
+            agent: limits.agent(false),
+            limits,
+            url: url.into(),
+            refresh,
+            etag: None,
+            checked_at: None,
+            gems: None,
+            last_error: None,
+            #[cfg(feature = "s3")]
+            s3: None,
+        }
+    }
+
+    /// A source for an `s3://bucket/key` object, fetched with requests signed
+    /// by `config` (`s3` feature)
+    #[cfg(feature = "s3")]
+    pub fn s3(url: &str, config: crate::s3::S3Config, refresh: Duration) -> std::io::Result<Self> {
+        let location = crate::s3::S3Location::parse(url)?;
+        let mut source = HttpSource::new(config.object_url(&location), refresh);
+        source.s3 = Some(config);
+        Ok(source)
+    }
+
+    /// Replace the default timeouts and size cap of every fetch
+    pub fn limits(mut self, limits: crate::fetch::FetchLimits) -> Self {
+        self.agent = limits.agent(false);
+        self.limits = limits;
+        self
+    }
+
+    /// The error from the most recent refresh, if it failed
+    pub fn last_error(&self) -> Option<&std::io::Error> {
+        self.last_error.as_ref()
+    }
+
+    /// Fetch the list, returning `None` when the server says it is unchanged
+    fn fetch(&self) -> std::io::Result<Option<(HashSet<String>, Option<String>)>> {
+        let mut request = self.agent.get(&self.url);
+        if let (Some(etag), Some(_)) = (&self.etag, &self.gems) {
+            request = request.header("If-None-Match", etag);
+        }
+        #[cfg(feature = "s3")]
+        if let Some(config) = &self.s3 {
+            for (name, value) in config.sign_get(&self.url, std::time::SystemTime::now()) {
+                request = request.header(name, value);
+            }
+        }
+        let response = request
+            .call()
+            .map_err(|e| crate::fetch::call_error(&self.url, e))?;
+
+        match response.status().as_u16() {
+            200 => {
+                let etag = response
+                    .headers()
+                    .get("etag")
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from);
+                let reader = BufReader::new(self.limits.body(&self.url, response)?);
+                Ok(Some((parse_gem_list(reader)?, etag)))
+            }
+            304 => Ok(None),
+            status => Err(std::io::Error::other(format!(
+                "Failed to fetch {}: HTTP {}",
+                self.url, status
+            ))),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl AllowlistSource for HttpSource {
+    fn gems(&mut self) -> std::io::Result<Arc<HashSet<String>>> {
+        let due = self
+            .checked_at
+            .is_none_or(|checked| checked.elapsed() >= self.refresh);
+        if !due {
+            if let Some(gems) = &self.gems {
+                return Ok(Arc::clone(gems));
+            }
+        }
+
+        self.checked_at = Some(Instant::now());
+        match (self.fetch(), &self.gems) {
+            (Ok(Some((gems, etag))), _) => {
+                self.gems = Some(Arc::new(gems));
+                self.etag = etag;
+                self.last_error = None;
+            }
+            (Ok(None), _) => self.last_error = None,
+            (Err(e), Some(_)) => self.last_error = Some(e),
+            (Err(e), None) => return Err(e),
+        }
+        Ok(Arc::clone(self.gems.as_ref().expect("a list was loaded")))
+    }
+}
+
+/// Members of a Redis set, e.g. `SADD mirror:allowlist rails` (`redis` feature)
+///
+/// Without [`watch`](Self::watch) the set is polled every `refresh`. With it,
+/// a background subscriber marks the list stale on every keyspace event for
+/// the key, so changes reach every replica on its next `gems()` call; polling
+/// stays on as the fallback for missed events. Keyspace events must be
+/// enabled on the server for set and generic commands
+/// (`notify-keyspace-events Kgs`). As with [`HttpSource`], a failed refresh
+/// keeps serving the last list that loaded.
+#[cfg(feature = "redis")]
+pub struct RedisSource {
+    client: redis::Client,
+    connection: Option<redis::Connection>,
+    key: String,
+    refresh: Duration,
+    checked_at: Option<Instant>,
+    changed: Arc<AtomicBool>,
+    gems: Option<Arc<HashSet<String>>>,
+    last_error: Option<std::io::Error>,
+}
+
+/// How long the subscriber blocks before checking whether its source is gone
+#[cfg(feature = "redis")]
+const WATCH_POLL: Duration = Duration::from_secs(1);
+
+#[cfg(feature = "redis")]
+impl RedisSource {
+    /// Create a source for the set at `key`; nothing connects until first use
+    pub fn new(url: &str, key: impl Into<String>, refresh: Duration) -> std::io::Result<Self> {
+        Ok(RedisSource {
+            client: redis::Client::open(url).map_err(redis_error)?,
+            connection: None,
+            key: key.into(),
+            refresh,
+            checked_at: None,
+            changed: Arc::new(AtomicBool::new(false)),
+            gems: None,
+            last_error: None,
+        })
+    }
+
+    /// The error from the most recent refresh, if it failed
+    pub fn last_error(&self) -> Option<&std::io::Error> {
+        self.last_error.as_ref()
+    }
+
+    /// Subscribe to keyspace notifications for the key on a background thread
+    ///
+    /// The thread reconnects after errors and exits once the source is dropped.
+    pub fn watch(&self) -> std::io::Result<()> {
+        let db = self.client.get_connection_info().redis_settings().db();
+        let channel = format!("__keyspace@{}__:{}", db, self.key);
+        let client = self.client.clone();
+        let changed = Arc::downgrade(&self.changed);
+
+        std::thread::Builder::new()
+            .name("redis-list-watch".into())
+            .spawn(move || {
+                while changed.strong_count() > 0 {
+                    // A gap in the subscription may have hidden an update
+                    if let Some(flag) = changed.upgrade() {
+                        flag.store(true, Ordering::Release);
+                    }
+                    if subscribe(&client, &channel, &changed).is_err() {
+                        std::thread::sleep(WATCH_POLL);
+                    }
+                }
+            })?;
+        Ok(())
+    }
+
+    fn fetch(&mut self) -> std::io::Result<HashSet<String>> {
+        use redis::Commands;
+
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => self
+                .connection
+                .insert(self.client.get_connection().map_err(redis_error)?),
+        };
+        let result = connection.smembers(&self.key);
+        if result.is_err() {
+            // Reconnect next time rather than reuse a connection in an unknown state
+            self.connection = None;
+        }
+        result.map_err(redis_error)
+    }
+}
+
+/// Flag `changed` on every message until the connection fails or the source is dropped
+#[cfg(feature = "redis")]
+fn subscribe(
+    client: &redis::Client,
+    channel: &str,
+    changed: &std::sync::Weak<AtomicBool>,
+) -> redis::RedisResult<()> {
+    let mut connection = client.get_connection()?;
+    let mut pubsub = connection.as_pubsub();
+    pubsub.subscribe(channel)?;
+    pubsub.set_read_timeout(Some(WATCH_POLL))?;
+
+    loop {
+        match pubsub.get_message() {
+            Ok(_) => match changed.upgrade() {
+                Some(flag) => flag.store(true, Ordering::Release),
+                None => return Ok(()),
+            },
+            Err(e) if e.is_timeout() => {
+                if changed.strong_count() == 0 {
+                    return Ok(());
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+impl AllowlistSource for RedisSource {
+    fn gems(&mut self) -> std::io::Result<Arc<HashSet<String>>> {
+        let notified = self.changed.swap(false, Ordering::Acquire);
+        let due = notified
+            || self
+                .checked_at
+                .is_none_or(|checked| checked.elapsed() >= self.refresh);
+        if !due {
+            if let Some(gems) = &self.gems {
+                return Ok(Arc::clone(gems));
+            }
+        }
+
+        self.checked_at = Some(Instant::now());
+        match (self.fetch(), &self.gems) {
+            (Ok(gems), _) => {
+                self.gems = Some(Arc::new(gems));
+                self.last_error = None;
+            }
+            (Err(e), Some(_)) => {
+                // Try again on the next call instead of waiting out the interval
+                self.changed.store(notified, Ordering::Release);
+                self.last_error = Some(e);
+            }
+            (Err(e), None) => return Err(e),
+        }
+        Ok(Arc::clone(self.gems.as_ref().expect("a list was loaded")))
+    }
+}
+
+#[cfg(feature = "redis")]
+fn redis_error(error: redis::RedisError) -> std::io::Error {
+    std::io::Error::other(format!("Redis: {}", error))
+}
+
+/// Table [`DbSource`] reads, valid for both SQLite and Postgres
+///
+/// `version_requirement` is an optional RubyGems requirement such as
+/// `>= 7.0, < 8`; `NULL` means any version.
+#[cfg(feature = "db")]
+pub const DB_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS gem_policy (
+    name TEXT PRIMARY KEY,
+    list TEXT NOT NULL CHECK (list IN ('allow', 'block')),
+    version_requirement TEXT
+)";
+
+/// Which rows of `gem_policy` a [`DbSource`] serves
+#[cfg(feature = "db")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListKind {
+    /// Rows with `list = 'allow'`
+    Allow,
+    /// Rows with `list = 'block'`
+    Block,
+}
+
+/// One list from the [`DB_SCHEMA`] table, reloaded every `refresh` (`db` feature)
+///
+/// Takes a `sqlite://` or `postgres://` URL. Queries run on a private
+/// single-threaded runtime, so call `gems()` from ordinary (or
+/// `spawn_blocking`) threads, not from inside an async task. As with
+/// [`HttpSource`], a failed reload keeps serving the last list that loaded.
+#[cfg(feature = "db")]
+pub struct DbSource {
+    runtime: tokio::runtime::Runtime,
+    pool: sqlx::AnyPool,
+    kind: ListKind,
+    refresh: Duration,
+    checked_at: Option<Instant>,
+    gems: Option<Arc<HashSet<String>>>,
+    requirements: Arc<HashMap<String, String>>,
+    last_error: Option<std::io::Error>,
+}
+
+#[cfg(feature = "db")]
+impl DbSource {
+    /// Connect to the database; the table is first read on the first `gems()` call
+    pub fn connect(url: &str, kind: ListKind, refresh: Duration) -> std::io::Result<Self> {
+        sqlx::any::install_default_drivers();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        // Reloads are rare and sequential; one connection is plenty
+        let pool = runtime
+            .block_on(
+                sqlx::any::AnyPoolOptions::new()
+                    .max_connections(1)
+                    .connect(url),
+            )
+            .map_err(db_error)?;
+
+        Ok(DbSource {
+            runtime,
+            pool,
+            kind,
+            refresh,
+            checked_at: None,
+            gems: None,
+            requirements: Arc::default(),
+            last_error: None,
+        })
+    }
+
+    /// Version requirements by gem name, as of the last successful load
+    pub fn version_requirements(&self) -> Arc<HashMap<String, String>> {
+        Arc::clone(&self.requirements)
+    }
+
+    /// The error from the most recent reload, if it failed
+    pub fn last_error(&self) -> Option<&std::io::Error> {
+        self.last_error.as_ref()
+    }
+
+    fn fetch(&self) -> std::io::Result<Vec<(String, Option<String>)>> {
+        // A fixed query per kind; nothing user-supplied is spliced in
+        let query = match self.kind {
+            ListKind::Allow => {
+                "SELECT name, version_requirement FROM gem_policy WHERE list = 'allow'"
+            }
+            ListKind::Block => {
+                "SELECT name, version_requirement FROM gem_policy WHERE list = 'block'"
+            }
+        };
+        self.runtime
+            .block_on(sqlx::query_as(query).fetch_all(&self.pool))
+            .map_err(db_error)
+    }
+}
+
+#[cfg(feature = "db")]
+impl AllowlistSource for DbSource {
+    fn gems(&mut self) -> std::io::Result<Arc<HashSet<String>>> {
+        let due = self
+            .checked_at
+            .is_none_or(|checked| checked.elapsed() >= self.refresh);
+        if !due {
+            if let Some(gems) = &self.gems {
+                return Ok(Arc::clone(gems));
+            }
+        }
+
+        self.checked_at = Some(Instant::now());
+        match (self.fetch(), &self.gems) {
+            (Ok(rows), _) => {
+                let mut gems = HashSet::with_capacity(rows.len());
+                let mut requirements = HashMap::new();
+                for (name, requirement) in rows {
+                    if let Some(requirement) = requirement {
+                        requirements.insert(name.clone(), requirement);
+                    }
+                    gems.insert(name);
+                }
+                self.gems = Some(Arc::new(gems));
+                self.requirements = Arc::new(requirements);
+                self.last_error = None;
+            }
+            (Err(e), Some(_)) => self.last_error = Some(e),
+            (Err(e), None) => return Err(e),
+        }
+        Ok(Arc::clone(self.gems.as_ref().expect("a list was loaded")))
+    }
+}
+
+#[cfg(feature = "db")]
+fn db_error(error: sqlx::Error) -> std::io::Error {
+    std::io::Error::other(format!("Database: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gem_list_skips_blanks_and_comments() {
+        let gems = parse_gem_list("# core\nrails\n\n  sinatra  \n#puma\n".as_bytes()).unwrap();
+        let mut gems: Vec<_> = gems.into_iter().collect();
+        gems.sort();
+        assert_eq!(gems, vec!["rails", "sinatra"]);
+
+        // A byte order mark must not end up in the first name
+        let gems = parse_gem_list("\u{FEFF}rails\n".as_bytes()).unwrap();
+        assert!(gems.contains("rails"));
+    }
+
+    #[test]
+    fn test_file_source_rereads_after_change() {
+        let path = std::env::temp_dir().join(format!("gem-list-{}.txt", std::process::id()));
+        std::fs::write(&path, "rails\n").unwrap();
+        let mut source = FileSource::new(&path);
+
+        let first = source.gems().unwrap();
+        assert!(Arc::ptr_eq(&first, &source.gems().unwrap()));
+
+        std::fs::write(&path, "rails\nsinatra\n").unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(source.gems().unwrap().len(), 2);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_redis_source_errors_without_a_list() {
+        assert!(RedisSource::new("not a url", "allowlist", Duration::ZERO).is_err());
+
+        // Nothing listens on port 1, and there is no earlier list to fall back to
+        let mut source =
+            RedisSource::new("redis://127.0.0.1:1/", "allowlist", Duration::ZERO).unwrap();
+        assert!(source.gems().is_err());
+    }
+
+    #[cfg(feature = "db")]
+    #[test]
+    fn test_db_source_reloads_policy_table() {
+        let path = std::env::temp_dir().join(format!("gem-policy-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+
+        let mut allow = DbSource::connect(&url, ListKind::Allow, Duration::ZERO).unwrap();
+        let execute = |source: &DbSource, sql: &str| {
+            source
+                .runtime
+                .block_on(sqlx::query(sql).execute(&source.pool))
+                .unwrap();
+        };
+        execute(&allow, DB_SCHEMA);
+        execute(
+            &allow,
+            "INSERT INTO gem_policy VALUES ('rails', 'allow', '>= 7.0'), ('puma', 'allow', NULL), ('left-pad', 'block', NULL)",
+        );
+
+        let gems = allow.gems().unwrap();
+        assert_eq!(gems.len(), 2);
+        assert!(gems.contains("rails") && gems.contains("puma"));
+        assert_eq!(
+            allow
+                .version_requirements()
+                .get("rails")
+                .map(String::as_str),
+            Some(">= 7.0")
+        );
+
+        execute(&allow, "DELETE FROM gem_policy WHERE name = 'puma'");
+        assert_eq!(allow.gems().unwrap().len(), 1);
+
+        let mut block = DbSource::connect(&url, ListKind::Block, Duration::ZERO).unwrap();
+        assert!(block.gems().unwrap().contains("left-pad"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,578 @@
+//! Filtering the legacy `specs.4.8.gz` indexes
+//!
+//! Before the compact index, RubyGems served `specs.4.8.gz`,
+//! `latest_specs.4.8.gz` and `prerelease_specs.4.8.gz`: gzipped Ruby Marshal
+//! dumps of one array of `[name, Gem::Version, platform]` tuples. `gem` and
+//! some older tooling still read them, so a mirror that filters `versions`
+//! needs to filter these with the same list to keep one policy for both.
+//!
+//! Marshal isn't line-based, so unlike the versions filter the whole
+//! decompressed index is held in memory (tens of megabytes for the full
+//! `specs.4.8`). Only the Marshal types these indexes use are supported.
+//! Shared objects (`@` links) are written back out as copies, which Ruby
+//! loads identically; symbols stay shared.
+
+use crate::FilterMode;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+/// Marshal format version written by every supported Ruby
+const MARSHAL_VERSION: [u8; 2] = [4, 8];
+
+/// Nesting allowed before a dump is rejected, far beyond the three levels of
+/// a specs index, so hostile input can't exhaust the stack
+const MAX_DEPTH: usize = 64;
+
+/// A decoded Marshal value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    True,
+    False,
+    Int(i64),
+    /// Raw string bytes; the encoding lives in the surrounding `Ivar`
+    String(Vec<u8>),
+    Symbol(Vec<u8>),
+    Float(Vec<u8>),
+    Array(Vec<Value>),
+    Hash(Vec<(Value, Value)>),
+    /// A value with instance variables, such as a string and its `E` encoding flag
+    Ivar(Box<Value>, Vec<(Vec<u8>, Value)>),
+    /// Plain object: class name and instance variables
+    Object(Vec<u8>, Vec<(Vec<u8>, Value)>),
+    /// Object dumped with `marshal_dump`, as `Gem::Version` is
+    UserMarshal(Vec<u8>, Box<Value>),
+    /// Object dumped with `_dump`: class name and its bytes
+    UserDefined(Vec<u8>, Vec<u8>),
+}
+
+impl Value {
+    /// The bytes of a string, looking through its instance variables
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::String(bytes) => Some(bytes),
+            Value::Ivar(inner, _) => inner.as_bytes(),
+            _ => None,
+        }
+    }
+}
+
+/// One entry of a specs index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spec {
+    /// Gem name
+    pub name: String,
+    /// Version number, as `Gem::Version` holds it
+    pub version: String,
+    /// Platform string, `ruby` for pure-Ruby gems
+    pub platform: String,
+}
+
+/// Counts from filtering a specs index
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpecsStats {
+    /// Entries in the input
+    pub specs: usize,
+    /// Entries written to the output
+    pub kept: usize,
+}
+
+/// Filter a gzipped specs index by gem name, writing a gzipped index
+pub fn filter_specs<R: Read, W: Write>(
+    input: R,
+    output: W,
+    mode: FilterMode,
+) -> std::io::Result<SpecsStats> {
+    let mut marshal = Vec::new();
+    flate2::read::MultiGzDecoder::new(input).read_to_end(&mut marshal)?;
+
+    let Value::Array(specs) = load(&marshal)? else {
+        return Err(invalid("specs index is not a Marshal array".to_string()));
+    };
+    let total = specs.len();
+    let kept: Vec<Value> = specs
+        .into_iter()
+        .filter(|spec| match spec_name(spec) {
+            Some(name) => match mode {
+                FilterMode::Passthrough => true,
+                FilterMode::Allow(gems) => gems.contains(name),
+                FilterMode::Block(gems) => !gems.contains(name),
+            },
+            // Keep what we can't read rather than silently drop it
+            None => true,
+        })
+        .collect();
+    let stats = SpecsStats {
+        specs: total,
+        kept: kept.len(),
+    };
+
+    let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+    dump(&Value::Array(kept), &mut encoder)?;
+    encoder.finish()?;
+    Ok(stats)
+}
+
+/// Read the entries of a decompressed specs index
+pub fn read_specs(marshal: &[u8]) -> std::io::Result<Vec<Spec>> {
+    let Value::Array(specs) = load(marshal)? else {
+        return Err(invalid("specs index is not a Marshal array".to_string()));
+    };
+    specs
+        .iter()
+        .map(|spec| {
+            let text = |value: Option<&Value>| {
+                value
+                    .and_then(Value::as_bytes)
+                    .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+            };
+            let Value::Array(fields) = spec else {
+                return None;
+            };
+            let version = match fields.get(1) {
+                Some(Value::UserMarshal(class, data)) if class == b"Gem::Version" => {
+                    match data.as_ref() {
+                        Value::Array(parts) => text(parts.first()),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            Some(Spec {
+                name: text(fields.first())?,
+                version: version?,
+                platform: text(fields.get(2))?,
+            })
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(|| invalid("specs entry is not [name, Gem::Version, platform]".to_string()))
+}
+
+/// Write `specs` as a decompressed specs index, with UTF-8 strings
+pub fn write_specs<W: Write>(specs: &[Spec], output: &mut W) -> std::io::Result<()> {
+    let string = |text: &str| {
+        Value::Ivar(
+            Box::new(Value::String(text.as_bytes().to_vec())),
+            vec![(b"E".to_vec(), Value::True)],
+        )
+    };
+    let entries = specs
+        .iter()
+        .map(|spec| {
+            Value::Array(vec![
+                string(&spec.name),
+                Value::UserMarshal(
+                    b"Gem::Version".to_vec(),
+                    Box::new(Value::Array(vec![string(&spec.version)])),
+                ),
+                string(&spec.platform),
+            ])
+        })
+        .collect();
+    dump(&Value::Array(entries), output)
+}
+
+/// The gem name of a `[name, version, platform]` entry
+fn spec_name(spec: &Value) -> Option<&str> {
+    let Value::Array(fields) = spec else {
+        return None;
+    };
+    std::str::from_utf8(fields.first()?.as_bytes()?).ok()
+}
+
+/// Decode a Marshal 4.8 dump
+pub fn load(data: &[u8]) -> std::io::Result<Value> {
+    if data.get(..2) != Some(&MARSHAL_VERSION[..]) {
+        return Err(invalid("not a Marshal 4.8 dump".to_string()));
+    }
+    let mut loader = Loader {
+        data,
+        pos: 2,
+        symbols: Vec::new(),
+        objects: Vec::new(),
+    };
+    let value = loader.value(0)?;
+    if loader.pos != data.len() {
+        return Err(invalid(format!(
+            "{} trailing bytes after the Marshal value",
+            data.len() - loader.pos
+        )));
+    }
+    Ok(value)
+}
+
+/// Encode `value` as a Marshal 4.8 dump
+pub fn dump<W: Write>(value: &Value, output: &mut W) -> std::io::Result<()> {
+    let mut dumper = Dumper {
+        out: Vec::new(),
+        symbols: HashMap::new(),
+    };
+    dumper.out.extend_from_slice(&MARSHAL_VERSION);
+    dumper.value(value);
+    output.write_all(&dumper.out)
+}
+
+struct Loader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    symbols: Vec<Vec<u8>>,
+    /// Objects in the order `@` links number them; `None` while still being read
+    objects: Vec<Option<Value>>,
+}
+
+impl Loader<'_> {
+    fn value(&mut self, depth: usize) -> std::io::Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("Marshal data nested too deeply".to_string()));
+        }
+        let tag = self.byte()?;
+        match tag {
+            b'0' => Ok(Value::Nil),
+            b'T' => Ok(Value::True),
+            b'F' => Ok(Value::False),
+            b'i' => Ok(Value::Int(self.int()?)),
+            b':' | b';' => Ok(Value::Symbol(self.symbol_after(tag)?)),
+            b'@' => {
+                let index = self.len()?;
+                match self.objects.get(index) {
+                    Some(Some(value)) => Ok(value.clone()),
+                    _ => Err(invalid(format!("invalid Marshal object link @{}", index))),
+                }
+            }
+            b'I' => {
+                let index = self.objects.len();
+                let inner = self.value(depth + 1)?;
+                let ivars = self.ivars(depth)?;
+                let value = Value::Ivar(Box::new(inner), ivars);
+                // The link refers to the object itself, encoding included
+                if let Some(slot) = self.objects.get_mut(index).filter(|_| depth > 0) {
+                    *slot = Some(value.clone());
+                }
+                Ok(value)
+            }
+            _ => {
+                let index = self.objects.len();
+                self.objects.push(None);
+                let value = self.object(tag, depth)?;
+                // Nothing can link back to the root, so don't hold a second copy of it
+                if depth > 0 {
+                    self.objects[index] = Some(value.clone());
+                }
+                Ok(value)
+            }
+        }
+    }
+
+    /// Values that `@` links can refer to
+    fn object(&mut self, tag: u8, depth: usize) -> std::io::Result<Value> {
+        match tag {
+            b'"' => Ok(Value::String(self.bytes()?.to_vec())),
+            b'f' => Ok(Value::Float(self.bytes()?.to_vec())),
+            b'[' => {
+                let len = self.len()?;
+                let mut items = Vec::with_capacity(len.min(self.remaining()));
+                for _ in 0..len {
+                    items.push(self.value(depth + 1)?);
+                }
+                Ok(Value::Array(items))
+            }
+            b'{' => {
+                let len = self.len()?;
+                let mut pairs = Vec::with_capacity(len.min(self.remaining()));
+                for _ in 0..len {
+                    pairs.push((self.value(depth + 1)?, self.value(depth + 1)?));
+                }
+                Ok(Value::Hash(pairs))
+            }
+            b'o' => {
+                let class = self.symbol()?;
+                Ok(Value::Object(class, self.ivars(depth)?))
+            }
+            b'U' => {
+                let class = self.symbol()?;
+                Ok(Value::UserMarshal(class, Box::new(self.value(depth + 1)?)))
+            }
+            b'u' => {
+                let class = self.symbol()?;
+                Ok(Value::UserDefined(class, self.bytes()?.to_vec()))
+            }
+            other => Err(invalid(format!(
+                "unsupported Marshal type '{}' at byte {}",
+                other.escape_ascii(),
+                self.pos - 1
+            ))),
+        }
+    }
+
+    fn ivars(&mut self, depth: usize) -> std::io::Result<Vec<(Vec<u8>, Value)>> {
+        let len = self.len()?;
+        let mut ivars = Vec::with_capacity(len.min(self.remaining()));
+        for _ in 0..len {
+            ivars.push((self.symbol()?, self.value(depth + 1)?));
+        }
+        Ok(ivars)
+    }
+
+    fn symbol(&mut self) -> std::io::Result<Vec<u8>> {
+        let tag = self.byte()?;
+        self.symbol_after(tag)
+    }
+
+    fn symbol_after(&mut self, tag: u8) -> std::io::Result<Vec<u8>> {
+        match tag {
+            b':' => {
+                let symbol = self.bytes()?.to_vec();
+                self.symbols.push(symbol.clone());
+                Ok(symbol)
+            }
+            b';' => {
+                let index = self.len()?;
+                self.symbols
+                    .get(index)
+                    .cloned()
+                    .ok_or_else(|| invalid(format!("invalid Marshal symbol link ;{}", index)))
+            }
+            _ => Err(invalid(format!(
+                "expected a Marshal symbol at byte {}",
+                self.pos - 1
+            ))),
+        }
+    }
+
+    fn bytes(&mut self) -> std::io::Result<&[u8]> {
+        let len = self.len()?;
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or_else(truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn len(&mut self) -> std::io::Result<usize> {
+        usize::try_from(self.int()?).map_err(|_| invalid("negative Marshal length".to_string()))
+    }
+
+    /// Marshal's variable-length integer
+    fn int(&mut self) -> std::io::Result<i64> {
+        let first = self.byte()? as i8;
+        Ok(match first {
+            0 => 0,
+            1..=4 => (0..first).try_fold(0i64, |value, shift| {
+                Ok::<_, std::io::Error>(value | i64::from(self.byte()?) << (8 * shift))
+            })?,
+            -4..=-1 => (0..-first).try_fold(-1i64, |value, shift| {
+                let byte = i64::from(self.byte()?);
+                Ok::<_, std::io::Error>(value & !(0xff << (8 * shift)) | byte << (8 * shift))
+            })?,
+            5.. => i64::from(first) - 5,
+            _ => i64::from(first) + 5,
+        })
+    }
+
+    fn byte(&mut self) -> std::io::Result<u8> {
+        let byte = *self.data.get(self.pos).ok_or_else(truncated)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+}
+
+struct Dumper {
+    out: Vec<u8>,
+    symbols: HashMap<Vec<u8>, usize>,
+}
+
+impl Dumper {
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Nil => self.out.push(b'0'),
+            Value::True => self.out.push(b'T'),
+            Value::False => self.out.push(b'F'),
+            Value::Int(n) => {
+                self.out.push(b'i');
+                self.int(*n);
+            }
+            Value::String(bytes) => {
+                self.out.push(b'"');
+                self.bytes(bytes);
+            }
+            Value::Symbol(symbol) => self.symbol(symbol),
+            Value::Float(bytes) => {
+                self.out.push(b'f');
+                self.bytes(bytes);
+            }
+            Value::Array(items) => {
+                self.out.push(b'[');
+                self.int(items.len() as i64);
+                items.iter().for_each(|item| self.value(item));
+            }
+            Value::Hash(pairs) => {
+                self.out.push(b'{');
+                self.int(pairs.len() as i64);
+                for (key, value) in pairs {
+                    self.value(key);
+                    self.value(value);
+                }
+            }
+            Value::Ivar(inner, ivars) => {
+                self.out.push(b'I');
+                self.value(inner);
+                self.ivars(ivars);
+            }
+            Value::Object(class, ivars) => {
+                self.out.push(b'o');
+                self.symbol(class);
+                self.ivars(ivars);
+            }
+            Value::UserMarshal(class, data) => {
+                self.out.push(b'U');
+                self.symbol(class);
+                self.value(data);
+            }
+            Value::UserDefined(class, bytes) => {
+                self.out.push(b'u');
+                self.symbol(class);
+                self.bytes(bytes);
+            }
+        }
+    }
+
+    fn ivars(&mut self, ivars: &[(Vec<u8>, Value)]) {
+        self.int(ivars.len() as i64);
+        for (name, value) in ivars {
+            self.symbol(name);
+            self.value(value);
+        }
+    }
+
+    fn symbol(&mut self, symbol: &[u8]) {
+        if let Some(&index) = self.symbols.get(symbol) {
+            self.out.push(b';');
+            self.int(index as i64);
+        } else {
+            self.symbols.insert(symbol.to_vec(), self.symbols.len());
+            self.out.push(b':');
+            self.bytes(symbol);
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.int(bytes.len() as i64);
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn int(&mut self, n: i64) {
+        match n {
+            0 => self.out.push(0),
+            1..=122 => self.out.push((n + 5) as u8),
+            -123..=-1 => self.out.push((n - 5) as u8),
+            _ => {
+                // Low bytes until only the sign is left, as Ruby's w_long writes them
+                let mut rest = n;
+                let mut bytes = Vec::new();
+                loop {
+                    bytes.push(rest as u8);
+                    rest >>= 8;
+                    if rest == 0 || rest == -1 {
+                        break;
+                    }
+                }
+                let len = bytes.len() as i8;
+                self.out
+                    .push(if rest == 0 { len as u8 } else { (-len) as u8 });
+                self.out.extend_from_slice(&bytes);
+            }
+        }
+    }
+}
+
+fn invalid(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn truncated() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Marshal data ends early")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// `Marshal.dump` of two specs sharing one "ruby" string, as Ruby writes it
+    const SPECS: &[u8] = b"\x04\x08[\x07\
+        [\x08I\"\x0arails\x06:\x06ET\
+        U:\x11Gem::Version[\x06I\"\x0a7.0.0\x06;\x00T\
+        I\"\x09ruby\x06;\x00T\
+        [\x08I\"\x09rack\x06;\x00T\
+        U;\x06[\x06I\"\x0a3.0.0\x06;\x00T\
+        @\x0b";
+
+    #[test]
+    fn test_read_specs_follows_links() {
+        let specs = read_specs(SPECS).unwrap();
+        assert_eq!(
+            specs[1],
+            Spec {
+                name: "rack".to_string(),
+                version: "3.0.0".to_string(),
+                platform: "ruby".to_string(),
+            }
+        );
+
+        // Written without links, the dump loads back to the same value
+        let mut written = Vec::new();
+        write_specs(&specs, &mut written).unwrap();
+        let mut dumped = Vec::new();
+        dump(&load(SPECS).unwrap(), &mut dumped).unwrap();
+        assert_eq!(written, dumped);
+        assert_eq!(load(&written).unwrap(), load(SPECS).unwrap());
+    }
+
+    #[test]
+    fn test_filter_specs_by_name() {
+        let mut input = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        input.write_all(SPECS).unwrap();
+        let input = input.finish().unwrap();
+
+        let allowlist: HashSet<&str> = ["rack"].into_iter().collect();
+        let mut output = Vec::new();
+        let stats = filter_specs(&input[..], &mut output, FilterMode::Allow(&allowlist)).unwrap();
+        assert_eq!((stats.specs, stats.kept), (2, 1));
+
+        let mut marshal = Vec::new();
+        flate2::read::GzDecoder::new(&output[..])
+            .read_to_end(&mut marshal)
+            .unwrap();
+        let specs = read_specs(&marshal).unwrap();
+        assert_eq!(specs.len(), 1);
+        assert_eq!(specs[0].name, "rack");
+    }
+
+    #[test]
+    fn test_int_round_trip() {
+        for n in [
+            0,
+            1,
+            122,
+            123,
+            -123,
+            -124,
+            255,
+            256,
+            -256,
+            -257,
+            65_536,
+            i64::from(i32::MIN),
+        ] {
+            let mut dumped = Vec::new();
+            dump(&Value::Int(n), &mut dumped).unwrap();
+            assert_eq!(load(&dumped).unwrap(), Value::Int(n), "{}", n);
+        }
+        assert!(load(b"\x04\x08[\x7f").is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! Counters from a filter run
+//!
+//! [`FilterStats`] always reports the buffer high-water marks the streaming
+//! filter itself controls, which is what backs the "only about one line in
+//! memory" claim: a read buffer of fixed size, an output batch, and the
+//! longest line that had to be carried over between reads. With the
+//! `instrument` feature and [`CountingAllocator`] installed as the global
+//! allocator, it also reports heap allocations and the peak heap growth
+//! during the run, including anything the caller's reader and writer do.
+
+use crate::slice::SkippedLines;
+
+/// What a filter run read, wrote and held in memory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterStats {
+    /// Hex digest of the output, when one was requested
+    pub digest: Option<String>,
+    /// Bytes read from the input
+    pub input_bytes: u64,
+    /// Bytes written to the output
+    pub output_bytes: u64,
+    /// Capacity of the read buffer
+    pub read_buffer_bytes: usize,
+    /// Largest output batch held before a write
+    pub peak_batch_bytes: usize,
+    /// Longest partial line carried over between reads
+    pub peak_carry_bytes: usize,
+    /// Heap activity, when [`CountingAllocator`] is the global allocator
+    pub memory: Option<MemoryStats>,
+    /// Lines dropped instead of failing the run, under
+    /// [`LineErrors::Collect`](crate::LineErrors::Collect)
+    pub skipped: SkippedLines,
+}
+
+/// Heap activity during a run, as seen by [`CountingAllocator`]
+///
+/// The counters are process-wide, so allocations made by other threads while
+/// the filter runs are included. Measure on an otherwise idle thread.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Allocations (including reallocations) made during the run
+    pub allocations: u64,
+    /// Highest number of live heap bytes above what was live when the run started
+    pub peak_heap_bytes: usize,
+}
+
+#[cfg(feature = "instrument")]
+pub use instrument::CountingAllocator;
+
+#[cfg(feature = "instrument")]
+mod instrument {
+    use super::MemoryStats;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
+    static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+    static LIVE: AtomicUsize = AtomicUsize::new(0);
+    static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+    /// System allocator that counts allocations and tracks peak live bytes
+    ///
+    /// Install it in the binary to have filter runs fill in
+    /// [`FilterStats::memory`](super::FilterStats::memory):
+    ///
+    /// ```ignore
+    /// #[global_allocator]
+    /// static ALLOC: gem_index_filter::stats::CountingAllocator = gem_index_filter::stats::CountingAllocator;
+    /// ```
+    pub struct CountingAllocator;
+
+    impl CountingAllocator {
+        fn record(size: usize) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            let live = LIVE.fetch_add(size, Ordering::Relaxed) + size;
+            PEAK.fetch_max(live, Ordering::Relaxed);
+        }
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            INSTALLED.store(true, Ordering::Relaxed);
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() {
+                Self::record(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = System.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() {
+                LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+                Self::record(new_size);
+            }
+            new_ptr
+        }
+    }
+
+    /// Counter values at the start of a run
+    pub(crate) struct Baseline {
+        allocations: u64,
+        live: usize,
+    }
+
+    /// Start measuring, or `None` if the counting allocator isn't installed
+    pub(crate) fn start() -> Option<Baseline> {
+        if !INSTALLED.load(Ordering::Relaxed) {
+            return None;
+        }
+        let live = LIVE.load(Ordering::Relaxed);
+        PEAK.store(live, Ordering::Relaxed);
+        Some(Baseline {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            live,
+        })
+    }
+
+    pub(crate) fn finish(baseline: Baseline) -> MemoryStats {
+        MemoryStats {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed) - baseline.allocations,
+            peak_heap_bytes: PEAK.load(Ordering::Relaxed).saturating_sub(baseline.live),
+        }
+    }
+}
+
+/// Heap measurement around a run; a no-op without the `instrument` feature
+pub(crate) struct MemoryProbe {
+    #[cfg(feature = "instrument")]
+    baseline: Option<instrument::Baseline>,
+}
+
+impl MemoryProbe {
+    pub(crate) fn start() -> Self {
+        MemoryProbe {
+            #[cfg(feature = "instrument")]
+            baseline: instrument::start(),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Option<MemoryStats> {
+        #[cfg(feature = "instrument")]
+        return self.baseline.map(instrument::finish);
+        #[cfg(not(feature = "instrument"))]
+        None
+    }
+}
@@ -0,0 +1,252 @@
+//! Filtered output as an async stream of chunks (`stream` feature)
+//!
+//! Async servers want the filter as a response body or an upload source, not
+//! a function that blocks on a `Read`. [`filter_stream`] reads from any
+//! [`AsyncBufRead`] and yields the filtered output as [`Bytes`] chunks, one
+//! per input buffer that completed a kept line, so it plugs into axum's
+//! `Body::from_stream` or an S3 multipart upload without channels or temp
+//! files. Lines are filtered by [`ChunkFilter`], so the output is byte for
+//! byte what [`filter_versions_streaming`](crate::filter_versions_streaming)
+//! writes.
+//!
+//! The trait is `futures-io`'s; Tokio readers convert with
+//! `tokio_util::compat::TokioAsyncReadCompatExt::compat`.
+//!
+//! Filters that need a `Read` and `Write`, such as a
+//! [`FilterPipeline`](crate::FilterPipeline) or
+//! [`filter_with_rules`](crate::filter_with_rules), run on a thread of their
+//! own with [`filter_on_thread`]. The download
+//! stream feeds the thread, and the thread feeds the returned stream,
+//! through channels of bounded capacity. A slow consumer stalls the filter,
+//! and a stalled filter stops the download from being read, so nothing
+//! buffers without limit.
+
+use crate::chunked::ChunkFilter;
+use crate::filter::OUTPUT_BATCH;
+use crate::{FilterMode, VersionOutput};
+use bytes::Bytes;
+use futures_channel::mpsc;
+use futures_util::io::{AsyncBufRead, AsyncBufReadExt};
+use futures_util::{FutureExt, SinkExt, Stream, StreamExt};
+use std::io::{Read, Write};
+
+/// Stream `input` through the filter, yielding non-empty chunks of output
+///
+/// A read error or an invalid file ends the stream with that error.
+pub fn filter_stream<'a, R>(
+    input: R,
+    mode: FilterMode<'a>,
+    version_output: VersionOutput,
+) -> impl Stream<Item = std::io::Result<Bytes>> + 'a
+where
+    R: AsyncBufRead + Unpin + 'a,
+{
+    let state = Some((input, ChunkFilter::new(mode, version_output)));
+    futures_util::stream::try_unfold(state, |state| async move {
+        let Some((mut input, mut filter)) = state else {
+            return Ok(None);
+        };
+        let mut output = Vec::new();
+        loop {
+            let chunk = input.fill_buf().await?;
+            if chunk.is_empty() {
+                filter.finish(&mut output)?;
+                return Ok((!output.is_empty()).then(|| (Bytes::from(output), None)));
+            }
+            let consumed = chunk.len();
+            filter.push(chunk, &mut output)?;
+            input.consume_unpin(consumed);
+            // Chunks that only extend an unfinished line produce nothing to yield
+            if !output.is_empty() {
+                return Ok(Some((Bytes::from(output), Some((input, filter)))));
+            }
+        }
+    })
+}
+
+/// Run a blocking `filter` on its own thread between two bounded channels
+///
+/// `input` chunks go to `filter` as a `Read`, and what it writes comes back
+/// as chunks of up to 32 KiB. Each direction holds at most `capacity` chunks
+/// in flight. The returned stream drives `input` as it is polled. It ends
+/// once `filter` returns, with the filter's error as its last item if it
+/// failed. Dropping the stream makes the filter's next write fail, which
+/// stops the thread.
+pub fn filter_on_thread<S, F>(
+    input: S,
+    capacity: usize,
+    filter: F,
+) -> std::io::Result<impl Stream<Item = std::io::Result<Bytes>>>
+where
+    S: Stream<Item = std::io::Result<Bytes>>,
+    F: FnOnce(&mut dyn Read, &mut dyn Write) -> std::io::Result<()> + Send + 'static,
+{
+    // mpsc::channel adds one slot per sender; a capacity of 1 is one chunk
+    let slots = capacity.max(1) - 1;
+    let (input_tx, input_rx) = mpsc::channel(slots);
+    let (output_tx, output_rx) = mpsc::channel(slots);
+
+    std::thread::Builder::new()
+        .name("gem-index-filter".to_string())
+        .spawn(move || {
+            let mut reader = ChannelReader {
+                chunks: input_rx,
+                current: Bytes::new(),
+            };
+            let mut writer = ChannelWriter {
+                chunks: output_tx,
+                batch: Vec::new(),
+            };
+            let result = filter(&mut reader, &mut writer).and_then(|()| writer.flush());
+            if let Err(e) = result {
+                // The consumer may be gone already, and then nobody is left to tell
+                let _ = futures_executor::block_on(writer.chunks.send(Err(e)));
+            }
+        })?;
+
+    // Forwarding ends when the input does or the filter stops reading
+    let forward = input
+        .map(Ok)
+        .forward(input_tx)
+        .into_stream()
+        .filter_map(|_| std::future::ready(None));
+    Ok(futures_util::stream::select(forward, output_rx))
+}
+
+/// The filter thread's view of the input channel
+struct ChannelReader {
+    chunks: mpsc::Receiver<std::io::Result<Bytes>>,
+    current: Bytes,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.current.is_empty() {
+            match futures_executor::block_on(self.chunks.next()) {
+                Some(chunk) => self.current = chunk?,
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.current.len());
+        buf[..n].copy_from_slice(&self.current.split_to(n));
+        Ok(n)
+    }
+}
+
+/// The filter thread's view of the output channel
+struct ChannelWriter {
+    chunks: mpsc::Sender<std::io::Result<Bytes>>,
+    batch: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.batch.extend_from_slice(buf);
+        if self.batch.len() >= OUTPUT_BATCH {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::take(&mut self.batch));
+        futures_executor::block_on(self.chunks.send(Ok(chunk))).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "Output stream was dropped before the filter finished",
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter_versions_streaming;
+    use futures_util::TryStreamExt;
+    use std::collections::HashSet;
+
+    const VERSIONS: &str = "created_at: 2024-04-01T00:00:05Z\n---\n\
+        rails 7.0.0,7.0.1 abc123\n\
+        sinatra 3.0.0 def456\n\
+        rails 7.0.2 ghi789";
+
+    #[test]
+    fn test_stream_matches_streaming_filter() {
+        let gems: HashSet<&str> = ["rails"].into_iter().collect();
+        let mut expected = Vec::new();
+        filter_versions_streaming(
+            VERSIONS.as_bytes(),
+            &mut expected,
+            FilterMode::Allow(&gems),
+            VersionOutput::Preserve,
+            None,
+        )
+        .unwrap();
+
+        // A tiny buffer splits lines across reads
+        let input = futures_util::io::BufReader::with_capacity(5, VERSIONS.as_bytes());
+        let chunks: Vec<Bytes> =
+            filter_stream(input, FilterMode::Allow(&gems), VersionOutput::Preserve)
+                .try_collect()
+                .now_or_never()
+                .expect("an in-memory reader never waits")
+                .unwrap();
+        assert!(chunks.len() > 1 && chunks.iter().all(|chunk| !chunk.is_empty()));
+        assert_eq!(chunks.concat(), expected);
+
+        let err = filter_stream(
+            "rails 7.0.0 abc123\n".as_bytes(),
+            FilterMode::Passthrough,
+            VersionOutput::Preserve,
+        )
+        .try_collect::<Vec<Bytes>>()
+        .now_or_never()
+        .unwrap();
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_filter_on_thread_round_trips_through_channels() {
+        let run = |chunks: Vec<std::io::Result<Bytes>>| {
+            let output = filter_on_thread(
+                futures_util::stream::iter(chunks),
+                1,
+                |input, mut output| {
+                    let gems: HashSet<&str> = ["rails"].into_iter().collect();
+                    filter_versions_streaming(
+                        input,
+                        &mut output,
+                        FilterMode::Allow(&gems),
+                        VersionOutput::Preserve,
+                        None,
+                    )
+                    .map(drop)
+                },
+            )
+            .unwrap();
+            futures_executor::block_on(output.try_collect::<Vec<Bytes>>())
+        };
+
+        let chunks = VERSIONS
+            .as_bytes()
+            .chunks(7)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        assert_eq!(
+            run(chunks).unwrap().concat(),
+            b"created_at: 2024-04-01T00:00:05Z\n---\n\
+              rails 7.0.0,7.0.1 abc123\n\
+              rails 7.0.2 ghi789"
+        );
+
+        let failed = vec![
+            Ok(Bytes::from_static(b"created_at: 2024-04-01T00:00:05Z\n")),
+            Err(std::io::Error::other("connection reset")),
+        ];
+        assert_eq!(run(failed).unwrap_err().to_string(), "connection reset");
+    }
+}
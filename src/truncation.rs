@@ -0,0 +1,212 @@
+//! Detecting truncated downloads
+//!
+//! A versions file cut short by a dropped connection still filters cleanly:
+//! every complete line is valid, and the output is a plausible but stale
+//! index. [`CompleteReader`] wraps the input and turns such an end of input
+//! into a [`Truncated`] error, so a server or mirror fails the run instead of
+//! caching a partial index.
+
+use memchr::memrchr;
+use std::fmt;
+use std::io::Read;
+
+/// Why the input looks cut short
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Truncated {
+    /// Fewer bytes arrived than the expected length (e.g. `Content-Length`)
+    ShortRead {
+        /// Bytes the caller expected
+        expected: u64,
+        /// Bytes actually read
+        actual: u64,
+    },
+    /// The input does not end with a newline
+    MissingNewline,
+    /// The last line is neither the `---` separator nor a complete gem line
+    IncompleteLastLine,
+}
+
+impl Truncated {
+    /// The truncation behind an I/O error returned by a filter run, if any
+    pub fn from_io_error(error: &std::io::Error) -> Option<&Truncated> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for Truncated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Truncated::ShortRead { expected, actual } => write!(
+                f,
+                "Input truncated: expected {} bytes, got {}",
+                expected, actual
+            ),
+            Truncated::MissingNewline => f.write_str("Input truncated: no trailing newline"),
+            Truncated::IncompleteLastLine => {
+                f.write_str("Input truncated: last line is not a complete gem line")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Truncated {}
+
+impl From<Truncated> for std::io::Error {
+    fn from(truncated: Truncated) -> Self {
+        std::io::Error::new(std::io::ErrorKind::UnexpectedEof, truncated)
+    }
+}
+
+/// Reader wrapper that fails at end of input if the input looks truncated
+///
+/// Only the last line is kept for the check, so memory stays bounded by the
+/// longest line, as in the filter itself.
+pub struct CompleteReader<R: Read> {
+    inner: R,
+    expected_len: Option<u64>,
+    read: u64,
+    line: Vec<u8>,
+    last_line: Vec<u8>,
+}
+
+impl<R: Read> CompleteReader<R> {
+    /// Wrap `inner`, checking the newline and last line at end of input
+    pub fn new(inner: R) -> Self {
+        CompleteReader {
+            inner,
+            expected_len: None,
+            read: 0,
+            line: Vec::new(),
+            last_line: Vec::new(),
+        }
+    }
+
+    /// Also require at least `len` bytes, typically the response's `Content-Length`
+    pub fn expect_len(mut self, len: u64) -> Self {
+        self.expected_len = Some(len);
+        self
+    }
+
+    /// Bytes read so far
+    pub fn bytes_read(&self) -> u64 {
+        self.read
+    }
+
+    fn track(&mut self, data: &[u8]) {
+        let Some(end) = memrchr(b'\n', data) else {
+            self.line.extend_from_slice(data);
+            return;
+        };
+        match memrchr(b'\n', &data[..end]) {
+            Some(start) => {
+                self.last_line.clear();
+                self.last_line.extend_from_slice(&data[start + 1..end]);
+            }
+            None => {
+                self.line.extend_from_slice(&data[..end]);
+                std::mem::swap(&mut self.line, &mut self.last_line);
+            }
+        }
+        self.line.clear();
+        self.line.extend_from_slice(&data[end + 1..]);
+    }
+
+    fn check(&self) -> Result<(), Truncated> {
+        if let Some(expected) = self.expected_len {
+            if self.read < expected {
+                return Err(Truncated::ShortRead {
+                    expected,
+                    actual: self.read,
+                });
+            }
+        }
+        if !self.line.is_empty() {
+            return Err(Truncated::MissingNewline);
+        }
+        if !is_complete_line(&self.last_line) {
+            return Err(Truncated::IncompleteLastLine);
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for CompleteReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.check()?;
+        }
+        self.track(&buf[..n]);
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+/// `---` (an index with no gems yet) or a `name versions md5` gem line
+fn is_complete_line(line: &[u8]) -> bool {
+    let line = line.trim_ascii();
+    line == b"---" || line.split(|&b| b == b' ').filter(|f| !f.is_empty()).count() == 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filter_versions_streaming, FilterMode, VersionOutput};
+
+    const VERSIONS: &str = "created_at: 2024-04-01T00:00:05Z\n---\n\
+        rails 7.0.0,7.0.1 0123456789abcdef0123456789abcdef\n\
+        sinatra 3.0.0 fedcba9876543210fedcba9876543210\n";
+
+    fn filter(reader: CompleteReader<&[u8]>) -> Result<Vec<u8>, Option<Truncated>> {
+        let mut output = Vec::new();
+        filter_versions_streaming(
+            reader,
+            &mut output,
+            FilterMode::Passthrough,
+            VersionOutput::Preserve,
+            None,
+        )
+        .map(|_| output)
+        .map_err(|e| Truncated::from_io_error(&e).cloned())
+    }
+
+    #[test]
+    fn test_complete_input_passes() {
+        let input = VERSIONS.as_bytes();
+        assert_eq!(
+            filter(CompleteReader::new(input).expect_len(input.len() as u64)),
+            Ok(input.to_vec())
+        );
+        assert!(filter(CompleteReader::new(b"created_at: x\n---\n")).is_ok());
+    }
+
+    #[test]
+    fn test_truncations_are_detected() {
+        let input = VERSIONS.as_bytes();
+
+        // Cut mid-line: no trailing newline
+        let cut = &input[..input.len() - 10];
+        assert_eq!(
+            filter(CompleteReader::new(cut)),
+            Err(Some(Truncated::MissingNewline))
+        );
+
+        // Cut at a line boundary, but short of the advertised length
+        let cut = &input[..input.len() - 47];
+        assert!(cut.ends_with(b"abcdef\n"));
+        assert_eq!(
+            filter(CompleteReader::new(cut).expect_len(input.len() as u64)),
+            Err(Some(Truncated::ShortRead {
+                expected: input.len() as u64,
+                actual: cut.len() as u64,
+            }))
+        );
+
+        // A line that ends early but still has its newline
+        let cut = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0\n";
+        assert_eq!(
+            filter(CompleteReader::new(cut.as_bytes())),
+            Err(Some(Truncated::IncompleteLastLine))
+        );
+    }
+}
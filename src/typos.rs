@@ -0,0 +1,130 @@
+//! Spotting list entries that almost name a real gem
+//!
+//! A list entry naming no gem is silently dead, and the usual cause is a
+//! typo: `rest_client` for `rest-client`, `Nokogiri` for `nokogiri`, one
+//! letter dropped or swapped. [`near_misses`] checks the entries missing from
+//! a set of real names, typically those of the index being filtered, and
+//! suggests the names each one nearly matches. Names are compared with case
+//! ignored and `_` taken as `-`, then within one edit: a letter inserted,
+//! removed, replaced or swapped with its neighbour.
+
+use std::collections::{BTreeSet, HashMap};
+
+/// Entries shorter than this are only matched up to case and separators,
+/// since one edit away from a short name is most other short names
+const MIN_EDIT_LEN: usize = 4;
+
+/// A list entry that names no gem but nearly names some
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NearMiss {
+    /// The entry as listed
+    pub entry: String,
+    /// Real names it nearly matches, closest first, then sorted
+    pub suggestions: Vec<String>,
+}
+
+/// Entries absent from `names` that differ from a name in it by case,
+/// separators or one edit, in entry order
+pub fn near_misses<'a, I>(entries: I, names: &BTreeSet<String>) -> Vec<NearMiss>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut by_key: HashMap<String, Vec<&str>> = HashMap::new();
+    for name in names {
+        by_key.entry(normalize(name)).or_default().push(name);
+    }
+
+    let mut misses = Vec::new();
+    for entry in entries {
+        if names.contains(entry) {
+            continue;
+        }
+        let key = normalize(entry);
+        let mut suggestions: Vec<String> = by_key
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .map(|name| name.to_string())
+            .collect();
+        if key.len() >= MIN_EDIT_LEN {
+            let mut close: Vec<&str> = by_key
+                .iter()
+                .filter(|(other, _)| one_edit_apart(&key, other))
+                .flat_map(|(_, names)| names.iter().copied())
+                .collect();
+            close.sort_unstable();
+            suggestions.extend(close.into_iter().map(String::from));
+        }
+        if !suggestions.is_empty() {
+            misses.push(NearMiss {
+                entry: entry.to_string(),
+                suggestions,
+            });
+        }
+    }
+    misses
+}
+
+/// Lowercase with `_` as `-`, the spellings people confuse
+fn normalize(name: &str) -> String {
+    name.to_ascii_lowercase().replace('_', "-")
+}
+
+/// Whether `b` is `a` with one byte inserted, removed, replaced, or two
+/// neighbouring bytes swapped
+fn one_edit_apart(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a == b || a.len().abs_diff(b.len()) > 1 {
+        return false;
+    }
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    match a.len().cmp(&b.len()) {
+        std::cmp::Ordering::Less => a == &b[1..],
+        std::cmp::Ordering::Greater => &a[1..] == b,
+        std::cmp::Ordering::Equal => {
+            a[1..] == b[1..] || (a.len() >= 2 && a[0] == b[1] && a[1] == b[0] && a[2..] == b[2..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_near_misses_suggest_real_names() {
+        let names: BTreeSet<String> = ["rest-client", "nokogiri", "rails", "rack", "racc"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let entries = [
+            "rest_client",
+            "Nokogiri",
+            "rials",
+            "nokogir",
+            "rack",
+            "rakc",
+            "zzz",
+        ];
+
+        let misses = near_misses(entries, &names);
+        let found: Vec<(&str, Vec<&str>)> = misses
+            .iter()
+            .map(|miss| {
+                let suggestions = miss.suggestions.iter().map(String::as_str).collect();
+                (miss.entry.as_str(), suggestions)
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("rest_client", vec!["rest-client"]),
+                ("Nokogiri", vec!["nokogiri"]),
+                ("rials", vec!["rails"]),
+                ("nokogir", vec!["nokogiri"]),
+                ("rakc", vec!["racc", "rack"]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,441 @@
+//! Append-only updates of a filtered versions file
+//!
+//! Compact-index clients fetch updates with `Range: bytes=<size>-`, so a
+//! mirror must only ever grow its files by appending. Given the previously
+//! published filtered file and a newer upstream file, [`append_new_lines`]
+//! re-runs the filter and checks that the existing file is a byte prefix of
+//! the new output, emitting just the bytes that follow it. When the prefix
+//! does not match (upstream compaction, a policy change) nothing is written
+//! and the caller must publish a full re-filter instead.
+//!
+//! Allowlist edits are handled the same way. When an edit only adds gems,
+//! [`append_allowed_gems`] reads the upstream copy the file was filtered from
+//! and emits just the added gems' lines, so a policy change costs one pass
+//! over the cached copy instead of a new download and a full re-filter.
+//! Removals can't be expressed as an append and are reported instead.
+
+use crate::filter::{filter_versions_streaming, write_gem_line_stripped};
+use crate::parser::{Entry, VersionsReader};
+use crate::slice::gem_name;
+use crate::{FilterMode, VersionOutput};
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// Result of an append-only update attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateOutcome {
+    /// The existing file is a prefix of the new output; the remaining bytes were written
+    Appended {
+        /// Number of bytes written to the output (zero when nothing changed)
+        bytes: u64,
+    },
+    /// The existing file is not a prefix of the new output and nothing was written
+    Diverged {
+        /// Byte offset in the existing file where the outputs first differ
+        offset: u64,
+    },
+}
+
+/// Result of updating a filtered file for an edited allowlist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowlistUpdate {
+    /// Only gems were added; their lines were written
+    Appended {
+        /// Gems added to the allowlist, sorted
+        gems: Vec<String>,
+        /// Gem lines written
+        lines: usize,
+        /// Bytes written
+        bytes: u64,
+    },
+    /// Gems were removed from the allowlist and nothing was written
+    Removed {
+        /// Gems no longer allowed, sorted
+        gems: Vec<String>,
+    },
+}
+
+/// Write the lines of gems `new` allows and `old` did not, in upstream order
+///
+/// `upstream` must be the copy the existing file was filtered from with
+/// `old`, and `version_output` the setting it used. Each line written ends
+/// with `\n`, so the existing file must too. The appended file offers the
+/// same versions and checksums as a full re-filter with `new`, but the added
+/// gems' lines sit at the end rather than in upstream order, so the next
+/// [`append_new_lines`] against it diverges and the file should be rebuilt
+/// with a full re-filter then.
+pub fn append_allowed_gems<R: Read, W: Write>(
+    upstream: R,
+    output: &mut W,
+    old: &HashSet<&str>,
+    new: &HashSet<&str>,
+    version_output: VersionOutput,
+) -> std::io::Result<AllowlistUpdate> {
+    let sorted = |gems: std::collections::hash_set::Difference<'_, &str, _>| {
+        let mut gems: Vec<String> = gems.map(|gem| gem.to_string()).collect();
+        gems.sort_unstable();
+        gems
+    };
+    let removed = sorted(old.difference(new));
+    if !removed.is_empty() {
+        return Ok(AllowlistUpdate::Removed { gems: removed });
+    }
+    let added: HashSet<&str> = new.difference(old).copied().collect();
+
+    let mut reader = VersionsReader::new(upstream);
+    let (mut lines, mut bytes) = (0, 0);
+    let mut line = Vec::new();
+    while let Some(entry) = reader.next_entry()? {
+        line.clear();
+        let (text, raw) = match entry {
+            Entry::Gem(gem) if added.contains(gem.name) => (gem.line, gem.raw),
+            // Lines short of a hash are kept as the filter keeps them
+            Entry::Malformed { line, raw }
+                if gem_name(line).is_some_and(|name| added.contains(name)) =>
+            {
+                (line, raw)
+            }
+            _ => continue,
+        };
+        match version_output {
+            // The bytes a full re-filter writes, line ending and all
+            VersionOutput::Preserve => {
+                line.extend_from_slice(raw.as_bytes());
+                if !raw.ends_with('\n') {
+                    line.push(b'\n');
+                }
+            }
+            VersionOutput::Strip => write_gem_line_stripped(text, &mut line)?,
+        }
+        output.write_all(&line)?;
+        lines += 1;
+        bytes += line.len() as u64;
+    }
+
+    Ok(AllowlistUpdate::Appended {
+        gems: sorted(new.difference(old)),
+        lines,
+        bytes,
+    })
+}
+
+/// Filter `upstream` and write only the output that follows `existing`
+///
+/// `existing` must be the output of a previous run with the same `mode` and
+/// `version_output`. On [`UpdateOutcome::Appended`], appending the written
+/// bytes to `existing` yields exactly the output of a full filter run.
+pub fn append_new_lines<R1: Read, R2: Read, W: Write>(
+    existing: R1,
+    upstream: R2,
+    output: &mut W,
+    mode: FilterMode,
+    version_output: VersionOutput,
+) -> std::io::Result<UpdateOutcome> {
+    let mut writer = AppendWriter {
+        existing: BufReader::new(existing),
+        existing_done: false,
+        output,
+        matched: 0,
+        appended: 0,
+        diverged: false,
+    };
+
+    match filter_versions_streaming(upstream, &mut writer, mode, version_output, None) {
+        Ok(_) => {}
+        Err(_) if writer.diverged => {
+            return Ok(UpdateOutcome::Diverged {
+                offset: writer.matched,
+            })
+        }
+        Err(e) => return Err(e),
+    }
+
+    // Existing data left over means the new output is shorter, which is not an append
+    if !writer.existing_done && !writer.existing.fill_buf()?.is_empty() {
+        return Ok(UpdateOutcome::Diverged {
+            offset: writer.matched,
+        });
+    }
+
+    Ok(UpdateOutcome::Appended {
+        bytes: writer.appended,
+    })
+}
+
+/// Writer that matches incoming bytes against the existing file and forwards
+/// only what comes after it
+struct AppendWriter<'a, R: Read, W: Write> {
+    existing: BufReader<R>,
+    existing_done: bool,
+    output: &'a mut W,
+    matched: u64,
+    appended: u64,
+    diverged: bool,
+}
+
+impl<'a, R: Read, W: Write> Write for AppendWriter<'a, R, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // The filter's output buffer retries its pending bytes when dropped
+        if self.diverged {
+            return Err(diverged_error());
+        }
+        let mut remaining = buf;
+
+        while !self.existing_done && !remaining.is_empty() {
+            let available = self.existing.fill_buf()?;
+            if available.is_empty() {
+                self.existing_done = true;
+                break;
+            }
+
+            let n = available.len().min(remaining.len());
+            if available[..n] != remaining[..n] {
+                // Report the exact offset of the first differing byte
+                let same = available[..n]
+                    .iter()
+                    .zip(&remaining[..n])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                self.matched += same as u64;
+                self.diverged = true;
+                return Err(diverged_error());
+            }
+
+            self.existing.consume(n);
+            self.matched += n as u64;
+            remaining = &remaining[n..];
+        }
+
+        if !remaining.is_empty() {
+            self.output.write_all(remaining)?;
+            self.appended += remaining.len() as u64;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+fn diverged_error() -> std::io::Error {
+    std::io::Error::other("Existing file is not a prefix of the filtered output")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const UPSTREAM: &str = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0 abc123
+activerecord 7.0.0 def456
+sinatra 3.0.0 ghi789
+"#;
+
+    fn filtered(input: &str, allowlist: &HashSet<&str>) -> Vec<u8> {
+        let mut output = Vec::new();
+        filter_versions_streaming(
+            input.as_bytes(),
+            &mut output,
+            FilterMode::Allow(allowlist),
+            VersionOutput::Preserve,
+            None,
+        )
+        .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_appends_only_new_matching_lines() {
+        let allowlist: HashSet<&str> = ["rails", "sinatra"].into_iter().collect();
+        let existing = filtered(UPSTREAM, &allowlist);
+        let newer = format!(
+            "{}activerecord 7.0.1 jkl000\nrails 7.0.1 mno111\n",
+            UPSTREAM
+        );
+
+        let mut appended = Vec::new();
+        let outcome = append_new_lines(
+            existing.as_slice(),
+            newer.as_bytes(),
+            &mut appended,
+            FilterMode::Allow(&allowlist),
+            VersionOutput::Preserve,
+        )
+        .unwrap();
+
+        assert_eq!(appended, b"rails 7.0.1 mno111\n");
+        assert_eq!(outcome, UpdateOutcome::Appended { bytes: 19 });
+
+        // Existing + appended must equal a full re-filter
+        let mut combined = existing.clone();
+        combined.extend_from_slice(&appended);
+        assert_eq!(combined, filtered(&newer, &allowlist));
+    }
+
+    #[test]
+    fn test_no_change_appends_nothing() {
+        let allowlist: HashSet<&str> = ["rails"].into_iter().collect();
+        let existing = filtered(UPSTREAM, &allowlist);
+
+        let mut appended = Vec::new();
+        let outcome = append_new_lines(
+            existing.as_slice(),
+            UPSTREAM.as_bytes(),
+            &mut appended,
+            FilterMode::Allow(&allowlist),
+            VersionOutput::Preserve,
+        )
+        .unwrap();
+
+        assert_eq!(outcome, UpdateOutcome::Appended { bytes: 0 });
+        assert!(appended.is_empty());
+    }
+
+    #[test]
+    fn test_policy_change_diverges() {
+        let old_allowlist: HashSet<&str> = ["rails", "activerecord"].into_iter().collect();
+        let existing = filtered(UPSTREAM, &old_allowlist);
+        let new_allowlist: HashSet<&str> = ["rails", "sinatra"].into_iter().collect();
+
+        let mut appended = Vec::new();
+        let outcome = append_new_lines(
+            existing.as_slice(),
+            UPSTREAM.as_bytes(),
+            &mut appended,
+            FilterMode::Allow(&new_allowlist),
+            VersionOutput::Preserve,
+        )
+        .unwrap();
+
+        // Divergence is at the start of the activerecord/sinatra line
+        let offset = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n".len() as u64;
+        assert_eq!(outcome, UpdateOutcome::Diverged { offset });
+        assert!(appended.is_empty());
+    }
+
+    #[test]
+    fn test_compacted_upstream_diverges() {
+        let existing = filtered(UPSTREAM, &["rails", "sinatra"].into_iter().collect());
+        let compacted = "created_at: 2024-05-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+
+        let mut appended = Vec::new();
+        let outcome = append_new_lines(
+            existing.as_slice(),
+            compacted.as_bytes(),
+            &mut appended,
+            FilterMode::Passthrough,
+            VersionOutput::Preserve,
+        )
+        .unwrap();
+
+        assert!(matches!(outcome, UpdateOutcome::Diverged { .. }));
+        assert!(appended.is_empty());
+    }
+
+    #[test]
+    fn test_shorter_output_diverges() {
+        let existing = filtered(UPSTREAM, &["rails", "sinatra"].into_iter().collect());
+        let shorter = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+
+        let outcome = append_new_lines(
+            existing.as_slice(),
+            shorter.as_bytes(),
+            &mut Vec::new(),
+            FilterMode::Passthrough,
+            VersionOutput::Preserve,
+        )
+        .unwrap();
+
+        assert_eq!(
+            outcome,
+            UpdateOutcome::Diverged {
+                offset: shorter.len() as u64
+            }
+        );
+    }
+
+    #[test]
+    fn test_allowlist_additions_append_their_lines() {
+        let old: HashSet<&str> = ["rails"].into_iter().collect();
+        let new: HashSet<&str> = ["rails", "sinatra", "activerecord"].into_iter().collect();
+        let existing = filtered(UPSTREAM, &old);
+
+        let mut appended = Vec::new();
+        let outcome = append_allowed_gems(
+            UPSTREAM.as_bytes(),
+            &mut appended,
+            &old,
+            &new,
+            VersionOutput::Preserve,
+        )
+        .unwrap();
+        assert_eq!(
+            appended,
+            b"activerecord 7.0.0 def456\nsinatra 3.0.0 ghi789\n"
+        );
+        assert_eq!(
+            outcome,
+            AllowlistUpdate::Appended {
+                gems: vec!["activerecord".to_string(), "sinatra".to_string()],
+                lines: 2,
+                bytes: 47
+            }
+        );
+
+        // Same lines as a full re-filter, only in a different order
+        let mut combined = existing.clone();
+        combined.extend_from_slice(&appended);
+        let mut combined: Vec<&[u8]> = combined.split(|&b| b == b'\n').collect();
+        let full = filtered(UPSTREAM, &new);
+        let mut full: Vec<&[u8]> = full.split(|&b| b == b'\n').collect();
+        combined.sort_unstable();
+        full.sort_unstable();
+        assert_eq!(combined, full);
+
+        let outcome = append_allowed_gems(
+            UPSTREAM.as_bytes(),
+            &mut Vec::new(),
+            &new,
+            &old,
+            VersionOutput::Preserve,
+        )
+        .unwrap();
+        assert_eq!(
+            outcome,
+            AllowlistUpdate::Removed {
+                gems: vec!["activerecord".to_string(), "sinatra".to_string()]
+            }
+        );
+
+        // Fields after the hash survive, as they do in a full filter
+        let mut appended = Vec::new();
+        append_allowed_gems(
+            "---\nsinatra 3.0.0,3.0.1 ghi789 extra\n".as_bytes(),
+            &mut appended,
+            &old,
+            &new,
+            VersionOutput::Strip,
+        )
+        .unwrap();
+        assert_eq!(appended, b"sinatra 0 ghi789 extra\n");
+
+        // Preserved lines keep their bytes, as a full re-filter writes them
+        let upstream = "---\r\nrails 7.0.0 abc123\r\nsinatra 3.0.0 ghi789\r\n";
+        let mut appended = Vec::new();
+        append_allowed_gems(
+            upstream.as_bytes(),
+            &mut appended,
+            &old,
+            &new,
+            VersionOutput::Preserve,
+        )
+        .unwrap();
+        let full = filtered(upstream, &new);
+        assert_eq!(appended, b"sinatra 3.0.0 ghi789\r\n");
+        assert!(full.ends_with(&appended));
+    }
+}
@@ -0,0 +1,451 @@
+//! Compact-index consistency checks for a mirror directory
+//!
+//! Bundler trusts a compact-index source to uphold a few invariants that a
+//! filtering mirror can easily break:
+//!
+//! - `versions` has a metadata section ending in `---` and well-formed gem lines
+//! - `names` lists exactly the gems present in `versions`, sorted
+//! - every gem in `versions` has an `info/<gem>` file whose MD5 matches the
+//!   checksum on the gem's last `versions` line
+//! - `versions` only ever grows by appending, so `Range` requests stay valid
+//!
+//! [`verify_mirror`] checks a directory laid out like the output of the
+//! `mirror` subcommand and reports every violation with its path and line.
+
+use crate::slice::gem_name;
+use md5::{Digest, Md5};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// A single broken invariant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// File the problem was found in
+    pub path: PathBuf,
+    /// 1-based line number, when the problem is tied to a line
+    pub line: Option<usize>,
+    /// Human-readable description
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+/// Result of verifying a mirror directory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of distinct gems found in `versions`
+    pub gems: usize,
+    /// Number of info files whose checksum was compared
+    pub info_files_checked: usize,
+    /// Every violation found, in discovery order
+    pub violations: Vec<Violation>,
+}
+
+impl VerifyReport {
+    /// Whether the mirror upholds every checked invariant
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    fn violation(&mut self, path: &Path, line: Option<usize>, message: impl Into<String>) {
+        self.violations.push(Violation {
+            path: path.to_path_buf(),
+            line,
+            message: message.into(),
+        });
+    }
+}
+
+/// Verify the mirror in `dir`, optionally against a previous `versions` snapshot
+///
+/// I/O errors (such as a missing `versions` file) are returned as errors;
+/// content problems are collected into the report.
+pub fn verify_mirror(
+    dir: &Path,
+    previous_versions: Option<&Path>,
+) -> std::io::Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+
+    let versions_path = dir.join("versions");
+    let last_checksums = check_versions(&versions_path, &mut report)?;
+    report.gems = last_checksums.len();
+
+    let mut gems: Vec<&String> = last_checksums.keys().collect();
+    gems.sort_unstable();
+
+    check_names(&dir.join("names"), &gems, &mut report)?;
+
+    for gem in gems {
+        let (checksum, line) = &last_checksums[gem];
+        check_info(
+            &dir.join("info").join(gem),
+            checksum,
+            *line,
+            &versions_path,
+            &mut report,
+        )?;
+    }
+
+    if let Some(previous) = previous_versions {
+        check_append_only(previous, &versions_path, &mut report)?;
+    }
+
+    Ok(report)
+}
+
+/// Check the versions file format and return each gem's last checksum and line
+fn check_versions(
+    path: &Path,
+    report: &mut VerifyReport,
+) -> std::io::Result<HashMap<String, (String, usize)>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut last_checksums: HashMap<String, (String, usize)> = HashMap::new();
+    let mut line = String::new();
+    let mut line_number = 0;
+    let mut in_metadata = true;
+    let mut has_created_at = false;
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        line_number += 1;
+        let trimmed = line.trim();
+
+        if in_metadata {
+            if trimmed == "---" {
+                in_metadata = false;
+                if !has_created_at {
+                    report.violation(path, Some(line_number), "metadata is missing created_at");
+                }
+            } else if trimmed.starts_with("created_at:") {
+                has_created_at = true;
+            }
+            continue;
+        }
+
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = trimmed.split(' ').collect();
+        if fields.len() < 3 || fields.iter().any(|field| field.is_empty()) {
+            report.violation(
+                path,
+                Some(line_number),
+                "expected 'name versions checksum' separated by single spaces",
+            );
+            continue;
+        }
+
+        match last_checksums.get_mut(fields[0]) {
+            Some(entry) => *entry = (fields[2].to_string(), line_number),
+            None => {
+                last_checksums.insert(fields[0].to_string(), (fields[2].to_string(), line_number));
+            }
+        }
+    }
+
+    if in_metadata {
+        report.violation(path, None, "no '---' separator found");
+    }
+
+    Ok(last_checksums)
+}
+
+/// Check that the names file lists exactly the gems in versions, sorted
+fn check_names(path: &Path, gems: &[&String], report: &mut VerifyReport) -> std::io::Result<()> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut names: Vec<String> = Vec::new();
+    let mut saw_separator = false;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let trimmed = line.trim();
+        if !saw_separator {
+            if trimmed != "---" {
+                report.violation(path, Some(index + 1), "names must start with '---'");
+            }
+            saw_separator = true;
+            continue;
+        }
+        if trimmed.is_empty() {
+            continue;
+        }
+        if names
+            .last()
+            .is_some_and(|previous| previous.as_str() >= trimmed)
+        {
+            report.violation(
+                path,
+                Some(index + 1),
+                format!("'{}' is out of order", trimmed),
+            );
+        }
+        names.push(trimmed.to_string());
+    }
+
+    let mut sorted_names: Vec<&str> = names.iter().map(String::as_str).collect();
+    sorted_names.sort_unstable();
+    for gem in gems {
+        if sorted_names.binary_search(&gem.as_str()).is_err() {
+            report.violation(
+                path,
+                None,
+                format!("missing gem '{}' present in versions", gem),
+            );
+        }
+    }
+    for name in &names {
+        if gems.binary_search_by(|gem| gem.as_str().cmp(name)).is_err() {
+            report.violation(
+                path,
+                None,
+                format!("lists '{}' which is not in versions", name),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Check an info file's header and that its MD5 matches the versions checksum
+fn check_info(
+    path: &Path,
+    expected_checksum: &str,
+    versions_line: usize,
+    versions_path: &Path,
+    report: &mut VerifyReport,
+) -> std::io::Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            report.violation(
+                versions_path,
+                Some(versions_line),
+                format!("info file {} is missing", path.display()),
+            );
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut first_line = String::new();
+    reader.read_line(&mut first_line)?;
+    if first_line.trim() != "---" {
+        report.violation(path, Some(1), "info file must start with '---'");
+    }
+
+    let mut hasher = Md5::new();
+    hasher.update(first_line.as_bytes());
+    let mut buffer = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    let actual_checksum = hex::encode(hasher.finalize());
+    report.info_files_checked += 1;
+    if actual_checksum != expected_checksum {
+        report.violation(
+            versions_path,
+            Some(versions_line),
+            format!(
+                "checksum {} does not match MD5 {} of {}",
+                expected_checksum,
+                actual_checksum,
+                path.display()
+            ),
+        );
+    }
+
+    Ok(())
+}
+
+/// Check that the previous versions snapshot is a byte prefix of the current one
+fn check_append_only(
+    previous_path: &Path,
+    current_path: &Path,
+    report: &mut VerifyReport,
+) -> std::io::Result<()> {
+    let mut previous = BufReader::new(File::open(previous_path)?);
+    let mut current = BufReader::new(File::open(current_path)?);
+    let mut previous_line = String::new();
+    let mut current_line = String::new();
+    let mut line_number = 0;
+
+    loop {
+        previous_line.clear();
+        if previous.read_line(&mut previous_line)? == 0 {
+            return Ok(());
+        }
+        line_number += 1;
+
+        current_line.clear();
+        if current.read_line(&mut current_line)? == 0 {
+            report.violation(
+                current_path,
+                Some(line_number),
+                format!("shorter than previous snapshot {}", previous_path.display()),
+            );
+            return Ok(());
+        }
+
+        // The previous file's last line may legitimately be completed by the append
+        let is_previous_tail = !previous_line.ends_with('\n');
+        let matches = if is_previous_tail {
+            current_line.starts_with(previous_line.as_str())
+        } else {
+            previous_line == current_line
+        };
+        if !matches {
+            let gem = gem_name(previous_line.trim()).unwrap_or("");
+            report.violation(
+                current_path,
+                Some(line_number),
+                format!(
+                    "not an append of {} (line for '{}' was rewritten); clients using Range requests will corrupt their copy",
+                    previous_path.display(),
+                    gem
+                ),
+            );
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const VERSIONS: &str = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 RAILS0\nsinatra 3.0.0 SINATRA\nrails 7.0.1 RAILS1\n";
+    const RAILS_INFO: &str = "---\n7.0.0 |checksum:aaa\n7.0.1 |checksum:bbb\n";
+    const SINATRA_INFO: &str = "---\n3.0.0 rack:>= 2.2|checksum:ccc\n";
+
+    fn md5_hex(data: &str) -> String {
+        hex::encode(Md5::digest(data.as_bytes()))
+    }
+
+    /// Write a consistent mirror into a fresh temporary directory
+    fn write_mirror(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "gem-index-filter-verify-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("info")).unwrap();
+
+        let versions = VERSIONS
+            .replace("RAILS0", "0123")
+            .replace("RAILS1", &md5_hex(RAILS_INFO))
+            .replace("SINATRA", &md5_hex(SINATRA_INFO));
+        fs::write(dir.join("versions"), versions).unwrap();
+        fs::write(dir.join("names"), "---\nrails\nsinatra\n").unwrap();
+        fs::write(dir.join("info/rails"), RAILS_INFO).unwrap();
+        fs::write(dir.join("info/sinatra"), SINATRA_INFO).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_consistent_mirror_passes() {
+        let dir = write_mirror("ok");
+
+        let report = verify_mirror(&dir, None).unwrap();
+        assert!(report.is_ok(), "{:?}", report.violations);
+        assert_eq!(report.gems, 2);
+        assert_eq!(report.info_files_checked, 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_checksum_mismatch_reported_with_line() {
+        let dir = write_mirror("checksum");
+        fs::write(dir.join("info/rails"), "---\n7.0.0 |checksum:changed\n").unwrap();
+
+        let report = verify_mirror(&dir, None).unwrap();
+        assert_eq!(report.violations.len(), 1);
+        assert_eq!(report.violations[0].path, dir.join("versions"));
+        // The last rails line is authoritative
+        assert_eq!(report.violations[0].line, Some(5));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_names_and_info_problems() {
+        let dir = write_mirror("names");
+        fs::write(dir.join("names"), "---\nsinatra\npuma\n").unwrap();
+        fs::remove_file(dir.join("info/sinatra")).unwrap();
+
+        let report = verify_mirror(&dir, None).unwrap();
+        let messages: Vec<String> = report.violations.iter().map(|v| v.to_string()).collect();
+        assert!(messages
+            .iter()
+            .any(|m| m.contains("'puma' is out of order")));
+        assert!(messages.iter().any(|m| m.contains("missing gem 'rails'")));
+        assert!(messages.iter().any(|m| m.contains("lists 'puma'")));
+        assert!(messages
+            .iter()
+            .any(|m| m.contains("info/sinatra is missing")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_versions_format_problems() {
+        let dir = write_mirror("format");
+        fs::write(
+            dir.join("versions"),
+            "generated: today\n---\nrails  7.0.0 abc\nbroken\n",
+        )
+        .unwrap();
+
+        let report = verify_mirror(&dir, None).unwrap();
+        let lines: Vec<Option<usize>> = report
+            .violations
+            .iter()
+            .filter(|v| v.path == dir.join("versions"))
+            .map(|v| v.line)
+            .collect();
+        assert_eq!(lines, vec![Some(2), Some(3), Some(4)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_append_only_against_previous() {
+        let dir = write_mirror("append");
+        let versions = fs::read_to_string(dir.join("versions")).unwrap();
+
+        let prefix_end = versions.find("sinatra").unwrap();
+        let previous = dir.join("versions.previous");
+        fs::write(&previous, &versions[..prefix_end]).unwrap();
+        assert!(verify_mirror(&dir, Some(&previous)).unwrap().is_ok());
+
+        fs::write(&previous, versions.replace("rails 7.0.0", "rails 6.1.0")).unwrap();
+        let report = verify_mirror(&dir, Some(&previous)).unwrap();
+        assert_eq!(report.violations.len(), 1);
+        assert_eq!(report.violations[0].line, Some(3));
+        assert!(report.violations[0].message.contains("not an append"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+//! RubyGems version ordering and requirements
+//!
+//! Policies like `rails = ">= 7.0, < 8"` need the same answers `gem install`
+//! would give, so this follows `Gem::Version` and `Gem::Requirement`: versions
+//! are split into numeric and alphabetic segments (`1.0.0.rc1` is
+//! `1, 0, 0, "rc", 1`), alphabetic segments sort before numbers, which is what
+//! makes prereleases older than their release, and missing trailing segments
+//! count as zero.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+/// One `Gem::Version` segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Number(u64),
+    Text(String),
+}
+
+/// A parsed RubyGems version, without platform
+#[derive(Debug, Clone)]
+pub struct GemVersion {
+    segments: Vec<Segment>,
+}
+
+impl GemVersion {
+    /// Parse a version such as `7.0.1` or `2.0.0.beta3`
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim();
+        let valid = !version.is_empty()
+            && version.starts_with(|c: char| c.is_ascii_digit())
+            && version
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.');
+        if !valid {
+            return None;
+        }
+
+        let mut segments = Vec::new();
+        let mut rest = version;
+        while let Some(start) = rest.find(|c: char| c.is_ascii_alphanumeric()) {
+            rest = &rest[start..];
+            let numeric = rest.starts_with(|c: char| c.is_ascii_digit());
+            let end = rest
+                .find(|c: char| c == '.' || c.is_ascii_digit() != numeric)
+                .unwrap_or(rest.len());
+            segments.push(if numeric {
+                Segment::Number(rest[..end].parse().ok()?)
+            } else {
+                Segment::Text(rest[..end].to_string())
+            });
+            rest = &rest[end..];
+        }
+        Some(GemVersion { segments })
+    }
+
+    /// Whether this is a prerelease, i.e. has any letters
+    pub fn is_prerelease(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Text(_)))
+    }
+
+    /// The version with prerelease segments (and everything after them) removed
+    fn release(&self) -> GemVersion {
+        let segments = self
+            .segments
+            .iter()
+            .take_while(|segment| matches!(segment, Segment::Number(_)))
+            .cloned()
+            .collect();
+        GemVersion { segments }
+    }
+
+    /// Upper bound for `~>`: drop the last segment and increment the new last
+    fn bump(&self) -> GemVersion {
+        let mut segments = self.release().segments;
+        if segments.len() > 1 {
+            segments.pop();
+        }
+        if let Some(Segment::Number(last)) = segments.last_mut() {
+            *last += 1;
+        }
+        GemVersion { segments }
+    }
+}
+
+impl Ord for GemVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let zero = Segment::Number(0);
+        let len = self.segments.len().max(other.segments.len());
+        for i in 0..len {
+            let left = self.segments.get(i).unwrap_or(&zero);
+            let right = other.segments.get(i).unwrap_or(&zero);
+            let ordering = match (left, right) {
+                (Segment::Number(a), Segment::Number(b)) => a.cmp(b),
+                (Segment::Text(a), Segment::Text(b)) => a.cmp(b),
+                (Segment::Text(_), Segment::Number(_)) => Ordering::Less,
+                (Segment::Number(_), Segment::Text(_)) => Ordering::Greater,
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for GemVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for GemVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for GemVersion {}
+
+/// Requirement operators, as in `Gem::Requirement::OPS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    GreaterOrEqual,
+    LessOrEqual,
+    Pessimistic,
+}
+
+/// A requirement such as `>= 7.0, < 8` or `~> 2.3`; every clause must hold
+#[derive(Debug, Clone)]
+pub struct Requirement {
+    source: String,
+    clauses: Vec<(Operator, GemVersion)>,
+}
+
+impl Requirement {
+    /// Parse comma-separated clauses; a bare version means `=`
+    pub fn parse(requirement: &str) -> Option<Self> {
+        let mut clauses = Vec::new();
+        for clause in requirement.split(',') {
+            let clause = clause.trim();
+            let (operator, version) = [
+                ("~>", Operator::Pessimistic),
+                (">=", Operator::GreaterOrEqual),
+                ("<=", Operator::LessOrEqual),
+                ("!=", Operator::NotEqual),
+                (">", Operator::Greater),
+                ("<", Operator::Less),
+                ("=", Operator::Equal),
+            ]
+            .into_iter()
+            .find_map(|(prefix, operator)| clause.strip_prefix(prefix).map(|v| (operator, v)))
+            .unwrap_or((Operator::Equal, clause));
+            clauses.push((operator, GemVersion::parse(version)?));
+        }
+        Some(Requirement {
+            source: requirement.trim().to_string(),
+            clauses,
+        })
+    }
+
+    /// Whether `version` satisfies every clause
+    pub fn matches(&self, version: &GemVersion) -> bool {
+        self.clauses.iter().all(|(operator, bound)| match operator {
+            Operator::Equal => version == bound,
+            Operator::NotEqual => version != bound,
+            Operator::Greater => version > bound,
+            Operator::Less => version < bound,
+            Operator::GreaterOrEqual => version >= bound,
+            Operator::LessOrEqual => version <= bound,
+            Operator::Pessimistic => version >= bound && version.release() < bound.bump(),
+        })
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(version: &str) -> GemVersion {
+        GemVersion::parse(version).unwrap()
+    }
+
+    #[test]
+    fn test_version_ordering() {
+        assert!(v("1.0.0") == v("1"));
+        assert!(v("1.10") > v("1.9"));
+        assert!(v("2.0.0.rc1") < v("2.0.0"));
+        assert!(v("2.0.0.beta2") < v("2.0.0.rc1"));
+        assert!(v("1.0.a") < v("1.0.0"));
+        assert!(v("7.0.8.1") > v("7.0.8"));
+        assert!(v("2.0.0.rc1").is_prerelease());
+        assert!(!v("2.0.0").is_prerelease());
+        assert!(GemVersion::parse("-1.0").is_none());
+        assert!(GemVersion::parse("").is_none());
+    }
+
+    #[test]
+    fn test_requirements() {
+        let range = Requirement::parse(">= 7.0, < 8").unwrap();
+        assert!(range.matches(&v("7.0.0")));
+        assert!(range.matches(&v("7.1.3")));
+        assert!(!range.matches(&v("8.0.0")));
+        assert!(!range.matches(&v("6.1.7")));
+
+        let pessimistic = Requirement::parse("~> 2.3").unwrap();
+        assert!(pessimistic.matches(&v("2.3")));
+        assert!(pessimistic.matches(&v("2.9.1")));
+        assert!(!pessimistic.matches(&v("3.0")));
+        assert!(!pessimistic.matches(&v("3.0.0.rc1")));
+
+        let patch = Requirement::parse("~> 2.3.1").unwrap();
+        assert!(patch.matches(&v("2.3.9")));
+        assert!(!patch.matches(&v("2.4.0")));
+
+        assert!(Requirement::parse("1.2.3").unwrap().matches(&v("1.2.3")));
+        assert!(!Requirement::parse("!= 1.2.3").unwrap().matches(&v("1.2.3")));
+        assert!(Requirement::parse(">= banana").is_none());
+    }
+}
@@ -0,0 +1,167 @@
+//! JavaScript bindings for edge runtimes (`wasm` feature)
+//!
+//! Build a cdylib with `cargo rustc --lib --crate-type cdylib --target
+//! wasm32-unknown-unknown --no-default-features --features wasm`, run
+//! `wasm-bindgen` on the result and call it from a Cloudflare Worker (or any
+//! wasm-bindgen host):
+//!
+//! ```js
+//! import { filterVersions } from "gem-index-filter";
+//!
+//! const upstream = await fetch("https://rubygems.org/versions");
+//! const bytes = new Uint8Array(await upstream.arrayBuffer());
+//! const result = filterVersions(bytes, ["rails", "sinatra"], { digest: "sha256" });
+//! return new Response(result.output, { headers: { "X-Checksum-Sha256": result.digest } });
+//! ```
+//!
+//! `options` is an optional object:
+//!
+//! - `mode`: `"allow"` (default) or `"block"`, how the gem list is applied
+//! - `stripVersions`: replace version lists with `0`
+//! - `digest`: `"sha256"` or `"sha512"` to also return a checksum of the output
+//!   (needs the `digest` feature as well)
+//!
+//! Passing `null` for the gem list passes every gem through.
+
+use crate::{filter_versions_streaming, DigestAlgorithm, FilterMode, VersionOutput};
+use std::collections::HashSet;
+use wasm_bindgen::prelude::*;
+
+/// Filtered output and optional checksum returned to JavaScript
+#[wasm_bindgen]
+pub struct FilterResult {
+    output: Vec<u8>,
+    digest: Option<String>,
+}
+
+#[wasm_bindgen]
+impl FilterResult {
+    /// The filtered versions file
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> Vec<u8> {
+        self.output.clone()
+    }
+
+    /// Hex checksum of `output`, if a digest was requested
+    #[wasm_bindgen(getter)]
+    pub fn digest(&self) -> Option<String> {
+        self.digest.clone()
+    }
+}
+
+/// Filter a complete versions file held in memory
+#[wasm_bindgen(js_name = filterVersions)]
+pub fn filter_versions(
+    bytes: &[u8],
+    gems: Option<Vec<String>>,
+    options: JsValue,
+) -> Result<FilterResult, JsError> {
+    let options = parse_options(&options)?;
+    filter_bytes(bytes, gems.as_deref(), &options).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Options decoded from the JavaScript `options` object
+#[derive(Debug, Default)]
+struct WasmOptions {
+    block: bool,
+    version_output: Option<VersionOutput>,
+    digest: Option<DigestAlgorithm>,
+}
+
+fn parse_options(options: &JsValue) -> Result<WasmOptions, JsError> {
+    let mut parsed = WasmOptions::default();
+    if options.is_undefined() || options.is_null() {
+        return Ok(parsed);
+    }
+
+    let get = |key: &str| js_sys::Reflect::get(options, &JsValue::from_str(key)).ok();
+
+    parsed.block = match get("mode").and_then(|v| v.as_string()).as_deref() {
+        None | Some("allow") => false,
+        Some("block") => true,
+        Some(other) => return Err(JsError::new(&format!("Unknown mode '{}'", other))),
+    };
+    if get("stripVersions").and_then(|v| v.as_bool()) == Some(true) {
+        parsed.version_output = Some(VersionOutput::Strip);
+    }
+    parsed.digest = match get("digest").and_then(|v| v.as_string()).as_deref() {
+        None => None,
+        Some(name) => Some(
+            name.parse::<DigestAlgorithm>()
+                .map_err(|e| JsError::new(&e.to_string()))?,
+        ),
+    };
+
+    Ok(parsed)
+}
+
+/// The JavaScript-independent part of [`filter_versions`]
+fn filter_bytes(
+    bytes: &[u8],
+    gems: Option<&[String]>,
+    options: &WasmOptions,
+) -> std::io::Result<FilterResult> {
+    let set: Option<HashSet<&str>> = gems.map(|gems| gems.iter().map(String::as_str).collect());
+    let mode = match &set {
+        None => FilterMode::Passthrough,
+        Some(set) if options.block => FilterMode::Block(set),
+        Some(set) => FilterMode::Allow(set),
+    };
+
+    let mut output = Vec::with_capacity(bytes.len() / 4);
+    let digest = filter_versions_streaming(
+        bytes,
+        &mut output,
+        mode,
+        options.version_output.unwrap_or(VersionOutput::Preserve),
+        options.digest,
+    )?;
+
+    Ok(FilterResult { output, digest })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSIONS: &str = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0,7.0.1 abc123
+sinatra 3.0.0 def456
+"#;
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn test_filter_bytes_allow_with_digest() {
+        let gems = vec!["sinatra".to_string()];
+        let options = WasmOptions {
+            digest: Some(DigestAlgorithm::Sha256),
+            ..WasmOptions::default()
+        };
+        let result = filter_bytes(VERSIONS.as_bytes(), Some(&gems), &options).unwrap();
+
+        assert_eq!(
+            String::from_utf8(result.output).unwrap(),
+            "created_at: 2024-04-01T00:00:05Z\n---\nsinatra 3.0.0 def456\n"
+        );
+        assert_eq!(result.digest.unwrap().len(), 64);
+    }
+
+    #[test]
+    fn test_filter_bytes_block_and_passthrough() {
+        let gems = vec!["sinatra".to_string()];
+        let block = WasmOptions {
+            block: true,
+            version_output: Some(VersionOutput::Strip),
+            ..WasmOptions::default()
+        };
+        let result = filter_bytes(VERSIONS.as_bytes(), Some(&gems), &block).unwrap();
+        assert!(String::from_utf8(result.output)
+            .unwrap()
+            .ends_with("---\nrails 0 abc123\n"));
+
+        let result = filter_bytes(VERSIONS.as_bytes(), None, &WasmOptions::default()).unwrap();
+        assert_eq!(result.output, VERSIONS.as_bytes());
+        assert!(result.digest.is_none());
+    }
+}
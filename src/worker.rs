@@ -0,0 +1,239 @@
+//! Cloudflare Workers fetch handler (`worker` feature)
+//!
+//! Serves a filtered compact index straight from the edge. `/versions` is
+//! streamed from upstream through a [`ChunkFilter`] using Web Streams, so the
+//! Worker never holds the whole file, and `/info/<gem>` is proxied only for
+//! gems the policy lets through. Every other path is a 404.
+//!
+//! ```js
+//! import init, { handleFetch } from "./pkg/gem_index_filter.js";
+//! import wasm from "./pkg/gem_index_filter_bg.wasm";
+//!
+//! await init(wasm);
+//! export default { fetch: (request, env) => handleFetch(request, env) };
+//! ```
+//!
+//! Configuration comes from the Worker's bindings:
+//!
+//! - `GEM_LISTS`: KV namespace holding `allowlist` and/or `blocklist` keys
+//!   in the same one-name-per-line format as the CLI's list files
+//! - `UPSTREAM`: compact index to filter (default `https://rubygems.org`)
+//! - `STRIP_VERSIONS`: `"true"` to replace version lists with `0`
+
+use crate::slice::strip_bom;
+use crate::{ChunkFilter, FilterMode, VersionOutput};
+use futures_channel::mpsc;
+use futures_util::{SinkExt, StreamExt};
+use js_sys::{Function, Promise, Reflect, Uint8Array};
+use std::collections::HashSet;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{Headers, Request, Response, ResponseInit, Url, WorkerGlobalScope};
+
+/// Upstream used when the `UPSTREAM` binding is not set
+const DEFAULT_UPSTREAM: &str = "https://rubygems.org";
+
+/// How many filtered chunks may queue up before reading upstream pauses
+const CHANNEL_CAPACITY: usize = 8;
+
+/// Handle a Worker `fetch` event
+#[wasm_bindgen(js_name = handleFetch)]
+pub async fn handle_fetch(request: Request, env: JsValue) -> Result<Response, JsValue> {
+    let path = Url::new(&request.url())?.pathname();
+    let config = WorkerConfig::from_env(&env).await?;
+
+    if path == "/versions" {
+        return filtered_versions(config).await;
+    }
+    if let Some(gem) = path.strip_prefix("/info/") {
+        if config.allows(gem) {
+            return fetch(&format!("{}/info/{}", config.upstream, gem)).await;
+        }
+    }
+    not_found()
+}
+
+/// Policy and upstream read from the Worker's bindings
+struct WorkerConfig {
+    upstream: String,
+    version_output: VersionOutput,
+    gems: Option<HashSet<String>>,
+    block: bool,
+}
+
+impl WorkerConfig {
+    async fn from_env(env: &JsValue) -> Result<Self, JsValue> {
+        let upstream = Reflect::get(env, &"UPSTREAM".into())?
+            .as_string()
+            .unwrap_or_else(|| DEFAULT_UPSTREAM.to_string());
+        let version_output = match Reflect::get(env, &"STRIP_VERSIONS".into())?.as_string() {
+            Some(value) if value == "true" => VersionOutput::Strip,
+            _ => VersionOutput::Preserve,
+        };
+
+        let kv = Reflect::get(env, &"GEM_LISTS".into())?;
+        let (allowlist, blocklist) = if kv.is_undefined() {
+            (None, None)
+        } else {
+            (
+                kv_text(&kv, "allowlist").await?,
+                kv_text(&kv, "blocklist").await?,
+            )
+        };
+        let (gems, block) = effective_gems(allowlist.as_deref(), blocklist.as_deref());
+
+        Ok(WorkerConfig {
+            upstream: upstream.trim_end_matches('/').to_string(),
+            version_output,
+            gems,
+            block,
+        })
+    }
+
+    fn allows(&self, gem: &str) -> bool {
+        match &self.gems {
+            None => true,
+            Some(gems) => gems.contains(gem) != self.block,
+        }
+    }
+}
+
+/// Combine the lists the way the CLI does: allowlist minus blocklist when both are set
+///
+/// Returns the gem set to check against and whether it is a blocklist.
+fn effective_gems(
+    allowlist: Option<&str>,
+    blocklist: Option<&str>,
+) -> (Option<HashSet<String>>, bool) {
+    match (allowlist.map(parse_gem_list), blocklist.map(parse_gem_list)) {
+        (Some(mut allowed), Some(blocked)) => {
+            allowed.retain(|gem| !blocked.contains(gem));
+            (Some(allowed), false)
+        }
+        (Some(allowed), None) => (Some(allowed), false),
+        (None, Some(blocked)) => (Some(blocked), true),
+        (None, None) => (None, false),
+    }
+}
+
+/// Parse a gem list, skipping blank lines and `#` comments
+fn parse_gem_list(text: &str) -> HashSet<String> {
+    strip_bom(text)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// Stream upstream `/versions` through the filter into the response body
+async fn filtered_versions(config: WorkerConfig) -> Result<Response, JsValue> {
+    let upstream = fetch(&format!("{}/versions", config.upstream)).await?;
+    if !upstream.ok() {
+        return Err(JsError::new(&format!("Upstream returned HTTP {}", upstream.status())).into());
+    }
+    let body = upstream
+        .body()
+        .ok_or_else(|| JsError::new("Upstream response has no body"))?;
+
+    // The filter borrows the gem set, so both live inside the spawned task
+    let (mut sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    spawn_local(async move {
+        if let Err(e) = pump(body, &config, &mut sender).await {
+            let _ = sender.send(Err(e)).await;
+        }
+    });
+
+    let headers = Headers::new()?;
+    headers.set("content-type", "text/plain; charset=utf-8")?;
+    let init = ResponseInit::new();
+    init.set_status(200);
+    init.set_headers(&headers);
+    let stream = wasm_streams::ReadableStream::from_stream(receiver).into_raw();
+    Response::new_with_opt_readable_stream_and_init(Some(&stream), &init)
+}
+
+/// Filter each upstream chunk as it arrives and forward the output
+async fn pump(
+    body: web_sys::ReadableStream,
+    config: &WorkerConfig,
+    sender: &mut mpsc::Sender<Result<JsValue, JsValue>>,
+) -> Result<(), JsValue> {
+    let gems: Option<HashSet<&str>> = config
+        .gems
+        .as_ref()
+        .map(|gems| gems.iter().map(String::as_str).collect());
+    let mode = match &gems {
+        None => FilterMode::Passthrough,
+        Some(gems) if config.block => FilterMode::Block(gems),
+        Some(gems) => FilterMode::Allow(gems),
+    };
+
+    let mut filter = ChunkFilter::new(mode, config.version_output);
+    let mut chunks = wasm_streams::ReadableStream::from_raw(body).into_stream();
+    let mut output = Vec::new();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = Uint8Array::new(&chunk?).to_vec();
+        filter.push(&chunk, &mut output).map_err(to_js_error)?;
+        if !output.is_empty() && !send(sender, &mut output).await {
+            // The client went away; stop reading upstream
+            return Ok(());
+        }
+    }
+    filter.finish(&mut output).map_err(to_js_error)?;
+    send(sender, &mut output).await;
+    Ok(())
+}
+
+/// Send and clear `output`, returning false once the response body was dropped
+async fn send(sender: &mut mpsc::Sender<Result<JsValue, JsValue>>, output: &mut Vec<u8>) -> bool {
+    let chunk = Uint8Array::from(output.as_slice());
+    output.clear();
+    sender.send(Ok(chunk.into())).await.is_ok()
+}
+
+/// Read a KV value as text, `None` when the key is missing
+async fn kv_text(kv: &JsValue, key: &str) -> Result<Option<String>, JsValue> {
+    let get: Function = Reflect::get(kv, &"get".into())?.dyn_into()?;
+    let promise: Promise = get.call1(kv, &key.into())?.dyn_into()?;
+    Ok(JsFuture::from(promise).await?.as_string())
+}
+
+async fn fetch(url: &str) -> Result<Response, JsValue> {
+    let global: WorkerGlobalScope = js_sys::global().unchecked_into();
+    JsFuture::from(global.fetch_with_str(url)).await?.dyn_into()
+}
+
+fn not_found() -> Result<Response, JsValue> {
+    let init = ResponseInit::new();
+    init.set_status(404);
+    Response::new_with_opt_str_and_init(Some("Not Found"), &init)
+}
+
+fn to_js_error(error: std::io::Error) -> JsValue {
+    JsError::new(&error.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effective_gems_matches_cli_semantics() {
+        let allowlist = "# core\nrails\nsinatra\n\npuma\n";
+        let blocklist = "puma\n";
+
+        let (gems, block) = effective_gems(Some(allowlist), Some(blocklist));
+        let mut gems: Vec<_> = gems.unwrap().into_iter().collect();
+        gems.sort();
+        assert_eq!(gems, vec!["rails", "sinatra"]);
+        assert!(!block);
+
+        let (gems, block) = effective_gems(None, Some(blocklist));
+        assert_eq!(gems.unwrap().len(), 1);
+        assert!(block);
+
+        assert_eq!(effective_gems(None, None), (None, false));
+    }
+}
@@ -0,0 +1,505 @@
+//! Composable writer wrappers
+//!
+//! Each wrapper takes its inner writer by value. Pass `&mut writer` to keep
+//! using the writer afterwards, or the writer itself to hand it over and get
+//! it back from `into_inner`. They stack, so one pass over the output can be
+//! hashed, counted and copied:
+//!
+//! ```
+//! use gem_index_filter::writers::{CountingWriter, TeeWriter};
+//! use std::io::Write;
+//!
+//! let (mut file, mut cache) = (Vec::new(), Vec::new());
+//! let mut output = CountingWriter::new(TeeWriter::new().with(&mut file).with(&mut cache));
+//! output.write_all(b"---\nrails 7.0.0 abc123\n")?;
+//! assert_eq!((output.bytes(), output.lines()), (23, 2));
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::parser::GemLine;
+use std::io::Write;
+
+/// Writer that counts the bytes and lines passing through it
+pub struct CountingWriter<W: Write> {
+    inner: W,
+    bytes: u64,
+    lines: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    /// Wrap `inner`, starting both counts at zero
+    pub fn new(inner: W) -> Self {
+        CountingWriter {
+            inner,
+            bytes: 0,
+            lines: 0,
+        }
+    }
+
+    /// Bytes accepted by the inner writer so far
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Newlines among those bytes
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    /// Return the inner writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes += n as u64;
+        self.lines += memchr::memchr_iter(b'\n', &buf[..n]).count() as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writer that copies everything to each of its sinks
+///
+/// Every write goes to every sink in full, in the order they were added, and
+/// the first error stops the write. A sink that failed may be behind the
+/// others, so treat the set as broken after an error.
+#[derive(Default)]
+pub struct TeeWriter<'a> {
+    sinks: Vec<Box<dyn Write + 'a>>,
+}
+
+impl<'a> TeeWriter<'a> {
+    /// A tee with no sinks, which discards everything
+    pub fn new() -> Self {
+        TeeWriter::default()
+    }
+
+    /// Add a sink
+    pub fn with(mut self, sink: impl Write + 'a) -> Self {
+        self.push(sink);
+        self
+    }
+
+    /// Add a sink to an existing tee
+    pub fn push(&mut self, sink: impl Write + 'a) {
+        self.sinks.push(Box::new(sink));
+    }
+}
+
+impl Write for TeeWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for sink in &mut self.sinks {
+            sink.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.sinks.iter_mut().try_for_each(|sink| sink.flush())
+    }
+}
+
+/// Writer that normalizes a versions file into one canonical byte sequence
+///
+/// Lines are trimmed and end in a single `\n`, blank lines are dropped, and
+/// the header lines before `---` are sorted, so the same index written on
+/// any platform or by any producer hashes and signs the same. The header is
+/// held until the separator arrives and a final line may lack its newline,
+/// so call [`finish`](CanonicalWriter::finish) once everything is written.
+pub struct CanonicalWriter<W: Write> {
+    inner: W,
+    line: Vec<u8>,
+    /// Header lines seen so far; `None` once the separator has been written
+    header: Option<Vec<Vec<u8>>>,
+}
+
+impl<W: Write> CanonicalWriter<W> {
+    /// Wrap `inner`, expecting the start of a versions file
+    pub fn new(inner: W) -> Self {
+        CanonicalWriter {
+            inner,
+            line: Vec::new(),
+            header: Some(Vec::new()),
+        }
+    }
+
+    /// Write any unterminated last line and a header without separator,
+    /// returning the inner writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let line = std::mem::take(&mut self.line);
+        self.emit(&line)?;
+        if let Some(header) = self.header.take() {
+            self.write_header(header)?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn emit(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return Ok(());
+        }
+        let Some(header) = &mut self.header else {
+            self.inner.write_all(line)?;
+            return self.inner.write_all(b"\n");
+        };
+        if line != b"---" {
+            // A byte order mark would sort the first line out of place
+            let line = line.strip_prefix(b"\xef\xbb\xbf").unwrap_or(line);
+            header.push(line.to_vec());
+            return Ok(());
+        }
+        let header = self.header.take().unwrap_or_default();
+        self.write_header(header)?;
+        self.inner.write_all(b"---\n")
+    }
+
+    fn write_header(&mut self, mut header: Vec<Vec<u8>>) -> std::io::Result<()> {
+        header.sort_unstable();
+        for line in header {
+            self.inner.write_all(&line)?;
+            self.inner.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for CanonicalWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut rest = buf;
+        while let Some(newline) = memchr::memchr(b'\n', rest) {
+            // Taken so emit can borrow self; the allocation is handed back
+            let mut line = std::mem::take(&mut self.line);
+            line.extend_from_slice(&rest[..newline]);
+            self.emit(&line)?;
+            line.clear();
+            self.line = line;
+            rest = &rest[newline + 1..];
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "server-support")]
+pub(crate) use digest::base64;
+#[cfg(feature = "digest")]
+pub use digest::{DigestWriter, UploadChecksums};
+
+/// Writer turning versions lines into one JSON record per gem line
+///
+/// Each gem line becomes `{"name":…,"versions":[…],"md5":…}` on a line of its
+/// own, with the version list split into its raw entries. Fields after the
+/// hash, when a line has any, follow as `"extra":[…]`. The header and
+/// `---` are dropped. Lines are converted as they complete, so filtering
+/// into this writer produces NDJSON in the same pass. A final line may lack
+/// its newline, so call [`finish`](NdjsonWriter::finish) once everything is
+/// written.
+pub struct NdjsonWriter<W: Write> {
+    inner: W,
+    line: Vec<u8>,
+    in_body: bool,
+}
+
+impl<W: Write> NdjsonWriter<W> {
+    /// Wrap `inner`, expecting the start of a versions file
+    pub fn new(inner: W) -> Self {
+        NdjsonWriter {
+            inner,
+            line: Vec::new(),
+            in_body: false,
+        }
+    }
+
+    /// Convert any unterminated last line, returning the inner writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let line = std::mem::take(&mut self.line);
+        self.emit(&line)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn emit(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return Ok(());
+        }
+        if !self.in_body {
+            self.in_body = line == b"---";
+            return Ok(());
+        }
+        let gem = std::str::from_utf8(line).ok().and_then(GemLine::parse);
+        let Some(gem) = gem else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Not a gem line: '{}'", String::from_utf8_lossy(line)),
+            ));
+        };
+
+        self.inner.write_all(b"{\"name\":")?;
+        serde_json::to_writer(&mut self.inner, gem.name)?;
+        self.inner.write_all(b",\"versions\":[")?;
+        for (i, version) in gem.versions.split(',').enumerate() {
+            if i > 0 {
+                self.inner.write_all(b",")?;
+            }
+            serde_json::to_writer(&mut self.inner, version)?;
+        }
+        self.inner.write_all(b"],\"md5\":")?;
+        serde_json::to_writer(&mut self.inner, gem.md5)?;
+        let extra: Vec<&str> = gem.extra_fields().collect();
+        if !extra.is_empty() {
+            self.inner.write_all(b",\"extra\":")?;
+            serde_json::to_writer(&mut self.inner, &extra)?;
+        }
+        self.inner.write_all(b"}\n")
+    }
+}
+
+impl<W: Write> Write for NdjsonWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut rest = buf;
+        while let Some(newline) = memchr::memchr(b'\n', rest) {
+            let mut line = std::mem::take(&mut self.line);
+            line.extend_from_slice(&rest[..newline]);
+            self.emit(&line)?;
+            line.clear();
+            self.line = line;
+            rest = &rest[newline + 1..];
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "digest")]
+mod digest {
+    use crate::DigestAlgorithm;
+    use md5::Md5;
+    use sha2::{Digest, Sha256, Sha512};
+    use std::io::Write;
+
+    /// Internal enum for holding active digest state
+    enum DigestState {
+        Sha256(Sha256),
+        Sha512(Sha512),
+    }
+
+    /// Writer wrapper that computes digest of data as it's written
+    /// This enables streaming checksum computation with zero buffering
+    pub struct DigestWriter<W: Write> {
+        inner: W,
+        state: DigestState,
+    }
+
+    impl<W: Write> DigestWriter<W> {
+        /// Create a new DigestWriter with the specified algorithm
+        pub fn new(inner: W, algorithm: DigestAlgorithm) -> Self {
+            let state = match algorithm {
+                DigestAlgorithm::Sha256 => DigestState::Sha256(Sha256::new()),
+                DigestAlgorithm::Sha512 => DigestState::Sha512(Sha512::new()),
+            };
+            DigestWriter { inner, state }
+        }
+
+        /// Finalize the digest and return the hex-encoded checksum
+        pub fn finalize(self) -> String {
+            self.into_parts().1
+        }
+
+        /// Return the inner writer along with the hex-encoded checksum
+        pub fn into_parts(self) -> (W, String) {
+            let digest = match self.state {
+                DigestState::Sha256(hasher) => hex::encode(hasher.finalize()),
+                DigestState::Sha512(hasher) => hex::encode(hasher.finalize()),
+            };
+            (self.inner, digest)
+        }
+    }
+
+    impl<W: Write> Write for DigestWriter<W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            // Only hash what the inner writer actually accepted
+            let n = self.inner.write(buf)?;
+            match &mut self.state {
+                DigestState::Sha256(hasher) => hasher.update(&buf[..n]),
+                DigestState::Sha512(hasher) => hasher.update(&buf[..n]),
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    /// Sink that takes the MD5 and SHA-256 of everything written to it, for
+    /// S3 to verify an upload against
+    ///
+    /// Add it to a [`TeeWriter`](crate::TeeWriter) next to the real output so
+    /// the checksums cover exactly the bytes sent, compressed or not.
+    #[derive(Default)]
+    pub struct UploadChecksums {
+        md5: Md5,
+        sha256: Sha256,
+    }
+
+    impl UploadChecksums {
+        /// Checksums of nothing yet
+        pub fn new() -> Self {
+            UploadChecksums::default()
+        }
+
+        /// `Content-MD5` and `x-amz-checksum-sha256` headers for the bytes written
+        pub fn headers(self) -> [(&'static str, String); 2] {
+            [
+                ("Content-MD5", base64(&self.md5.finalize())),
+                ("x-amz-checksum-sha256", base64(&self.sha256.finalize())),
+            ]
+        }
+    }
+
+    impl Write for UploadChecksums {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.md5.update(buf);
+            self.sha256.update(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Standard padded base64, the encoding HTTP checksum headers use
+    pub(crate) fn base64(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8; 64] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+        for group in bytes.chunks(3) {
+            let n = group
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+            for i in 0..4 {
+                encoded.push(if i <= group.len() {
+                    ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char
+                } else {
+                    '='
+                });
+            }
+        }
+        encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts at most three bytes per write, like a pipe that's nearly full
+    struct Trickle(Vec<u8>);
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_counting_and_tee() {
+        let mut first = Vec::new();
+        let mut output =
+            CountingWriter::new(TeeWriter::new().with(&mut first).with(Trickle(Vec::new())));
+        output.write_all(b"---\nrails 7.0.0 abc123\n").unwrap();
+        output.write_all(b"sinatra").unwrap();
+
+        assert_eq!((output.bytes(), output.lines()), (30, 2));
+        drop(output);
+        assert_eq!(first, b"---\nrails 7.0.0 abc123\nsinatra");
+    }
+
+    #[test]
+    fn test_canonical_writer_normalizes_lines_and_header() {
+        let mut output = CanonicalWriter::new(Vec::new());
+        output
+            .write_all(b"\xef\xbb\xbfz: 1\r\ncreated_at: 2024-04-01\r\n\n---\r\nrails 7.0.0 abc")
+            .unwrap();
+        output
+            .write_all(b"123  \r\n\n\nsinatra 3.0.0 def456")
+            .unwrap();
+        assert_eq!(
+            output.finish().unwrap(),
+            b"created_at: 2024-04-01\nz: 1\n---\nrails 7.0.0 abc123\nsinatra 3.0.0 def456\n"
+        );
+    }
+
+    #[test]
+    fn test_ndjson_writer_converts_gem_lines() {
+        let mut output = NdjsonWriter::new(Vec::new());
+        output
+            .write_all(b"created_at: 2024-04-01\n---\nrails 7.0.0,-7.0.1,7.0.2-java abc")
+            .unwrap();
+        output.write_all(b"123\nsinatra 0 def456 extra").unwrap();
+        assert_eq!(
+            String::from_utf8(output.finish().unwrap()).unwrap(),
+            "{\"name\":\"rails\",\"versions\":[\"7.0.0\",\"-7.0.1\",\"7.0.2-java\"],\"md5\":\"abc123\"}\n\
+             {\"name\":\"sinatra\",\"versions\":[\"0\"],\"md5\":\"def456\",\"extra\":[\"extra\"]}\n"
+        );
+
+        let mut output = NdjsonWriter::new(Vec::new());
+        assert!(output.write_all(b"---\nnot-a-gem-line\n").is_err());
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn test_digest_writer_owns_and_hashes_accepted_bytes() {
+        use crate::DigestAlgorithm;
+
+        let mut writer = DigestWriter::new(Trickle(Vec::new()), DigestAlgorithm::Sha256);
+        writer.write_all(b"abc").unwrap();
+        writer.write_all(b"defgh").unwrap();
+        let (inner, digest) = writer.into_parts();
+
+        assert_eq!(inner.0, b"abcdefgh");
+        assert_eq!(
+            digest,
+            "9c56cc51b374c3ba189210d5b6d4bf57790d351c96c47c02190ecf1e430635ab"
+        );
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn test_upload_checksums_are_base64() {
+        let [md5, sha256] = UploadChecksums::new().headers();
+        assert_eq!(md5, ("Content-MD5", "1B2M2Y8AsgTpgAmY7PhCfg==".to_string()));
+        assert_eq!(sha256.1, "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=");
+
+        let mut checksums = UploadChecksums::new();
+        checksums.write_all(b"abc").unwrap();
+        let [md5, _] = checksums.headers();
+        assert_eq!(md5.1, "kAFQmDzST7DWlj99KOF/cg==");
+    }
+}
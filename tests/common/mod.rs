@@ -0,0 +1,102 @@
+//! Shared helpers for integration tests
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+
+/// Serve fixed responses over HTTP on a local port, returning the base URL
+///
+/// Each route maps a request path to a response body; unknown paths get 404.
+/// Responses carry an `ETag` of the body length, and a matching
+/// `If-None-Match` gets a 304. The server thread lives until the test process exits.
+pub fn serve(routes: Vec<(&'static str, Vec<u8>)>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+            // Drain headers up to the blank line, keeping If-None-Match
+            let mut header = String::new();
+            let mut if_none_match = None;
+            while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("if-none-match") {
+                        if_none_match = Some(value.trim().to_string());
+                    }
+                }
+                header.clear();
+            }
+
+            let path = request_line.split_whitespace().nth(1).unwrap_or("");
+            let response = match routes.iter().find(|(route, _)| *route == path) {
+                Some((_, body)) if if_none_match == Some(format!("\"{}\"", body.len())) => {
+                    b"HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n".to_vec()
+                }
+                Some((_, body)) => {
+                    let mut response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {0}\r\nETag: \"{0}\"\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .into_bytes();
+                    response.extend_from_slice(body);
+                    response
+                }
+                None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_vec(),
+            };
+            let _ = stream.write_all(&response);
+        }
+    });
+
+    base_url
+}
+
+/// Serve `body` on every path, but answer the first `failures` requests for
+/// each path with `503 Service Unavailable`, like an upstream under load
+pub fn serve_flaky(body: &'static str, failures: usize) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        let mut seen = std::collections::HashMap::<String, usize>::new();
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+            let mut header = String::new();
+            while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+                header.clear();
+            }
+
+            let path = request_line.split_whitespace().nth(1).unwrap_or("");
+            let count = seen.entry(path.to_string()).or_default();
+            *count += 1;
+            let response = if *count <= failures {
+                "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            } else {
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            };
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+
+    base_url
+}
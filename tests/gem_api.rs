@@ -0,0 +1,89 @@
+#![cfg(feature = "http")]
+
+mod common;
+
+use gem_index_filter::gem_api::{
+    select_by_downloads, select_gems, ApiOptions, ApiPolicy, GemApiClient,
+};
+use std::fs;
+use std::time::Duration;
+
+#[test]
+fn test_select_by_downloads_with_cache() {
+    let api_base = common::serve(vec![
+        (
+            "/api/v1/gems/rails.json",
+            br#"{"name":"rails","downloads":500000000}"#.to_vec(),
+        ),
+        (
+            "/api/v1/gems/left-pad.json",
+            br#"{"name":"left-pad","downloads":12}"#.to_vec(),
+        ),
+    ]);
+
+    let cache_dir =
+        std::env::temp_dir().join(format!("gem-index-filter-api-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&cache_dir);
+
+    let options = ApiOptions {
+        api_base,
+        cache_dir: Some(cache_dir.clone()),
+        min_interval: Duration::ZERO,
+        ..ApiOptions::default()
+    };
+    let names: Vec<String> = ["left-pad", "missing", "rails"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+    let mut client = GemApiClient::new(options.clone());
+    let selected = select_by_downloads(&names, 1_000, &mut client).unwrap();
+    assert_eq!(selected, vec!["rails"]);
+    assert_eq!(client.requests(), 3);
+
+    // Every answer, including the 404, is cached for the next run
+    assert_eq!(fs::read(cache_dir.join("missing.json")).unwrap(), b"null");
+    let mut client = GemApiClient::new(options);
+    let selected = select_by_downloads(&names, 10, &mut client).unwrap();
+    assert_eq!(selected, vec!["left-pad", "rails"]);
+    assert_eq!(client.requests(), 0);
+
+    fs::remove_dir_all(&cache_dir).unwrap();
+}
+
+#[test]
+fn test_select_by_license() {
+    let api_base = common::serve(vec![
+        (
+            "/api/v1/gems/rails.json",
+            br#"{"name":"rails","downloads":500000000,"licenses":["MIT"]}"#.to_vec(),
+        ),
+        (
+            "/api/v1/gems/copyleft.json",
+            br#"{"name":"copyleft","downloads":900,"licenses":["GPL-3.0"]}"#.to_vec(),
+        ),
+        (
+            "/api/v1/gems/unlicensed.json",
+            br#"{"name":"unlicensed","downloads":900,"licenses":[]}"#.to_vec(),
+        ),
+    ]);
+
+    let mut client = GemApiClient::new(ApiOptions {
+        api_base,
+        min_interval: Duration::ZERO,
+        ..ApiOptions::default()
+    });
+    let policy = ApiPolicy {
+        allowed_licenses: Some(vec!["MIT".to_string(), "Apache-2.0".to_string()]),
+        ..ApiPolicy::default()
+    };
+    let names: Vec<String> = ["copyleft", "rails", "unlicensed"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+    assert_eq!(
+        select_gems(&names, &policy, &mut client).unwrap(),
+        vec!["rails"]
+    );
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "instrument")]
+
+use gem_index_filter::stats::CountingAllocator;
+use gem_index_filter::{filter_versions_with_stats, FilterMode, NamelessLines, VersionOutput};
+use std::collections::HashSet;
+
+#[global_allocator]
+static ALLOC: CountingAllocator = CountingAllocator;
+
+/// Peak heap growth must stay bounded by the buffers, not the input size
+#[test]
+fn test_peak_heap_does_not_grow_with_input() {
+    let allowlist: HashSet<&str> = ["gem1"].into_iter().collect();
+    let mut peaks = Vec::new();
+
+    for lines in [1_000, 100_000] {
+        let mut input = String::from("created_at: 2024-04-01T00:00:05Z\n---\n");
+        for i in 0..lines {
+            input.push_str(&format!("gem{} 1.0.0,1.0.1 abc123\n", i));
+        }
+
+        let mut output = Vec::with_capacity(64);
+        let stats = filter_versions_with_stats(
+            input.as_bytes(),
+            &mut output,
+            FilterMode::Allow(&allowlist).into(),
+            VersionOutput::Preserve,
+            None,
+            NamelessLines::Skip,
+        )
+        .unwrap();
+
+        let memory = stats.memory.expect("counting allocator is installed");
+        assert!(memory.allocations > 0);
+        peaks.push(memory.peak_heap_bytes);
+    }
+
+    // Input grew a hundredfold; the buffers did not
+    assert!(peaks[1] <= peaks[0] + 1024, "peaks: {:?}", peaks);
+    assert!(peaks[1] < 128 * 1024, "peaks: {:?}", peaks);
+}
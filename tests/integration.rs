@@ -1,3 +1,5 @@
+#![cfg(feature = "std")]
+
 use gem_index_filter::{filter_versions_streaming, FilterMode, VersionOutput};
 use std::collections::HashSet;
 
@@ -364,8 +366,8 @@ rack 2.0.0 aaa111
     effective_allowlist.insert("sinatra");
     effective_allowlist.insert("puma");
 
-    let blocklist = vec!["activerecord", "puma"];
-    effective_allowlist.retain(|gem| !blocklist.contains(&gem.as_ref()));
+    let blocklist = ["activerecord", "puma"];
+    effective_allowlist.retain(|gem| !blocklist.contains(gem));
 
     let mut output = Vec::new();
     filter_versions_streaming(
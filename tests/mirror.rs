@@ -0,0 +1,325 @@
+#![cfg(feature = "http")]
+
+mod common;
+
+use gem_index_filter::{build_mirror, FilterMode, InfoFetcher, MirrorOptions, Truncated};
+use std::collections::HashSet;
+use std::fs;
+
+#[test]
+fn test_mirror_builds_static_layout() {
+    let versions = r#"created_at: 2024-04-01T00:00:05Z
+---
+rails 7.0.0,7.0.1 abc123
+activerecord 7.0.0 def456
+sinatra 3.0.0 ghi789
+rails 7.0.2 jkl000
+"#;
+    let rails_info = "---\n7.0.0 |checksum:aaa\n7.0.1 |checksum:bbb\n7.0.2 |checksum:ccc\n";
+    let sinatra_info = "---\n3.0.0 rack:>= 2.2|checksum:ddd\n";
+
+    let upstream = common::serve(vec![
+        ("/versions", versions.as_bytes().to_vec()),
+        ("/info/rails", rails_info.as_bytes().to_vec()),
+        ("/info/sinatra", sinatra_info.as_bytes().to_vec()),
+    ]);
+
+    let dest = std::env::temp_dir().join(format!("gem-index-filter-mirror-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dest);
+
+    let mut allowlist = HashSet::new();
+    allowlist.insert("rails");
+    allowlist.insert("sinatra");
+    allowlist.insert("puma"); // Not present upstream: no info fetch expected
+
+    let options = MirrorOptions {
+        upstream,
+        ..MirrorOptions::new(&dest)
+    };
+    let stats = build_mirror(FilterMode::Allow(&allowlist), &options).unwrap();
+
+    assert_eq!(stats.gems, 2);
+    assert_eq!(stats.info_files, 2);
+
+    let mirrored_versions = fs::read_to_string(dest.join("versions")).unwrap();
+    assert_eq!(
+        mirrored_versions,
+        "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0,7.0.1 abc123\nsinatra 3.0.0 ghi789\nrails 7.0.2 jkl000\n"
+    );
+    assert_eq!(stats.versions_bytes, mirrored_versions.len() as u64);
+
+    assert_eq!(
+        fs::read_to_string(dest.join("names")).unwrap(),
+        "---\nrails\nsinatra\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dest.join("info/rails")).unwrap(),
+        rails_info
+    );
+    assert_eq!(
+        fs::read_to_string(dest.join("info/sinatra")).unwrap(),
+        sinatra_info
+    );
+    assert!(!dest.join("info/activerecord").exists());
+
+    // A gem dropped from the allowlist loses its info file on the next run
+    allowlist.remove("sinatra");
+    let stats = build_mirror(FilterMode::Allow(&allowlist), &options).unwrap();
+    assert_eq!((stats.gems, stats.info_removed), (1, 1));
+    assert!(dest.join("info/rails").exists());
+    assert!(!dest.join("info/sinatra").exists());
+
+    fs::remove_dir_all(&dest).unwrap();
+}
+
+#[test]
+fn test_mirror_fails_on_missing_info() {
+    let versions = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+    let upstream = common::serve(vec![("/versions", versions.as_bytes().to_vec())]);
+
+    let dest = std::env::temp_dir().join(format!(
+        "gem-index-filter-mirror-missing-{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dest);
+
+    let options = MirrorOptions {
+        upstream,
+        ..MirrorOptions::new(&dest)
+    };
+    assert!(build_mirror(FilterMode::Passthrough, &options).is_err());
+    // The failed download must not leave a partial info file behind
+    assert!(!dest.join("info/rails").exists());
+
+    fs::remove_dir_all(&dest).unwrap();
+}
+
+#[test]
+fn test_info_fetcher_retries_transient_failures() {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    let info = "---\n7.0.0 |checksum:aaa\n";
+    let names = ["rails", "rack", "sinatra", "puma", "nokogiri"];
+    let dest = std::env::temp_dir().join(format!(
+        "gem-index-filter-info-retries-{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dest);
+
+    let fetcher = InfoFetcher {
+        concurrency: 3,
+        backoff: Duration::from_millis(10),
+        ..InfoFetcher::new(common::serve_flaky(info, 2))
+    };
+    let done = Mutex::new(Vec::new());
+    let stats = fetcher
+        .fetch_all(&names, &dest, |progress| {
+            assert_eq!(progress.total, names.len());
+            done.lock().unwrap().push(progress.done);
+        })
+        .unwrap();
+    assert_eq!((stats.fetched, stats.retries), (5, 10));
+    let mut done = done.into_inner().unwrap();
+    done.sort();
+    assert_eq!(done, [1, 2, 3, 4, 5]);
+    for name in names {
+        assert_eq!(fs::read_to_string(dest.join(name)).unwrap(), info);
+    }
+
+    // Out of retries, the 503 fails the run
+    let fetcher = InfoFetcher {
+        upstream: common::serve_flaky(info, 2),
+        retries: 1,
+        ..fetcher
+    };
+    let error = fetcher.fetch_all(&["rails"], &dest, |_| {}).unwrap_err();
+    assert!(error.to_string().ends_with("/info/rails: HTTP 503"));
+
+    fs::remove_dir_all(&dest).unwrap();
+}
+
+#[test]
+fn test_mirror_rejects_truncated_versions() {
+    // Cut off mid-line, as by a dropped connection
+    let versions = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\nsinatra 3.0";
+    let upstream = common::serve(vec![("/versions", versions.as_bytes().to_vec())]);
+
+    let dest = std::env::temp_dir().join(format!(
+        "gem-index-filter-mirror-truncated-{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dest);
+
+    let options = MirrorOptions {
+        upstream,
+        ..MirrorOptions::new(&dest)
+    };
+    let error = build_mirror(FilterMode::Passthrough, &options).unwrap_err();
+    assert_eq!(
+        Truncated::from_io_error(&error),
+        Some(&Truncated::MissingNewline)
+    );
+    assert!(!dest.join("versions").exists());
+
+    fs::remove_dir_all(&dest).unwrap();
+}
+
+#[test]
+fn test_mirror_refilters_unchanged_snapshot() {
+    let versions = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+    let upstream = common::serve(vec![
+        ("/versions", versions.as_bytes().to_vec()),
+        ("/info/rails", b"---\n7.0.0 |checksum:aaa\n".to_vec()),
+    ]);
+
+    let dest = std::env::temp_dir().join(format!(
+        "gem-index-filter-mirror-snapshot-{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dest);
+
+    let options = MirrorOptions {
+        upstream,
+        snapshot_dir: Some(dest.join("upstream")),
+        ..MirrorOptions::new(dest.join("mirror"))
+    };
+    build_mirror(FilterMode::Passthrough, &options).unwrap();
+    assert_eq!(
+        fs::read_to_string(dest.join("upstream/versions.etag")).unwrap(),
+        format!("\"{}\"", versions.len())
+    );
+
+    // The test server derives ETags from the body length, so this one answers 304
+    let changed = versions.replace("abc123", "zzz999");
+    let options = MirrorOptions {
+        upstream: common::serve(vec![
+            ("/versions", changed.into_bytes()),
+            ("/info/rails", b"---\n7.0.0 |checksum:aaa\n".to_vec()),
+        ]),
+        ..options
+    };
+    let stats = build_mirror(FilterMode::Passthrough, &options).unwrap();
+    assert_eq!(
+        fs::read_to_string(dest.join("mirror/versions")).unwrap(),
+        versions
+    );
+    // The info file's ETag was kept too, so it isn't downloaded again
+    assert_eq!((stats.info_files, stats.info_unchanged), (0, 1));
+
+    fs::remove_dir_all(&dest).unwrap();
+}
+
+#[test]
+fn test_mirror_rejects_oversized_versions() {
+    use gem_index_filter::{FetchError, FetchLimits};
+
+    let versions = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+    let upstream = common::serve(vec![("/versions", versions.as_bytes().to_vec())]);
+
+    let dest = std::env::temp_dir().join(format!(
+        "gem-index-filter-mirror-limits-{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dest);
+
+    let options = MirrorOptions {
+        upstream,
+        limits: FetchLimits {
+            max_bytes: Some(16),
+            ..FetchLimits::default()
+        },
+        ..MirrorOptions::new(&dest)
+    };
+    let error = build_mirror(FilterMode::Passthrough, &options).unwrap_err();
+    assert!(matches!(
+        FetchError::from_io_error(&error),
+        Some(FetchError::TooLarge { limit: 16, .. })
+    ));
+    assert!(!dest.join("versions").exists());
+
+    fs::remove_dir_all(&dest).unwrap();
+}
+
+#[cfg(feature = "digest")]
+#[test]
+fn test_mirror_keeps_previous_versions_on_digest_mismatch() {
+    use gem_index_filter::ChecksumMismatch;
+
+    let versions = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+    let upstream = common::serve(vec![("/versions", versions.as_bytes().to_vec())]);
+
+    let dest = std::env::temp_dir().join(format!(
+        "gem-index-filter-mirror-pinned-{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dest);
+    fs::create_dir_all(&dest).unwrap();
+    fs::write(dest.join("versions"), "previous\n").unwrap();
+
+    let options = MirrorOptions {
+        upstream,
+        upstream_digest: Some("0".repeat(64)),
+        ..MirrorOptions::new(&dest)
+    };
+    let error = build_mirror(FilterMode::Passthrough, &options).unwrap_err();
+    let mismatch = ChecksumMismatch::from_io_error(&error).unwrap();
+    assert_eq!(
+        mismatch.actual,
+        "8b3ecdd38845689b6742975916f2358f8db22b0562e332dea181cd5d0b69998e"
+    );
+    assert_eq!(
+        fs::read_to_string(dest.join("versions")).unwrap(),
+        "previous\n"
+    );
+
+    fs::remove_dir_all(&dest).unwrap();
+}
+
+#[cfg(feature = "signing")]
+#[test]
+fn test_mirror_checks_signed_manifest() {
+    use gem_index_filter::sign::{parse_signing_key, public_key_hex};
+    use gem_index_filter::{ChecksumMismatch, SignedManifest, SigningWriter};
+    use std::io::Write;
+
+    let versions = "created_at: 2024-04-01T00:00:05Z\n---\nrails 7.0.0 abc123\n";
+    let manifest = "8b3ecdd38845689b6742975916f2358f8db22b0562e332dea181cd5d0b69998e  versions\n";
+    let key = parse_signing_key("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+        .unwrap();
+    let mut signed = Vec::new();
+    let mut writer = SigningWriter::new(&mut signed);
+    writer.write_all(manifest.as_bytes()).unwrap();
+    let signature = writer.sign(&key);
+
+    let upstream = common::serve(vec![
+        ("/versions", versions.as_bytes().to_vec()),
+        ("/versions.sha256", manifest.as_bytes().to_vec()),
+        ("/versions.sha256.sig", signature.into_bytes()),
+    ]);
+    let dest = std::env::temp_dir().join(format!(
+        "gem-index-filter-mirror-manifest-{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dest);
+
+    let mut options = MirrorOptions::new(&dest);
+    options.upstream_manifest = Some(SignedManifest {
+        url: format!("{}/versions.sha256", upstream),
+        public_key: public_key_hex(&key),
+    });
+    options.upstream = upstream;
+    // No info file is served, but versions must have passed the check to get that far
+    let error = build_mirror(FilterMode::Passthrough, &options).unwrap_err();
+    assert!(ChecksumMismatch::from_io_error(&error).is_none());
+    assert_eq!(fs::read_to_string(dest.join("versions")).unwrap(), versions);
+
+    // A manifest signed by another key is refused before anything is written
+    fs::remove_dir_all(&dest).unwrap();
+    let other_key = parse_signing_key(&"11".repeat(32)).unwrap();
+    options.upstream_manifest.as_mut().unwrap().public_key = public_key_hex(&other_key);
+    assert!(build_mirror(FilterMode::Passthrough, &options).is_err());
+    assert!(!dest.join("versions").exists());
+
+    fs::remove_dir_all(&dest).unwrap();
+}
@@ -0,0 +1,220 @@
+#![cfg(feature = "std")]
+
+//! Property tests over generated versions files
+//!
+//! Each case builds a structurally valid index (duplicated gem names, yanked
+//! and platform versions, extra trailing fields, blank lines) and checks
+//! invariants that must hold for every mode rather than for hand-picked input.
+
+#[cfg(feature = "digest")]
+use gem_index_filter::DigestAlgorithm;
+use gem_index_filter::{filter_versions_streaming, FilterMode, VersionOutput};
+use proptest::prelude::*;
+#[cfg(feature = "digest")]
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+/// A small name pool so gems repeat, the way updates append to the real file
+fn gem_name() -> impl Strategy<Value = String> {
+    prop::sample::select(vec![
+        "rails",
+        "rack",
+        "sinatra",
+        "active_model_serializers",
+        "aws-sdk-s3",
+        "0mq",
+        ".cat",
+        "-A",
+        "nokogiri",
+        "puma",
+    ])
+    .prop_map(String::from)
+}
+
+fn version() -> impl Strategy<Value = String> {
+    (
+        any::<bool>(),
+        0u8..20,
+        0u8..20,
+        0u8..20,
+        prop::option::of(prop::sample::select(vec![
+            "java",
+            "x86_64-linux",
+            "arm64-darwin",
+        ])),
+    )
+        .prop_map(|(yanked, major, minor, patch, platform)| {
+            let mut version = format!(
+                "{}{}.{}.{}",
+                if yanked { "-" } else { "" },
+                major,
+                minor,
+                patch
+            );
+            if let Some(platform) = platform {
+                version.push('-');
+                version.push_str(platform);
+            }
+            version
+        })
+}
+
+fn gem_line() -> impl Strategy<Value = String> {
+    (
+        gem_name(),
+        prop::collection::vec(version(), 1..6),
+        "[0-9a-f]{32}",
+        prop::option::of("[a-z0-9]{1,8}"),
+    )
+        .prop_map(|(name, versions, md5, extra)| {
+            let mut line = format!("{} {} {}", name, versions.join(","), md5);
+            if let Some(extra) = extra {
+                line.push(' ');
+                line.push_str(&extra);
+            }
+            line
+        })
+}
+
+/// A versions file, with the gem lines it contains in order
+fn versions_file() -> impl Strategy<Value = (String, Vec<String>)> {
+    prop::collection::vec((gem_line(), any::<bool>()), 0..40).prop_map(|lines| {
+        let mut file = String::from("created_at: 2024-04-01T00:00:05Z\n---\n");
+        for (line, blank_after) in &lines {
+            file.push_str(line);
+            file.push('\n');
+            if *blank_after {
+                file.push('\n');
+            }
+        }
+        (file, lines.into_iter().map(|(line, _)| line).collect())
+    })
+}
+
+fn gem_set() -> impl Strategy<Value = HashSet<String>> {
+    prop::collection::hash_set(gem_name(), 0..6)
+}
+
+fn filter(input: &str, mode: FilterMode, version_output: VersionOutput) -> String {
+    let mut output = Vec::new();
+    filter_versions_streaming(input.as_bytes(), &mut output, mode, version_output, None).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+fn borrow(set: &HashSet<String>) -> HashSet<&str> {
+    set.iter().map(String::as_str).collect()
+}
+
+/// The body lines of a filtered file, after the separator
+fn body(output: &str) -> Vec<&str> {
+    output.split_once("---\n").unwrap().1.lines().collect()
+}
+
+/// `name versions md5 [extra]` -> `name 0 md5 [extra]`
+fn stripped(line: &str) -> String {
+    let mut parts = line.split(' ');
+    let name = parts.next().unwrap();
+    parts.next();
+    let rest: Vec<&str> = parts.collect();
+    format!("{} 0 {}", name, rest.join(" "))
+}
+
+fn is_subsequence(needle: &[String], haystack: &[String]) -> bool {
+    let mut haystack = haystack.iter();
+    needle
+        .iter()
+        .all(|line| haystack.any(|candidate| candidate == line))
+}
+
+proptest! {
+    #[test]
+    fn output_is_an_ordered_subsequence_of_input(
+        (input, lines) in versions_file(),
+        gems in gem_set(),
+        mode_index in 0..3usize,
+        strip in any::<bool>(),
+    ) {
+        let gems = borrow(&gems);
+        let mode = [FilterMode::Passthrough, FilterMode::Allow(&gems), FilterMode::Block(&gems)][mode_index];
+        let version_output = if strip { VersionOutput::Strip } else { VersionOutput::Preserve };
+
+        let output = filter(&input, mode, version_output);
+        prop_assert!(output.starts_with("created_at: 2024-04-01T00:00:05Z\n---\n"));
+
+        let expected: Vec<String> = if strip {
+            lines.iter().map(|line| stripped(line)).collect()
+        } else {
+            lines.clone()
+        };
+        let written: Vec<String> = body(&output).into_iter().map(String::from).collect();
+        prop_assert!(is_subsequence(&written, &expected), "{:?} not in {:?}", written, expected);
+
+        // Every line is kept or dropped purely on its gem name
+        let kept = |line: &String| {
+            let name = line.split(' ').next().unwrap();
+            match mode {
+                FilterMode::Passthrough => true,
+                FilterMode::Allow(gems) => gems.contains(name),
+                FilterMode::Block(gems) => !gems.contains(name),
+            }
+        };
+        let selected: Vec<String> = expected.into_iter().filter(kept).collect();
+        prop_assert_eq!(written, selected);
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn digest_is_sha256_of_written_bytes(
+        (input, _) in versions_file(),
+        gems in gem_set(),
+        strip in any::<bool>(),
+    ) {
+        let gems = borrow(&gems);
+        let version_output = if strip { VersionOutput::Strip } else { VersionOutput::Preserve };
+        let mut output = Vec::new();
+        let digest = filter_versions_streaming(
+            input.as_bytes(),
+            &mut output,
+            FilterMode::Allow(&gems),
+            version_output,
+            Some(DigestAlgorithm::Sha256),
+        )
+        .unwrap();
+
+        prop_assert_eq!(digest, Some(hex::encode(Sha256::digest(&output))));
+    }
+
+    #[test]
+    fn allow_then_block_equals_preprocessed_allow(
+        (input, _) in versions_file(),
+        allow in gem_set(),
+        block in gem_set(),
+        strip in any::<bool>(),
+    ) {
+        let version_output = if strip { VersionOutput::Strip } else { VersionOutput::Preserve };
+        let allowlist = borrow(&allow);
+        let blocklist = borrow(&block);
+
+        let allowed = filter(&input, FilterMode::Allow(&allowlist), version_output);
+        let composed = filter(&allowed, FilterMode::Block(&blocklist), VersionOutput::Preserve);
+
+        // What main.rs builds when given both --allow and --block
+        let difference: HashSet<&str> = allowlist.difference(&blocklist).copied().collect();
+        let preprocessed = filter(&input, FilterMode::Allow(&difference), version_output);
+
+        prop_assert_eq!(composed, preprocessed);
+    }
+
+    #[test]
+    fn filtering_is_idempotent(
+        (input, _) in versions_file(),
+        gems in gem_set(),
+        strip in any::<bool>(),
+    ) {
+        let gems = borrow(&gems);
+        let version_output = if strip { VersionOutput::Strip } else { VersionOutput::Preserve };
+        let once = filter(&input, FilterMode::Block(&gems), version_output);
+        let twice = filter(&once, FilterMode::Block(&gems), version_output);
+        prop_assert_eq!(once, twice);
+    }
+}